@@ -1,7 +1,54 @@
+use std::process::Command;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Only the gRPC service of the `signing` feature uses the protocol buffers.
+    #[cfg(feature = "signing")]
     tonic_build::configure()
         .build_server(true)
-        .file_descriptor_set_path("src/descriptor.bin")
+        .file_descriptor_set_path(
+            std::path::Path::new(&std::env::var("OUT_DIR")?).join("descriptor.bin"),
+        )
         .compile_protos(&["../../proto/key_pool.proto"], &["../../"])?;
+    build_info();
     Ok(())
 }
+
+/// Rebuild when the commit changes: when HEAD moves to another branch, or the branch it refers
+/// to moves (a loose ref, or one in `packed-refs` after `git gc`).
+fn watch_git_head(git_dir: &str) {
+    let head = format!("{}/HEAD", git_dir);
+    let Ok(contents) = std::fs::read_to_string(&head) else {
+        return;
+    };
+    println!("cargo:rerun-if-changed={}", head);
+    if let Some(reference) = contents.trim().strip_prefix("ref: ") {
+        let reference = format!("{}/{}", git_dir, reference);
+        if std::path::Path::new(&reference).exists() {
+            println!("cargo:rerun-if-changed={}", reference);
+        }
+        let packed_refs = format!("{}/packed-refs", git_dir);
+        if std::path::Path::new(&packed_refs).exists() {
+            println!("cargo:rerun-if-changed={}", packed_refs);
+        }
+    }
+}
+
+/// Export the commit hash and build timestamp for `version`. The timestamp is taken from
+/// `SOURCE_DATE_EPOCH` (set to the commit time by the Makefile), never from the clock, so that
+/// builds remain reproducible.
+fn build_info() {
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // Docker builds have no .git directory; they pass the commit in GIT_COMMIT.
+    let git_commit = std::env::var("GIT_COMMIT").ok().filter(|x| !x.is_empty()).or_else(|| {
+        let output = Command::new("git").args(["rev-parse", "HEAD"]).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+    watch_git_head("../../.git");
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH").ok().filter(|x| !x.is_empty());
+    println!("cargo:rustc-env=SOVEREIGN_GIT_COMMIT={}", git_commit.as_deref().unwrap_or("unknown"));
+    println!(
+        "cargo:rustc-env=SOVEREIGN_BUILD_TIMESTAMP={}",
+        build_timestamp.as_deref().unwrap_or("unknown")
+    );
+}
@@ -0,0 +1,71 @@
+//! Break-glass signing key derived from a passphrase.
+//!
+//! For disaster recovery, e.g., to sign a recovery transaction after the pool's key material
+//! has been lost, a pool can be configured (`break-glass` in the config) to accept a key that
+//! is derived with Argon2id from a passphrase held by the operators. The passphrase is never
+//! part of the configuration: operators encrypt it to the pool's sealing key (see
+//! `config::SealedValue`) and send it with the `LoadBreakGlassKey` RPC, which governance must
+//! authorize for the resulting public key. The key is then available to the signing RPCs under
+//! `BREAK_GLASS_KEY_INDEX` until the sovereign restarts; it is not part of key-sync.
+//!
+//! Threat model: anyone who learns or guesses the passphrase can derive the key outside of any
+//! enclave, so the passphrase strength (together with the Argon2id cost and the salt) is the
+//! only protection of the break-glass key. Use a long, randomly generated passphrase and keep
+//! it offline. The randomly generated signing keys are unaffected: they are neither derived
+//! from nor replaced by the break-glass key.
+
+use anyhow::{anyhow, bail, Result};
+use zeroize::Zeroizing;
+
+use crate::config::BreakGlassConfig;
+
+/// The key index under which the signing RPCs use the break-glass key.
+pub const BREAK_GLASS_KEY_INDEX: u32 = u32::MAX;
+
+/// Minimum passphrase length in bytes; a weak passphrase makes the key guessable offline.
+pub const MIN_PASSPHRASE_BYTES: usize = 24;
+
+/// Minimum time between two attempts to load the break-glass key: each attempt runs Argon2id
+/// with the configured (deliberately expensive) cost.
+pub const MIN_ATTEMPT_INTERVAL_MILLIS: u64 = 10_000;
+
+/// Derive the break-glass key from `passphrase` with Argon2id as configured.
+pub fn derive_key(passphrase: &[u8], config: &BreakGlassConfig) -> Result<k256::SecretKey> {
+    use argon2::{Algorithm, Argon2, Params, Version};
+
+    if passphrase.len() < MIN_PASSPHRASE_BYTES {
+        bail!("break-glass passphrase must be at least {} bytes", MIN_PASSPHRASE_BYTES);
+    }
+    let params =
+        Params::new(config.memory_kib(), config.iterations(), config.parallelism(), Some(32))
+            .map_err(|e| anyhow!("invalid Argon2id parameters: {}", e))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, config.salt.as_bytes(), key.as_mut())
+        .map_err(|e| anyhow!("Argon2id key derivation failed: {}", e))?;
+    k256::SecretKey::from_slice(key.as_ref())
+        .map_err(|_| anyhow!("passphrase does not yield a valid secp256k1 key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_key() {
+        let config = BreakGlassConfig {
+            salt: "pool-1-break-glass".to_string(),
+            memory_kib: Some(64),
+            iterations: Some(1),
+            parallelism: Some(1),
+        };
+        assert!(config.validate().is_ok());
+        let passphrase = b"correct horse battery staple";
+        let key = derive_key(passphrase, &config).unwrap();
+        assert_eq!(key, derive_key(passphrase, &config).unwrap());
+        assert_ne!(key, derive_key(b"correct horse battery stapler", &config).unwrap());
+        let other_salt = BreakGlassConfig { salt: "pool-2-break-glass".to_string(), ..config };
+        assert_ne!(key, derive_key(passphrase, &other_salt).unwrap());
+        assert!(derive_key(b"too short", &other_salt).is_err());
+    }
+}
@@ -1,7 +1,8 @@
 //! This module deals with the configuration of a sovereign running inside a TEE pool.
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Configuration which instructs the sovereign how to access a Safe for
 /// authorizing measurements during startup and in the key-sync protocol.
@@ -17,8 +18,260 @@ pub struct SafeConfig {
     pub http_endpoint_port: u32,
     #[serde(rename = "chain-id")]
     pub chain_id: u64,
+    /// If set, the host of `http-endpoint` must be one of these (e.g., `safe-transaction-mainnet.safe.global`).
+    #[serde(rename = "allowed-safe-hosts", default)]
+    pub allowed_safe_hosts: Option<Vec<String>>,
+    /// Limit on the time for each request to `http-endpoint` (default: 30 seconds).
+    #[serde(rename = "request-timeout-seconds", default)]
+    pub request_timeout_seconds: Option<u64>,
+    /// Further transaction services (e.g., mirrors) to query if `http-endpoint` fails.
+    ///
+    /// Messages are looked up at all endpoints in order until one has found the message, so
+    /// that a mirror lagging behind cannot hide a message (e.g., a revocation) that another
+    /// endpoint has. In turn, every endpoint must be trusted as much as `http-endpoint`: any of
+    /// them can produce a message. Lookups only fail if all endpoints fail.
+    #[serde(rename = "fallback-endpoints", default)]
+    pub fallback_endpoints: Vec<SafeEndpoint>,
 }
 
+/// A Safe transaction service and the host port through which it is reached.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SafeEndpoint {
+    #[serde(rename = "http-endpoint")]
+    pub http_endpoint: String,
+    #[serde(rename = "http-endpoint-port")]
+    pub http_endpoint_port: u32,
+}
+
+impl SafeConfig {
+    /// All endpoints as `(http-endpoint, http-endpoint-port)`, the primary one first.
+    pub fn endpoints(&self) -> impl Iterator<Item = (&str, u32)> {
+        std::iter::once((self.http_endpoint.as_str(), self.http_endpoint_port)).chain(
+            self.fallback_endpoints
+                .iter()
+                .map(|endpoint| (endpoint.http_endpoint.as_str(), endpoint.http_endpoint_port)),
+        )
+    }
+
+    /// Check the hosts of all endpoints against `allowed-safe-hosts` (if configured).
+    pub fn check_endpoint_host(&self) -> Result<()> {
+        let Some(allowed_safe_hosts) = &self.allowed_safe_hosts else {
+            return Ok(());
+        };
+        for (http_endpoint, _) in self.endpoints() {
+            let uri = http_endpoint
+                .parse::<hyper::Uri>()
+                .with_context(|| format!("invalid Safe endpoint {}", http_endpoint))?;
+            let host = uri.host().context("missing host in Safe endpoint")?;
+            if !allowed_safe_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+                bail!(
+                    "Safe endpoint host {} is not in allowed-safe-hosts {:?}",
+                    host,
+                    allowed_safe_hosts
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The `wallet-address` in its EIP-55 checksummed form, checking that it is well-formed.
+    pub fn normalized_wallet_address(&self) -> Result<String> {
+        let address = crate::safe::parse_wallet_address(&self.wallet_address)
+            .with_context(|| format!("invalid Safe wallet-address {}", self.wallet_address))?;
+        Ok(crate::safe::checksum_address(&address))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.check_endpoint_host()?;
+        self.normalized_wallet_address()?;
+        if self.request_timeout_seconds == Some(0) {
+            bail!("Safe request timeout must be at least one second");
+        }
+        Ok(())
+    }
+
+    /// The configured `request-timeout-seconds` or its default.
+    pub fn request_timeout(&self) -> std::time::Duration {
+        self.request_timeout_seconds
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(crate::http::DEFAULT_REQUEST_TIMEOUT)
+    }
+}
+
+/// How many of several Safes must authorize a measurement.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum Quorum {
+    /// Every Safe must authorize.
+    #[serde(rename = "all")]
+    All,
+    /// At least this many Safes must authorize.
+    #[serde(rename = "any-of")]
+    AnyOf(usize),
+}
+
+/// Configuration for periodically pushing metrics to a Prometheus Pushgateway
+/// (through the host), so that scrapers see continuity across enclave restarts.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct MetricsPushConfig {
+    /// Base URL of the Pushgateway, e.g., `http://pushgateway:9091`.
+    #[serde(rename = "http-endpoint")]
+    pub http_endpoint: String,
+    #[serde(rename = "http-endpoint-port")]
+    pub http_endpoint_port: u32,
+    /// Job label under which the metrics are grouped in the Pushgateway.
+    #[serde(rename = "job")]
+    pub job: String,
+    #[serde(rename = "interval-seconds")]
+    pub interval_seconds: u64,
+    /// Value of the `Authorization` header sent to the Pushgateway (e.g., `Bearer <token>`),
+    /// preferably sealed.
+    #[serde(rename = "authorization", default)]
+    pub authorization: Option<SealedValue>,
+}
+
+/// A configuration value given either in plain text (a JSON string) or sealed to the pool
+/// (`{"sealed": "<hex>"}`), i.e., encrypted with `ecies_envelope` to the pool's sealing key.
+///
+/// The sealing key is derived from the pool's shared key material (see
+/// `KeyServer::sealing_key`), so only attested sovereigns that obtained the key material
+/// can unseal values; its public key is logged at startup. Sealed values are unsealed once
+/// the key material is available, i.e., after key-sync.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SealedValue {
+    Plain(String),
+    Sealed {
+        #[serde(rename = "sealed")]
+        sealed: String,
+    },
+}
+
+impl SealedValue {
+    /// The plain text value, decrypting it with `sealing_key` if sealed.
+    pub fn unseal(&self, sealing_key: &k256::SecretKey) -> Result<String> {
+        match self {
+            SealedValue::Plain(value) => Ok(value.clone()),
+            SealedValue::Sealed { sealed } => {
+                let envelope = hex::decode(sealed).context("sealed value is not hex")?;
+                let plaintext = crate::ecies_envelope::decrypt(&sealing_key.to_bytes(), &envelope)
+                    .context("cannot unseal value")?;
+                String::from_utf8(plaintext).context("sealed value is not UTF-8")
+            }
+        }
+    }
+
+    /// The value with plain text replaced by `<redacted>`; sealed values are kept.
+    pub fn redacted(&self) -> Self {
+        match self {
+            SealedValue::Plain(_) => SealedValue::Plain("<redacted>".to_string()),
+            SealedValue::Sealed { .. } => self.clone(),
+        }
+    }
+}
+
+impl MetricsPushConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_seconds == 0 {
+            bail!("metrics push interval must be at least one second");
+        }
+        if self.job.is_empty() || self.job.contains('/') {
+            bail!("invalid metrics push job name '{}'", self.job);
+        }
+        Ok(())
+    }
+}
+
+/// Default for `nsm.peer-cid`: the parent instance of a Nitro enclave.
+pub const DEFAULT_NSM_PEER_CID: u32 = 3;
+
+/// Default for `nsm.listen-cid`: any CID (`VMADDR_CID_ANY`).
+pub const DEFAULT_NSM_LISTEN_CID: u32 = u32::MAX;
+
+/// VSOCK addressing of a Nitro enclave; ignored by other security modules.
+///
+/// NSM attestation requests take no options beyond `nonce`, `public_key`, and `user_data`,
+/// so there is nothing to tune about them here.
+#[derive(PartialEq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct NsmConfig {
+    /// CID to which outbound connections (key-sync, Safe, metrics push) are made (default: 3).
+    #[serde(rename = "peer-cid", default)]
+    pub peer_cid: Option<u32>,
+    /// CID on which listeners are bound (default: any).
+    #[serde(rename = "listen-cid", default)]
+    pub listen_cid: Option<u32>,
+}
+
+impl NsmConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.peer_cid == Some(DEFAULT_NSM_LISTEN_CID) {
+            bail!("nsm peer-cid must be a specific CID");
+        }
+        Ok(())
+    }
+
+    /// The configured `peer-cid` or its default.
+    pub fn peer_cid(&self) -> u32 {
+        self.peer_cid.unwrap_or(DEFAULT_NSM_PEER_CID)
+    }
+
+    /// The configured `listen-cid` or its default.
+    pub fn listen_cid(&self) -> u32 {
+        self.listen_cid.unwrap_or(DEFAULT_NSM_LISTEN_CID)
+    }
+}
+
+/// Argon2id parameters of the break-glass key, see `break_glass`. The passphrase itself is
+/// never configured.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct BreakGlassConfig {
+    /// Salt of the key derivation (at least 16 bytes), e.g., a name unique to the pool.
+    #[serde(rename = "salt")]
+    pub salt: String,
+    /// Memory cost in KiB (default: 65536, i.e., 64 MiB).
+    #[serde(rename = "memory-kib", default)]
+    pub memory_kib: Option<u32>,
+    /// Number of passes (default: 3).
+    #[serde(rename = "iterations", default)]
+    pub iterations: Option<u32>,
+    /// Degree of parallelism (default: 1).
+    #[serde(rename = "parallelism", default)]
+    pub parallelism: Option<u32>,
+}
+
+impl BreakGlassConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.salt.len() < 16 {
+            bail!("break-glass salt must be at least 16 bytes");
+        }
+        if self.iterations() == 0 || self.parallelism() == 0 {
+            bail!("break-glass iterations and parallelism must be at least one");
+        }
+        if self.memory_kib() < 8 * self.parallelism() {
+            bail!("break-glass memory-kib must be at least 8 times the parallelism");
+        }
+        Ok(())
+    }
+
+    /// The configured `memory-kib` or its default.
+    pub fn memory_kib(&self) -> u32 {
+        self.memory_kib.unwrap_or(65536)
+    }
+
+    /// The configured `iterations` or its default.
+    pub fn iterations(&self) -> u32 {
+        self.iterations.unwrap_or(3)
+    }
+
+    /// The configured `parallelism` or its default.
+    pub fn parallelism(&self) -> u32 {
+        self.parallelism.unwrap_or(1)
+    }
+}
+
+/// Whether this build accepts `Governance::TestingOnly`, i.e., was built with the
+/// `allow-testing-governance` feature (or for unit tests).
+pub const TESTING_GOVERNANCE_ALLOWED: bool = cfg!(any(test, feature = "allow-testing-governance"));
+
 /// A TEE pool is governed by a Safe (Ethereum smart contract).
 /// Alternatively, a testing deployment can forgo the Safe authorizations,
 /// but only for sovereigns that are running in debug mode.
@@ -26,12 +279,51 @@ pub struct SafeConfig {
 pub enum Governance {
     /// This governance version is only available in debug mode.
     /// It simply checks that the local and remote attestation documents are showing sovereigns running in debug mode.
+    /// Builds without the `allow-testing-governance` feature reject it.
     #[default]
     #[serde(rename = "testing-only")]
     TestingOnly,
     /// A production sovereign should use this configuration option.
     #[serde(rename = "safe")]
     Safe(SafeConfig),
+    /// Like `Safe`, but governed by several Safes (e.g., separate security and operations
+    /// multisigs), of which the quorum specified by `require` must authorize.
+    #[serde(rename = "multi-safe")]
+    MultiSafe { safes: Vec<SafeConfig>, require: Quorum },
+}
+
+impl Governance {
+    /// Fail unless this is not `TestingOnly` or this build accepts it.
+    pub fn check_testing_allowed(&self) -> Result<()> {
+        if *self == Governance::TestingOnly && !TESTING_GOVERNANCE_ALLOWED {
+            bail!(
+                "testing-only governance is disabled in this build \
+                 (built without the allow-testing-governance feature)"
+            );
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.check_testing_allowed()?;
+        if let Governance::Safe(safe) = self {
+            safe.validate()?;
+        }
+        if let Governance::MultiSafe { safes, require } = self {
+            for safe in safes {
+                safe.validate()?;
+            }
+            if safes.is_empty() {
+                bail!("multi-safe governance requires at least one safe");
+            }
+            if let Quorum::AnyOf(n) = require {
+                if *n == 0 || *n > safes.len() {
+                    bail!("multi-safe quorum must be between 1 and {}: was {}", safes.len(), n);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Exactly one sovereign per TEE pool should generate its own secret keys.
@@ -70,6 +362,10 @@ impl Default for SecretKeyRetrieval {
     }
 }
 
+/// Whether this build has signing keys, the gRPC service, and key-sync (the `signing` feature).
+/// Builds without it only serve attestation documents and metrics.
+pub const SIGNING_ENABLED: bool = cfg!(feature = "signing");
+
 /// Complete configuration of the sovereign.
 #[derive(PartialEq, Default, Debug, Clone, Serialize, Deserialize)]
 pub struct SovereignConfig {
@@ -81,9 +377,33 @@ pub struct SovereignConfig {
     /// Alternative names to use for the self-signed server certificate.
     #[serde(rename = "alt-names")]
     pub alt_names: Vec<String>,
+    /// Port on which to also serve the gRPC signing service over TLS (using the server
+    /// certificate); the service is always served on the local Unix domain socket.
+    /// Requires `grpc-auth-token`, as the port is reachable through the host.
+    #[serde(rename = "grpc-vsock-port", default)]
+    pub grpc_vsock_port: Option<u32>,
     /// Port on which to serve key-sync requests.
     #[serde(rename = "key-sync-port")]
     pub key_sync_port: Option<u32>,
+    /// Maximum number of key-sync sessions served concurrently; further followers are rejected
+    /// until a session completes. Unlimited if not set.
+    #[serde(rename = "max-concurrent-key-syncs", default)]
+    pub max_concurrent_key_syncs: Option<usize>,
+    /// Run key-sync over TLS, using the server certificate on the leader (default: false).
+    /// Must be set alike on leader and followers. Key-sync is authenticated by attestation
+    /// either way; with TLS, both attestations also bind the TLS session, so the host can
+    /// neither read the transcript nor relay it to another session. Message sizes remain visible.
+    #[serde(rename = "key-sync-tls", default)]
+    pub key_sync_tls: Option<bool>,
+    /// How often a follower tries to reach its leader before giving up (default: 10). Only
+    /// failures before the leader's first message (e.g., the leader is not listening yet)
+    /// are retried.
+    #[serde(rename = "key-sync-connect-attempts", default)]
+    pub key_sync_connect_attempts: Option<u32>,
+    /// Delay before the first retry of a follower's key-sync (default: 1000 milliseconds);
+    /// doubled for each further retry, up to 30 seconds.
+    #[serde(rename = "key-sync-retry-delay-milliseconds", default)]
+    pub key_sync_retry_delay_milliseconds: Option<u64>,
     /// Port on which to serve monitoring requests.
     #[serde(rename = "monitoring-port")]
     pub monitoring_port: Option<u32>,
@@ -93,14 +413,714 @@ pub struct SovereignConfig {
     /// Port on which to serve HTTPs attestation requests.
     #[serde(rename = "https-attestation-port")]
     pub https_attestation_port: Option<u32>,
+    /// Also serve attestations over WebSocket: upgrade `GET /` on the attestation ports
+    /// and answer each nonce frame with an attestation document frame.
+    #[serde(rename = "websocket-attestation", default)]
+    pub websocket_attestation: bool,
     // Trace = 0, Debug = 1, Info = 2, Warn = 3, Error = 4.
     #[serde(rename = "trace-level", default)]
     pub trace_level: usize,
+    /// Accept PCRs that already contain the expected measurements
+    /// (e.g., when restarting within the same enclave) instead of requiring zeroed PCRs.
+    #[serde(rename = "idempotent-measurements", default)]
+    pub idempotent_measurements: bool,
+    /// Optionally push metrics to a Prometheus Pushgateway.
+    #[serde(rename = "metrics-push", default)]
+    pub metrics_push: Option<MetricsPushConfig>,
+    /// Interval between heartbeat log messages (default: 60 seconds).
+    #[serde(rename = "heartbeat-interval-seconds", default)]
+    pub heartbeat_interval_seconds: Option<u64>,
+    /// Close host connections (key-sync, monitoring, attestation) on which nothing has been
+    /// read or written for this long (default: 300 seconds).
+    #[serde(rename = "idle-timeout-seconds", default)]
+    pub idle_timeout_seconds: Option<u64>,
+    /// Close HTTP(S) connections (monitoring, attestation) after serving this many requests
+    /// on them (default: 100).
+    #[serde(rename = "http-max-requests-per-connection", default)]
+    pub http_max_requests_per_connection: Option<u64>,
+    /// Close HTTP(S) connections on which the headers of the next request do not arrive within
+    /// this long of accepting the connection or sending the previous response (default: 30
+    /// seconds). Unlike `idle-timeout-seconds`, a client dribbling bytes does not reset it.
+    #[serde(rename = "http-keep-alive-timeout-seconds", default)]
+    pub http_keep_alive_timeout_seconds: Option<u64>,
+    /// Number of consecutive failed outbound requests (e.g., Safe fetches) to an authority
+    /// after which further requests to it fail fast for `host-circuit-breaker-cooldown-seconds`
+    /// (default: 5).
+    #[serde(rename = "host-circuit-breaker-failures", default)]
+    pub host_circuit_breaker_failures: Option<u32>,
+    /// Time for which requests to an authority fail fast once its circuit is open, before a
+    /// request probes it again (default: 30 seconds).
+    #[serde(rename = "host-circuit-breaker-cooldown-seconds", default)]
+    pub host_circuit_breaker_cooldown_seconds: Option<u64>,
+    /// Cap on the exponential backoff between retries after consecutive errors accepting
+    /// host connections (default: 5000 milliseconds).
+    #[serde(rename = "max-accept-backoff-milliseconds", default)]
+    pub max_accept_backoff_milliseconds: Option<u64>,
+    /// Maximum size of messages hashed and signed by `SignMessage` (default: 1 MiB).
+    #[serde(rename = "max-sign-message-bytes", default)]
+    pub max_sign_message_bytes: Option<usize>,
+    /// Hash function used by `SignMessage` and `VerifySignature` for a key index when the
+    /// request leaves it unspecified, e.g., `{"1": "keccak256", "2": "sha256"}`.
+    #[serde(rename = "default-hash-functions", default)]
+    pub default_hash_functions: Option<BTreeMap<u32, MessageHashFunction>>,
+    /// Return an attestation with every `SignDigest` and `SignMessage` response,
+    /// as if `include_attestation` were set on each request.
+    #[serde(rename = "attest-signatures", default)]
+    pub attest_signatures: bool,
+    /// Register the gRPC reflection service, which exposes the proto schema to gRPC clients
+    /// (default: true). Hardened deployments may want to disable it.
+    #[serde(rename = "grpc-reflection-enabled", default)]
+    pub grpc_reflection_enabled: Option<bool>,
+    /// Absolute path of the Unix domain socket on which the gRPC service is served
+    /// (default: `/tmp/enclave.sock`).
+    #[serde(rename = "grpc-uds-path", default)]
+    pub grpc_uds_path: Option<String>,
+    /// Shared secret that clients must present as `authorization: Bearer <token>` metadata on
+    /// signing RPCs, preferably sealed. Signing RPCs are unauthenticated if not set.
+    #[serde(rename = "grpc-auth-token", default)]
+    pub grpc_auth_token: Option<SealedValue>,
+    /// Maximum number of signatures per key index, e.g., `{"1": 1000000}`; key index 0 is the
+    /// `SignP256` key. Once a key's budget is exhausted, signing with it fails until governance
+    /// authorizes `ResetKeyBudget`. Keys without a budget are unlimited. Budgets are not
+    /// persisted across restarts.
+    #[serde(rename = "signature-budgets", default)]
+    pub signature_budgets: Option<BTreeMap<u32, u64>>,
+    /// Accept a passphrase-derived break-glass key for disaster recovery (see `break_glass`).
+    /// Requires `grpc-auth-token`: `LoadBreakGlassKey` attempts are throttled for all clients
+    /// together, so unauthenticated clients could otherwise lock the operator out.
+    #[serde(rename = "break-glass", default)]
+    pub break_glass: Option<BreakGlassConfig>,
+    /// VSOCK addressing for the NSM security module.
+    #[serde(rename = "nsm", default)]
+    pub nsm: NsmConfig,
+    /// Instance ID of the host (e.g., `i-1234567890abcdef0`), included in the `user_data` of
+    /// attestations served by `GET /` so that verifiers can correlate them with an instance.
+    /// Self-reported: verifiers must check it against PCR4 (see `user_data`). Differs between
+    /// the instances of a pool, so it is not part of the measured configuration (see
+    /// `canonical_json`).
+    #[serde(rename = "instance-id", default)]
+    pub instance_id: Option<String>,
+    /// Number of secret keys that key-sync must deliver; a follower refuses to start with key
+    /// material of a different size. Must match `generate` if keys are generated.
+    #[serde(rename = "expected-num-keys", default)]
+    pub expected_num_keys: Option<u32>,
+}
+
+/// Hash functions that can be configured in `default-hash-functions`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum MessageHashFunction {
+    #[serde(rename = "sha256")]
+    Sha256,
+    #[serde(rename = "keccak256")]
+    Keccak256,
+    #[serde(rename = "sha3-256")]
+    Sha3_256,
+}
+
+/// A subject alternative name of the server certificate (see `alt-names`).
+#[derive(PartialEq, Clone, Debug)]
+pub enum AltName {
+    /// A DNS name, possibly with a leading wildcard label (e.g., `*.example.com`).
+    Dns(String),
+    /// An IPv4 or IPv6 address.
+    Ip(std::net::IpAddr),
 }
 
+impl AltName {
+    /// Parse an `alt-names` entry: an IP address if it is one, and a DNS name otherwise.
+    pub fn parse(name: &str) -> Result<AltName> {
+        if let Ok(ip) = name.parse() {
+            return Ok(AltName::Ip(ip));
+        }
+        let dns_name = name.strip_prefix("*.").unwrap_or(name);
+        if pki_types::DnsName::try_from(dns_name).is_err() {
+            bail!("invalid alternative name '{}': neither a DNS name nor an IP address", name);
+        }
+        Ok(AltName::Dns(name.to_string()))
+    }
+}
+
+/// Default for `key-sync-connect-attempts`.
+pub const DEFAULT_KEY_SYNC_CONNECT_ATTEMPTS: u32 = 10;
+
+/// Default for `key-sync-retry-delay-milliseconds`.
+pub const DEFAULT_KEY_SYNC_RETRY_DELAY_MILLISECONDS: u64 = 1000;
+
+/// Default for `grpc-uds-path`.
+pub const DEFAULT_GRPC_UDS_PATH: &str = "/tmp/enclave.sock";
+
+/// Default for `max-sign-message-bytes`.
+pub const DEFAULT_MAX_SIGN_MESSAGE_BYTES: usize = 1 << 20;
+
+/// Upper bound for `max-sign-message-bytes`; gRPC messages are not meant to carry more.
+const MAX_SIGN_MESSAGE_BYTES_CEILING: usize = 64 << 20;
+
+/// Room for the fields of a `SignMessage` request besides the message, see
+/// `max_grpc_decoding_message_size`.
+const GRPC_MESSAGE_OVERHEAD_BYTES: usize = 64 << 10;
+
+/// Top-level configuration keys that are not measured, see `SovereignConfig::canonical_json`.
+const UNMEASURED_CONFIG_KEYS: [&str; 1] = ["instance-id"];
+
+/// tonic's default limit on decoded gRPC messages.
+const DEFAULT_GRPC_DECODING_MESSAGE_SIZE: usize = 4 << 20;
+
 impl SovereignConfig {
+    /// Check that no two listeners are configured on the same port.
+    pub fn check_ports(&self) -> Result<()> {
+        let ports = [
+            ("key-sync-port", self.key_sync_port),
+            ("monitoring-port", self.monitoring_port),
+            ("http-attestation-port", self.http_attestation_port),
+            ("https-attestation-port", self.https_attestation_port),
+            ("grpc-vsock-port", self.grpc_vsock_port),
+        ];
+        for (i, (name, port)) in ports.iter().enumerate() {
+            for (other_name, other_port) in &ports[i + 1..] {
+                if let (Some(port), Some(other_port)) = (port, other_port) {
+                    if port == other_port {
+                        bail!("duplicate port {} configured for {} and {}", port, name, other_name);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject options that attestation-only builds (see `SIGNING_ENABLED`) cannot honor.
+    /// `secret-keys-from` is ignored: such builds generate no signing keys.
+    pub fn check_attestation_only(&self) -> Result<()> {
+        if SIGNING_ENABLED {
+            return Ok(());
+        }
+        let unsupported = [
+            ("key-sync-port", self.key_sync_port.is_some()),
+            ("grpc-vsock-port", self.grpc_vsock_port.is_some()),
+            (
+                "secret-keys-from key-sync",
+                matches!(self.secret_keys_from, SecretKeyRetrieval::KeySync(_)),
+            ),
+        ];
+        for (name, configured) in unsupported {
+            if configured {
+                bail!("{} is not supported by attestation-only builds", name);
+            }
+        }
+        Ok(())
+    }
+
     pub fn validate(&self) -> Result<()> {
+        self.check_ports()?;
+        self.check_attestation_only()?;
+        for name in &self.alt_names {
+            AltName::parse(name).context("alt-names")?;
+        }
         self.secret_keys_from.validate()?;
+        if let Some(instance_id) = &self.instance_id {
+            let valid = instance_id.strip_prefix("i-").is_some_and(|id| {
+                matches!(id.len(), 8 | 17)
+                    && id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+            });
+            if !valid {
+                bail!("invalid instance-id '{}': expected i- and 8 or 17 hex digits", instance_id);
+            }
+        }
+        if let Some(expected) = self.expected_num_keys {
+            SecretKeyRetrieval::Generate(expected).validate().context("expected-num-keys")?;
+            if let SecretKeyRetrieval::Generate(num) = self.secret_keys_from {
+                if num != expected {
+                    bail!("expected-num-keys is {} but {} keys are generated", expected, num);
+                }
+            }
+        }
+        self.governance.validate()?;
+        self.nsm.validate()?;
+        if let Some(metrics_push) = &self.metrics_push {
+            metrics_push.validate()?;
+        }
+        if let Some(break_glass) = &self.break_glass {
+            break_glass.validate()?;
+            if self.grpc_auth_token.is_none() {
+                bail!("break-glass requires grpc-auth-token");
+            }
+        }
+        if let Some(path) = &self.grpc_uds_path {
+            if !std::path::Path::new(path).is_absolute() {
+                bail!("grpc-uds-path must be an absolute path: was {}", path);
+            }
+        }
+        if self.key_sync_connect_attempts == Some(0) {
+            bail!("key-sync-connect-attempts must be at least one");
+        }
+        if self.key_sync_retry_delay_milliseconds == Some(0) {
+            bail!("key-sync-retry-delay-milliseconds must be at least one");
+        }
+        if self.max_concurrent_key_syncs == Some(0) {
+            bail!("max-concurrent-key-syncs must be at least one");
+        }
+        if self.heartbeat_interval_seconds == Some(0) {
+            bail!("heartbeat interval must be at least one second");
+        }
+        if self.idle_timeout_seconds == Some(0) {
+            bail!("idle timeout must be at least one second");
+        }
+        if self.http_max_requests_per_connection == Some(0) {
+            bail!("http-max-requests-per-connection must be at least one");
+        }
+        if self.http_keep_alive_timeout_seconds == Some(0) {
+            bail!("http-keep-alive-timeout-seconds must be at least one");
+        }
+        if self.host_circuit_breaker_failures == Some(0) {
+            bail!("host-circuit-breaker-failures must be at least one");
+        }
+        if self.host_circuit_breaker_cooldown_seconds == Some(0) {
+            bail!("host-circuit-breaker-cooldown-seconds must be at least one");
+        }
+        if self.max_accept_backoff_milliseconds == Some(0) {
+            bail!("max-accept-backoff-milliseconds must be at least one");
+        }
+        if self.grpc_vsock_port.is_some() && self.grpc_auth_token.is_none() {
+            bail!("grpc-vsock-port requires grpc-auth-token");
+        }
+        if let Some(defaults) = &self.default_hash_functions {
+            if defaults.contains_key(&0) {
+                bail!("default-hash-functions: key indices start at 1");
+            }
+        }
+        match self.max_sign_message_bytes {
+            Some(0) => bail!("max-sign-message-bytes must be at least one"),
+            Some(n) if n > MAX_SIGN_MESSAGE_BYTES_CEILING => bail!(
+                "max-sign-message-bytes {} exceeds the limit of {} bytes",
+                n,
+                MAX_SIGN_MESSAGE_BYTES_CEILING
+            ),
+            _ => {}
+        }
         Ok(())
     }
+
+    /// The configured default hash function for `key_index`, if any.
+    pub fn default_hash_function(&self, key_index: u32) -> Option<MessageHashFunction> {
+        self.default_hash_functions.as_ref()?.get(&key_index).copied()
+    }
+
+    /// The configured signature budget for `key_index`, if any.
+    pub fn signature_budget(&self, key_index: u32) -> Option<u64> {
+        self.signature_budgets.as_ref()?.get(&key_index).copied()
+    }
+
+    /// The canonical JSON serialization of the configuration: object keys sorted, no
+    /// whitespace, and map keys (e.g., of `default-hash-functions`) as strings.
+    ///
+    /// This is security-relevant: it is measured at startup (see
+    /// `KeyServer::config_measurement`), so governance authorizes exactly these bytes. It must
+    /// not depend on field declaration order or on the serde_json features that are enabled.
+    /// Per-instance settings (`UNMEASURED_CONFIG_KEYS`) are left out, so that all instances of a
+    /// pool share one measurement.
+    pub fn canonical_json(&self) -> Result<Vec<u8>> {
+        fn canonicalize(value: serde_json::Value) -> serde_json::Value {
+            use serde_json::Value;
+            match value {
+                Value::Object(map) => {
+                    let sorted: BTreeMap<String, Value> =
+                        map.into_iter().map(|(k, v)| (k, canonicalize(v))).collect();
+                    Value::Object(sorted.into_iter().collect())
+                }
+                Value::Array(items) => Value::Array(items.into_iter().map(canonicalize).collect()),
+                other => other,
+            }
+        }
+        let mut value = serde_json::to_value(self)?;
+        if let serde_json::Value::Object(map) = &mut value {
+            for key in UNMEASURED_CONFIG_KEYS {
+                map.remove(key);
+            }
+        }
+        Ok(serde_json::to_vec(&canonicalize(value))?)
+    }
+
+    /// The configuration with plain text secrets redacted, see `SealedValue::redacted`.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        config.grpc_auth_token = config.grpc_auth_token.as_ref().map(SealedValue::redacted);
+        if let Some(metrics_push) = &mut config.metrics_push {
+            metrics_push.authorization =
+                metrics_push.authorization.as_ref().map(SealedValue::redacted);
+        }
+        config
+    }
+
+    /// The configured `grpc-reflection-enabled` or its default.
+    pub fn grpc_reflection_enabled(&self) -> bool {
+        self.grpc_reflection_enabled.unwrap_or(true)
+    }
+
+    /// The configured `key-sync-tls` or its default.
+    pub fn key_sync_tls(&self) -> bool {
+        self.key_sync_tls.unwrap_or(false)
+    }
+
+    /// The configured `key-sync-connect-attempts` or its default.
+    pub fn key_sync_connect_attempts(&self) -> u32 {
+        self.key_sync_connect_attempts.unwrap_or(DEFAULT_KEY_SYNC_CONNECT_ATTEMPTS)
+    }
+
+    /// The configured `key-sync-retry-delay-milliseconds` or its default.
+    pub fn key_sync_retry_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(
+            self.key_sync_retry_delay_milliseconds
+                .unwrap_or(DEFAULT_KEY_SYNC_RETRY_DELAY_MILLISECONDS),
+        )
+    }
+
+    /// The configured `grpc-uds-path` or its default.
+    pub fn grpc_uds_path(&self) -> &str {
+        self.grpc_uds_path.as_deref().unwrap_or(DEFAULT_GRPC_UDS_PATH)
+    }
+
+    /// The number of secret keys this sovereign expects: `expected-num-keys` or, if keys are
+    /// generated, their number.
+    pub fn expected_num_keys(&self) -> Option<u32> {
+        match self.secret_keys_from {
+            SecretKeyRetrieval::Generate(num) => Some(num),
+            SecretKeyRetrieval::KeySync(_) => self.expected_num_keys,
+        }
+    }
+
+    /// The configured `max-sign-message-bytes` or its default.
+    pub fn max_sign_message_bytes(&self) -> usize {
+        self.max_sign_message_bytes.unwrap_or(DEFAULT_MAX_SIGN_MESSAGE_BYTES)
+    }
+
+    /// The limit on decoded gRPC requests: large enough for `SignMessage` requests of
+    /// `max-sign-message-bytes`, but never below tonic's default.
+    pub fn max_grpc_decoding_message_size(&self) -> usize {
+        DEFAULT_GRPC_DECODING_MESSAGE_SIZE
+            .max(self.max_sign_message_bytes() + GRPC_MESSAGE_OVERHEAD_BYTES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_ports() {
+        let config = SovereignConfig {
+            key_sync_port: Some(5000),
+            monitoring_port: Some(5001),
+            http_attestation_port: Some(5002),
+            https_attestation_port: Some(5003),
+            ..SovereignConfig::default()
+        };
+        assert!(config.check_ports().is_ok());
+        let config = SovereignConfig { https_attestation_port: Some(5001), ..config };
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "duplicate port 5001 configured for monitoring-port and https-attestation-port"
+        );
+        // Unset ports never collide.
+        let config = SovereignConfig::default();
+        assert!(config.check_ports().is_ok());
+    }
+
+    #[test]
+    fn test_allowed_safe_hosts() {
+        let safe = SafeConfig {
+            wallet_address: "0x0000000000000000000000000000000000000001".to_string(),
+            threshold: 1,
+            http_endpoint: "https://safe-transaction-mainnet.safe.global/api/v1/messages"
+                .to_string(),
+            http_endpoint_port: 50000,
+            chain_id: 1,
+            allowed_safe_hosts: None,
+            request_timeout_seconds: None,
+            fallback_endpoints: Vec::new(),
+        };
+        assert!(safe.check_endpoint_host().is_ok());
+        assert_eq!(safe.request_timeout(), crate::http::DEFAULT_REQUEST_TIMEOUT);
+        let timeout = SafeConfig { request_timeout_seconds: Some(0), ..safe.clone() };
+        assert!(timeout.validate().is_err());
+        let timeout = SafeConfig { request_timeout_seconds: Some(5), ..timeout };
+        assert_eq!(timeout.request_timeout(), std::time::Duration::from_secs(5));
+        // The wallet address is checked and normalized to its EIP-55 form.
+        let lowercase = "0xd8da6bf26964af9d7eed9e03e53415d37aa96045".to_string();
+        let checksummed = SafeConfig { wallet_address: lowercase, ..safe.clone() };
+        assert!(checksummed.validate().is_ok());
+        assert_eq!(
+            checksummed.normalized_wallet_address().unwrap(),
+            "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045"
+        );
+        let malformed = SafeConfig { wallet_address: "0x1234".to_string(), ..safe.clone() };
+        assert!(malformed.validate().is_err());
+        let allowed = Some(vec!["Safe-Transaction-Mainnet.safe.global".to_string()]);
+        let safe = SafeConfig { allowed_safe_hosts: allowed, ..safe };
+        assert!(safe.check_endpoint_host().is_ok());
+        let mirror = SafeEndpoint {
+            http_endpoint: "https://attacker.example/api".to_string(),
+            http_endpoint_port: 50001,
+        };
+        let with_mirror = SafeConfig { fallback_endpoints: vec![mirror], ..safe.clone() };
+        let endpoints: Vec<_> = with_mirror.endpoints().map(|(_, port)| port).collect();
+        assert_eq!(endpoints, [50000, 50001]);
+        let err = with_mirror.check_endpoint_host().unwrap_err();
+        assert!(err.to_string().contains("attacker.example is not in allowed-safe-hosts"));
+        let safe = SafeConfig { http_endpoint: "https://attacker.example/api".to_string(), ..safe };
+        let err = safe.check_endpoint_host().unwrap_err();
+        assert!(err.to_string().contains("attacker.example is not in allowed-safe-hosts"));
+        assert!(Governance::Safe(safe).validate().is_err());
+    }
+
+    #[test]
+    fn test_grpc_reflection_enabled() {
+        let config = SovereignConfig::default();
+        assert!(config.grpc_reflection_enabled());
+        assert!(config.grpc_auth_token.is_none());
+        assert!(!config.key_sync_tls());
+        assert_eq!(config.key_sync_connect_attempts(), DEFAULT_KEY_SYNC_CONNECT_ATTEMPTS);
+        assert!(SovereignConfig { key_sync_connect_attempts: Some(0), ..Default::default() }
+            .validate()
+            .is_err());
+        let config = SovereignConfig { grpc_reflection_enabled: Some(false), ..config };
+        assert!(!config.grpc_reflection_enabled());
+        assert_eq!(config.grpc_uds_path(), DEFAULT_GRPC_UDS_PATH);
+        let config = SovereignConfig { grpc_uds_path: Some("enclave.sock".to_string()), ..config };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_nsm_config() {
+        let config: SovereignConfig = serde_json::from_str(
+            r#"{
+                "secret-keys-from": {"generate": 2},
+                "governance": "testing-only",
+                "alt-names": [],
+                "key-sync-port": null,
+                "monitoring-port": null,
+                "http-attestation-port": null,
+                "https-attestation-port": null
+            }"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.nsm.peer_cid(), DEFAULT_NSM_PEER_CID);
+        assert_eq!(config.nsm.listen_cid(), DEFAULT_NSM_LISTEN_CID);
+        let nsm: NsmConfig = serde_json::from_str(r#"{"peer-cid": 16, "listen-cid": 17}"#).unwrap();
+        assert_eq!((nsm.peer_cid(), nsm.listen_cid()), (16, 17));
+        assert!(nsm.validate().is_ok());
+        let nsm = NsmConfig { peer_cid: Some(u32::MAX), ..nsm };
+        assert!(SovereignConfig { nsm, ..config }.validate().is_err());
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_expected_num_keys() {
+        let follower = SovereignConfig {
+            secret_keys_from: SecretKeyRetrieval::KeySync(5000),
+            ..Default::default()
+        };
+        assert!(follower.validate().is_ok());
+        assert_eq!(follower.expected_num_keys(), None);
+        let follower = SovereignConfig { expected_num_keys: Some(3), ..follower };
+        assert!(follower.validate().is_ok());
+        assert_eq!(follower.expected_num_keys(), Some(3));
+        assert!(SovereignConfig { expected_num_keys: Some(1), ..follower }.validate().is_err());
+
+        let leader = SovereignConfig {
+            secret_keys_from: SecretKeyRetrieval::Generate(3),
+            ..Default::default()
+        };
+        assert_eq!(leader.expected_num_keys(), Some(3));
+        assert!(SovereignConfig { expected_num_keys: Some(3), ..leader.clone() }
+            .validate()
+            .is_ok());
+        assert!(SovereignConfig { expected_num_keys: Some(4), ..leader }.validate().is_err());
+    }
+
+    #[test]
+    fn test_max_sign_message_bytes() {
+        let config = SovereignConfig::default();
+        assert_eq!(config.max_sign_message_bytes(), DEFAULT_MAX_SIGN_MESSAGE_BYTES);
+        let config = SovereignConfig { max_sign_message_bytes: Some(4096), ..config };
+        assert_eq!(config.max_sign_message_bytes(), 4096);
+        assert_eq!(config.max_grpc_decoding_message_size(), 4 << 20);
+        assert!(config.validate().is_ok());
+        let config = SovereignConfig { max_sign_message_bytes: Some(16 << 20), ..config };
+        assert!(config.max_grpc_decoding_message_size() > 16 << 20);
+        assert!(config.validate().is_ok());
+        let config = SovereignConfig { max_sign_message_bytes: Some(0), ..config };
+        assert!(config.validate().is_err());
+        let config = SovereignConfig { max_sign_message_bytes: Some(1 << 30), ..config };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_default_hash_functions() {
+        let config: SovereignConfig = serde_json::from_str(
+            r#"{
+                "secret-keys-from": {"generate": 2},
+                "governance": "testing-only",
+                "alt-names": [],
+                "key-sync-port": null,
+                "monitoring-port": null,
+                "http-attestation-port": null,
+                "https-attestation-port": null,
+                "default-hash-functions": {"1": "keccak256", "2": "sha256"}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.default_hash_function(1), Some(MessageHashFunction::Keccak256));
+        assert_eq!(config.default_hash_function(2), Some(MessageHashFunction::Sha256));
+        assert_eq!(config.default_hash_function(3), None);
+        let defaults = Some(BTreeMap::from([(0, MessageHashFunction::Sha3_256)]));
+        let config = SovereignConfig { default_hash_functions: defaults, ..config };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_grpc_vsock_port_requires_auth_token() {
+        let config = SovereignConfig { grpc_vsock_port: Some(5004), ..Default::default() };
+        assert!(config.validate().is_err());
+        let token = Some(SealedValue::Plain("secret".to_string()));
+        let config = SovereignConfig { grpc_auth_token: token, ..config };
+        assert_eq!(config.validate().is_ok(), SIGNING_ENABLED);
+    }
+
+    #[test]
+    fn test_break_glass_requires_auth_token() {
+        let break_glass = BreakGlassConfig {
+            salt: "test-pool-break-glass".to_string(),
+            memory_kib: None,
+            iterations: None,
+            parallelism: None,
+        };
+        let config = SovereignConfig { break_glass: Some(break_glass), ..Default::default() };
+        assert!(config.validate().is_err());
+        let token = Some(SealedValue::Plain("secret".to_string()));
+        let config = SovereignConfig { grpc_auth_token: token, ..config };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_check_attestation_only() {
+        let config = SovereignConfig { grpc_vsock_port: Some(5004), ..Default::default() };
+        assert_eq!(config.check_attestation_only().is_ok(), SIGNING_ENABLED);
+        let config = SovereignConfig {
+            secret_keys_from: SecretKeyRetrieval::KeySync(5000),
+            ..Default::default()
+        };
+        assert_eq!(config.check_attestation_only().is_ok(), SIGNING_ENABLED);
+        assert!(SovereignConfig::default().check_attestation_only().is_ok());
+    }
+
+    #[test]
+    fn test_alt_names() {
+        for name in ["enclave.example.com", "localhost", "*.example.com", "xn--bcher-kva.example"] {
+            assert_eq!(AltName::parse(name).unwrap(), AltName::Dns(name.to_string()));
+        }
+        for name in ["10.0.0.1", "::1", "2001:db8::8"] {
+            assert_eq!(AltName::parse(name).unwrap(), AltName::Ip(name.parse().unwrap()));
+        }
+        for name in ["", "a.example,b.example", "exa mple.com", "*", "a.*.example", "host!"] {
+            assert!(AltName::parse(name).is_err(), "{}", name);
+        }
+
+        let config = SovereignConfig {
+            alt_names: vec!["a.example".to_string(), "127.0.0.1".to_string()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        let config = SovereignConfig {
+            alt_names: vec!["a.example".to_string(), "b example".to_string()],
+            ..config
+        };
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(err.contains("'b example'"), "{}", err);
+    }
+
+    #[test]
+    fn test_instance_id() {
+        for instance_id in ["i-1234567890abcdef0", "i-0123abcd"] {
+            let config = SovereignConfig {
+                instance_id: Some(instance_id.to_string()),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok(), "{}", instance_id);
+        }
+        for instance_id in ["", "i-", "1234567890abcdef0", "i-1234567890ABCDEF0", "i-123"] {
+            let config = SovereignConfig {
+                instance_id: Some(instance_id.to_string()),
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{}", instance_id);
+        }
+    }
+
+    #[test]
+    fn test_canonical_json() {
+        let json = |governance_first: bool| {
+            let fields = [
+                r#""governance": "testing-only""#,
+                r#""default-hash-functions": {"2": "sha256", "1": "keccak256"}"#,
+                r#""secret-keys-from": {"generate": 2}"#,
+                r#""alt-names": ["b.example", "a.example"]"#,
+            ];
+            let mut fields = fields.to_vec();
+            if !governance_first {
+                fields.reverse();
+            }
+            format!("{{{}}}", fields.join(", "))
+        };
+        let a: SovereignConfig = serde_json::from_str(&json(true)).unwrap();
+        let b: SovereignConfig = serde_json::from_str(&json(false)).unwrap();
+        let canonical = a.canonical_json().unwrap();
+        assert_eq!(canonical, b.canonical_json().unwrap());
+        let text = String::from_utf8(canonical).unwrap();
+        assert!(!text.contains(' '));
+        assert!(text.starts_with(r#"{"alt-names":["b.example","a.example"],"attest-signatures""#));
+        assert!(text.contains(r#""default-hash-functions":{"1":"keccak256","2":"sha256"}"#));
+        let c = SovereignConfig { alt_names: vec!["a.example".to_string()], ..a.clone() };
+        assert_ne!(c.canonical_json().unwrap(), b.canonical_json().unwrap());
+        // The instances of a pool share the measurement.
+        let d = SovereignConfig { instance_id: Some("i-1234567890abcdef0".to_string()), ..a };
+        assert_eq!(d.canonical_json().unwrap(), b.canonical_json().unwrap());
+        assert!(!text.contains("instance-id"));
+    }
+
+    #[test]
+    fn test_sealed_value() {
+        let sealing_key = k256::SecretKey::from_slice(&[3; 32]).unwrap();
+        let envelope = crate::ecies_envelope::encrypt(
+            &sealing_key.public_key().to_sec1_bytes(),
+            b"Bearer secret-token",
+        )
+        .unwrap();
+        let json = format!(r#"["Bearer plain-token", {{"sealed": "{}"}}]"#, hex::encode(envelope));
+        let values: Vec<SealedValue> = serde_json::from_str(&json).unwrap();
+        assert_eq!(values[0], SealedValue::Plain("Bearer plain-token".to_string()));
+        assert_eq!(values[0].unseal(&sealing_key).unwrap(), "Bearer plain-token");
+        assert_eq!(values[1].unseal(&sealing_key).unwrap(), "Bearer secret-token");
+        let other_key = k256::SecretKey::from_slice(&[4; 32]).unwrap();
+        assert!(values[1].unseal(&other_key).is_err());
+        let malformed = SealedValue::Sealed { sealed: "zz".to_string() };
+        assert!(malformed.unseal(&sealing_key).is_err());
+
+        // Redaction hides plain text values only.
+        let config = SovereignConfig {
+            grpc_auth_token: Some(values[0].clone()),
+            metrics_push: Some(MetricsPushConfig {
+                http_endpoint: "http://pushgateway".to_string(),
+                http_endpoint_port: 9091,
+                job: "sovereign".to_string(),
+                interval_seconds: 60,
+                authorization: Some(values[1].clone()),
+            }),
+            ..Default::default()
+        };
+        let redacted = config.redacted();
+        assert_eq!(redacted.grpc_auth_token, Some(SealedValue::Plain("<redacted>".to_string())));
+        assert_eq!(redacted.metrics_push.unwrap().authorization, Some(values[1].clone()));
+    }
 }
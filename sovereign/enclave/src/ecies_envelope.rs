@@ -0,0 +1,128 @@
+//! Versioned ECIES encryption of the secret state sent during key-sync.
+//!
+//! Version 1 is the scheme of the `ecies` crate (0.2, default configuration):
+//! - curve: secp256k1; a fresh ephemeral key pair per message,
+//! - key derivation: HKDF-SHA256 over `ephemeral_pk || shared_point` (both uncompressed),
+//! - encryption: AES-256-GCM with a 16 byte nonce and a 16 byte tag.
+//!
+//! The envelope is `version (1 byte) || ephemeral_pk (65 bytes) || nonce (16 bytes)
+//! || tag (16 bytes) || ciphertext`. Envelopes with another version are rejected, and
+//! `self_test` checks at startup that the `ecies` crate still decrypts a known version 1
+//! envelope and produces this layout, so that a dependency change cannot silently alter the
+//! wire format between builds.
+
+use anyhow::{anyhow, bail, Result};
+
+/// Version byte of the current envelope format.
+pub const ECIES_VERSION: u8 = 1;
+
+const EPHEMERAL_PUBLIC_KEY_SIZE: usize = 65;
+const NONCE_SIZE: usize = 16;
+const TAG_SIZE: usize = 16;
+const OVERHEAD: usize = EPHEMERAL_PUBLIC_KEY_SIZE + NONCE_SIZE + TAG_SIZE;
+
+/// The secret key of the known-answer test of `self_test`.
+const KNOWN_ANSWER_SECRET_KEY: [u8; 32] = [0x11; 32];
+
+/// A version 1 envelope of `KNOWN_ANSWER_PLAINTEXT` to `KNOWN_ANSWER_SECRET_KEY`.
+const KNOWN_ANSWER_ENVELOPE: &str = "01047e2c32353f9702de2fe7e760bd882ae3d401c0ae2ce56cdeb2f45aa39e0e3432d7277ef2665d9560ca8b8e5c1bd6b635ee868797b264120867b795bd5eeb6c705934b8a2b5802c8582e6b32bd8c97f1ba839c85fff1a448104f67b87412d75722f3ad4a5f578397218561d3133d620";
+
+const KNOWN_ANSWER_PLAINTEXT: &[u8] = b"ecies self-test";
+
+/// Check that an `ecies` ciphertext for a `plaintext_len` byte message has the version 1 layout.
+fn check_layout(ciphertext: &[u8], plaintext_len: usize) -> Result<()> {
+    if ciphertext.len() != OVERHEAD + plaintext_len {
+        bail!(
+            "unexpected ECIES ciphertext length {} for {} byte plaintext (expected {})",
+            ciphertext.len(),
+            plaintext_len,
+            OVERHEAD + plaintext_len
+        );
+    }
+    if ciphertext[0] != 0x04 {
+        bail!("unexpected ECIES ephemeral public key encoding (expected uncompressed)");
+    }
+    Ok(())
+}
+
+/// Encrypt `plaintext` to the SEC1-encoded secp256k1 public key `public_key`.
+pub fn encrypt(public_key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let ciphertext = ecies::encrypt(public_key, plaintext).map_err(|x| anyhow!("encrypt {}", x))?;
+    check_layout(&ciphertext, plaintext.len())?;
+    let mut envelope = Vec::with_capacity(1 + ciphertext.len());
+    envelope.push(ECIES_VERSION);
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+
+/// Decrypt an envelope produced by `encrypt` using the 32 byte secp256k1 `secret_key`.
+pub fn decrypt(secret_key: &[u8], envelope: &[u8]) -> Result<Vec<u8>> {
+    let (version, ciphertext) = envelope.split_first().ok_or_else(|| anyhow!("empty envelope"))?;
+    if *version != ECIES_VERSION {
+        bail!("unsupported ECIES envelope version {} (expected {})", version, ECIES_VERSION);
+    }
+    if ciphertext.len() < OVERHEAD {
+        bail!("ECIES envelope too short: {} bytes", envelope.len());
+    }
+    check_layout(ciphertext, ciphertext.len() - OVERHEAD)?;
+    ecies::decrypt(secret_key, ciphertext).map_err(|x| anyhow!("decrypt {}", x))
+}
+
+/// Check that a known envelope decrypts and that encryption round-trips with the expected
+/// layout, see the module documentation.
+pub fn self_test() -> Result<()> {
+    let known_envelope = hex::decode(KNOWN_ANSWER_ENVELOPE)?;
+    let decrypted = decrypt(&KNOWN_ANSWER_SECRET_KEY, &known_envelope)
+        .map_err(|e| anyhow!("ECIES self-test failed: known envelope: {}", e))?;
+    if decrypted != KNOWN_ANSWER_PLAINTEXT {
+        bail!("ECIES self-test failed: known envelope mismatch");
+    }
+    let secret_key = k256::SecretKey::random(&mut elliptic_curve::rand_core::OsRng);
+    let public_key = secret_key.public_key().to_sec1_bytes();
+    let envelope = encrypt(&public_key, KNOWN_ANSWER_PLAINTEXT)?;
+    if decrypt(&secret_key.to_bytes(), &envelope)? != KNOWN_ANSWER_PLAINTEXT {
+        bail!("ECIES self-test failed: round-trip mismatch");
+    }
+    tracing::info!("ECIES self-test passed (envelope version {})", ECIES_VERSION);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test() {
+        assert!(self_test().is_ok());
+    }
+
+    #[test]
+    fn test_known_answer() {
+        let envelope = hex::decode(KNOWN_ANSWER_ENVELOPE).unwrap();
+        assert_eq!(envelope.len(), 1 + OVERHEAD + KNOWN_ANSWER_PLAINTEXT.len());
+        let decrypted = decrypt(&KNOWN_ANSWER_SECRET_KEY, &envelope).unwrap();
+        assert_eq!(decrypted, KNOWN_ANSWER_PLAINTEXT);
+        // The tag authenticates the ciphertext.
+        let mut tampered = envelope.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&KNOWN_ANSWER_SECRET_KEY, &tampered).is_err());
+        assert!(decrypt(&[0x22; 32], &envelope).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip_and_version() {
+        let secret_key = k256::SecretKey::from_slice(&[7; 32]).unwrap();
+        let public_key = secret_key.public_key().to_sec1_bytes();
+        let mut envelope = encrypt(&public_key, b"secret state").unwrap();
+        assert_eq!(envelope[0], ECIES_VERSION);
+        assert_eq!(envelope.len(), 1 + OVERHEAD + 12);
+        assert_eq!(decrypt(&secret_key.to_bytes(), &envelope).unwrap(), b"secret state");
+
+        // Unknown versions and truncated envelopes are rejected.
+        envelope[0] = 2;
+        let err = decrypt(&secret_key.to_bytes(), &envelope).unwrap_err();
+        assert!(err.to_string().contains("unsupported ECIES envelope version 2"));
+        assert!(decrypt(&secret_key.to_bytes(), &[ECIES_VERSION; OVERHEAD]).is_err());
+        assert!(decrypt(&secret_key.to_bytes(), &[]).is_err());
+    }
+}
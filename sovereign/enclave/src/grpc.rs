@@ -1,7 +1,11 @@
 use crate::key_server::{self, KeyServer};
 use crate::secmod::Secmod;
+use crate::user_data::{AttestationUserData, PURPOSE_SIGNATURE};
 use rlp::{Rlp, RlpStream};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use tiny_keccak::{Hasher, Keccak};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tonic::{Request, Response, Status};
 
 pub mod pb {
@@ -10,20 +14,82 @@ pub mod pb {
 
 use crate::grpc::pb::{
     key_pool_service_server::KeyPoolService, BuiltinSigningKey, EcdsaSignature,
-    GetEthereumAddressRequest, GetEthereumAddressResponse, HashFunction, SignDigestRequest,
-    SignDigestResponse, SignEthereumTransactionRequest, SignEthereumTransactionResponse,
-    SignMessageRequest, SignMessageResponse, SigningKey,
+    GetAuditHeadRequest, GetAuditHeadResponse, GetCertificateRequest, GetCertificateResponse,
+    GetEthereumAddressRequest, GetEthereumAddressResponse, GetP256PublicKeyRequest,
+    GetP256PublicKeyResponse, GetVersionRequest, GetVersionResponse, GetXOnlyPublicKeyRequest,
+    GetXOnlyPublicKeyResponse, HashFunction, KeyHealth, KeyInfo, ListKeysRequest, ListKeysResponse,
+    LoadBreakGlassKeyRequest, LoadBreakGlassKeyResponse, ResetKeyBudgetRequest,
+    ResetKeyBudgetResponse, RotateCertificateRequest, RotateCertificateResponse, RotateKeysRequest,
+    RotateKeysResponse, SelfTestRequest, SelfTestResponse, SetLogLevelRequest, SetLogLevelResponse,
+    SignAuthorizationRequest, SignAuthorizationResponse, SignDigestDerRequest,
+    SignDigestDerResponse, SignDigestRequest, SignDigestResponse, SignEthereumTransactionRequest,
+    SignEthereumTransactionResponse, SignMessageRequest, SignMessageResponse, SignP256Request,
+    SignP256Response, SignSafeTransactionRequest, SignSafeTransactionResponse, SignSchnorrRequest,
+    SignSchnorrResponse, SignTypedDataRequest, SignTypedDataResponse, SignatureAttestation,
+    SigningKey, VerifySignatureRequest, VerifySignatureResponse,
 };
 
+/// Maximum length of the domain label of a `SigningKey`.
+const MAX_DOMAIN_LABEL_BYTES: usize = 256;
+
+/// The fields of a legacy (and, with the last three, EIP-155) transaction, in RLP order.
+const TRANSACTION_FIELDS: [&str; 9] =
+    ["nonce", "gasPrice", "gasLimit", "to", "value", "data", "chainId", "r", "s"];
+
+/// Prefix of EIP-7702 set-code authorization digests.
+const EIP7702_MAGIC: u8 = 0x05;
+
+/// The digest signed by `SelfTest`. Arbitrary; its signatures are never returned.
+const SELF_TEST_DIGEST: [u8; 32] = [0x5a; 32];
+
+/// Time a client on `grpc-vsock-port` has to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Maximum number of TLS handshakes in progress on `grpc-vsock-port`; further connections
+/// wait to be accepted.
+const MAX_PENDING_TLS_HANDSHAKES: usize = 64;
+
+/// Longest validity of a `SET-LOG-LEVEL` authorization, see `SetLogLevelRequest`.
+const MAX_LOG_LEVEL_AUTHORIZATION_TTL: std::time::Duration =
+    std::time::Duration::from_secs(60 * 60);
+
 pub struct SignerServiceImpl<SM: Secmod> {
     pub key: std::sync::Arc<KeyServer<SM>>,
+    pub log_level: crate::logging::LogLevelHandle,
+    /// `expires_at` of the last accepted `SET-LOG-LEVEL` authorization; later requests must
+    /// carry a larger one, so an authorization cannot be replayed.
+    pub log_level_expires_at: std::sync::Mutex<u64>,
+    /// The unsealed `grpc-auth-token`, if configured.
+    pub auth_token: Option<String>,
+}
+
+/// A legacy or EIP-155 transaction of `SignEthereumTransaction`, checked to be canonically
+/// RLP-encoded but not yet signed.
+struct UnsignedTransaction {
+    chain_id: Option<u64>,
+    nonce: u64,
+    to: Vec<u8>,
+    value: Vec<u8>,
+    /// The RLP fields, without the signature.
+    fields: Vec<Vec<u8>>,
+    /// The signing hash.
+    digest: [u8; 32],
 }
 
 impl<SM: Secmod> SignerServiceImpl<SM> {
+    /// Sign a legacy or EIP-155 `transaction`; also returns the signing hash, for the audit log.
+    #[cfg(test)]
     async fn sign_ethereum_transaction(
         signing_key: &key_server::SecretPubKeyPair,
         transaction: &[u8],
-    ) -> Result<Response<SignEthereumTransactionResponse>, Status> {
+    ) -> Result<(Response<SignEthereumTransactionResponse>, [u8; 32]), Status> {
+        let transaction = Self::parse_ethereum_transaction(transaction)?;
+        let digest = transaction.digest;
+        Ok((Self::sign_unsigned_transaction(signing_key, transaction)?, digest))
+    }
+
+    /// Parse and check a legacy or EIP-155 `transaction`, before anything is signed.
+    fn parse_ethereum_transaction(transaction: &[u8]) -> Result<UnsignedTransaction, Status> {
         // Parse RLP to determine if it's EIP-155
         let rlp = Rlp::new(transaction);
         let item_count =
@@ -37,46 +103,261 @@ impl<SM: Secmod> SignerServiceImpl<SM> {
         let chain_id = if item_count == 9 {
             let chain_id =
                 rlp.val_at::<u64>(6).map_err(|_| Status::invalid_argument("chain ID"))?;
+            // Reject chain IDs for which `v` does not fit, before signing anything.
+            if Self::eip155_v(chain_id, true).is_none() {
+                return Err(Status::invalid_argument(format!(
+                    "chain ID {} too large for EIP-155 v",
+                    chain_id
+                )));
+            }
             Some(chain_id)
         } else {
             None
         };
+        // Decode the fields echoed back in the response.
+        let nonce = rlp.val_at::<u64>(0).map_err(|_| Status::invalid_argument("nonce"))?;
+        let to = rlp
+            .at(3)
+            .and_then(|to| to.data().map(<[u8]>::to_vec))
+            .map_err(|_| Status::invalid_argument("to"))?;
+        // An empty `to` denotes contract creation.
+        if !to.is_empty() && to.len() != 20 {
+            return Err(Status::invalid_argument(format!(
+                "to must be 20 bytes - was {}",
+                to.len()
+            )));
+        }
+        let value = Self::canonical_uint(&rlp, 4, "value")?;
+        // Re-encode all fields canonically; integers must be minimal-length big-endian.
+        let mut fields = Vec::with_capacity(item_count);
+        for (i, name) in TRANSACTION_FIELDS.iter().enumerate().take(item_count) {
+            let field = match *name {
+                "to" => to.clone(),
+                "data" => rlp
+                    .at(i)
+                    .and_then(|data| data.data().map(<[u8]>::to_vec))
+                    .map_err(|_| Status::invalid_argument("data"))?,
+                _ => Self::canonical_uint(&rlp, i, name)?,
+            };
+            fields.push(field);
+        }
+        let mut unsigned = RlpStream::new_list(item_count);
+        for field in &fields {
+            unsigned.append(field);
+        }
+        if unsigned.out().as_ref() != transaction {
+            return Err(Status::invalid_argument("transaction is not canonically RLP-encoded"));
+        }
         let digest = Self::hash_message(transaction, HashFunction::Keccak256)?;
+        Ok(UnsignedTransaction { chain_id, nonce, to, value, fields, digest })
+    }
 
+    /// Sign `transaction`, see `parse_ethereum_transaction`.
+    fn sign_unsigned_transaction(
+        signing_key: &key_server::SecretPubKeyPair,
+        transaction: UnsignedTransaction,
+    ) -> Result<Response<SignEthereumTransactionResponse>, Status> {
+        let UnsignedTransaction { chain_id, nonce, to, value, fields, digest } = transaction;
         let EcdsaSignature { r, s, is_y_odd, is_x_reduced: _ } =
             Self::sign_digest_internal(signing_key, &digest)?;
 
         // Compute v according to EIP-155 if chain_id is present
         let recovery_id = is_y_odd as u64;
         let v = if let Some(chain_id) = chain_id {
-            (chain_id * 2 + 35) + recovery_id
+            Self::eip155_v(chain_id, is_y_odd)
+                .ok_or_else(|| Status::invalid_argument("chain ID too large for EIP-155 v"))?
         } else {
             27 + recovery_id
         };
         // Create signed transaction
         let mut stream = RlpStream::new_list(9);
         // first 6 elements (nonce, gasPrice, gasLimit, to, value, data)
-        for i in 0..6 {
-            let val = rlp.at(i).map_err(|_| Status::invalid_argument("decode element"))?;
-            stream.append_raw(val.as_raw(), 1);
+        for field in &fields[..6] {
+            stream.append(field);
         }
         stream.append(&v);
-        stream.append(&r);
-        stream.append(&s);
-        let response = SignEthereumTransactionResponse { tx_data: stream.out().to_vec() };
+        // r and s are RLP integers, i.e., without leading zero bytes.
+        stream.append(&Self::strip_leading_zeros(&r).to_vec());
+        stream.append(&Self::strip_leading_zeros(&s).to_vec());
+        let tx_data = stream.out().to_vec();
+        let tx_hash = Self::hash_message(&tx_data, HashFunction::Keccak256)?;
+        let response = SignEthereumTransactionResponse {
+            tx_data,
+            tx_hash: tx_hash.to_vec(),
+            to,
+            value,
+            nonce,
+            chain_id: chain_id.unwrap_or_default(),
+        };
         Ok(Response::new(response))
     }
 
+    /// The EIP-155 `v`, i.e., `chain_id * 2 + 35 + is_y_odd`, or `None` if it exceeds 64 bits.
+    fn eip155_v(chain_id: u64, is_y_odd: bool) -> Option<u64> {
+        chain_id.checked_mul(2)?.checked_add(35 + is_y_odd as u64)
+    }
+
+    /// The EIP-7702 digest `keccak256(0x05 || rlp([chain_id, address, nonce]))`.
+    fn authorization_digest(chain_id: u64, address: &[u8; 20], nonce: u64) -> [u8; 32] {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&chain_id);
+        stream.append(&address.to_vec());
+        stream.append(&nonce);
+        let mut output = [0u8; 32];
+        let mut hasher = Keccak::v256();
+        hasher.update(&[EIP7702_MAGIC]);
+        hasher.update(&stream.out());
+        hasher.finalize(&mut output);
+        output
+    }
+
+    /// A 20 byte address, or the zero address if `bytes` is empty and `optional`.
+    fn address(bytes: Vec<u8>, name: &str, optional: bool) -> Result<[u8; 20], Status> {
+        if bytes.is_empty() && optional {
+            return Ok([0; 20]);
+        }
+        bytes.try_into().map_err(|x: Vec<u8>| {
+            Status::invalid_argument(format!("{} must be 20 bytes - was {}", name, x.len()))
+        })
+    }
+
+    /// The `SafeTx` of a `SignSafeTransaction` request.
+    fn safe_transaction(
+        request: SignSafeTransactionRequest,
+    ) -> Result<crate::safe::SafeTransaction, Status> {
+        if request.value.len() > 32 {
+            return Err(Status::invalid_argument("value exceeds 256 bits"));
+        }
+        let mut value = [0u8; 32];
+        value[32 - request.value.len()..].copy_from_slice(&request.value);
+        let operation = match request.operation {
+            0 | 1 => request.operation as u8,
+            n => return Err(Status::invalid_argument(format!("invalid operation: {}", n))),
+        };
+        Ok(crate::safe::SafeTransaction {
+            to: Self::address(request.to, "to", false)?,
+            value,
+            data: request.data,
+            operation,
+            safe_tx_gas: request.safe_tx_gas,
+            base_gas: request.base_gas,
+            gas_price: request.gas_price,
+            gas_token: Self::address(request.gas_token, "gas_token", true)?,
+            refund_receiver: Self::address(request.refund_receiver, "refund_receiver", true)?,
+            nonce: request.nonce,
+        })
+    }
+
+    /// The RLP integer at `index` as minimal-length big-endian bytes (empty for zero),
+    /// rejecting non-canonical encodings.
+    fn canonical_uint(rlp: &Rlp, index: usize, name: &str) -> Result<Vec<u8>, Status> {
+        let item = rlp.at(index).map_err(|_| Status::invalid_argument(name.to_string()))?;
+        if !item.is_data() {
+            return Err(Status::invalid_argument(format!("{} must be an integer", name)));
+        }
+        let data = item.data().map_err(|_| Status::invalid_argument(name.to_string()))?;
+        if data.first() == Some(&0) {
+            return Err(Status::invalid_argument(format!("{} has leading zero bytes", name)));
+        }
+        if data.len() > 32 {
+            return Err(Status::invalid_argument(format!("{} exceeds 256 bits", name)));
+        }
+        Ok(data.to_vec())
+    }
+
+    fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+        let first = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+        &bytes[first..]
+    }
+
+    /// Sign `digest`, returning `r` and `s` as exactly 32 big-endian bytes each (left-padded
+    /// with zeros), as fixed-width consumers such as Ethereum clients expect.
     fn sign_digest_internal(
         signing_key: &key_server::SecretPubKeyPair,
         digest: &[u8; 32],
     ) -> Result<EcdsaSignature, Status> {
         let key_server::EcdsaSignature { r, s, is_y_odd, is_x_reduced } =
-            signing_key.ecdsa_sign_prehash(&digest).map_err(|x| Status::internal(x.to_string()))?;
-
+            signing_key.ecdsa_sign_prehash(digest).map_err(|x| Status::internal(x.to_string()))?;
+        // Ethereum's recovery ID only encodes the parity of y; the (astronomically unlikely)
+        // signatures with a reduced x coordinate cannot be recovered from r, s, and v.
+        if is_x_reduced {
+            return Err(Status::internal("signature has a reduced x coordinate; please retry"));
+        }
         Ok(EcdsaSignature { r: r.to_vec(), s: s.to_vec(), is_y_odd, is_x_reduced })
     }
 
+    /// Convert a prehash of any length to the 32 byte digest that is signed: longer ones are
+    /// truncated to their leftmost 32 bytes, shorter ones are left-padded with zeros (as integers,
+    /// their value is kept). This is the FIPS 186 / RFC 6979 rule for the 256 bit secp256k1 order.
+    fn prehash_digest(digest: &[u8], strict: bool) -> Result<[u8; 32], Status> {
+        if digest.len() != 32 && (strict || digest.is_empty()) {
+            return Err(Status::invalid_argument(format!(
+                "digest must be 32 bytes - was {}",
+                digest.len()
+            )));
+        }
+        let mut result = [0u8; 32];
+        if digest.len() >= 32 {
+            result.copy_from_slice(&digest[..32]);
+        } else {
+            result[32 - digest.len()..].copy_from_slice(digest);
+        }
+        Ok(result)
+    }
+
+    /// Sign `digest`, returning the DER encoded signature (without recovery ID).
+    fn sign_digest_der_internal(
+        signing_key: &key_server::SecretPubKeyPair,
+        digest: &[u8; 32],
+    ) -> Result<Vec<u8>, Status> {
+        let key_server::EcdsaSignature { r, s, .. } =
+            signing_key.ecdsa_sign_prehash(digest).map_err(|x| Status::internal(x.to_string()))?;
+        let signature = k256::ecdsa::Signature::from_scalars(r, s)
+            .map_err(|x| Status::internal(x.to_string()))?;
+        Ok(signature.to_der().as_bytes().to_vec())
+    }
+
+    /// Verify a 64 byte `r || s` or 65 byte `r || s || v` signature on `digest`.
+    ///
+    /// Malformed signatures are rejected with an error; well-formed signatures that
+    /// do not verify (including high-s signatures) yield `false`.
+    fn verify_digest_internal(
+        public_key: &k256::PublicKey,
+        digest: &[u8; 32],
+        signature: &[u8],
+    ) -> Result<bool, Status> {
+        use k256::ecdsa::signature::hazmat::PrehashVerifier;
+        use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+        let (rs, v) = match signature.len() {
+            64 => (signature, None),
+            65 => (&signature[..64], Some(signature[64])),
+            n => {
+                return Err(Status::invalid_argument(format!(
+                    "signature must be 64 or 65 bytes - was {}",
+                    n
+                )))
+            }
+        };
+        let signature = Signature::from_slice(rs)
+            .map_err(|_| Status::invalid_argument("signature r or s out of range"))?;
+        let verifying_key = VerifyingKey::from(public_key);
+        match v {
+            None => Ok(verifying_key.verify_prehash(digest, &signature).is_ok()),
+            Some(v) => {
+                let recovery_id = match v {
+                    0 | 1 => v,
+                    27 | 28 => v - 27,
+                    _ => return Err(Status::invalid_argument(format!("invalid v: {}", v))),
+                };
+                let recovery_id = RecoveryId::from_byte(recovery_id)
+                    .ok_or_else(|| Status::invalid_argument("invalid recovery ID"))?;
+                Ok(VerifyingKey::recover_from_prehash(digest, &signature, recovery_id)
+                    .is_ok_and(|recovered| recovered == verifying_key))
+            }
+        }
+    }
+
     fn hash_message(message: &[u8], hash_function: HashFunction) -> Result<[u8; 32], Status> {
         match hash_function {
             HashFunction::Sha256 => {
@@ -103,13 +384,30 @@ impl<SM: Secmod> SignerServiceImpl<SM> {
         }
     }
 
+    /// Resolve the signing key, returning its (one-based) key index and the key pair.
     fn signing_key(
         &self,
         signing_key: SigningKey,
         default: BuiltinSigningKey,
-    ) -> Result<&key_server::SecretPubKeyPair, Status> {
+    ) -> Result<(u32, key_server::SecretPubKeyPair), Status> {
         assert!(default != BuiltinSigningKey::Unspecified);
-        let key_index = if signing_key.key_index as u32 == BuiltinSigningKey::Unspecified as u32 {
+        // Resolve the address and the key from the same snapshot, in case of a rotation.
+        let keys = self.key.signing_keys();
+        let key_index = if !signing_key.ethereum_address.is_empty() {
+            if signing_key.key_index != 0 {
+                return Err(Status::invalid_argument(
+                    "key_index and ethereum_address are mutually exclusive",
+                ));
+            }
+            let address: [u8; 20] = signing_key
+                .ethereum_address
+                .as_slice()
+                .try_into()
+                .map_err(|_| Status::invalid_argument("ethereum_address must be 20 bytes"))?;
+            keys.key_index_by_ethereum_address(&address).ok_or_else(|| {
+                Status::not_found(format!("no key with address 0x{}", hex::encode(address)))
+            })?
+        } else if signing_key.key_index == BuiltinSigningKey::Unspecified as u32 {
             default as u32
         } else {
             signing_key.key_index
@@ -117,88 +415,832 @@ impl<SM: Secmod> SignerServiceImpl<SM> {
         if key_index == 0 {
             return Err(Status::invalid_argument("key_index must not be zero"));
         }
+        if key_index == crate::break_glass::BREAK_GLASS_KEY_INDEX {
+            let Some(pair) = self.key.break_glass_key() else {
+                return Err(Status::failed_precondition("break-glass key not loaded"));
+            };
+            if !signing_key.domain.is_empty() {
+                return Err(Status::invalid_argument("break-glass key has no domain keys"));
+            }
+            return Ok((key_index, pair));
+        }
         // Note that key_index zero corresponds to BUILTIN_SIGNING_KEY_UNSPECIFIED.
         // Thus, the valid values for key_index are 1..N where N is as configured.
-        let key_index = key_index - 1;
-        if key_index as usize >= self.key.pairs.len() {
+        let pair_index = key_index - 1;
+        let Some(pair) = keys.pairs.get(pair_index as usize) else {
             return Err(Status::invalid_argument(format!(
                 "key_index must not be greater than {}",
-                self.key.pairs.len()
+                keys.pairs.len()
+            )));
+        };
+        if signing_key.domain.is_empty() {
+            return Ok((key_index, pair.clone()));
+        }
+        if signing_key.domain.len() > MAX_DOMAIN_LABEL_BYTES {
+            return Err(Status::invalid_argument(format!(
+                "domain must be at most {} bytes",
+                MAX_DOMAIN_LABEL_BYTES
+            )));
+        }
+        let domain_key = pair
+            .derive_domain_key(&signing_key.domain)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok((key_index, domain_key))
+    }
+
+    /// Resolve an unspecified hash function using the key's configured default, if any.
+    fn resolve_hash_function(&self, key_index: u32, requested: HashFunction) -> HashFunction {
+        use crate::config::MessageHashFunction;
+        if requested != HashFunction::Unspecified {
+            return requested;
+        }
+        match self.key.config.default_hash_function(key_index) {
+            Some(MessageHashFunction::Sha256) => HashFunction::Sha256,
+            Some(MessageHashFunction::Keccak256) => HashFunction::Keccak256,
+            Some(MessageHashFunction::Sha3_256) => HashFunction::Sha3256,
+            None => HashFunction::Unspecified,
+        }
+    }
+
+    /// Reject messages larger than the configured `max-sign-message-bytes`.
+    fn check_message_size(&self, message: &[u8]) -> Result<(), Status> {
+        let max = self.key.config.max_sign_message_bytes();
+        if message.len() > max {
+            return Err(Status::invalid_argument(format!(
+                "message too long: {} bytes; at most {} bytes allowed",
+                message.len(),
+                max
             )));
         }
-        Ok(&self.key.pairs[key_index as usize])
+        Ok(())
+    }
+
+    /// The `user_data` of a signature attestation, see `SignatureAttestation` in the proto.
+    fn signature_binding(digest: &[u8; 32], compressed_public_key: &[u8]) -> [u8; 32] {
+        use sha2::Digest;
+        sha2::Sha256::new()
+            .chain_update(digest)
+            .chain_update(compressed_public_key)
+            .finalize()
+            .into()
+    }
+
+    /// An attestation for a signature on `digest` by `signing_key`, if requested or configured.
+    fn signature_attestation(
+        &self,
+        requested: bool,
+        compressed_public_key: Vec<u8>,
+        digest: &[u8; 32],
+    ) -> Result<Option<SignatureAttestation>, Status> {
+        if !requested && !self.key.config.attest_signatures {
+            return Ok(None);
+        }
+        let binding = Self::signature_binding(digest, &compressed_public_key);
+        let attestation_document = AttestationUserData::new(PURPOSE_SIGNATURE)
+            .with_sha256(&binding)
+            .encode()
+            .and_then(|user_data| {
+                self.key.metrics.time_attestation("grpc", || {
+                    SM::new_attestation(&self.key.attestor, None, None, Some(user_data))
+                })
+            })
+            .map_err(|e| Status::internal(format!("cannot create attestation: {}", e)))?;
+        Ok(Some(SignatureAttestation { attestation_document, compressed_public_key }))
+    }
+
+    /// Sign `SELF_TEST_DIGEST` with `signing_key` and verify the signature, including its
+    /// recovery ID, against the key's public key.
+    fn self_test_key(signing_key: &key_server::SecretPubKeyPair) -> Result<(), Status> {
+        let EcdsaSignature { r, s, is_y_odd, .. } =
+            Self::sign_digest_internal(signing_key, &SELF_TEST_DIGEST)?;
+        let signature = [r, s, vec![is_y_odd as u8]].concat();
+        if !Self::verify_digest_internal(&signing_key.public_key, &SELF_TEST_DIGEST, &signature)? {
+            return Err(Status::internal("signature does not verify against the public key"));
+        }
+        Ok(())
+    }
+
+    /// Check the `authorization` metadata of a signing request against the configured token.
+    fn authenticate<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let Some(auth_token) = &self.auth_token else {
+            return Ok(());
+        };
+        let presented = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+        if !nsm_attestation::ct_eq(presented.as_bytes(), auth_token.as_bytes()) {
+            return Err(Status::unauthenticated("invalid bearer token"));
+        }
+        Ok(())
+    }
+
+    /// Use up one signature of the key's budget, see `signature-budgets` in the config.
+    /// Fails with `DeadlineExceeded` instead if the client has given up (see `request_deadline`),
+    /// so that no budget is spent on a signature nobody receives.
+    fn spend_signature_budget(
+        &self,
+        key_index: u32,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<(), Status> {
+        if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+            return Err(Status::deadline_exceeded("deadline exceeded before signing"));
+        }
+        self.key
+            .spend_signature_budget(key_index)
+            .map_err(|e| Status::resource_exhausted(e.to_string()))
+    }
+
+    /// Record a signing operation in the audit log.
+    fn audit(&self, key_index: u32, operation: &'static str, digest: &[u8; 32]) {
+        self.key.metrics.signing_operations_total.with_label_values(&[operation]).inc();
+        match self.key.audit_log.lock() {
+            Ok(mut audit_log) => audit_log.record(key_index, operation, digest),
+            Err(e) => tracing::error!("audit log unavailable: {}", e),
+        }
     }
 }
 
-#[tonic::async_trait]
-impl<SM: Secmod + 'static> KeyPoolService for SignerServiceImpl<SM> {
-    async fn sign_digest(
+/// Handlers of the signing RPCs, which check the deadline of the request just before spending
+/// the signature budget. Tonic also drops handlers once the deadline passes, but never between
+/// spending the budget and returning the signature: the handlers do not await in between.
+impl<SM: Secmod + 'static> SignerServiceImpl<SM> {
+    async fn handle_sign_digest(
         &self,
-        request: Request<SignDigestRequest>,
+        request: SignDigestRequest,
+        deadline: Option<tokio::time::Instant>,
     ) -> Result<Response<SignDigestResponse>, Status> {
-        let request = request.into_inner();
         let signing_key = request.signing_key.unwrap_or_default();
-        let signing_key = self.signing_key(signing_key, BuiltinSigningKey::ServiceResponse)?;
-        let digest: [u8; 32] = request.digest.try_into().map_err(|x: Vec<u8>| {
-            Status::invalid_argument(format!("digest must be 32 bytes - was {}", x.len()))
-        })?;
-        let ecdsa_signature = Self::sign_digest_internal(signing_key, &digest)?;
-        let response = SignDigestResponse { signature: Some(ecdsa_signature) };
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::ServiceResponse)?;
+        let digest = Self::prehash_digest(&request.digest, request.strict_digest_length)?;
+        self.spend_signature_budget(key_index, deadline)?;
+        let ecdsa_signature = Self::sign_digest_internal(&signing_key, &digest)?;
+        self.audit(key_index, "sign_digest", &digest);
+        let attestation = self.signature_attestation(
+            request.include_attestation,
+            signing_key.compressed_public_key(),
+            &digest,
+        )?;
+        let response = SignDigestResponse { signature: Some(ecdsa_signature), attestation };
         Ok(Response::new(response))
     }
 
-    async fn sign_message(
+    async fn handle_sign_digest_der(
         &self,
-        request: Request<SignMessageRequest>,
-    ) -> Result<Response<SignMessageResponse>, Status> {
-        let request = request.into_inner();
+        request: SignDigestDerRequest,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Response<SignDigestDerResponse>, Status> {
         let signing_key = request.signing_key.unwrap_or_default();
-        let signing_key = self.signing_key(signing_key, BuiltinSigningKey::ServiceResponse)?;
-        let hash_function = request.hash_function();
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::ServiceResponse)?;
+        let digest = Self::prehash_digest(&request.digest, request.strict_digest_length)?;
+        self.spend_signature_budget(key_index, deadline)?;
+        let signature = Self::sign_digest_der_internal(&signing_key, &digest)?;
+        self.audit(key_index, "sign_digest_der", &digest);
+        Ok(Response::new(SignDigestDerResponse { signature }))
+    }
+
+    async fn handle_sign_p256(
+        &self,
+        request: SignP256Request,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Response<SignP256Response>, Status> {
+        use p256::ecdsa::signature::hazmat::PrehashSigner;
+        let digest: [u8; 32] = request
+            .digest
+            .as_slice()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("digest must be 32 bytes"))?;
+        self.spend_signature_budget(key_server::P256_KEY_INDEX, deadline)?;
+        let signature: p256::ecdsa::Signature = self
+            .key
+            .p256_signing_key
+            .sign_prehash(&digest)
+            .map_err(|e| Status::internal(format!("cannot sign digest: {}", e)))?;
+        let signature = signature.normalize_s().unwrap_or(signature);
+        self.audit(key_server::P256_KEY_INDEX, "sign_p256", &digest);
+        let compressed_public_key =
+            self.key.p256_signing_key.verifying_key().to_encoded_point(true).as_bytes().to_vec();
+        let attestation = self.signature_attestation(
+            request.include_attestation,
+            compressed_public_key,
+            &digest,
+        )?;
+        Ok(Response::new(SignP256Response {
+            signature: signature.to_bytes().to_vec(),
+            signature_der: signature.to_der().as_bytes().to_vec(),
+            attestation,
+        }))
+    }
+
+    async fn handle_sign_message(
+        &self,
+        request: SignMessageRequest,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Response<SignMessageResponse>, Status> {
+        let signing_key = request.signing_key.clone().unwrap_or_default();
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::ServiceResponse)?;
+        let hash_function = self.resolve_hash_function(key_index, request.hash_function());
+        let include_attestation = request.include_attestation;
         let message = request.message;
-        if message.len() > (1 << 20) {
-            return Err(Status::invalid_argument("message too long"));
-        }
+        self.check_message_size(&message)?;
         let digest = Self::hash_message(&message, hash_function)?;
-        let mut ecdsa_signature = Self::sign_digest_internal(signing_key, &digest)?;
+        self.spend_signature_budget(key_index, deadline)?;
+        let mut ecdsa_signature = Self::sign_digest_internal(&signing_key, &digest)?;
+        self.audit(key_index, "sign_message", &digest);
         let mut eth_format = Vec::new();
         eth_format.append(&mut ecdsa_signature.r);
         eth_format.append(&mut ecdsa_signature.s);
         eth_format.push(ecdsa_signature.is_y_odd as u8);
-        let response = SignMessageResponse { signature: eth_format };
+        let attestation = self.signature_attestation(
+            include_attestation,
+            signing_key.compressed_public_key(),
+            &digest,
+        )?;
+        let response = SignMessageResponse { signature: eth_format, attestation };
         Ok(Response::new(response))
     }
 
-    async fn sign_ethereum_transaction(
+    async fn handle_sign_ethereum_transaction(
         &self,
-        request: Request<SignEthereumTransactionRequest>,
+        request: SignEthereumTransactionRequest,
+        deadline: Option<tokio::time::Instant>,
     ) -> Result<Response<SignEthereumTransactionResponse>, Status> {
-        let request = request.into_inner();
         let signing_key = request.signing_key.unwrap_or_default();
-        let signing_key = self.signing_key(signing_key, BuiltinSigningKey::Ethereum)?;
-        let response = Self::sign_ethereum_transaction(signing_key, &request.tx_data).await?;
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::Ethereum)?;
+        let transaction = Self::parse_ethereum_transaction(&request.tx_data)?;
+        let digest = transaction.digest;
+        self.spend_signature_budget(key_index, deadline)?;
+        let response = Self::sign_unsigned_transaction(&signing_key, transaction)?;
+        self.audit(key_index, "sign_ethereum_transaction", &digest);
         Ok(response)
     }
 
+    async fn handle_sign_authorization(
+        &self,
+        request: SignAuthorizationRequest,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Response<SignAuthorizationResponse>, Status> {
+        let signing_key = request.signing_key.unwrap_or_default();
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::Ethereum)?;
+        let address: [u8; 20] = request.address.try_into().map_err(|x: Vec<u8>| {
+            Status::invalid_argument(format!("address must be 20 bytes - was {}", x.len()))
+        })?;
+        let digest = Self::authorization_digest(request.chain_id, &address, request.nonce);
+        self.spend_signature_budget(key_index, deadline)?;
+        let ecdsa_signature = Self::sign_digest_internal(&signing_key, &digest)?;
+        self.audit(key_index, "sign_authorization", &digest);
+        let response = SignAuthorizationResponse { signature: Some(ecdsa_signature) };
+        Ok(Response::new(response))
+    }
+
+    async fn handle_sign_safe_transaction(
+        &self,
+        request: SignSafeTransactionRequest,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Response<SignSafeTransactionResponse>, Status> {
+        let signing_key = request.signing_key.clone().unwrap_or_default();
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::Ethereum)?;
+        let chain_id = request.chain_id;
+        let safe_address = Self::address(request.safe_address.clone(), "safe_address", false)?;
+        self.check_message_size(&request.data)?;
+        let transaction = Self::safe_transaction(request)?;
+        let digest = crate::safe::safe_transaction_hash(chain_id, &safe_address, &transaction)
+            .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        self.spend_signature_budget(key_index, deadline)?;
+        let ecdsa_signature = Self::sign_digest_internal(&signing_key, &digest)?;
+        self.audit(key_index, "sign_safe_transaction", &digest);
+        let mut safe_signature = [ecdsa_signature.r.as_slice(), &ecdsa_signature.s].concat();
+        safe_signature.push(27 + ecdsa_signature.is_y_odd as u8);
+        let response = SignSafeTransactionResponse {
+            safe_tx_hash: digest.to_vec(),
+            signature: Some(ecdsa_signature),
+            safe_signature,
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn handle_sign_typed_data(
+        &self,
+        request: SignTypedDataRequest,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Response<SignTypedDataResponse>, Status> {
+        let signing_key = request.signing_key.unwrap_or_default();
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::Ethereum)?;
+        self.check_message_size(request.typed_data.as_bytes())?;
+        let digest = crate::typed_data::TypedData::from_json(&request.typed_data)
+            .and_then(|typed_data| typed_data.digest())
+            .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        self.spend_signature_budget(key_index, deadline)?;
+        let ecdsa_signature = Self::sign_digest_internal(&signing_key, &digest)?;
+        self.audit(key_index, "sign_typed_data", &digest);
+        let mut eth_signature = [ecdsa_signature.r.as_slice(), &ecdsa_signature.s].concat();
+        eth_signature.push(27 + ecdsa_signature.is_y_odd as u8);
+        let response = SignTypedDataResponse {
+            digest: digest.to_vec(),
+            signature: Some(ecdsa_signature),
+            eth_signature,
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn handle_sign_schnorr(
+        &self,
+        request: SignSchnorrRequest,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Response<SignSchnorrResponse>, Status> {
+        let signing_key = request.signing_key.unwrap_or_default();
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::ServiceResponse)?;
+        let message: [u8; 32] = request.message.try_into().map_err(|x: Vec<u8>| {
+            Status::invalid_argument(format!("message must be 32 bytes - was {}", x.len()))
+        })?;
+        self.spend_signature_budget(key_index, deadline)?;
+        let signature =
+            signing_key.schnorr_sign(&message).map_err(|x| Status::internal(x.to_string()))?;
+        self.audit(key_index, "sign_schnorr", &message);
+        Ok(Response::new(SignSchnorrResponse { signature: signature.to_vec() }))
+    }
+}
+
+/// The deadline of a request from its `grpc-timeout` header, if any.
+fn request_deadline<T>(request: &Request<T>) -> Result<Option<tokio::time::Instant>, Status> {
+    let Some(value) = request.metadata().get("grpc-timeout") else {
+        return Ok(None);
+    };
+    let invalid = || Status::invalid_argument("invalid grpc-timeout header");
+    let value = value.to_str().map_err(|_| invalid())?;
+    // At most 8 digits followed by a unit, see the gRPC over HTTP2 protocol.
+    if value.len() < 2 || value.len() > 9 {
+        return Err(invalid());
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    if !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let timeout = match unit {
+        "H" => std::time::Duration::from_secs(amount * 60 * 60),
+        "M" => std::time::Duration::from_secs(amount * 60),
+        "S" => std::time::Duration::from_secs(amount),
+        "m" => std::time::Duration::from_millis(amount),
+        "u" => std::time::Duration::from_micros(amount),
+        "n" => std::time::Duration::from_nanos(amount),
+        _ => return Err(invalid()),
+    };
+    Ok(tokio::time::Instant::now().checked_add(timeout))
+}
+
+#[tonic::async_trait]
+impl<SM: Secmod + 'static> KeyPoolService for SignerServiceImpl<SM> {
+    async fn sign_digest(
+        &self,
+        request: Request<SignDigestRequest>,
+    ) -> Result<Response<SignDigestResponse>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_digest(request.into_inner(), deadline).await
+    }
+
+    async fn sign_digest_der(
+        &self,
+        request: Request<SignDigestDerRequest>,
+    ) -> Result<Response<SignDigestDerResponse>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_digest_der(request.into_inner(), deadline).await
+    }
+
+    async fn sign_p256(
+        &self,
+        request: Request<SignP256Request>,
+    ) -> Result<Response<SignP256Response>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_p256(request.into_inner(), deadline).await
+    }
+
+    async fn get_p256_public_key(
+        &self,
+        _request: Request<GetP256PublicKeyRequest>,
+    ) -> Result<Response<GetP256PublicKeyResponse>, Status> {
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+        use p256::pkcs8::EncodePublicKey;
+        let public_key = p256::PublicKey::from(self.key.p256_signing_key.verifying_key());
+        let public_key_der = public_key
+            .to_public_key_der()
+            .map_err(|e| Status::internal(format!("cannot encode public key: {}", e)))?;
+        Ok(Response::new(GetP256PublicKeyResponse {
+            compressed_public_key: public_key.to_encoded_point(true).as_bytes().to_vec(),
+            uncompressed_public_key: public_key.to_encoded_point(false).as_bytes().to_vec(),
+            public_key_der: public_key_der.as_bytes().to_vec(),
+        }))
+    }
+
+    async fn sign_message(
+        &self,
+        request: Request<SignMessageRequest>,
+    ) -> Result<Response<SignMessageResponse>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_message(request.into_inner(), deadline).await
+    }
+
+    async fn sign_ethereum_transaction(
+        &self,
+        request: Request<SignEthereumTransactionRequest>,
+    ) -> Result<Response<SignEthereumTransactionResponse>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_ethereum_transaction(request.into_inner(), deadline).await
+    }
+
+    async fn sign_authorization(
+        &self,
+        request: Request<SignAuthorizationRequest>,
+    ) -> Result<Response<SignAuthorizationResponse>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_authorization(request.into_inner(), deadline).await
+    }
+
+    async fn sign_safe_transaction(
+        &self,
+        request: Request<SignSafeTransactionRequest>,
+    ) -> Result<Response<SignSafeTransactionResponse>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_safe_transaction(request.into_inner(), deadline).await
+    }
+
+    async fn sign_typed_data(
+        &self,
+        request: Request<SignTypedDataRequest>,
+    ) -> Result<Response<SignTypedDataResponse>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_typed_data(request.into_inner(), deadline).await
+    }
+
     async fn get_ethereum_address(
         &self,
         request: Request<GetEthereumAddressRequest>,
     ) -> Result<Response<GetEthereumAddressResponse>, Status> {
         let request = request.into_inner();
         let signing_key = request.signing_key.unwrap_or_default();
-        let signing_key = self.signing_key(signing_key, BuiltinSigningKey::Ethereum)?;
+        let (_, signing_key) = self.signing_key(signing_key, BuiltinSigningKey::Ethereum)?;
         let addr = signing_key.ethereum_address();
         let hex_addr = hex::encode(addr);
         let response = GetEthereumAddressResponse { ethereum_address: hex_addr };
         Ok(Response::new(response))
     }
+
+    async fn list_keys(
+        &self,
+        _request: Request<ListKeysRequest>,
+    ) -> Result<Response<ListKeysResponse>, Status> {
+        use elliptic_curve::sec1::ToEncodedPoint;
+
+        let keys = self.key.signing_keys();
+        let pairs = &keys.pairs;
+        let keys: Vec<KeyInfo> = pairs
+            .iter()
+            .zip(1..)
+            .map(|(pair, key_index)| KeyInfo {
+                key_index,
+                compressed_public_key: pair.public_key.to_encoded_point(true).as_bytes().to_vec(),
+                uncompressed_public_key: pair
+                    .public_key
+                    .to_encoded_point(false)
+                    .as_bytes()
+                    .to_vec(),
+                ethereum_address: hex::encode(pair.ethereum_address()),
+            })
+            .collect();
+        let response = ListKeysResponse { count: keys.len() as u32, keys };
+        Ok(Response::new(response))
+    }
+
+    async fn verify_signature(
+        &self,
+        request: Request<VerifySignatureRequest>,
+    ) -> Result<Response<VerifySignatureResponse>, Status> {
+        use crate::grpc::pb::verify_signature_request::Data;
+
+        let request = request.into_inner();
+        let signing_key = request.signing_key.clone().unwrap_or_default();
+        let (key_index, signing_key) =
+            self.signing_key(signing_key, BuiltinSigningKey::ServiceResponse)?;
+        let hash_function = self.resolve_hash_function(key_index, request.hash_function());
+        let digest = match request.data {
+            Some(Data::Message(message)) => {
+                self.check_message_size(&message)?;
+                Self::hash_message(&message, hash_function)?
+            }
+            Some(Data::Digest(digest)) => Self::prehash_digest(&digest, false)?,
+            None => return Err(Status::invalid_argument("message or digest required")),
+        };
+        let valid =
+            Self::verify_digest_internal(&signing_key.public_key, &digest, &request.signature)?;
+        Ok(Response::new(VerifySignatureResponse { valid }))
+    }
+
+    async fn get_audit_head(
+        &self,
+        _request: Request<GetAuditHeadRequest>,
+    ) -> Result<Response<GetAuditHeadResponse>, Status> {
+        let (head_hash, entry_count) = self
+            .key
+            .audit_log
+            .lock()
+            .map_err(|e| Status::internal(format!("audit log unavailable: {}", e)))?
+            .head();
+        let response = GetAuditHeadResponse { head_hash: head_hash.to_vec(), entry_count };
+        Ok(Response::new(response))
+    }
+
+    async fn rotate_keys(
+        &self,
+        _request: Request<RotateKeysRequest>,
+    ) -> Result<Response<RotateKeysResponse>, Status> {
+        let (old_keys, new_keys) = self
+            .key
+            .rotate_keys()
+            .await
+            .map_err(|e| Status::permission_denied(format!("{:#}", e)))?;
+        let response = RotateKeysResponse {
+            old_public_keys: old_keys.iter().map(|k| k.to_sec1_bytes().to_vec()).collect(),
+            new_public_keys: new_keys.iter().map(|k| k.to_sec1_bytes().to_vec()).collect(),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn rotate_certificate(
+        &self,
+        request: Request<RotateCertificateRequest>,
+    ) -> Result<Response<RotateCertificateResponse>, Status> {
+        let alt_names = request.into_inner().alt_names;
+        let certificate = self
+            .key
+            .rotate_certificate(alt_names)
+            .await
+            .map_err(|e| Status::permission_denied(format!("{:#}", e)))?;
+        Ok(Response::new(RotateCertificateResponse { certificate: certificate.to_vec() }))
+    }
+
+    async fn load_break_glass_key(
+        &self,
+        request: Request<LoadBreakGlassKeyRequest>,
+    ) -> Result<Response<LoadBreakGlassKeyResponse>, Status> {
+        use elliptic_curve::sec1::ToEncodedPoint;
+        self.authenticate(&request)?;
+        self.key
+            .throttle_break_glass_attempt()
+            .map_err(|e| Status::resource_exhausted(e.to_string()))?;
+        let sealed_passphrase = request.into_inner().sealed_passphrase;
+        let public_key = self
+            .key
+            .load_break_glass_key(&sealed_passphrase)
+            .await
+            .map_err(|e| Status::permission_denied(format!("{:#}", e)))?;
+        let public_key = public_key.to_encoded_point(true).as_bytes().to_vec();
+        Ok(Response::new(LoadBreakGlassKeyResponse { public_key }))
+    }
+
+    async fn reset_key_budget(
+        &self,
+        request: Request<ResetKeyBudgetRequest>,
+    ) -> Result<Response<ResetKeyBudgetResponse>, Status> {
+        let key_index = request.into_inner().key_index;
+        let remaining = self
+            .key
+            .reset_key_budget(key_index)
+            .await
+            .map_err(|e| Status::permission_denied(format!("{:#}", e)))?;
+        Ok(Response::new(ResetKeyBudgetResponse { remaining }))
+    }
+
+    async fn set_log_level(
+        &self,
+        request: Request<SetLogLevelRequest>,
+    ) -> Result<Response<SetLogLevelResponse>, Status> {
+        let request = request.into_inner();
+        let trace_level = request.trace_level as usize;
+        crate::logging::level_filter(trace_level)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| Status::internal(e.to_string()))?
+            .as_secs();
+        if request.expires_at <= now
+            || request.expires_at > now + MAX_LOG_LEVEL_AUTHORIZATION_TTL.as_secs()
+        {
+            return Err(Status::invalid_argument(format!(
+                "expires-at {} must be within {} seconds after the current time {}",
+                request.expires_at,
+                MAX_LOG_LEVEL_AUTHORIZATION_TTL.as_secs(),
+                now
+            )));
+        }
+        let message = format!("SET-LOG-LEVEL:{}:{}", trace_level, request.expires_at);
+        crate::key_sync::authorize_operation::<SM>(
+            &self.key.attestor,
+            &self.key.config.governance,
+            &message,
+        )
+        .await
+        .map_err(|e| Status::permission_denied(format!("{} not authorized: {:#}", message, e)))?;
+        let mut last = self.log_level_expires_at.lock().unwrap_or_else(|e| e.into_inner());
+        if request.expires_at <= *last {
+            return Err(Status::permission_denied(format!(
+                "{} was already used or superseded",
+                message
+            )));
+        }
+        crate::logging::set_level(&self.log_level, trace_level)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        *last = request.expires_at;
+        Ok(Response::new(SetLogLevelResponse {}))
+    }
+
+    async fn sign_schnorr(
+        &self,
+        request: Request<SignSchnorrRequest>,
+    ) -> Result<Response<SignSchnorrResponse>, Status> {
+        self.authenticate(&request)?;
+        let deadline = request_deadline(&request)?;
+        self.handle_sign_schnorr(request.into_inner(), deadline).await
+    }
+
+    async fn get_x_only_public_key(
+        &self,
+        request: Request<GetXOnlyPublicKeyRequest>,
+    ) -> Result<Response<GetXOnlyPublicKeyResponse>, Status> {
+        let request = request.into_inner();
+        let signing_key = request.signing_key.unwrap_or_default();
+        let (_, signing_key) = self.signing_key(signing_key, BuiltinSigningKey::ServiceResponse)?;
+        let x_only_public_key = signing_key.x_only_public_key().to_vec();
+        Ok(Response::new(GetXOnlyPublicKeyResponse { x_only_public_key }))
+    }
+
+    async fn get_version(
+        &self,
+        _request: Request<GetVersionRequest>,
+    ) -> Result<Response<GetVersionResponse>, Status> {
+        let build_info = crate::version::build_info();
+        Ok(Response::new(GetVersionResponse {
+            version: build_info.version.to_string(),
+            git_commit: build_info.git_commit.to_string(),
+            build_timestamp: build_info.build_timestamp.to_string(),
+        }))
+    }
+
+    async fn get_certificate(
+        &self,
+        _request: Request<GetCertificateRequest>,
+    ) -> Result<Response<GetCertificateResponse>, Status> {
+        let certificate = self.key.cert.certificate_der().to_vec();
+        let config = pem::EncodeConfig::new().set_line_ending(pem::LineEnding::LF);
+        let certificate_pem =
+            pem::encode_config(&pem::Pem::new("CERTIFICATE", certificate.clone()), config);
+        Ok(Response::new(GetCertificateResponse {
+            certificate,
+            certificate_pem,
+            public_key: self.key.cert_public_key_der.clone(),
+        }))
+    }
+
+    async fn self_test(
+        &self,
+        _request: Request<SelfTestRequest>,
+    ) -> Result<Response<SelfTestResponse>, Status> {
+        // Deliberately bypasses `spend_signature_budget` and `audit`: no signature leaves the
+        // enclave.
+        let keys = self.key.signing_keys();
+        let pairs = &keys.pairs;
+        let keys: Vec<KeyHealth> = pairs
+            .iter()
+            .zip(1..)
+            .map(|(pair, key_index)| {
+                let error = match Self::self_test_key(pair) {
+                    Ok(()) => String::new(),
+                    Err(e) => {
+                        tracing::error!("self-test of key {} failed: {}", key_index, e.message());
+                        e.message().to_string()
+                    }
+                };
+                KeyHealth {
+                    key_index,
+                    healthy: error.is_empty(),
+                    ethereum_address: hex::encode(pair.ethereum_address()),
+                    error,
+                }
+            })
+            .collect();
+        let healthy = keys.iter().all(|key| key.healthy);
+        Ok(Response::new(SelfTestResponse { keys, healthy }))
+    }
+}
+
+/// A TLS connection accepted on the gRPC VSOCK port, see `tls_incoming`.
+pub struct TlsGrpcStream<S>(tokio_rustls::server::TlsStream<S>);
+
+impl<S> tonic::transport::server::Connected for TlsGrpcStream<S> {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsGrpcStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsGrpcStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Accept connections on `listener` and perform the TLS handshake (each in its own task, at
+/// most `MAX_PENDING_TLS_HANDSHAKES` at a time and within `TLS_HANDSHAKE_TIMEOUT`), yielding
+/// the established connections for `Server::serve_with_incoming`. Accept errors are counted
+/// in `accept_errors_total` and retried with backoff up to `max_accept_backoff`.
+pub fn tls_incoming<SM: Secmod + 'static>(
+    listener: SM::Listener,
+    tls_acceptor: tokio_rustls::TlsAcceptor,
+    max_accept_backoff: std::time::Duration,
+    accept_errors_total: prometheus::IntCounter,
+) -> tokio_stream::wrappers::ReceiverStream<std::io::Result<TlsGrpcStream<SM::Stream>>> {
+    let (sender, receiver) = tokio::sync::mpsc::channel(16);
+    let handshakes = std::sync::Arc::new(tokio::sync::Semaphore::new(MAX_PENDING_TLS_HANDSHAKES));
+    tokio::spawn(async move {
+        let mut backoff = crate::AcceptBackoff::new(max_accept_backoff);
+        loop {
+            let Ok(permit) = handshakes.clone().acquire_owned().await else {
+                return;
+            };
+            let stream = match SM::accept(&listener).await {
+                Ok(stream) => {
+                    backoff.reset();
+                    stream
+                }
+                Err(e) => {
+                    accept_errors_total.inc();
+                    let delay = backoff.next_delay();
+                    tracing::error!("gRPC accept: {}; retrying in {:?}", e, delay);
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+            let sender = sender.clone();
+            let tls_acceptor = tls_acceptor.clone();
+            tokio::spawn(async move {
+                let handshake = tls_acceptor.accept(stream);
+                let result = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await;
+                drop(permit);
+                match result {
+                    Ok(Ok(tls_stream)) => {
+                        let _ = sender.send(Ok(TlsGrpcStream(tls_stream))).await;
+                    }
+                    Ok(Err(e)) => tracing::error!("gRPC TLS accept error: {}", e),
+                    Err(_) => tracing::warn!("gRPC TLS handshake timed out"),
+                }
+            });
+        }
+    });
+    tokio_stream::wrappers::ReceiverStream::new(receiver)
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use hex;
     use key_server::SecretPubKeyPair;
     use rlp::{Rlp, RlpStream};
 
@@ -226,9 +1268,18 @@ mod tests {
         )
         .await;
         assert!(result.is_ok());
-        let response = result.unwrap().into_inner();
+        let response = result.unwrap().0.into_inner();
         let expected = hex::decode("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83").unwrap();
         assert_eq!(expected, response.tx_data);
+        // The decoded fields are echoed back.
+        assert_eq!(response.nonce, 9);
+        assert_eq!(response.to, vec![0x35; 20]);
+        assert_eq!(response.value, hex::decode("0de0b6b3a7640000").unwrap());
+        assert_eq!(response.chain_id, 1);
+        assert_eq!(
+            hex::encode(&response.tx_hash),
+            "33469b22e9f636356c4160a87eb19df52b7412e8eac32a4a55ffe88ea8350788"
+        );
         // Verify the signed transaction
         let rlp = Rlp::new(&response.tx_data);
         assert_eq!(rlp.item_count().unwrap(), 9);
@@ -288,7 +1339,7 @@ mod tests {
         )
         .await;
         assert!(result.is_ok());
-        let response = result.unwrap().into_inner();
+        let response = result.unwrap().0.into_inner();
         // Verify the signed transaction
         let rlp = Rlp::new(&response.tx_data);
         assert_eq!(rlp.item_count().unwrap(), 9);
@@ -301,6 +1352,30 @@ mod tests {
         assert!(!r.is_empty() && !s.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_sign_contract_creation() {
+        let signing_key = create_test_key();
+        let mut stream = RlpStream::new_list(6);
+        stream.append(&7u64); // nonce
+        stream.append(&20_000_000_000u64); // gasPrice
+        stream.append(&100_000u64); // gasLimit
+        stream.append(&Vec::<u8>::new()); // to: empty for contract creation
+        stream.append(&0u64); // value
+        stream.append(&vec![0x60, 0x00]); // data: init code
+        let response = SignerServiceImpl::<crate::nsm::Nsm>::sign_ethereum_transaction(
+            &signing_key,
+            &stream.out(),
+        )
+        .await
+        .unwrap()
+        .0
+        .into_inner();
+        assert_eq!(response.nonce, 7);
+        assert!(response.to.is_empty());
+        assert!(response.value.is_empty());
+        assert_eq!(response.chain_id, 0);
+    }
+
     #[tokio::test]
     async fn test_invalid_rlp() {
         let signing_key = create_test_key();
@@ -314,6 +1389,188 @@ mod tests {
         assert!(matches!(result.unwrap_err().code(), tonic::Code::InvalidArgument));
     }
 
+    #[tokio::test]
+    async fn test_non_canonical_rlp() {
+        type Service = SignerServiceImpl<crate::nsm::Nsm>;
+        let signing_key = &create_test_key();
+        // A legacy transaction whose gasPrice (the second field) is encoded as `price`.
+        let transaction = |price: &[u8]| {
+            let mut stream = RlpStream::new_list(6);
+            stream.append(&7u64);
+            stream.append_raw(price, 1);
+            stream.append(&21000u64);
+            stream.append(&vec![0x35u8; 20]);
+            stream.append(&0u64);
+            stream.append(&Vec::<u8>::new());
+            stream.out().to_vec()
+        };
+        let sign = |transaction: Vec<u8>| async move {
+            Service::sign_ethereum_transaction(signing_key, &transaction).await
+        };
+        assert!(sign(transaction(&[0x82, 0x01, 0x00])).await.is_ok());
+        assert!(sign(transaction(&[0x05])).await.is_ok());
+        let non_canonical: [&[u8]; 4] = [
+            &[0x83, 0x00, 0x01, 0x00], // leading zero byte
+            &[0x81, 0x05],             // single byte below 0x80 with a length prefix
+            &[0x81, 0x00],             // zero as a byte instead of the empty string
+            &[0xc1, 0x05],             // a list instead of an integer
+        ];
+        for price in non_canonical {
+            let err = sign(transaction(price)).await.unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument, "{}", hex::encode(price));
+        }
+    }
+
+    #[test]
+    fn test_authorization_digest() {
+        type Service = SignerServiceImpl<crate::nsm::Nsm>;
+        // keccak256(0x05 || rlp([1, 0x7fa9...1496, 0])), i.e., of
+        // 0x05d701947fa9385be102ac3eac297483dd6233d62b3e149680.
+        let address: [u8; 20] =
+            hex::decode("7fa9385be102ac3eac297483dd6233d62b3e1496").unwrap().try_into().unwrap();
+        assert_eq!(
+            hex::encode(Service::authorization_digest(1, &address, 0)),
+            "b2e4926962c19114f3cd1920fd1eac31a7898041ba00f0c3bce45d244393ddd6"
+        );
+        // keccak256(0x05 || rlp([1337, 0x00...01, 42])), i.e., of
+        // 0x05d98205399400000000000000000000000000000000000000012a.
+        let mut address = [0u8; 20];
+        address[19] = 1;
+        let digest = Service::authorization_digest(1337, &address, 42);
+        assert_eq!(
+            hex::encode(digest),
+            "e057e62b3a576986a30318545ef197d83e89bd43032cba73fd843f5dc984e0e0"
+        );
+        // The signature recovers the signer's public key.
+        let signing_key = create_test_key();
+        let signature = Service::sign_digest_internal(&signing_key, &digest).unwrap();
+        let eth_format = [signature.r, signature.s, vec![signature.is_y_odd as u8]].concat();
+        let public_key = signing_key.public_key;
+        assert!(Service::verify_digest_internal(&public_key, &digest, &eth_format).unwrap());
+    }
+
+    #[test]
+    fn test_verify_digest() {
+        type Service = SignerServiceImpl<crate::nsm::Nsm>;
+        let signing_key = create_test_key();
+        let public_key = signing_key.public_key;
+        let digest = Service::hash_message(b"hello", HashFunction::Keccak256).unwrap();
+        let signature = Service::sign_digest_internal(&signing_key, &digest).unwrap();
+        let mut eth_format = [signature.r.clone(), signature.s.clone()].concat();
+        assert!(Service::verify_digest_internal(&public_key, &digest, &eth_format).unwrap());
+        eth_format.push(signature.is_y_odd as u8);
+        assert!(Service::verify_digest_internal(&public_key, &digest, &eth_format).unwrap());
+        eth_format[64] += 27;
+        assert!(Service::verify_digest_internal(&public_key, &digest, &eth_format).unwrap());
+        // Wrong recovery ID (27 and 28 swapped).
+        eth_format[64] = 55 - eth_format[64];
+        assert!(!Service::verify_digest_internal(&public_key, &digest, &eth_format).unwrap());
+        // Wrong digest.
+        let other = Service::hash_message(b"hellO", HashFunction::Keccak256).unwrap();
+        assert!(!Service::verify_digest_internal(&public_key, &other, &eth_format[..64]).unwrap());
+        // Wrong key.
+        let other_key = k256::SecretKey::from_slice(&[1; 32]).unwrap().public_key();
+        assert!(!Service::verify_digest_internal(&other_key, &digest, &eth_format[..64]).unwrap());
+        // Malformed signatures.
+        let err = Service::verify_digest_internal(&public_key, &digest, &eth_format[..63]);
+        assert_eq!(err.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let err = Service::verify_digest_internal(&public_key, &digest, &[0; 64]);
+        assert_eq!(err.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_sign_digest_fixed_width() {
+        type Service = SignerServiceImpl<crate::nsm::Nsm>;
+        let signing_key = create_test_key();
+        // About one in 256 signatures has an r (or s) with a leading zero byte.
+        let (mut short_r, mut short_s) = (false, false);
+        for i in 0u32.. {
+            let digest = Service::hash_message(&i.to_be_bytes(), HashFunction::Keccak256).unwrap();
+            let signature = Service::sign_digest_internal(&signing_key, &digest).unwrap();
+            assert_eq!((signature.r.len(), signature.s.len()), (32, 32));
+            if signature.r[0] != 0 && signature.s[0] != 0 {
+                continue;
+            }
+            short_r |= signature.r[0] == 0;
+            short_s |= signature.s[0] == 0;
+            let eth_format = [signature.r, signature.s, vec![signature.is_y_odd as u8]].concat();
+            let public_key = signing_key.public_key;
+            assert!(Service::verify_digest_internal(&public_key, &digest, &eth_format).unwrap());
+            if short_r && short_s {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_prehash_digest() {
+        use k256::ecdsa::signature::hazmat::PrehashVerifier;
+        type Service = SignerServiceImpl<crate::nsm::Nsm>;
+        let signing_key = create_test_key();
+        let verifying_key = k256::ecdsa::VerifyingKey::from(&signing_key.public_key);
+        let prehash: Vec<u8> = (1..=48).collect();
+
+        let digest = Service::prehash_digest(&prehash[..32], true).unwrap();
+        assert_eq!(digest[..], prehash[..32]);
+        assert_eq!(Service::prehash_digest(&prehash[..32], false).unwrap(), digest);
+        // Longer prehashes are truncated to the leftmost 32 bytes.
+        assert_eq!(Service::prehash_digest(&prehash, false).unwrap(), digest);
+        // Shorter prehashes are left-padded.
+        let digest = Service::prehash_digest(&prehash[..20], false).unwrap();
+        assert_eq!(digest[..12], [0; 12]);
+        assert_eq!(digest[12..], prehash[..20]);
+
+        // The signatures verify as signatures on the original prehash (FIPS 186 / RFC 6979).
+        for len in [20, 32, 48] {
+            let digest = Service::prehash_digest(&prehash[..len], false).unwrap();
+            let raw = Service::sign_digest_internal(&signing_key, &digest).unwrap();
+            let signature = k256::ecdsa::Signature::from_slice(&[raw.r, raw.s].concat()).unwrap();
+            assert!(verifying_key.verify_prehash(&prehash[..len], &signature).is_ok());
+        }
+
+        for len in [20, 48] {
+            let err = Service::prehash_digest(&prehash[..len], true).unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+        assert!(Service::prehash_digest(&[], false).is_err());
+    }
+
+    #[test]
+    fn test_sign_digest_der() {
+        type Service = SignerServiceImpl<crate::nsm::Nsm>;
+        let signing_key = create_test_key();
+        for message in [&b"hello"[..], b"world", b""] {
+            let digest = Service::hash_message(message, HashFunction::Sha256).unwrap();
+            let der = Service::sign_digest_der_internal(&signing_key, &digest).unwrap();
+            assert_eq!(der[0], 0x30);
+            assert!(der.len() <= 72);
+            // Signing is deterministic (RFC 6979), so the raw form has the same r and s.
+            let raw = Service::sign_digest_internal(&signing_key, &digest).unwrap();
+            let decoded = k256::ecdsa::Signature::from_der(&der).unwrap();
+            assert_eq!(decoded.r().to_bytes().as_slice(), raw.r);
+            assert_eq!(decoded.s().to_bytes().as_slice(), raw.s);
+            let rs = [raw.r, raw.s].concat();
+            assert!(Service::verify_digest_internal(&signing_key.public_key, &digest, &rs).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_overflow() {
+        type Service = SignerServiceImpl<crate::nsm::Nsm>;
+        // The largest chain ID for which v fits into 64 bits.
+        let max_chain_id = (u64::MAX - 36) / 2;
+        assert_eq!(Service::eip155_v(max_chain_id, true), Some(u64::MAX - 1));
+        assert_eq!(Service::eip155_v(u64::MAX / 2, false), None);
+        let signing_key = create_test_key();
+        let transaction = create_test_transaction(Some(max_chain_id));
+        assert!(Service::sign_ethereum_transaction(&signing_key, &transaction).await.is_ok());
+        for chain_id in [max_chain_id + 1, u64::MAX / 2, u64::MAX] {
+            let transaction = create_test_transaction(Some(chain_id));
+            let result = Service::sign_ethereum_transaction(&signing_key, &transaction).await;
+            assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+        }
+    }
+
     #[tokio::test]
     async fn test_invalid_item_count() {
         let signing_key = create_test_key();
@@ -329,4 +1586,400 @@ mod tests {
         assert!(result.is_err());
         assert!(matches!(result.unwrap_err().code(), tonic::Code::InvalidArgument));
     }
+
+    /// A service with three random signing keys, a debug mock attestor, and no auth token.
+    #[cfg(feature = "test-utils")]
+    fn test_service(
+        config: crate::config::SovereignConfig,
+    ) -> SignerServiceImpl<crate::mock_secmod::MockSecmod> {
+        use crate::mock_secmod::MockSecmod;
+        use elliptic_curve::rand_core::OsRng;
+
+        let secret = key_server::SecretKeyMaterial::generate_random(3, &mut OsRng).unwrap();
+        let attestor = MockSecmod::init_debug_attestor();
+        let key =
+            std::sync::Arc::new(KeyServer::<MockSecmod>::new(attestor, config, secret).unwrap());
+        let (_, log_level) =
+            tracing_subscriber::reload::Layer::new(tracing_subscriber::filter::LevelFilter::INFO);
+        SignerServiceImpl {
+            key,
+            log_level,
+            log_level_expires_at: Default::default(),
+            auth_token: None,
+        }
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_signature_attestation() {
+        use crate::mock_secmod::MockSecmod;
+        use crate::secmod::AttestationDocument;
+        use elliptic_curve::sec1::ToEncodedPoint;
+
+        let service = test_service(Default::default());
+        let key = service.key.clone();
+        let digest = [0x42u8; 32];
+        let request = |include_attestation| SignDigestRequest {
+            signing_key: None,
+            digest: digest.to_vec(),
+            include_attestation,
+            strict_digest_length: false,
+        };
+
+        let response = service.sign_digest(Request::new(request(false))).await.unwrap();
+        assert!(response.into_inner().attestation.is_none());
+
+        let response = service.sign_digest(Request::new(request(true))).await.unwrap();
+        let attestation = response.into_inner().attestation.unwrap();
+        let public_key =
+            key.signing_keys().pairs[BuiltinSigningKey::ServiceResponse as usize - 1].public_key;
+        assert_eq!(attestation.compressed_public_key, public_key.to_encoded_point(true).as_bytes());
+        let doc = MockSecmod::parse(&attestation.attestation_document).unwrap();
+        use sha2::Digest;
+        let expected = sha2::Sha256::new()
+            .chain_update(digest)
+            .chain_update(&attestation.compressed_public_key)
+            .finalize();
+        let user_data = crate::user_data::UserData::parse(doc.user_data().unwrap()).unwrap();
+        assert!(matches!(user_data, crate::user_data::UserData::Structured(_)));
+        assert!(user_data.check_sha256(PURPOSE_SIGNATURE, &expected).is_ok());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_auth_token() {
+        let auth_token = Some("s3cret".to_string());
+        let service = SignerServiceImpl { auth_token, ..test_service(Default::default()) };
+        let request = |authorization: Option<&str>| {
+            let mut request = Request::new(SignDigestRequest {
+                signing_key: None,
+                digest: vec![0x42; 32],
+                include_attestation: false,
+                strict_digest_length: false,
+            });
+            if let Some(authorization) = authorization {
+                request.metadata_mut().insert("authorization", authorization.parse().unwrap());
+            }
+            request
+        };
+
+        for authorization in [None, Some("s3cret"), Some("Bearer wrong")] {
+            let err = service.sign_digest(request(authorization)).await.unwrap_err();
+            assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        }
+        assert!(service.sign_digest(request(Some("Bearer s3cret"))).await.is_ok());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_load_break_glass_key_access() {
+        let auth_token = Some("s3cret".to_string());
+        let service = SignerServiceImpl { auth_token, ..test_service(Default::default()) };
+        let request = |authorization: Option<&str>| {
+            let mut request =
+                Request::new(LoadBreakGlassKeyRequest { sealed_passphrase: vec![0x42; 100] });
+            if let Some(authorization) = authorization {
+                request.metadata_mut().insert("authorization", authorization.parse().unwrap());
+            }
+            request
+        };
+
+        let err = service.load_break_glass_key(request(None)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        let err = service.load_break_glass_key(request(Some("Bearer s3cret"))).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = service.load_break_glass_key(request(Some("Bearer s3cret"))).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+    }
+
+    #[test]
+    fn test_request_deadline() {
+        use std::time::Duration;
+        let request = |timeout: &str| {
+            let mut request = Request::new(());
+            request.metadata_mut().insert("grpc-timeout", timeout.parse().unwrap());
+            request
+        };
+        assert_eq!(request_deadline(&Request::new(())).unwrap(), None);
+        let before = tokio::time::Instant::now();
+        let deadline = request_deadline(&request("100m")).unwrap().unwrap();
+        assert!(deadline >= before + Duration::from_millis(100));
+        assert!(deadline <= tokio::time::Instant::now() + Duration::from_millis(100));
+        let deadline = request_deadline(&request("2H")).unwrap().unwrap();
+        assert!(deadline >= before + Duration::from_secs(7200));
+        for invalid in ["", "m", "100", "100x", "123456789S", "-1S", "+1S"] {
+            let err = request_deadline(&request(invalid)).unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_signature_budget() {
+        use std::collections::BTreeMap;
+
+        let config = crate::config::SovereignConfig {
+            signature_budgets: Some(BTreeMap::from([(2, 2)])),
+            ..Default::default()
+        };
+        let service = test_service(config);
+        let key = service.key.clone();
+        let request = |key_index: u32| {
+            Request::new(SignDigestRequest {
+                signing_key: Some(SigningKey { key_index, ..Default::default() }),
+                digest: vec![0x42; 32],
+                include_attestation: false,
+                strict_digest_length: false,
+            })
+        };
+        let remaining = || key.metrics.signature_budget_remaining.with_label_values(&["2"]).get();
+
+        assert_eq!(remaining(), 2);
+        // Invalid requests are rejected before the budget is spent.
+        let invalid = SignDigestRequest { digest: vec![], ..request(2).into_inner() };
+        let err = service.sign_digest(Request::new(invalid)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        let invalid = SignSchnorrRequest {
+            signing_key: Some(SigningKey { key_index: 2, ..Default::default() }),
+            message: vec![0x42; 31],
+        };
+        let err = service.sign_schnorr(Request::new(invalid)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        // So are requests whose deadline has passed.
+        let mut expired = request(2);
+        expired.metadata_mut().insert("grpc-timeout", "0n".parse().unwrap());
+        let err = service.sign_digest(expired).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
+        assert_eq!(remaining(), 2);
+        for _ in 0..2 {
+            assert!(service.sign_digest(request(2)).await.is_ok());
+        }
+        assert_eq!(remaining(), 0);
+        let err = service.sign_digest(request(2)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        // Keys without a budget are unlimited.
+        for _ in 0..3 {
+            assert!(service.sign_digest(request(1)).await.is_ok());
+        }
+
+        let reset = Request::new(ResetKeyBudgetRequest { key_index: 2 });
+        assert_eq!(service.reset_key_budget(reset).await.unwrap().into_inner().remaining, 2);
+        assert_eq!(remaining(), 2);
+        assert!(service.sign_digest(request(2)).await.is_ok());
+        assert_eq!(key.signature_budget(2), Some(1));
+        let reset = Request::new(ResetKeyBudgetRequest { key_index: 1 });
+        assert!(service.reset_key_budget(reset).await.is_err());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_signing_key_by_ethereum_address() {
+        let service = test_service(Default::default());
+        let key = service.key.clone();
+        let by_address =
+            |ethereum_address: Vec<u8>| SigningKey { ethereum_address, ..Default::default() };
+
+        let keys = key.signing_keys();
+        let pairs = &keys.pairs;
+        let address = pairs[2].ethereum_address();
+        let (key_index, pair) =
+            service.signing_key(by_address(address.to_vec()), BuiltinSigningKey::Ethereum).unwrap();
+        assert_eq!(key_index, 3);
+        assert_eq!(pair.public_key, pairs[2].public_key);
+
+        let err = service
+            .signing_key(by_address(vec![0x42; 20]), BuiltinSigningKey::Ethereum)
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let err = service
+            .signing_key(by_address(vec![0x42; 19]), BuiltinSigningKey::Ethereum)
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        let both = SigningKey { key_index: 3, ..by_address(address.to_vec()) };
+        let err = service.signing_key(both, BuiltinSigningKey::Ethereum).err().unwrap();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_audit_transaction_signing_hash() {
+        let service = test_service(Default::default());
+        let tx_data = create_test_transaction(Some(1));
+        let request =
+            SignEthereumTransactionRequest { signing_key: None, tx_data: tx_data.clone() };
+        let response = service.handle_sign_ethereum_transaction(request, None).await.unwrap();
+        let audit_log = service.key.audit_log.lock().unwrap();
+        let entry = audit_log.last().unwrap();
+        assert_eq!(entry.operation, "sign_ethereum_transaction");
+        // The signing hash of the unsigned transaction, not the hash of the signed one.
+        let signing_hash = SignerServiceImpl::<crate::mock_secmod::MockSecmod>::hash_message(
+            &tx_data,
+            HashFunction::Keccak256,
+        )
+        .unwrap();
+        assert_eq!(entry.digest, signing_hash);
+        assert_ne!(entry.digest[..], response.into_inner().tx_hash);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_set_log_level_replay() {
+        let config = crate::config::SovereignConfig {
+            governance: crate::config::Governance::TestingOnly,
+            ..Default::default()
+        };
+        // The handle only works while its layer is alive.
+        let (_layer, log_level) =
+            tracing_subscriber::reload::Layer::new(tracing_subscriber::filter::LevelFilter::INFO);
+        let service = SignerServiceImpl { log_level, ..test_service(config) };
+        let now =
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let request = |expires_at| Request::new(SetLogLevelRequest { trace_level: 1, expires_at });
+
+        let err = service.set_log_level(request(now - 1)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        let too_late = now + MAX_LOG_LEVEL_AUTHORIZATION_TTL.as_secs() + 60;
+        let err = service.set_log_level(request(too_late)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        assert!(service.set_log_level(request(now + 60)).await.is_ok());
+        // The same authorization, or an earlier one, cannot be replayed.
+        let err = service.set_log_level(request(now + 60)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = service.set_log_level(request(now + 30)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        assert!(service.set_log_level(request(now + 120)).await.is_ok());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_signing_key_by_ethereum_address_after_rotation() {
+        let config = crate::config::SovereignConfig {
+            governance: crate::config::Governance::TestingOnly,
+            ..Default::default()
+        };
+        let service = test_service(config);
+        let by_address =
+            |ethereum_address: Vec<u8>| SigningKey { ethereum_address, ..Default::default() };
+        let old_address = service.key.signing_keys().pairs[2].ethereum_address();
+
+        service.key.rotate_keys().await.unwrap();
+        let err = service
+            .signing_key(by_address(old_address.to_vec()), BuiltinSigningKey::Ethereum)
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let keys = service.key.signing_keys();
+        let address = keys.pairs[2].ethereum_address();
+        let (key_index, pair) =
+            service.signing_key(by_address(address.to_vec()), BuiltinSigningKey::Ethereum).unwrap();
+        assert_eq!(key_index, 3);
+        assert_eq!(pair.public_key, keys.pairs[2].public_key);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_get_certificate() {
+        let service = test_service(Default::default());
+        let key = service.key.clone();
+
+        let response = service
+            .get_certificate(Request::new(GetCertificateRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.certificate, key.cert.certificate_der().to_vec());
+        let parsed = pem::parse(&response.certificate_pem).unwrap();
+        assert_eq!(parsed.tag(), "CERTIFICATE");
+        assert_eq!(parsed.contents(), response.certificate);
+        assert_eq!(response.public_key, key.cert_public_key_der);
+        // The certificate is issued for the measured public key.
+        assert!(response
+            .certificate
+            .windows(response.public_key.len())
+            .any(|w| w == response.public_key));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_self_test() {
+        use std::collections::BTreeMap;
+
+        let config = crate::config::SovereignConfig {
+            signature_budgets: Some(BTreeMap::from([(1, 1)])),
+            ..Default::default()
+        };
+        let service = test_service(config);
+        let key = service.key.clone();
+
+        for _ in 0..2 {
+            let response =
+                service.self_test(Request::new(SelfTestRequest {})).await.unwrap().into_inner();
+            assert!(response.healthy);
+            let keys = key.signing_keys();
+            let pairs = &keys.pairs;
+            assert_eq!(response.keys.len(), pairs.len());
+            for (health, (pair, key_index)) in response.keys.iter().zip(pairs.iter().zip(1..)) {
+                assert_eq!(health.key_index, key_index);
+                assert!(health.healthy);
+                assert!(health.error.is_empty());
+                assert_eq!(health.ethereum_address, hex::encode(pair.ethereum_address()));
+            }
+        }
+        // Neither budgets nor the audit log are affected.
+        assert_eq!(key.signature_budget(1), Some(1));
+        assert_eq!(key.audit_log.lock().unwrap().head().1, 0);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_sign_p256() {
+        use p256::ecdsa::signature::hazmat::PrehashVerifier;
+        use p256::pkcs8::DecodePublicKey;
+        use std::collections::BTreeMap;
+
+        let config = crate::config::SovereignConfig {
+            signature_budgets: Some(BTreeMap::from([(key_server::P256_KEY_INDEX, 1)])),
+            ..Default::default()
+        };
+        let service = test_service(config);
+        let key = service.key.clone();
+
+        let public_key = service
+            .get_p256_public_key(Request::new(GetP256PublicKeyRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(public_key.compressed_public_key.len(), 33);
+        assert_eq!(public_key.uncompressed_public_key.len(), 65);
+        let verifying_key =
+            p256::ecdsa::VerifyingKey::from_public_key_der(&public_key.public_key_der).unwrap();
+        assert_eq!(
+            verifying_key,
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(&public_key.compressed_public_key).unwrap()
+        );
+        // Not the certificate key.
+        assert_ne!(public_key.public_key_der, key.cert_public_key_der);
+
+        let digest = [0x42; 32];
+        let request = SignP256Request { digest: digest.to_vec(), include_attestation: true };
+        let response = service.sign_p256(Request::new(request)).await.unwrap().into_inner();
+        let signature = p256::ecdsa::Signature::from_slice(&response.signature).unwrap();
+        assert!(signature.normalize_s().is_none());
+        verifying_key.verify_prehash(&digest, &signature).unwrap();
+        assert_eq!(p256::ecdsa::Signature::from_der(&response.signature_der).unwrap(), signature);
+        let attestation = response.attestation.unwrap();
+        assert_eq!(attestation.compressed_public_key, public_key.compressed_public_key);
+        assert_eq!(key.audit_log.lock().unwrap().head().1, 1);
+
+        let request = SignP256Request { digest: vec![0x42; 31], include_attestation: false };
+        let status = service.sign_p256(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        // The P-256 key is budgeted under key index 0.
+        let request = SignP256Request { digest: digest.to_vec(), include_attestation: false };
+        let status = service.sign_p256(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    }
 }
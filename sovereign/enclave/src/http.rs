@@ -1,8 +1,9 @@
 //! This module contains helpful utility functions for dealing with HTTP(s) requests and responses.
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use http_body_util::Full;
 use hyper::{body::Bytes, body::Incoming, Request, Response, Uri};
+use std::time::Duration;
 
 use crate::secmod::Secmod;
 
@@ -36,19 +37,45 @@ pub fn get_query_param<'a>(query: Option<&'a str>, param: &str) -> Option<&'a st
         .and_then(|p| p.split('=').nth(1))
 }
 
+/// Label of the PEM block returned for `encoding=pem`.
+pub const PEM_LABEL: &str = "ATTESTATION DOCUMENT";
+
+/// Encode `data` as requested by the `encoding` query parameter:
+/// `binary`, `hex`, `base64url` (without padding), `pem`, or `base64` (the default).
+/// Returns the encoded data and its content type.
+fn encode(data: Vec<u8>, encoding: &str) -> (Vec<u8>, &'static str) {
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    match encoding {
+        "binary" => (data, "application/octet-stream"),
+        "hex" => (hex::encode(data).into_bytes(), "text/plain"),
+        "base64url" => (base64::Engine::encode(&URL_SAFE_NO_PAD, data).into_bytes(), "text/plain"),
+        "pem" => {
+            let config = pem::EncodeConfig::new().set_line_ending(pem::LineEnding::LF);
+            let block = pem::Pem::new(PEM_LABEL, data);
+            (pem::encode_config(&block, config).into_bytes(), "text/plain")
+        }
+        _ => (base64::Engine::encode(&STANDARD, data).into_bytes(), "text/plain"),
+    }
+}
+
+/// PEM-encode the certificate chain of an attestation document:
+/// the leaf `certificate` first, followed by the `cabundle` from the closest issuer to the root.
+pub fn certificate_chain_pem(certificate: &[u8], cabundle: &[serde_bytes::ByteBuf]) -> String {
+    let config = pem::EncodeConfig::new().set_line_ending(pem::LineEnding::LF);
+    let blocks: Vec<pem::Pem> = std::iter::once(certificate)
+        .chain(cabundle.iter().rev().map(|cert| cert.as_slice()))
+        .filter(|cert| !cert.is_empty())
+        .map(|cert| pem::Pem::new("CERTIFICATE", cert))
+        .collect();
+    pem::encode_many_config(&blocks, config)
+}
+
 pub fn encode_with_encoding(
     data: Vec<u8>,
     uri: &Uri,
 ) -> Result<Response<Full<hyper::body::Bytes>>> {
     let encoding = get_query_param(uri.query(), "encoding").unwrap_or("base64");
-    let (encoded, encoding) = match encoding {
-        "binary" => (data, "application/octet-stream"),
-        "hex" => (hex::encode(data).into_bytes(), "text/plain"),
-        _ => (
-            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, data).into_bytes(),
-            "text/plain",
-        ),
-    };
+    let (encoded, encoding) = encode(data, encoding);
     Ok(Response::builder().header(hyper::header::CONTENT_TYPE, encoding).body(full(encoded))?)
 }
 
@@ -56,9 +83,145 @@ pub fn full<T: Into<Bytes>>(chunk: T) -> Full<Bytes> {
     Full::new(chunk.into())
 }
 
+/// Default limit on the time for an outbound request, see `make_request`.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default number of consecutive failed requests to an authority that open its circuit.
+pub const DEFAULT_CIRCUIT_BREAKER_FAILURES: u32 = 5;
+
+/// Default time for which an open circuit fails requests fast.
+pub const DEFAULT_CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    /// Whether the circuit of each authority is open (1) or closed (0), see `CircuitBreakers`.
+    pub static ref HOST_CIRCUIT_OPEN: prometheus::IntGaugeVec = prometheus::IntGaugeVec::new(
+        prometheus::Opts::new(
+            "host_circuit_open",
+            "whether outbound requests to an authority fail fast after consecutive failures",
+        ),
+        &["authority"],
+    )
+    .expect("metric can be created");
+}
+
+/// The circuit breakers applied by `make_request`. Outbound requests are made by associated
+/// functions without state (e.g., for governance), so the circuits are process-wide.
+static CIRCUIT_BREAKERS: std::sync::OnceLock<CircuitBreakers> = std::sync::OnceLock::new();
+
+/// Configure the circuit breakers of `make_request`; fails if they are already configured (or
+/// in use) differently.
+pub fn configure_circuit_breakers(failures: u32, cooldown: Duration) -> Result<()> {
+    let current = CIRCUIT_BREAKERS.get_or_init(|| CircuitBreakers::new(failures, cooldown));
+    if (current.failures, current.cooldown) != (failures, cooldown) {
+        bail!("circuit breakers are already configured differently");
+    }
+    Ok(())
+}
+
+fn circuit_breakers() -> &'static CircuitBreakers {
+    CIRCUIT_BREAKERS.get_or_init(|| {
+        CircuitBreakers::new(DEFAULT_CIRCUIT_BREAKER_FAILURES, DEFAULT_CIRCUIT_BREAKER_COOLDOWN)
+    })
+}
+
+/// Circuit breakers for outbound requests, keyed by target authority, so that a dependency
+/// that is down does not make every caller wait for its connect and handshake timeouts.
+///
+/// After `failures` consecutive failed requests to an authority, its circuit opens and
+/// requests to it fail fast for `cooldown`. The first request after that probes the authority:
+/// if it succeeds, the circuit closes; meanwhile and if it fails, requests fail fast for
+/// another `cooldown`.
+pub struct CircuitBreakers {
+    failures: u32,
+    cooldown: Duration,
+    circuits: std::sync::Mutex<std::collections::HashMap<String, Circuit>>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    /// Requests fail fast until then, if set.
+    open_until: Option<std::time::Instant>,
+}
+
+impl CircuitBreakers {
+    pub fn new(failures: u32, cooldown: Duration) -> Self {
+        Self { failures, cooldown, circuits: Default::default() }
+    }
+
+    /// Fail if the circuit of `authority` is open; otherwise, the request may proceed.
+    pub fn check(&self, authority: &str) -> Result<()> {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(circuit) = circuits.get_mut(authority) else {
+            return Ok(());
+        };
+        let Some(open_until) = circuit.open_until else {
+            return Ok(());
+        };
+        let now = std::time::Instant::now();
+        if now < open_until {
+            bail!(
+                "circuit open for {} after {} consecutive failures; failing fast for {:?}",
+                authority,
+                circuit.consecutive_failures,
+                open_until - now
+            );
+        }
+        // This request probes; others fail fast until its outcome is known (or for another
+        // cooldown, in case it is abandoned).
+        tracing::info!("probing {} after circuit cooldown", authority);
+        circuit.open_until = Some(now + self.cooldown);
+        Ok(())
+    }
+
+    /// Record the outcome of a request to `authority` that `check` allowed.
+    pub fn record(&self, authority: &str, success: bool) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        if success {
+            if circuits.remove(authority).is_some_and(|c| c.open_until.is_some()) {
+                tracing::info!("circuit closed for {}", authority);
+                HOST_CIRCUIT_OPEN.with_label_values(&[authority]).set(0);
+            }
+            return;
+        }
+        let circuit = circuits.entry(authority.to_string()).or_default();
+        circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+        if circuit.consecutive_failures >= self.failures {
+            if circuit.open_until.is_none() {
+                tracing::warn!(
+                    "circuit opened for {} after {} consecutive failures",
+                    authority,
+                    circuit.consecutive_failures
+                );
+                HOST_CIRCUIT_OPEN.with_label_values(&[authority]).set(1);
+            }
+            circuit.open_until = Some(std::time::Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// Send `request` to the host through `out_port`, failing if connecting, the handshakes,
+/// and receiving the response headers together take longer than `timeout`, and failing fast
+/// while the circuit of the target authority is open (see `CircuitBreakers`).
 pub async fn make_request<SM: Secmod + 'static>(
     out_port: u32,
     request: Request<Full<Bytes>>,
+    timeout: Duration,
+) -> Result<Response<Incoming>> {
+    let authority = request.uri().authority().map(|x| x.to_string()).unwrap_or_default();
+    let breakers = circuit_breakers();
+    breakers.check(&authority)?;
+    let result = tokio::time::timeout(timeout, make_request_inner::<SM>(out_port, request))
+        .await
+        .map_err(|_| anyhow!("outbound request to {} timed out after {:?}", authority, timeout))
+        .and_then(|result| result);
+    breakers.record(&authority, result.is_ok());
+    result
+}
+
+async fn make_request_inner<SM: Secmod + 'static>(
+    out_port: u32,
+    request: Request<Full<Bytes>>,
 ) -> Result<Response<Incoming>> {
     let uri = request.uri().clone();
     let scheme = uri.scheme_str().context("missing scheme")?;
@@ -145,29 +308,248 @@ pub fn error_response(
         .unwrap()
 }
 
+/// Bounds on how long a client can hold on to an HTTP/1.1 keep-alive connection.
+#[derive(Clone, Copy, Debug)]
+pub struct KeepAliveLimits {
+    /// Requests served on one connection; the response to the last one closes it.
+    pub max_requests: u64,
+    /// Time allowed for the headers of the next request to arrive, starting when the connection
+    /// is accepted or the previous response has been sent; the connection is closed afterwards.
+    pub timeout: Duration,
+}
+
+impl KeepAliveLimits {
+    /// A connection builder enforcing `timeout`.
+    pub fn builder(&self) -> hyper::server::conn::http1::Builder {
+        let mut builder = hyper::server::conn::http1::Builder::new();
+        builder.timer(hyper_util::rt::TokioTimer::new()).header_read_timeout(self.timeout);
+        builder
+    }
+
+    /// Counts the requests served on a new connection, see `RequestCounter`.
+    pub fn counter(&self) -> std::sync::Arc<RequestCounter> {
+        std::sync::Arc::new(RequestCounter {
+            served: std::sync::atomic::AtomicU64::new(0),
+            max_requests: self.max_requests,
+        })
+    }
+}
+
+/// Enforces `KeepAliveLimits::max_requests` on a connection.
+pub struct RequestCounter {
+    served: std::sync::atomic::AtomicU64,
+    max_requests: u64,
+}
+
+impl RequestCounter {
+    /// Count `response`, marking it `Connection: close` if it is the last one allowed on the
+    /// connection; hyper closes the connection once it has been sent.
+    /// The upgrade response of a WebSocket session is counted but never marked; the session
+    /// counts its own requests, see `count`.
+    pub fn track<B>(&self, response: &mut Response<B>) {
+        if self.count() && response.status() != hyper::StatusCode::SWITCHING_PROTOCOLS {
+            response.headers_mut().insert(
+                hyper::header::CONNECTION,
+                hyper::header::HeaderValue::from_static("close"),
+            );
+        }
+    }
+
+    /// Count one request; true if it is the last one allowed on the connection.
+    pub fn count(&self) -> bool {
+        let served = self.served.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+        served >= self.max_requests
+    }
+}
+
+/// Per-connection state handed to request handlers through the request extensions.
+///
+/// Work that outlives its request, such as a WebSocket session after an upgrade, is spawned
+/// here rather than with `tokio::spawn`: the connection future waits for it, so it stays under
+/// the connection's idle timeout and request limit and is aborted when the connection is dropped.
+#[derive(Clone)]
+pub struct ConnectionTasks {
+    tasks: std::sync::Arc<std::sync::Mutex<tokio::task::JoinSet<()>>>,
+    counter: std::sync::Arc<RequestCounter>,
+}
+
+impl ConnectionTasks {
+    pub fn new(counter: std::sync::Arc<RequestCounter>) -> Self {
+        ConnectionTasks { tasks: Default::default(), counter }
+    }
+
+    /// The request counter of the connection. Spawned tasks must hold on to this rather than to
+    /// `self`, which would keep them from being aborted with the connection.
+    pub fn counter(&self) -> std::sync::Arc<RequestCounter> {
+        self.counter.clone()
+    }
+
+    pub fn spawn<F: std::future::Future<Output = ()> + Send + 'static>(&self, task: F) {
+        self.tasks.lock().unwrap().spawn(task);
+    }
+
+    /// Wait for all spawned tasks; called once the HTTP connection itself has finished.
+    pub async fn join(&self) {
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
 pub async fn serve_http_connection<SM: Secmod, T, F, Fut>(
     io: hyper_util::rt::TokioIo<T>,
+    limits: KeepAliveLimits,
     service: F,
 ) -> Result<()>
 where
-    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
     F: Fn(hyper::Request<hyper::body::Incoming>) -> Fut + Clone + Send + 'static,
     Fut: std::future::Future<
             Output = Result<hyper::Response<http_body_util::Full<hyper::body::Bytes>>>,
         > + Send,
 {
     // Connection builder.
-    let builder = hyper::server::conn::http1::Builder::new();
-    let service_fn = |x| async {
-        let ok = match service(x).await {
+    let builder = limits.builder();
+    let counter = limits.counter();
+    let tasks = ConnectionTasks::new(counter.clone());
+    let service_fn = |mut x: hyper::Request<Incoming>| async {
+        x.extensions_mut().insert(tasks.clone());
+        let mut ok = match service(x).await {
             Ok(response) => response,
             Err(err) => {
                 tracing::error!("request processing error: {}", err);
                 error_response(hyper::StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
             }
         };
+        counter.track(&mut ok);
         Ok::<_, hyper::Error>(ok)
     };
-    builder.serve_connection(io, hyper::service::service_fn(service_fn)).await?;
+    builder.serve_connection(io, hyper::service::service_fn(service_fn)).with_upgrades().await?;
+    tasks.join().await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    use base64::Engine;
+
+    /// Includes bytes that map to `+` and `/` in standard base64 and needs padding.
+    fn test_data() -> Vec<u8> {
+        vec![0xfb, 0xff, 0xbf, 0x00, 0x01]
+    }
+
+    #[test]
+    fn test_encode_binary_and_hex() {
+        assert_eq!(encode(test_data(), "binary"), (test_data(), "application/octet-stream"));
+        let (encoded, _) = encode(test_data(), "hex");
+        assert_eq!(hex::decode(encoded).unwrap(), test_data());
+    }
+
+    #[test]
+    fn test_encode_base64() {
+        let (encoded, _) = encode(test_data(), "base64");
+        assert_eq!(String::from_utf8(encoded.clone()).unwrap(), "+/+/AAE=");
+        assert_eq!(STANDARD.decode(encoded).unwrap(), test_data());
+        // Unknown encodings fall back to base64.
+        assert_eq!(encode(test_data(), "unknown").0, encode(test_data(), "base64").0);
+    }
+
+    #[test]
+    fn test_encode_base64url() {
+        let (encoded, _) = encode(test_data(), "base64url");
+        assert_eq!(String::from_utf8(encoded.clone()).unwrap(), "-_-_AAE");
+        assert_eq!(URL_SAFE_NO_PAD.decode(encoded).unwrap(), test_data());
+    }
+
+    #[test]
+    fn test_encode_pem() {
+        // Long enough to need several lines.
+        let data: Vec<u8> = (0..=255).collect();
+        let (encoded, _) = encode(data.clone(), "pem");
+        let encoded = String::from_utf8(encoded).unwrap();
+        assert!(encoded.starts_with("-----BEGIN ATTESTATION DOCUMENT-----\n"));
+        assert!(encoded.ends_with("-----END ATTESTATION DOCUMENT-----\n"));
+        assert!(encoded.lines().all(|line| line.len() <= 64));
+        let parsed = pem::parse(&encoded).unwrap();
+        assert_eq!(parsed.tag(), PEM_LABEL);
+        assert_eq!(parsed.contents(), data);
+    }
+
+    #[test]
+    fn test_request_counter() {
+        let limits = KeepAliveLimits { max_requests: 2, timeout: Duration::from_secs(1) };
+        let counter = limits.counter();
+        let closes = |response: &Response<Full<Bytes>>| {
+            response.headers().get(hyper::header::CONNECTION).is_some_and(|v| v == "close")
+        };
+        let mut first = Response::new(full("first"));
+        counter.track(&mut first);
+        assert!(!closes(&first));
+        let mut second = Response::new(full("second"));
+        counter.track(&mut second);
+        assert!(closes(&second));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_keep_alive_timeout() {
+        // A client that connects but never sends a request is disconnected.
+        let limits = KeepAliveLimits { max_requests: 10, timeout: Duration::from_millis(100) };
+        let (stream, _client) = tokio::io::duplex(1024);
+        let io = hyper_util::rt::TokioIo::new(stream);
+        let served = serve_http_connection::<crate::mock_secmod::MockSecmod, _, _, _>(
+            io,
+            limits,
+            |_| async { Ok(Response::new(full("unreachable"))) },
+        );
+        assert!(tokio::time::timeout(Duration::from_secs(5), served).await.is_ok());
+    }
+
+    #[test]
+    fn test_circuit_breakers() {
+        let breakers = CircuitBreakers::new(2, Duration::from_millis(50));
+        let authority = "safe.example:443";
+        let open = || HOST_CIRCUIT_OPEN.with_label_values(&[authority]).get();
+        assert!(breakers.check(authority).is_ok());
+        breakers.record(authority, false);
+        // A success resets the count of consecutive failures.
+        breakers.record(authority, true);
+        breakers.record(authority, false);
+        assert!(breakers.check(authority).is_ok());
+        breakers.record(authority, false);
+        assert_eq!(open(), 1);
+        let err = breakers.check(authority).unwrap_err();
+        assert!(err.to_string().starts_with("circuit open for safe.example:443"));
+        // Other authorities are not affected.
+        assert!(breakers.check("other.example:443").is_ok());
+
+        // After the cooldown, one request probes; a failure keeps the circuit open.
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breakers.check(authority).is_ok());
+        assert!(breakers.check(authority).is_err());
+        breakers.record(authority, false);
+        assert!(breakers.check(authority).is_err());
+        // A successful probe closes it.
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breakers.check(authority).is_ok());
+        breakers.record(authority, true);
+        assert_eq!(open(), 0);
+        assert!(breakers.check(authority).is_ok());
+        assert!(breakers.check(authority).is_ok());
+    }
+
+    #[test]
+    fn test_certificate_chain_pem() {
+        use serde_bytes::ByteBuf;
+        let root = ByteBuf::from(vec![1, 1, 1]);
+        let intermediate = ByteBuf::from(vec![2, 2, 2]);
+        let chain = certificate_chain_pem(&[3, 3, 3], &[root, intermediate]);
+        let blocks = pem::parse_many(&chain).unwrap();
+        assert!(blocks.iter().all(|block| block.tag() == "CERTIFICATE"));
+        let contents: Vec<&[u8]> = blocks.iter().map(|block| block.contents()).collect();
+        assert_eq!(contents, [[3, 3, 3], [2, 2, 2], [1, 1, 1]]);
+        // Documents without certificates (e.g., mock attestations) yield no blocks.
+        assert_eq!(certificate_chain_pem(&[], &[]), "");
+    }
+}
@@ -0,0 +1,140 @@
+//! Closing connections on which no bytes are read or written for a while.
+//!
+//! Accepted streams are wrapped in an `IdleTimeoutStream`, which records the time of the last
+//! successful read or write, and their handlers are run by `with_idle_timeout`, which drops the
+//! handler (and thereby closes the stream) once the stream has been idle for the timeout.
+
+use anyhow::{bail, Result};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Instant;
+
+/// The time of the last read or write on an `IdleTimeoutStream`.
+#[derive(Clone)]
+pub struct Activity(Arc<Mutex<Instant>>);
+
+impl Activity {
+    fn touch(&self) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn last(&self) -> Instant {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A stream that records its activity, see the module documentation.
+pub struct IdleTimeoutStream<S> {
+    inner: S,
+    activity: Activity,
+}
+
+impl<S> IdleTimeoutStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, activity: Activity(Arc::new(Mutex::new(Instant::now()))) }
+    }
+
+    pub fn activity(&self) -> Activity {
+        self.activity.clone()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for IdleTimeoutStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(()))) && buf.filled().len() > filled {
+            self.activity.touch();
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for IdleTimeoutStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(n)) if n > 0) {
+            self.activity.touch();
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Run `handler` until it completes or until `activity` shows no progress for `timeout`.
+pub async fn with_idle_timeout<F>(handler: F, activity: Activity, timeout: Duration) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    tokio::pin!(handler);
+    loop {
+        let deadline = activity.last() + timeout;
+        tokio::select! {
+            result = &mut handler => return result,
+            _ = tokio::time::sleep_until(deadline) => {
+                if activity.last().elapsed() >= timeout {
+                    bail!("connection idle for {:?}; closing", timeout);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let timeout = Duration::from_millis(100);
+
+        // A peer that never sends anything is disconnected.
+        let (stream, _peer) = tokio::io::duplex(64);
+        let mut stream = IdleTimeoutStream::new(stream);
+        let activity = stream.activity();
+        let handler = async move {
+            stream.read_u8().await?;
+            Ok(())
+        };
+        let err = with_idle_timeout(handler, activity, timeout).await.unwrap_err();
+        assert!(err.to_string().contains("idle"));
+
+        // A peer that keeps sending is served for longer than the timeout.
+        let (stream, mut peer) = tokio::io::duplex(64);
+        let mut stream = IdleTimeoutStream::new(stream);
+        let activity = stream.activity();
+        tokio::spawn(async move {
+            for i in 0..10u8 {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                peer.write_u8(i).await.unwrap();
+            }
+        });
+        let handler = async move {
+            for i in 0..10u8 {
+                assert_eq!(stream.read_u8().await?, i);
+            }
+            Ok(())
+        };
+        assert!(with_idle_timeout(handler, activity, timeout).await.is_ok());
+    }
+}
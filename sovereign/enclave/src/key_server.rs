@@ -1,30 +1,180 @@
 use crate::config::SovereignConfig;
+use crate::nonce_cache::{NonceCache, CHALLENGE_CACHE_CAPACITY, CHALLENGE_TTL};
 use crate::secmod::Secmod;
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use elliptic_curve::rand_core::{self};
+#[cfg(feature = "signing")]
 use k256::ecdsa;
 use k256::elliptic_curve::generic_array::typenum::Unsigned;
-use std::sync::Arc;
+use nsm_attestation::clock::{Clock, SystemClock};
+#[cfg(feature = "signing")]
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
+/// The secret keys of the pool, as transferred by key-sync. Wiped when dropped.
 #[derive(PartialEq, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct SecretKeyMaterial {
     pub cert_secret_key: [u8; <p256::NistP256 as elliptic_curve::Curve>::FieldBytesSize::USIZE],
     pub secret_keys: Vec<[u8; <k256::Secp256k1 as elliptic_curve::Curve>::FieldBytesSize::USIZE]>,
 }
 
+impl Zeroize for SecretKeyMaterial {
+    fn zeroize(&mut self) {
+        self.cert_secret_key.zeroize();
+        self.secret_keys.zeroize();
+    }
+}
+
+impl Drop for SecretKeyMaterial {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SecretKeyMaterial {}
+
+/// Version of the binary encoding of `SecretKeyMaterial`, see `SecretKeyMaterial::to_bytes`.
+#[cfg(feature = "signing")]
+const SECRET_KEY_MATERIAL_VERSION: u8 = 1;
+
+/// Size of a secp256k1 or P-256 secret key.
+#[cfg(feature = "signing")]
+const SECRET_KEY_BYTES: usize = 32;
+
+/// Number of random bytes drawn by `rng_self_test` (20000 bits, as in FIPS 140-2).
+const RNG_SELF_TEST_BYTES: usize = 2500;
+
+/// Number of times `rng_self_test` runs its checks before giving up. A healthy source fails
+/// the FIPS 140-2 tests about once in 10^4 draws; a broken one fails every time.
+const RNG_SELF_TEST_ATTEMPTS: usize = 3;
+
+/// Basic health checks on `rng`, to catch a catastrophically broken random source.
+///
+/// Draws two blocks of 20000 bits and checks that they differ and that the first one
+/// passes the FIPS 140-2 monobit and long run tests, retrying failed checks with fresh
+/// blocks up to `RNG_SELF_TEST_ATTEMPTS` times. This is not an entropy estimate.
+pub fn rng_self_test<T: rand_core::RngCore>(rng: &mut T) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        match rng_self_test_once(rng) {
+            Err(e) if attempt < RNG_SELF_TEST_ATTEMPTS => {
+                tracing::warn!("{:#}; retrying", e);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn rng_self_test_once<T: rand_core::RngCore>(rng: &mut T) -> Result<()> {
+    let mut block = [0u8; RNG_SELF_TEST_BYTES];
+    let mut next_block = [0u8; RNG_SELF_TEST_BYTES];
+    rng.try_fill_bytes(&mut block)?;
+    rng.try_fill_bytes(&mut next_block)?;
+    if block == next_block {
+        bail!("RNG self-test failed: repeated output");
+    }
+    let ones: u32 = block.iter().map(|b| b.count_ones()).sum();
+    if !(9726..=10274).contains(&ones) {
+        bail!("RNG self-test failed: monobit test ({} of 20000 bits set)", ones);
+    }
+    let mut longest_run = 0;
+    let mut run = 0;
+    let mut previous = None;
+    for bit in block.iter().flat_map(|b| (0..8).map(move |i| (b >> i) & 1)) {
+        run = if previous == Some(bit) { run + 1 } else { 1 };
+        longest_run = longest_run.max(run);
+        previous = Some(bit);
+    }
+    if longest_run >= 26 {
+        bail!("RNG self-test failed: long run test (run of {} bits)", longest_run);
+    }
+    tracing::info!(
+        "RNG self-test passed ({} of 20000 bits set, longest run {})",
+        ones,
+        longest_run
+    );
+    Ok(())
+}
+
 impl SecretKeyMaterial {
+    /// Generate new key material, after checking that `rng` passes `rng_self_test`.
     pub fn generate_random<T>(num_keys: u32, rng: &mut T) -> Result<Self>
     where
         T: rand_core::RngCore,
         T: rand_core::CryptoRng,
     {
+        rng_self_test(rng)?;
         let mut result = SecretKeyMaterial::default();
         rng.try_fill_bytes(&mut result.cert_secret_key)?;
+        // Reserve up front, so that growing the vector leaves no copies of keys behind.
+        result.secret_keys.reserve_exact(num_keys as usize);
         for _ in 0..num_keys {
             let mut tmp: [u8; <k256::Secp256k1 as elliptic_curve::Curve>::FieldBytesSize::USIZE] =
                 [0; <k256::Secp256k1 as elliptic_curve::Curve>::FieldBytesSize::USIZE];
-            rng.try_fill_bytes(&mut tmp)?;
-            result.secret_keys.push(tmp);
+            let filled = rng.try_fill_bytes(&mut tmp);
+            if filled.is_ok() {
+                result.secret_keys.push(tmp);
+            }
+            tmp.zeroize();
+            filled?;
+        }
+        Ok(result)
+    }
+
+    /// Encode as transferred by key-sync: a version byte, the certificate secret key prefixed
+    /// with its length (u16, big-endian), the number of signing keys (u16, big-endian),
+    /// and the 32 byte signing keys.
+    #[cfg(feature = "signing")]
+    pub fn to_bytes(&self) -> Result<Zeroizing<Vec<u8>>> {
+        let count = u16::try_from(self.secret_keys.len()).context("too many secret keys")?;
+        let mut bytes = Zeroizing::new(Vec::with_capacity(
+            1 + 2 + SECRET_KEY_BYTES + 2 + self.secret_keys.len() * SECRET_KEY_BYTES,
+        ));
+        bytes.push(SECRET_KEY_MATERIAL_VERSION);
+        bytes.extend_from_slice(&(self.cert_secret_key.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.cert_secret_key);
+        bytes.extend_from_slice(&count.to_be_bytes());
+        for secret_key in &self.secret_keys {
+            bytes.extend_from_slice(secret_key);
+        }
+        Ok(bytes)
+    }
+
+    /// Decode the encoding of `to_bytes`.
+    #[cfg(feature = "signing")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+            if bytes.len() < n {
+                bail!("secret key material truncated");
+            }
+            let (head, tail) = bytes.split_at(n);
+            *bytes = tail;
+            Ok(head)
+        }
+        fn take_u16(bytes: &mut &[u8]) -> Result<usize> {
+            Ok(u16::from_be_bytes(take(bytes, 2)?.try_into()?) as usize)
+        }
+
+        let mut bytes = bytes;
+        let version = take(&mut bytes, 1)?[0];
+        if version != SECRET_KEY_MATERIAL_VERSION {
+            bail!("unsupported secret key material version {}", version);
+        }
+        let mut result = SecretKeyMaterial::default();
+        let cert_secret_key_len = take_u16(&mut bytes)?;
+        if cert_secret_key_len != result.cert_secret_key.len() {
+            bail!("invalid certificate secret key length {}", cert_secret_key_len);
+        }
+        result.cert_secret_key.copy_from_slice(take(&mut bytes, cert_secret_key_len)?);
+        let count = take_u16(&mut bytes)?;
+        if bytes.len() != count * SECRET_KEY_BYTES {
+            bail!("secret key material has {} bytes for {} keys", bytes.len(), count);
+        }
+        result.secret_keys.reserve_exact(count);
+        for secret_key in bytes.chunks_exact(SECRET_KEY_BYTES) {
+            result.secret_keys.push(secret_key.try_into()?);
         }
         Ok(result)
     }
@@ -32,11 +182,82 @@ impl SecretKeyMaterial {
 
 #[derive(Clone)]
 pub struct SecretPubKeyPair {
+    #[cfg(feature = "signing")]
     pub secret_key: k256::SecretKey,
     pub public_key: k256::PublicKey,
+    #[cfg(feature = "signing")]
     pub ecdsa_signing_key: ecdsa::SigningKey,
 }
 
+// `k256::SecretKey` and `ecdsa::SigningKey` wipe themselves when dropped.
+impl ZeroizeOnDrop for SecretPubKeyPair {}
+
+/// Minimum number of signing keys: one for each `BuiltinSigningKey`, whose public keys
+/// are measured at startup.
+pub const MIN_SECRET_KEYS: usize = 2;
+const _: () = assert!(MIN_SECRET_KEYS == nsm_attestation::MEASURED_SIGNING_KEYS);
+
+/// The measurement slot of the configuration: it is measured at startup after the certificate
+/// key and the `MIN_SECRET_KEYS` signing keys (for AWS, in PCR `16 + CONFIG_MEASUREMENT_SLOT`).
+pub const CONFIG_MEASUREMENT_SLOT: usize = 1 + MIN_SECRET_KEYS;
+
+/// The measurement slot of key rotations (for AWS, PCR `16 + ROTATION_MEASUREMENT_SLOT`).
+/// Unlike the slots measured at startup it stays unlocked: each rotation extends it with the
+/// `public_keys_hash` of the new keys, so that it holds the chain
+/// `SHA384(... SHA384(SHA384([0; 48] | hash_1) | hash_2) ... | hash_n)` of all rotations in the
+/// enclave (all zero if there were none).
+#[cfg(feature = "signing")]
+pub const ROTATION_MEASUREMENT_SLOT: usize = CONFIG_MEASUREMENT_SLOT + 1;
+
+/// SHA-256 of the concatenated SEC1 compressed public keys of `pairs`, see
+/// `KeyServer::rotate_keys`.
+#[cfg(feature = "signing")]
+fn public_keys_hash(pairs: &[SecretPubKeyPair]) -> [u8; 32] {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha256::new();
+    for pair in pairs {
+        hasher.update(pair.public_key.to_sec1_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// HKDF salt for domain keys, see `SecretPubKeyPair::derive_domain_key`.
+#[cfg(feature = "signing")]
+const DOMAIN_KEY_SALT: &[u8] = b"sovereign-domain-key-v1";
+
+/// HKDF salt for the config sealing key, see `KeyServer::sealing_key`.
+const SEALING_KEY_SALT: &[u8] = b"sovereign-sealing-key-v1";
+
+/// HKDF salt for the P-256 signing key, see `KeyServer::p256_signing_key`.
+#[cfg(feature = "signing")]
+const P256_SIGNING_KEY_SALT: &[u8] = b"sovereign-p256-signing-key-v1";
+
+/// The key index recorded in the audit log for signatures by the P-256 signing key (signing
+/// key indices start at 1).
+#[cfg(feature = "signing")]
+pub const P256_KEY_INDEX: u32 = 0;
+
+/// Derive a key from the certificate secret key with HKDF-SHA256 under `salt`, expanding `info`
+/// with successive counters until `parse` accepts the output as a secret key.
+fn derive_from_cert_key<T>(
+    cert_secret_key: &p256::SecretKey,
+    salt: &[u8],
+    info: &[u8],
+    parse: impl Fn(&[u8]) -> Option<T>,
+) -> Option<T> {
+    use ring::hkdf::{Salt, HKDF_SHA256};
+    let prk = Salt::new(HKDF_SHA256, salt).extract(&cert_secret_key.to_bytes());
+    for counter in 0..=u8::MAX {
+        let mut okm = Zeroizing::new([0u8; 32]);
+        prk.expand(&[info, &[counter]], HKDF_SHA256).ok()?.fill(&mut okm[..]).ok()?;
+        if let Some(key) = parse(&okm[..]) {
+            return Some(key);
+        }
+    }
+    None
+}
+
+#[cfg(feature = "signing")]
 pub struct EcdsaSignature {
     pub r: [u8; 32],
     pub s: [u8; 32],
@@ -44,7 +265,47 @@ pub struct EcdsaSignature {
     pub is_x_reduced: bool,
 }
 
+/// The signing keys, indexed by Ethereum address. Replaced as a whole on key rotation, so that
+/// a key resolved by address always belongs to the same snapshot.
+pub struct SigningKeys {
+    pub pairs: Vec<SecretPubKeyPair>,
+    /// The key index (one-based) of each signing key by Ethereum address.
+    #[cfg(feature = "signing")]
+    ethereum_addresses: HashMap<[u8; 20], u32>,
+}
+
+impl SigningKeys {
+    fn new(pairs: Vec<SecretPubKeyPair>) -> Self {
+        #[cfg(feature = "signing")]
+        let ethereum_addresses = pairs
+            .iter()
+            .enumerate()
+            .map(|(i, pair)| (pair.ethereum_address(), i as u32 + 1))
+            .collect();
+        SigningKeys {
+            pairs,
+            #[cfg(feature = "signing")]
+            ethereum_addresses,
+        }
+    }
+
+    #[cfg(feature = "signing")]
+    /// The key index (one-based, like `SigningKey.key_index`) of the signing key with this
+    /// Ethereum address, if any.
+    pub fn key_index_by_ethereum_address(&self, address: &[u8; 20]) -> Option<u32> {
+        self.ethereum_addresses.get(address).copied()
+    }
+}
+
 impl SecretPubKeyPair {
+    #[cfg(feature = "signing")]
+    /// The SEC1 compressed public key (33 bytes).
+    pub fn compressed_public_key(&self) -> Vec<u8> {
+        use elliptic_curve::sec1::ToEncodedPoint;
+        self.public_key.to_encoded_point(true).as_bytes().to_vec()
+    }
+
+    #[cfg(feature = "signing")]
     pub fn ethereum_address(&self) -> [u8; 20] {
         use elliptic_curve::sec1::ToEncodedPoint;
         // Get uncompressed public key bytes and skip first byte (0x04)
@@ -64,12 +325,45 @@ impl SecretPubKeyPair {
         address
     }
 
+    /// Derive the child key of this key for the signing domain `label` (e.g., "staging").
+    ///
+    /// The child secret key is the first valid secp256k1 scalar among
+    /// `HKDF-SHA256(salt = DOMAIN_KEY_SALT, ikm = secret key, info = label || counter)`
+    /// for `counter = 0, 1, ...` (in practice, always zero).
+    /// Derivation is deterministic: the same key and label always yield the same child key,
+    /// so every sovereign in a pool derives the same domain keys.
+    #[cfg(feature = "signing")]
+    pub fn derive_domain_key(&self, label: &str) -> Result<Self> {
+        use ring::hkdf::{Salt, HKDF_SHA256};
+        let secret_key_bytes = Zeroizing::new(self.secret_key.to_bytes());
+        let prk = Salt::new(HKDF_SHA256, DOMAIN_KEY_SALT).extract(&secret_key_bytes);
+        for counter in 0..=u8::MAX {
+            let info = [label.as_bytes(), &[counter]];
+            let mut child = Zeroizing::new([0u8; 32]);
+            prk.expand(&info, HKDF_SHA256)
+                .and_then(|okm| okm.fill(&mut child[..]))
+                .map_err(|_| anyhow!("HKDF expansion failed"))?;
+            if let Ok(secret_key) = k256::SecretKey::from_slice(&child[..]) {
+                return Ok(Self::from_secret_key(secret_key));
+            }
+        }
+        bail!("cannot derive a key for domain {}", label)
+    }
+
     pub fn from_secret_key(k: k256::SecretKey) -> Self {
         let public_key = k.public_key();
+        #[cfg(feature = "signing")]
         let ecdsa_signing_key = ecdsa::SigningKey::from(&k);
-        Self { secret_key: k, public_key, ecdsa_signing_key }
+        Self {
+            public_key,
+            #[cfg(feature = "signing")]
+            secret_key: k,
+            #[cfg(feature = "signing")]
+            ecdsa_signing_key,
+        }
     }
 
+    #[cfg(feature = "signing")]
     pub fn ecdsa_sign_prehash(&self, prehash: &[u8; 32]) -> Result<EcdsaSignature> {
         use k256::ecdsa::signature::hazmat::PrehashSigner;
         let signing_key: &ecdsa::SigningKey = &self.ecdsa_signing_key;
@@ -83,6 +377,207 @@ impl SecretPubKeyPair {
             is_x_reduced: recovery_id.is_x_reduced(),
         })
     }
+
+    /// The BIP-340 x-only public key, i.e., the x coordinate of the public key.
+    #[cfg(feature = "signing")]
+    pub fn x_only_public_key(&self) -> [u8; 32] {
+        use elliptic_curve::sec1::ToEncodedPoint;
+        let mut x_only = [0u8; 32];
+        x_only.copy_from_slice(&self.public_key.to_encoded_point(true).as_bytes()[1..]);
+        x_only
+    }
+
+    /// Sign `message` with BIP-340 Schnorr, using fresh auxiliary randomness.
+    #[cfg(feature = "signing")]
+    pub fn schnorr_sign(&self, message: &[u8; 32]) -> Result<[u8; 64]> {
+        use rand_core::RngCore;
+        let mut aux_rand = [0u8; 32];
+        rand_core::OsRng.try_fill_bytes(&mut aux_rand)?;
+        self.schnorr_sign_with_aux_rand(message, &aux_rand)
+    }
+
+    /// Sign `message` with BIP-340 Schnorr and the auxiliary randomness `aux_rand`.
+    ///
+    /// If the public key has an odd y coordinate, the negated secret key is used,
+    /// so that signatures verify with the x-only public key.
+    #[cfg(feature = "signing")]
+    pub fn schnorr_sign_with_aux_rand(
+        &self,
+        message: &[u8; 32],
+        aux_rand: &[u8; 32],
+    ) -> Result<[u8; 64]> {
+        let signing_key = k256::schnorr::SigningKey::from_bytes(&self.secret_key.to_bytes())?;
+        let signature = signing_key.sign_raw(message, aux_rand)?;
+        Ok(signature.to_bytes())
+    }
+}
+
+/// A single signing operation recorded in the audit log.
+#[cfg(feature = "signing")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    /// Milliseconds since the UNIX epoch.
+    pub timestamp_ms: u64,
+    pub key_index: u32,
+    pub operation: &'static str,
+    /// The digest that was signed.
+    pub digest: [u8; 32],
+    /// Hash of the previous entry; all zero for the first entry.
+    pub previous_hash: [u8; 32],
+    /// `SHA256(previous_hash | sequence | timestamp_ms | key_index | operation | digest)`.
+    pub hash: [u8; 32],
+}
+
+#[cfg(feature = "signing")]
+impl AuditEntry {
+    fn compute_hash(&self) -> [u8; 32] {
+        use sha2::Digest;
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(self.previous_hash);
+        hasher.update(self.sequence.to_be_bytes());
+        hasher.update(self.timestamp_ms.to_be_bytes());
+        hasher.update(self.key_index.to_be_bytes());
+        hasher.update((self.operation.len() as u32).to_be_bytes());
+        hasher.update(self.operation.as_bytes());
+        hasher.update(self.digest);
+        hasher.finalize().into()
+    }
+}
+
+/// Append-only, hash-chained record of all signing operations.
+///
+/// Entries are written to the log rather than kept in memory, but since each entry includes
+/// the hash of its predecessor, the head hash commits to every entry ever recorded.
+/// An external monitor that periodically reads the head (`GetAuditHead`) can thus check the
+/// logged entries and detect rewrites.
+#[cfg(feature = "signing")]
+pub struct AuditLog {
+    last: Option<AuditEntry>,
+    count: u64,
+    clock: Arc<dyn Clock>,
+}
+
+#[cfg(feature = "signing")]
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+#[cfg(feature = "signing")]
+impl AuditLog {
+    /// An empty log whose entries are timestamped by `clock`.
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { last: None, count: 0, clock }
+    }
+
+    /// Append an entry for a signature of `digest` and log it.
+    pub fn record(&mut self, key_index: u32, operation: &'static str, digest: &[u8; 32]) {
+        let timestamp_ms = self.clock.unix_millis();
+        let mut entry = AuditEntry {
+            sequence: self.count,
+            timestamp_ms,
+            key_index,
+            operation,
+            digest: *digest,
+            previous_hash: self.head().0,
+            hash: [0; 32],
+        };
+        entry.hash = entry.compute_hash();
+        tracing::info!(
+            "audit #{} at {}: {} with key {} on {}; hash {}",
+            entry.sequence,
+            timestamp_ms,
+            operation,
+            key_index,
+            hex::encode(digest),
+            hex::encode(entry.hash)
+        );
+        self.count += 1;
+        self.last = Some(entry);
+    }
+
+    /// Hash of the most recent entry (all zero if empty) and the total number of entries.
+    pub fn head(&self) -> ([u8; 32], u64) {
+        (self.last.as_ref().map_or([0; 32], |entry| entry.hash), self.count)
+    }
+
+    /// The most recent entry, if any.
+    #[cfg(test)]
+    pub fn last(&self) -> Option<&AuditEntry> {
+        self.last.as_ref()
+    }
+}
+
+/// The TLS certificate served on all TLS ports; replaced by `KeyServer::rotate_certificate`.
+#[derive(Debug)]
+pub struct CertificateResolver(RwLock<Arc<rustls::sign::CertifiedKey>>);
+
+impl CertificateResolver {
+    fn certified_key(
+        cert: &rcgen::Certificate,
+        key: &pki_types::PrivateKeyDer<'static>,
+    ) -> Result<Arc<rustls::sign::CertifiedKey>> {
+        let signing_key = rustls::crypto::ring::sign::any_ecdsa_type(key)
+            .map_err(|e| anyhow!("unsupported certificate key: {}", e))?;
+        Ok(Arc::new(rustls::sign::CertifiedKey::new(vec![cert.der().clone()], signing_key)))
+    }
+
+    fn new(cert: &rcgen::Certificate, key: &pki_types::PrivateKeyDer<'static>) -> Result<Self> {
+        Ok(Self(RwLock::new(Self::certified_key(cert, key)?)))
+    }
+
+    #[cfg(feature = "signing")]
+    fn set(
+        &self,
+        cert: &rcgen::Certificate,
+        key: &pki_types::PrivateKeyDer<'static>,
+    ) -> Result<()> {
+        let certified_key = Self::certified_key(cert, key)?;
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = certified_key;
+        Ok(())
+    }
+
+    #[cfg(feature = "signing")]
+    /// The DER encoding of the current certificate.
+    pub fn certificate_der(&self) -> pki_types::CertificateDer<'static> {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).cert[0].clone()
+    }
+}
+
+impl rustls::server::ResolvesServerCert for CertificateResolver {
+    fn resolve(
+        &self,
+        _client_hello: rustls::server::ClientHello,
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        Some(self.0.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+}
+
+/// Self-sign a certificate for `key_pair` with `alt_names` (and "localhost").
+fn self_signed_certificate(
+    key_pair: &rcgen::KeyPair,
+    alt_names: &[String],
+) -> Result<rcgen::Certificate> {
+    use crate::config::AltName;
+
+    let mut names =
+        alt_names.iter().map(|name| AltName::parse(name)).collect::<Result<Vec<_>>>()?;
+    let localhost = AltName::Dns("localhost".to_string());
+    if !names.contains(&localhost) {
+        names.push(localhost);
+    }
+    let mut params = rcgen::CertificateParams::default();
+    for name in names {
+        params.subject_alt_names.push(match name {
+            AltName::Dns(name) => rcgen::SanType::DnsName(
+                name.try_into().map_err(|e| anyhow!("failed to create certificate: {}", e))?,
+            ),
+            AltName::Ip(ip) => rcgen::SanType::IpAddress(ip),
+        });
+    }
+    params.self_signed(key_pair).map_err(|e| anyhow!("failed to sign certificate: {}", e))
 }
 
 pub struct KeyServer<SM: Secmod> {
@@ -90,20 +585,151 @@ pub struct KeyServer<SM: Secmod> {
     pub metrics: Arc<crate::monitoring::Metrics>,
     pub attestor: SM::Attestor,
     pub cert_secret_key: p256::SecretKey,
+    #[cfg(feature = "signing")]
     pub cert_secret_key_der: pki_types::PrivateKeyDer<'static>,
     pub cert_public_key_der: Vec<u8>,
-    pub cert: rcgen::Certificate,
-    pub pairs: Vec<SecretPubKeyPair>,
+    pub cert: Arc<CertificateResolver>,
+    /// The P-256 (ES256) signing key of `SignP256`. Derived like the sealing key from the
+    /// certificate secret key, so that it is shared by the pool without changing key-sync, but
+    /// never the certificate key itself: signing arbitrary digests with the certificate key
+    /// would allow impersonating the sovereign's TLS endpoints.
+    #[cfg(feature = "signing")]
+    pub p256_signing_key: p256::ecdsa::SigningKey,
+    /// The signing keys; see `signing_keys()`.
+    signing_keys: RwLock<Arc<SigningKeys>>,
+    /// The next measurement slot to extend (see `Secmod::measure_enclave`).
+    next_measurement: Mutex<usize>,
+    /// Serializes key and certificate rotations.
+    #[cfg(feature = "signing")]
+    rotation_lock: tokio::sync::Mutex<()>,
+    /// Limits concurrent key-sync sessions (see `max_concurrent_key_syncs`).
+    #[cfg(feature = "signing")]
+    pub key_sync_permits: Option<Arc<tokio::sync::Semaphore>>,
+    #[cfg(feature = "signing")]
+    pub audit_log: Mutex<AuditLog>,
+    /// Source of the current time for time-dependent checks (see `nsm_attestation::clock`).
+    #[cfg(feature = "signing")]
+    pub clock: Arc<dyn Clock>,
+    /// Outstanding attestation challenges (see `nonce_cache`).
+    pub challenges: Mutex<NonceCache>,
+    /// Remaining signatures of each key index with a configured `signature-budgets` entry.
+    #[cfg(feature = "signing")]
+    signature_budgets: Mutex<BTreeMap<u32, u64>>,
+    /// The break-glass key, once loaded (see `break_glass`).
+    #[cfg(feature = "signing")]
+    break_glass: RwLock<Option<SecretPubKeyPair>>,
+    /// When the last attempt to load the break-glass key started, in Unix milliseconds.
+    #[cfg(feature = "signing")]
+    last_break_glass_attempt: Mutex<Option<u64>>,
 }
 
 impl<SM: Secmod> KeyServer<SM> {
+    /// A snapshot of the current signing keys.
+    pub fn signing_keys(&self) -> Arc<SigningKeys> {
+        self.signing_keys.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    #[cfg(feature = "signing")]
     pub fn extract_secret_key_material(&self) -> SecretKeyMaterial {
-        let cert_secret_key = self.cert_secret_key.to_bytes().into();
-        let mut secret_keys = Vec::new();
-        for k in self.pairs.iter() {
-            secret_keys.push(k.secret_key.to_bytes().into());
+        let keys = self.signing_keys();
+        let pairs = &keys.pairs;
+        let mut result = SecretKeyMaterial::default();
+        let mut bytes = self.cert_secret_key.to_bytes();
+        result.cert_secret_key.copy_from_slice(&bytes);
+        bytes.zeroize();
+        result.secret_keys.reserve_exact(pairs.len());
+        for k in pairs.iter() {
+            let mut bytes = k.secret_key.to_bytes();
+            result.secret_keys.push(bytes.into());
+            bytes.zeroize();
+        }
+        result
+    }
+
+    /// The secp256k1 key to which configuration values are sealed (see `config::SealedValue`).
+    ///
+    /// Derived like domain keys, but from the certificate secret key, which is shared by the
+    /// pool and (unlike the signing keys) never rotated.
+    pub fn sealing_key(&self) -> Result<k256::SecretKey> {
+        derive_from_cert_key(&self.cert_secret_key, SEALING_KEY_SALT, &[], |okm| {
+            k256::SecretKey::from_slice(okm).ok()
+        })
+        .context("cannot derive the sealing key")
+    }
+
+    #[cfg(feature = "signing")]
+    /// Use up one signature of the budget of `key_index`, failing if it is exhausted.
+    /// Keys without a configured budget are unlimited.
+    pub fn spend_signature_budget(&self, key_index: u32) -> Result<()> {
+        let mut budgets = self.signature_budgets.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(remaining) = budgets.get_mut(&key_index) else {
+            return Ok(());
+        };
+        if *remaining == 0 {
+            bail!("signature budget of key {} exhausted", key_index);
+        }
+        *remaining -= 1;
+        self.record_signature_budget(key_index, *remaining);
+        Ok(())
+    }
+
+    #[cfg(feature = "signing")]
+    /// Record an attempt to load the break-glass key, failing if the previous one started less
+    /// than `break_glass::MIN_ATTEMPT_INTERVAL_MILLIS` ago.
+    pub fn throttle_break_glass_attempt(&self) -> Result<()> {
+        let now = self.clock.unix_millis();
+        let mut last = self.last_break_glass_attempt.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(last) = *last {
+            let next = last.saturating_add(crate::break_glass::MIN_ATTEMPT_INTERVAL_MILLIS);
+            if now < next {
+                bail!("break-glass attempts are rate-limited; retry in {} ms", next - now);
+            }
         }
-        SecretKeyMaterial { cert_secret_key, secret_keys }
+        *last = Some(now);
+        Ok(())
+    }
+
+    #[cfg(feature = "signing")]
+    /// The break-glass key, if it has been loaded.
+    pub fn break_glass_key(&self) -> Option<SecretPubKeyPair> {
+        self.break_glass.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// The remaining signature budget of `key_index`, if it has one.
+    #[cfg(all(test, feature = "test-utils"))]
+    pub fn signature_budget(&self, key_index: u32) -> Option<u64> {
+        self.signature_budgets.lock().unwrap_or_else(|e| e.into_inner()).get(&key_index).copied()
+    }
+
+    #[cfg(feature = "signing")]
+    fn record_signature_budget(&self, key_index: u32, remaining: u64) {
+        self.metrics
+            .signature_budget_remaining
+            .with_label_values(&[&key_index.to_string()])
+            .set(remaining.try_into().unwrap_or(i64::MAX));
+    }
+
+    /// The canonically serialized configuration, as measured at startup (see
+    /// `CONFIG_MEASUREMENT_SLOT` and `SovereignConfig::canonical_json`).
+    pub fn config_measurement(&self) -> Result<Vec<u8>> {
+        self.config.canonical_json()
+    }
+
+    /// Extend the enclave's measurements with `data`, continuing after any previous measurements.
+    pub fn measure(&self, data: Vec<Vec<u8>>) -> Result<()> {
+        let mut next_measurement = self.next_measurement.lock().unwrap_or_else(|e| e.into_inner());
+        let count = data.len();
+        SM::measure_enclave(
+            &self.attestor,
+            *next_measurement,
+            data,
+            self.config.idempotent_measurements,
+        )?;
+        *next_measurement += count;
+        for (index, value) in SM::describe_pcrs(&self.attestor)? {
+            tracing::info!(index, value = %hex::encode(value), "PCR");
+        }
+        Ok(())
     }
 
     pub fn new(
@@ -113,9 +739,16 @@ impl<SM: Secmod> KeyServer<SM> {
     ) -> Result<Self> {
         use elliptic_curve::generic_array::GenericArray;
 
+        if crate::config::SIGNING_ENABLED && key_material.secret_keys.len() < MIN_SECRET_KEYS {
+            bail!(
+                "secret key material contains {} keys; at least {} are required",
+                key_material.secret_keys.len(),
+                MIN_SECRET_KEYS
+            );
+        }
         let mut pairs = Vec::new();
-        for k in key_material.secret_keys {
-            let secret_key = k256::SecretKey::from_bytes(GenericArray::from_slice(&k))
+        for k in &key_material.secret_keys {
+            let secret_key = k256::SecretKey::from_bytes(GenericArray::from_slice(k))
                 .context("failed to create secret key")?;
             let pair = SecretPubKeyPair::from_secret_key(secret_key);
             pairs.push(pair);
@@ -138,41 +771,230 @@ impl<SM: Secmod> KeyServer<SM> {
 
         let cert_public_key_der = key_pair.public_key_der();
 
-        let mut subject_alt_names = config.alt_names.clone();
-        subject_alt_names.push("localhost".to_string());
-        subject_alt_names.dedup();
-        let cert = rcgen::CertificateParams::new(subject_alt_names)
-            .map_err(|e| anyhow!("failed to create certificate: {}", e))?
-            .self_signed(&key_pair)
-            .map_err(|e| anyhow!("failed to sign certificate: {}", e))?;
+        let cert = self_signed_certificate(&key_pair, &config.alt_names)?;
 
         let cert_secret_key_der = pki_types::PrivateKeyDer::from(cert_private_key_der);
+        let cert = Arc::new(CertificateResolver::new(&cert, &cert_secret_key_der)?);
+        #[cfg(feature = "signing")]
+        let p256_signing_key =
+            derive_from_cert_key(&cert_secret_key, P256_SIGNING_KEY_SALT, &[], |okm| {
+                p256::ecdsa::SigningKey::from_slice(okm).ok()
+            })
+            .context("cannot derive the P-256 signing key")?;
 
         let metrics = Arc::new(crate::monitoring::Metrics::new());
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        #[cfg(feature = "signing")]
+        let key_sync_permits =
+            config.max_concurrent_key_syncs.map(|n| Arc::new(tokio::sync::Semaphore::new(n)));
+        #[cfg(feature = "signing")]
+        let signature_budgets = config.signature_budgets.clone().unwrap_or_default();
+        #[cfg(feature = "signing")]
+        for (key_index, budget) in &signature_budgets {
+            metrics
+                .signature_budget_remaining
+                .with_label_values(&[&key_index.to_string()])
+                .set((*budget).try_into().unwrap_or(i64::MAX));
+        }
         Ok(KeyServer {
             config,
             metrics,
             attestor,
             cert_secret_key,
+            #[cfg(feature = "signing")]
             cert_secret_key_der,
             cert_public_key_der,
             cert,
-            pairs,
+            #[cfg(feature = "signing")]
+            p256_signing_key,
+            signing_keys: RwLock::new(Arc::new(SigningKeys::new(pairs))),
+            next_measurement: Mutex::new(0),
+            #[cfg(feature = "signing")]
+            rotation_lock: tokio::sync::Mutex::new(()),
+            #[cfg(feature = "signing")]
+            key_sync_permits,
+            #[cfg(feature = "signing")]
+            audit_log: Mutex::new(AuditLog::new(clock.clone())),
+            challenges: Mutex::new(NonceCache::new(
+                CHALLENGE_TTL,
+                CHALLENGE_CACHE_CAPACITY,
+                clock.clone(),
+            )),
+            #[cfg(feature = "signing")]
+            clock,
+            #[cfg(feature = "signing")]
+            signature_budgets: Mutex::new(signature_budgets),
+            #[cfg(feature = "signing")]
+            break_glass: RwLock::new(None),
+            #[cfg(feature = "signing")]
+            last_break_glass_attempt: Mutex::new(None),
         })
     }
 }
 
+#[cfg(feature = "signing")]
+impl<SM: Secmod + 'static> KeyServer<SM> {
+    /// Replace all signing keys with freshly generated ones ("drain and re-key").
+    ///
+    /// The rotation must be authorized by governance: the message
+    /// `ROTATE-KEYS:<hex public_keys_hash of the current keys>` must have been signed.
+    /// The new keys are measured in `ROTATION_MEASUREMENT_SLOT`, so attestations issued
+    /// afterwards reflect the rotation. Signing requests already in flight complete with the
+    /// old keys.
+    ///
+    /// Only the leader of a pool rotates: the new keys are random, so followers obtain them by
+    /// key-sync. Running followers keep the old keys until they are restarted.
+    ///
+    /// Returns the old and the new public keys.
+    pub async fn rotate_keys(&self) -> Result<(Vec<k256::PublicKey>, Vec<k256::PublicKey>)> {
+        if let crate::config::SecretKeyRetrieval::KeySync(_) = self.config.secret_keys_from {
+            bail!("followers cannot rotate keys; restart them to sync the leader's keys");
+        }
+        let _guard = self.rotation_lock.lock().await;
+        let old_keys = self.signing_keys();
+        let old_pairs = &old_keys.pairs;
+        let message = format!("ROTATE-KEYS:{}", hex::encode(public_keys_hash(old_pairs)));
+        crate::key_sync::authorize_operation::<SM>(
+            &self.attestor,
+            &self.config.governance,
+            &message,
+        )
+        .await
+        .with_context(|| format!("key rotation not authorized ({})", message))?;
+
+        rng_self_test(&mut rand_core::OsRng)?;
+        let new_pairs: Vec<_> = old_pairs
+            .iter()
+            .map(|_| {
+                SecretPubKeyPair::from_secret_key(k256::SecretKey::random(&mut rand_core::OsRng))
+            })
+            .collect();
+        // Measure before swapping: if measuring fails, the old keys stay in place.
+        SM::extend_measurement(
+            &self.attestor,
+            ROTATION_MEASUREMENT_SLOT,
+            public_keys_hash(&new_pairs).to_vec(),
+        )
+        .context("failed to measure rotated keys")?;
+
+        let new_public_keys = new_pairs.iter().map(|pair| pair.public_key).collect();
+        *self.signing_keys.write().unwrap_or_else(|e| e.into_inner()) =
+            Arc::new(SigningKeys::new(new_pairs));
+        tracing::warn!("signing keys rotated");
+        Ok((old_pairs.iter().map(|pair| pair.public_key).collect(), new_public_keys))
+    }
+
+    /// Re-issue the TLS certificate with `alt_names` after governance authorized the message
+    /// `ROTATE-CERTIFICATE:<alt names joined by ",">`, and serve it from now on.
+    ///
+    /// The certificate key stays the same, so its measurement (and the sealing key) remain valid;
+    /// the measured configuration keeps the original `alt-names`. Returns the new certificate.
+    pub async fn rotate_certificate(
+        &self,
+        alt_names: Vec<String>,
+    ) -> Result<pki_types::CertificateDer<'static>> {
+        use p256::pkcs8::EncodePrivateKey;
+
+        for name in &alt_names {
+            crate::config::AltName::parse(name)?;
+        }
+        let _guard = self.rotation_lock.lock().await;
+        let message = format!("ROTATE-CERTIFICATE:{}", alt_names.join(","));
+        crate::key_sync::authorize_operation::<SM>(
+            &self.attestor,
+            &self.config.governance,
+            &message,
+        )
+        .await
+        .with_context(|| format!("certificate rotation not authorized ({})", message))?;
+
+        let cert_pkcs8_der = self.cert_secret_key.to_pkcs8_der()?;
+        let key_pair = rcgen::KeyPair::from_pkcs8_der_and_sign_algo(
+            &pki_types::PrivatePkcs8KeyDer::from(cert_pkcs8_der.as_bytes()),
+            &rcgen::PKCS_ECDSA_P256_SHA256,
+        )
+        .map_err(|e| anyhow!("failed to create key pair: {}", e))?;
+        let cert = self_signed_certificate(&key_pair, &alt_names)?;
+        self.cert.set(&cert, &self.cert_secret_key_der)?;
+        tracing::warn!("TLS certificate rotated; alternative names {:?}", alt_names);
+        Ok(cert.der().clone())
+    }
+
+    /// Derive the break-glass key from a passphrase sealed to the sealing key (see `break_glass`)
+    /// and make it available for signing, after governance authorized the message
+    /// `LOAD-BREAK-GLASS-KEY:<hex of the compressed SEC1 public key>`. Returns the public key.
+    pub async fn load_break_glass_key(&self, sealed_passphrase: &[u8]) -> Result<k256::PublicKey> {
+        use elliptic_curve::sec1::ToEncodedPoint;
+
+        let Some(config) = &self.config.break_glass else {
+            bail!("break-glass is not configured");
+        };
+        let passphrase = Zeroizing::new(
+            crate::ecies_envelope::decrypt(&self.sealing_key()?.to_bytes(), sealed_passphrase)
+                .context("cannot unseal break-glass passphrase")?,
+        );
+        // Argon2id is expensive by design; keep it off the async workers.
+        let config = config.clone();
+        let secret_key = tokio::task::spawn_blocking(move || {
+            crate::break_glass::derive_key(&passphrase, &config)
+        })
+        .await
+        .context("break-glass key derivation panicked")??;
+        let pair = SecretPubKeyPair::from_secret_key(secret_key);
+        let public_key = pair.public_key;
+        let message = format!(
+            "LOAD-BREAK-GLASS-KEY:{}",
+            hex::encode(public_key.to_encoded_point(true).as_bytes())
+        );
+        crate::key_sync::authorize_operation::<SM>(
+            &self.attestor,
+            &self.config.governance,
+            &message,
+        )
+        .await
+        .with_context(|| format!("break-glass key not authorized ({})", message))?;
+
+        *self.break_glass.write().unwrap_or_else(|e| e.into_inner()) = Some(pair);
+        tracing::warn!("break-glass key loaded");
+        Ok(public_key)
+    }
+
+    /// Refill the signature budget of `key_index` to its configured value after governance
+    /// authorized the message `RESET-KEY-BUDGET:<key_index>`. Returns the new budget.
+    pub async fn reset_key_budget(&self, key_index: u32) -> Result<u64> {
+        let Some(budget) = self.config.signature_budget(key_index) else {
+            bail!("key {} has no signature budget", key_index);
+        };
+        let message = format!("RESET-KEY-BUDGET:{}", key_index);
+        crate::key_sync::authorize_operation::<SM>(
+            &self.attestor,
+            &self.config.governance,
+            &message,
+        )
+        .await
+        .with_context(|| format!("budget reset not authorized ({})", message))?;
+
+        self.signature_budgets.lock().unwrap_or_else(|e| e.into_inner()).insert(key_index, budget);
+        self.record_signature_budget(key_index, budget);
+        tracing::warn!("signature budget of key {} reset to {}", key_index, budget);
+        Ok(budget)
+    }
+}
+
 #[cfg(test)]
 // #[cfg(feature = "test-utils")]
 mod tests {
 
     use super::*;
     use elliptic_curve::rand_core::OsRng;
+    #[cfg(feature = "signing")]
     use ethereum_tx_sign::LegacyTransaction;
+    #[cfg(feature = "signing")]
     use ethereum_tx_sign::Transaction;
 
     // Ensure that the generated ECDSa signature is consistent
     // with anohter crate `ethereum_tx_sign` which itself has an extensive test suite.
+    #[cfg(feature = "signing")]
     #[tokio::test]
     async fn test_sign_eth() -> Result<()> {
         for k in 1..10 {
@@ -190,7 +1012,7 @@ mod tests {
             let hash = new_transaction.hash();
             let signed_hash = sec.ecdsa_sign_prehash(&hash)?;
             let ecdsa = new_transaction
-                .ecdsa(&sec_k.to_bytes().as_slice())
+                .ecdsa(sec_k.to_bytes().as_slice())
                 .map_err(|x| anyhow!("ecdsa {:?}", x))?;
             assert_eq!(signed_hash.r.to_vec(), ecdsa.r);
             assert_eq!(signed_hash.s.to_vec(), ecdsa.s);
@@ -198,4 +1020,277 @@ mod tests {
         }
         Ok(())
     }
+
+    /// Signing test vectors 0 to 3 from BIP-340:
+    /// secret key, x-only public key, auxiliary randomness, message, and signature.
+    #[cfg(feature = "signing")]
+    const BIP340_VECTORS: [[&str; 5]; 4] = [
+        [
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca8215\
+             25f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0",
+        ],
+        [
+            "b7e151628aed2a6abf7158809cf4f3c762e7160f38b4da56a784d9045190cfef",
+            "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+            "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de3341\
+             8906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a",
+        ],
+        [
+            "c90fdaa22168c234c4c6628b80dc1cd129024e088a67cc74020bbea63b14e5c9",
+            "dd308afec5777e13121fa72b9cc1b7cc0139715309b086c960e18fd969774eb8",
+            "c87aa53824b4d7ae2eb035a2b5bbbccc080e76cdc6d1692c4b0b62d798e6d906",
+            "7e2d58d8b3bcdf1abadec7829054f90dda9805aab56c77333024b9d0a508b75c",
+            "5831aaeed7b44bb74e5eab94ba9d4294c49bcf2a60728d8b4c200f50dd313c1b\
+             ab745879a5ad954a72c45a91c3a51d3c7adea98d82f8481e0e1e03674a6f3fb7",
+        ],
+        [
+            "0b432b2677937381aef05bb02a66ecd012773062cf3fa2549e44f58ed2401710",
+            "25d1dff95105f5253c4022f628a996ad3a0d95fbf21d468a1b33f8c160d8f517",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "7eb0509757e246f19449885651611cb965ecc1a187dd51b64fda1edc9637d5ec\
+             97582b9cb13db3933705b32ba982af5af25fd78881ebb32771fc5922efc66ea3",
+        ],
+    ];
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_schnorr_bip340_vectors() -> Result<()> {
+        for [secret_key, public_key, aux_rand, message, signature] in BIP340_VECTORS {
+            let pair = SecretPubKeyPair::from_secret_key(k256::SecretKey::from_slice(
+                &hex::decode(secret_key)?,
+            )?);
+            assert_eq!(hex::encode(pair.x_only_public_key()), public_key);
+            let aux_rand: [u8; 32] = hex::decode(aux_rand)?.try_into().unwrap();
+            let message: [u8; 32] = hex::decode(message)?.try_into().unwrap();
+            let actual = pair.schnorr_sign_with_aux_rand(&message, &aux_rand)?;
+            assert_eq!(hex::encode(actual), signature);
+
+            // Signatures with fresh randomness differ, but verify with the x-only key.
+            let randomized = pair.schnorr_sign(&message)?;
+            let verifying_key = k256::schnorr::VerifyingKey::from_bytes(&pair.x_only_public_key())?;
+            let randomized = k256::schnorr::Signature::try_from(randomized.as_slice())?;
+            verifying_key.verify_raw(&message, &randomized)?;
+        }
+        Ok(())
+    }
+
+    /// An RNG that repeatedly outputs the same byte.
+    struct ConstantRng(u8);
+
+    impl rand_core::RngCore for ConstantRng {
+        fn next_u32(&mut self) -> u32 {
+            u32::from_ne_bytes([self.0; 4])
+        }
+        fn next_u64(&mut self) -> u64 {
+            u64::from_ne_bytes([self.0; 8])
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(self.0)
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl rand_core::CryptoRng for ConstantRng {}
+
+    /// An RNG that outputs zeros at first and then the output of `OsRng`.
+    struct FlakyRng {
+        remaining_zero_bytes: usize,
+    }
+
+    impl rand_core::RngCore for FlakyRng {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            let zeros = dest.len().min(self.remaining_zero_bytes);
+            dest[..zeros].fill(0);
+            OsRng.fill_bytes(&mut dest[zeros..]);
+            self.remaining_zero_bytes -= zeros;
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_rng_self_test() {
+        assert!(rng_self_test(&mut OsRng).is_ok());
+        assert!(SecretKeyMaterial::generate_random(2, &mut OsRng).is_ok());
+        // 0x55 has half of its bits set, so only the repetition is detected.
+        for b in [0x00, 0xff, 0x55] {
+            assert!(rng_self_test(&mut ConstantRng(b)).is_err());
+            assert!(SecretKeyMaterial::generate_random(2, &mut ConstantRng(b)).is_err());
+        }
+        // A failed check is retried with fresh output.
+        let mut flaky = FlakyRng { remaining_zero_bytes: RNG_SELF_TEST_BYTES };
+        assert!(rng_self_test(&mut flaky).is_ok());
+    }
+
+    #[test]
+    fn test_secret_key_material_zeroize() {
+        fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+        assert_zeroize_on_drop::<SecretKeyMaterial>();
+        assert_zeroize_on_drop::<SecretPubKeyPair>();
+
+        let mut secret = SecretKeyMaterial::generate_random(2, &mut OsRng).unwrap();
+        assert_ne!(secret.cert_secret_key, [0; 32]);
+        assert_eq!(secret.secret_keys.len(), 2);
+        secret.zeroize();
+        assert_eq!(secret.cert_secret_key, [0; 32]);
+        assert!(secret.secret_keys.is_empty());
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_secret_key_material_bytes() {
+        let secret = SecretKeyMaterial::generate_random(3, &mut OsRng).unwrap();
+        let bytes = secret.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 1 + 2 + 32 + 2 + 3 * 32);
+        assert_eq!(bytes[..3], [SECRET_KEY_MATERIAL_VERSION, 0, 32]);
+        assert_eq!(bytes[35..37], [0, 3]);
+        assert!(SecretKeyMaterial::from_bytes(&bytes).unwrap() == secret);
+
+        let empty = SecretKeyMaterial::default();
+        let decoded = SecretKeyMaterial::from_bytes(&empty.to_bytes().unwrap()).unwrap();
+        assert!(decoded == empty);
+
+        let mut invalid = bytes.to_vec();
+        invalid[0] = 2;
+        let err = SecretKeyMaterial::from_bytes(&invalid).err().unwrap();
+        assert_eq!(err.to_string(), "unsupported secret key material version 2");
+        let err = SecretKeyMaterial::from_bytes(&bytes[..bytes.len() - 1]).err().unwrap();
+        assert_eq!(err.to_string(), "secret key material has 95 bytes for 3 keys");
+        let err = SecretKeyMaterial::from_bytes(&[bytes.as_slice(), &[0]].concat()).err().unwrap();
+        assert_eq!(err.to_string(), "secret key material has 97 bytes for 3 keys");
+        assert!(SecretKeyMaterial::from_bytes(&bytes[..20]).is_err());
+        assert!(SecretKeyMaterial::from_bytes(&[]).is_err());
+        let mut invalid = bytes.to_vec();
+        invalid[2] = 33;
+        assert!(SecretKeyMaterial::from_bytes(&invalid).is_err());
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_derive_domain_key() -> Result<()> {
+        let master = SecretPubKeyPair::from_secret_key(k256::SecretKey::from_slice(&[7; 32])?);
+        let mainnet = master.derive_domain_key("ethereum-mainnet")?;
+        let staging = master.derive_domain_key("staging")?;
+        // Distinct labels give distinct addresses, all different from the master key.
+        assert_ne!(mainnet.ethereum_address(), staging.ethereum_address());
+        assert_ne!(mainnet.ethereum_address(), master.ethereum_address());
+        assert_ne!(staging.ethereum_address(), master.ethereum_address());
+        // The same label always gives the same key.
+        let again = master.derive_domain_key("ethereum-mainnet")?;
+        assert_eq!(mainnet.secret_key, again.secret_key);
+        assert_eq!(mainnet.ethereum_address(), again.ethereum_address());
+        // Different master keys give different domain keys.
+        let other = SecretPubKeyPair::from_secret_key(k256::SecretKey::from_slice(&[8; 32])?);
+        let other_mainnet = other.derive_domain_key("ethereum-mainnet")?;
+        assert_ne!(mainnet.ethereum_address(), other_mainnet.ethereum_address());
+        Ok(())
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_p256_signing_key() -> Result<()> {
+        use crate::mock_secmod::MockSecmod;
+        use elliptic_curve::sec1::ToEncodedPoint;
+
+        let secret = SecretKeyMaterial::generate_random(2, &mut OsRng)?;
+        let new = |secret: SecretKeyMaterial| {
+            let attestor = MockSecmod::init_debug_attestor();
+            KeyServer::<MockSecmod>::new(attestor, Default::default(), secret)
+        };
+        let state = new(secret.clone())?;
+        let p256_public_key = state.p256_signing_key.verifying_key().to_encoded_point(false);
+        // Not the certificate key, nor the sealing key.
+        let cert_public_key = state.cert_secret_key.public_key().to_encoded_point(false);
+        assert_ne!(p256_public_key, cert_public_key);
+        assert_ne!(state.p256_signing_key.to_bytes(), state.sealing_key()?.to_bytes());
+        // Followers that received the key material derive the same key.
+        let follower = new(SecretKeyMaterial::from_bytes(&secret.to_bytes()?)?)?;
+        assert_eq!(
+            follower.p256_signing_key.verifying_key().to_encoded_point(false),
+            p256_public_key
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_rotate_keys() -> Result<()> {
+        use crate::config::{Governance, SecretKeyRetrieval, SovereignConfig};
+        use crate::mock_secmod::MockSecmod;
+
+        let config = SovereignConfig { governance: Governance::TestingOnly, ..Default::default() };
+        let secret = SecretKeyMaterial::generate_random(3, &mut OsRng)?;
+        let attestor = MockSecmod::init_debug_attestor();
+        let leader = KeyServer::<MockSecmod>::new(
+            attestor,
+            config.clone(),
+            SecretKeyMaterial::from_bytes(&secret.to_bytes()?)?,
+        )?;
+        let (old, rotated) = leader.rotate_keys().await?;
+        assert_eq!(rotated.len(), 3);
+        assert!(rotated.iter().all(|key| !old.contains(key)));
+        let hash = public_keys_hash(&leader.signing_keys().pairs);
+        assert_eq!(attestor.measurements()[ROTATION_MEASUREMENT_SLOT], hash);
+
+        // Each rotation generates other keys and extends the same measurement slot.
+        let (_, rotated_again) = leader.rotate_keys().await?;
+        assert!(rotated_again.iter().all(|key| !rotated.contains(key)));
+        let hash_again = public_keys_hash(&leader.signing_keys().pairs);
+        assert_eq!(attestor.measurements()[ROTATION_MEASUREMENT_SLOT], [hash, hash_again].concat());
+
+        // Followers obtain the rotated keys by key-sync, and cannot rotate themselves.
+        let follower = KeyServer::<MockSecmod>::new(
+            MockSecmod::init_debug_attestor(),
+            SovereignConfig { secret_keys_from: SecretKeyRetrieval::KeySync(5000), ..config },
+            leader.extract_secret_key_material(),
+        )?;
+        let synced: Vec<_> = follower.signing_keys().pairs.iter().map(|x| x.public_key).collect();
+        assert_eq!(synced, rotated_again);
+        assert!(follower.rotate_keys().await.is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_audit_log_chain() {
+        let clock = nsm_attestation::clock::MockClock::at_unix_seconds(1_700_000_000);
+        let mut log = AuditLog::new(Arc::new(clock.clone()));
+        assert_eq!(log.head(), ([0; 32], 0));
+        assert!(log.last().is_none());
+        let mut previous: Option<AuditEntry> = None;
+        for i in 0..5u8 {
+            log.record(1 + (i % 2) as u32, "sign_digest", &[i; 32]);
+            clock.advance(std::time::Duration::from_millis(1));
+            let entry = log.last().unwrap().clone();
+            assert_eq!(entry.hash, entry.compute_hash());
+            assert_eq!(log.head(), (entry.hash, i as u64 + 1));
+            match &previous {
+                Some(previous) => {
+                    assert_eq!(entry.previous_hash, previous.hash);
+                    assert_eq!(entry.sequence, previous.sequence + 1);
+                    assert_eq!(entry.timestamp_ms, previous.timestamp_ms + 1);
+                }
+                None => assert_eq!(entry.previous_hash, [0; 32]),
+            }
+            previous = Some(entry);
+        }
+    }
 }
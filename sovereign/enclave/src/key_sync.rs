@@ -1,12 +1,57 @@
 //! This module implements the key-sync protocol.
 
+use crate::user_data::{
+    AttestationUserData, UserData, PURPOSE_KEY_SYNC_FOLLOWER, PURPOSE_KEY_SYNC_LEADER,
+};
 use crate::{AttestationDocument, Secmod};
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use elliptic_curve::rand_core::{self, RngCore};
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tracing;
+
+/// Ensure that this sovereign itself is running in debug mode.
+fn ensure_self_debug<SM: Secmod + 'static>(attestor: &SM::Attestor) -> Result<()> {
+    let self_att_bytes: Vec<u8> = SM::new_attestation(attestor, None, None, None)?;
+    // We parse our own attestation document to get our PCR values.
+    let self_att = SM::parse(&self_att_bytes)?;
+    if self_att.code_measurement() != SM::measure_debug_code() {
+        bail!(
+            "self attestation not debug; was {} expected {}",
+            self_att.code_measurement(),
+            SM::measure_debug_code()
+        )
+    }
+    Ok(())
+}
+
+/// Authorize an operation on this sovereign (e.g., key rotation) described by `message`.
+///
+/// With Safe governance, `message` must have been signed in the Safe(s).
+/// With testing-only governance, any operation is authorized in debug mode.
+pub async fn authorize_operation<SM: Secmod + 'static>(
+    attestor: &SM::Attestor,
+    gov: &crate::config::Governance,
+    message: &str,
+) -> Result<()> {
+    use crate::config::Governance;
+    match gov {
+        Governance::TestingOnly => {
+            gov.check_testing_allowed()?;
+            ensure_self_debug::<SM>(attestor)?;
+            tracing::warn!("authorizing '{}' in debug mode", message);
+            Ok(())
+        }
+        Governance::Safe(config) => {
+            crate::safe::safe_authorize_message::<SM>(config, message).await
+        }
+        Governance::MultiSafe { safes, require } => {
+            crate::safe::multi_safe_authorize_message::<SM>(safes, require, message).await
+        }
+    }
+}
 
 async fn authorize_measurements<SM: Secmod + 'static>(
     attestor: &SM::Attestor,
@@ -16,6 +61,7 @@ async fn authorize_measurements<SM: Secmod + 'static>(
     use crate::config::Governance;
     match gov {
         Governance::TestingOnly => {
+            gov.check_testing_allowed()?;
             if att.code_measurement() != SM::measure_debug_code() {
                 bail!(
                     "remote attestation not debug; was {} expected {}",
@@ -23,16 +69,7 @@ async fn authorize_measurements<SM: Secmod + 'static>(
                     SM::measure_debug_code()
                 )
             }
-            let self_att_bytes: Vec<u8> = SM::new_attestation(attestor, None, None, None)?;
-            // We parse our own attestation document to get our PCR values.
-            let self_att = SM::parse(&self_att_bytes)?;
-            if self_att.code_measurement() != SM::measure_debug_code() {
-                bail!(
-                    "self attestation not debug; was {} expected {}",
-                    self_att.code_measurement(),
-                    SM::measure_debug_code()
-                )
-            }
+            ensure_self_debug::<SM>(attestor)?;
             tracing::warn!("authorizing measurements in debug mode");
             Ok(())
         }
@@ -42,6 +79,87 @@ async fn authorize_measurements<SM: Secmod + 'static>(
             //crate::safe::safe_authorize_message::<SM>(config, &att.instance_measurement()).await?;
             Ok(())
         }
+        Governance::MultiSafe { safes, require } => {
+            crate::safe::multi_safe_authorize_message::<SM>(
+                safes,
+                require,
+                &att.code_measurement(),
+            )
+            .await?;
+            Ok(())
+        }
+    }
+}
+
+/// Durations of the phases of one key-sync session, for logging and metrics.
+///
+/// The serve functions `begin` each phase; a phase ends when the next one begins or at `end`.
+/// If a session fails, the phase in progress is the one that failed.
+#[derive(Default, Debug)]
+pub struct KeySyncTimeline {
+    phases: Vec<(&'static str, Duration)>,
+    current: Option<(&'static str, Instant)>,
+    attestation_generation: Option<Duration>,
+}
+
+impl KeySyncTimeline {
+    fn begin(&mut self, phase: &'static str) {
+        self.end();
+        self.current = Some((phase, Instant::now()));
+    }
+
+    fn end(&mut self) {
+        if let Some((phase, started_at)) = self.current.take() {
+            self.phases.push((phase, started_at.elapsed()));
+        }
+    }
+
+    /// Generate this side's attestation document, recording how long it took.
+    fn generate_attestation<T>(&mut self, generate: impl FnOnce() -> Result<T>) -> Result<T> {
+        let started_at = Instant::now();
+        let result = generate();
+        self.attestation_generation = Some(started_at.elapsed());
+        result
+    }
+
+    /// How long generating this side's attestation document took, if it was generated.
+    pub fn attestation_generation(&self) -> Option<Duration> {
+        self.attestation_generation
+    }
+
+    /// The completed phases and their durations, in order.
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    /// The phase in progress, i.e., the phase that failed if the session did not complete.
+    pub fn failed_phase(&self) -> Option<&'static str> {
+        self.current.map(|(phase, _)| phase)
+    }
+
+    /// Log the duration of each completed phase and the failed phase (if any).
+    pub fn log_summary(&self, role: &str) {
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(phase, duration)| format!("{}={:.3}s", phase, duration.as_secs_f64()))
+            .collect();
+        let total: Duration = self.phases.iter().map(|(_, duration)| *duration).sum();
+        match self.failed_phase() {
+            None => tracing::info!(
+                "key-sync ({}) completed in {:.3}s: {}",
+                role,
+                total.as_secs_f64(),
+                phases.join(" ")
+            ),
+            Some(failed) => tracing::warn!(
+                "key-sync ({}) failed in phase {} after {:.3}s: {}",
+                role,
+                failed,
+                total.as_secs_f64(),
+                phases.join(" ")
+            ),
+        }
     }
 }
 
@@ -57,7 +175,7 @@ struct RemoteConfigMessage2 {
     // Should contain
     // nonce = leader_nonce,
     // public_key = follower public key
-    // user_data = follower_nonce
+    // user_data = follower_nonce (structured, see `user_data`)
     attestation_doc: Vec<u8>,
 }
 
@@ -66,12 +184,49 @@ struct RemoteConfigMessage2 {
 struct RemoteConfigMessage3 {
     // Should contain
     // nonce = follower_nonce,
-    // user_data = hash(encrypted_message)
+    // user_data = hash(encrypted_message) (structured, see `user_data`)
     attestation_doc: Vec<u8>,
     // RemoteConfigMessage3Contents encrypted with follower public key
     encrypted_message: Vec<u8>,
 }
 
+/// Why the leader aborted a key-sync session, see `KeySyncAbort`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+enum AbortReason {
+    /// The follower's message could not be decoded.
+    #[serde(rename = "invalid-message")]
+    InvalidMessage,
+    /// The follower's attestation document (or its public key) is invalid.
+    #[serde(rename = "invalid-attestation")]
+    InvalidAttestation,
+    /// The follower's measurements are not authorized by governance.
+    #[serde(rename = "unauthorized-measurement")]
+    UnauthorizedMeasurement,
+    /// The leader failed to encrypt or attest the secret state.
+    #[serde(rename = "internal-error")]
+    InternalError,
+}
+
+impl std::fmt::Display for AbortReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            AbortReason::InvalidMessage => "invalid message",
+            AbortReason::InvalidAttestation => "invalid attestation",
+            AbortReason::UnauthorizedMeasurement => "unauthorized measurement",
+            AbortReason::InternalError => "internal error",
+        };
+        f.write_str(reason)
+    }
+}
+
+// Alternative third message: from leader to follower, sent before closing the connection
+// when the leader refuses to share its secret state.
+#[derive(Serialize, Deserialize)]
+struct KeySyncAbort {
+    abort: AbortReason,
+    detail: String,
+}
+
 pub async fn read_message<R>(stream: &mut R) -> Result<Vec<u8>>
 where
     R: AsyncRead + Unpin,
@@ -98,13 +253,18 @@ where
     let len_bytes = (msg.len() as u32).to_be_bytes();
     stream.write_all(&len_bytes).await?;
     stream.write_all(msg).await?;
+    stream.flush().await?;
     Ok(())
 }
 
+/// Run key-sync as the follower. With `channel_binding` (see `channel_binding`), the
+/// attestations of both sides must be bound to the TLS session `stream` runs over.
 pub async fn serve_follower_key_sync<SM: Secmod + 'static, T>(
     attestor: &SM::Attestor,
     governance: &crate::config::Governance,
+    channel_binding: Option<&[u8]>,
     stream: &mut T,
+    timeline: &mut KeySyncTimeline,
 ) -> Result<Vec<u8>>
 where
     T: AsyncRead,
@@ -112,22 +272,32 @@ where
     T: Unpin,
 {
     // Read message
+    timeline.begin("receive");
     let message1_bytes = read_message(stream).await?;
     let message1: RemoteConfigMessage1 = serde_json::from_slice(&message1_bytes)?;
     let leader_nonce: [u8; 32] = message1.leader_nonce;
     tracing::info!("follower: received remote configuration request");
     // Generate follower components
+    timeline.begin("key-generation");
     let sec = k256::SecretKey::random(&mut rand_core::OsRng);
     let pubk = sec.public_key();
     let follower_nonce = random_nonce()?;
     // Generate attestation document with leader's nonce and our public key
-    let follower_att: Vec<u8> = SM::new_attestation(
-        attestor,
-        Some(ByteBuf::from(leader_nonce)),
-        Some(ByteBuf::from(pubk.to_sec1_bytes())),
-        Some(ByteBuf::from(follower_nonce)),
-    )?;
+    timeline.begin("attestation");
+    let user_data = AttestationUserData::new(PURPOSE_KEY_SYNC_FOLLOWER)
+        .with_nonce(&follower_nonce)
+        .with_channel_binding(channel_binding);
+    let user_data = user_data.encode()?;
+    let follower_att: Vec<u8> = timeline.generate_attestation(|| {
+        SM::new_attestation(
+            attestor,
+            Some(ByteBuf::from(leader_nonce)),
+            Some(ByteBuf::from(pubk.to_sec1_bytes())),
+            Some(user_data),
+        )
+    })?;
     // Send response with attestation doc
+    timeline.begin("exchange");
     let message2 = RemoteConfigMessage2 { attestation_doc: follower_att };
     let message2_bytes = serde_json::to_vec(&message2)?;
     tracing::trace!("follower: write message 2 / {} bytes", message2_bytes.len());
@@ -136,74 +306,239 @@ where
     tracing::info!("follower: waiting for attestation and encrypted message");
     let message3_bytes = read_message(stream).await?;
     tracing::trace!("follower: read message 3 / {} bytes", message3_bytes.len());
+    if let Ok(abort) = serde_json::from_slice::<KeySyncAbort>(&message3_bytes) {
+        bail!("leader aborted key-sync ({}): {}", abort.abort, abort.detail);
+    }
     let message3: RemoteConfigMessage3 = serde_json::from_slice(&message3_bytes)?;
+    timeline.begin("verify");
     let leader_att = SM::parse(&message3.attestation_doc)?;
+    tracing::debug!(format = %leader_att.format(), "parsed leader attestation");
     use sha2::Digest;
     let mut hasher = sha2::Sha256::new();
     hasher.update(&message3.encrypted_message);
     let enc_sha = hasher.finalize();
     use crate::secmod::AttestationDocumentExt;
-    leader_att.verify(
-        Some(&ByteBuf::from(&follower_nonce)),
-        None,
-        Some(&enc_sha.to_vec().into()),
-    )?;
-    authorize_measurements::<SM>(&attestor, governance, &leader_att).await?;
+    leader_att.verify(Some(&ByteBuf::from(follower_nonce)), None, None)?;
+    let Some(user_data) = leader_att.user_data() else {
+        bail!("leader attestation contains no user data");
+    };
+    let user_data = UserData::parse(user_data)?;
+    user_data.check_sha256(PURPOSE_KEY_SYNC_LEADER, &enc_sha)?;
+    user_data.check_channel_binding(channel_binding)?;
+    timeline.begin("authorization");
+    authorize_measurements::<SM>(attestor, governance, &leader_att).await?;
     // Decrypt the configuration using our secret key
-    let message_bytes = ecies::decrypt(&sec.to_bytes().as_slice(), &message3.encrypted_message)
-        .map_err(|x| anyhow!("decrypt {}", x))?;
+    timeline.begin("decryption");
+    let message_bytes =
+        crate::ecies_envelope::decrypt(sec.to_bytes().as_slice(), &message3.encrypted_message)?;
+    timeline.end();
     tracing::info!("key-sync successful (follower)");
     Ok(message_bytes)
 }
 
+/// Run key-sync as the leader, see `serve_follower_key_sync` for `channel_binding`.
 pub async fn serve_leader_key_sync<SM: Secmod + 'static, T>(
     attestor: &SM::Attestor,
     governance: &crate::config::Governance,
+    channel_binding: Option<&[u8]>,
     key_material: &[u8],
     stream: &mut T,
+    timeline: &mut KeySyncTimeline,
 ) -> Result<()>
 where
     T: AsyncRead,
     T: AsyncWrite,
     T: Unpin,
 {
+    timeline.begin("nonce");
     let leader_nonce = random_nonce()?;
+    timeline.begin("exchange");
     let message1 = RemoteConfigMessage1 { leader_nonce };
     let message1_bytes = serde_json::to_vec(&message1)?;
     tracing::trace!("leader: write message 1 / {} bytes", message1_bytes.len());
     write_message(stream, &message1_bytes).await?;
     let message2_bytes = read_message(stream).await?;
     tracing::trace!("leader: read message 2 / {} bytes", message2_bytes.len());
-    let message2: RemoteConfigMessage2 = serde_json::from_slice(&message2_bytes)?;
-    let follower_att = SM::parse(&message2.attestation_doc)?;
+    let message3 = match leader_message3::<SM>(
+        attestor,
+        governance,
+        channel_binding,
+        key_material,
+        &leader_nonce,
+        &message2_bytes,
+        timeline,
+    )
+    .await
+    {
+        Ok(message3) => message3,
+        Err((reason, e)) => {
+            // Tell the follower why, so that it does not just see the connection close.
+            let abort = KeySyncAbort { abort: reason, detail: e.to_string() };
+            if let Ok(abort_bytes) = serde_json::to_vec(&abort) {
+                let _ = write_message(stream, &abort_bytes).await;
+            }
+            return Err(e.context(format!("key-sync aborted ({})", reason)));
+        }
+    };
+    timeline.begin("send");
+    let message3_bytes = serde_json::to_vec(&message3)?;
+    tracing::trace!("leader: write message 3 / {} bytes", message3_bytes.len());
+    write_message(stream, &message3_bytes).await?;
+    timeline.end();
+    Ok(())
+}
+
+/// Check the follower's second message and, if it is authorized, produce the third message.
+/// On failure, returns the reason to send to the follower in a `KeySyncAbort`.
+async fn leader_message3<SM: Secmod + 'static>(
+    attestor: &SM::Attestor,
+    governance: &crate::config::Governance,
+    channel_binding: Option<&[u8]>,
+    key_material: &[u8],
+    leader_nonce: &[u8; 32],
+    message2_bytes: &[u8],
+    timeline: &mut KeySyncTimeline,
+) -> Result<RemoteConfigMessage3, (AbortReason, anyhow::Error)> {
+    use AbortReason::*;
+    let message2: RemoteConfigMessage2 =
+        serde_json::from_slice(message2_bytes).map_err(|e| (InvalidMessage, e.into()))?;
+    timeline.begin("verify");
+    let follower_att = SM::parse(&message2.attestation_doc).map_err(|e| (InvalidAttestation, e))?;
+    tracing::debug!(format = %follower_att.format(), "parsed follower attestation");
     use crate::secmod::AttestationDocumentExt;
-    follower_att.verify(Some(&ByteBuf::from(&leader_nonce)), None, None)?;
+    follower_att
+        .verify(Some(&ByteBuf::from(leader_nonce)), None, None)
+        .map_err(|e| (InvalidAttestation, e))?;
     let default_buf = ByteBuf::new();
-    let follower_nonce = follower_att.user_data().unwrap_or(&default_buf);
+    let follower_user_data = UserData::parse(follower_att.user_data().unwrap_or(&default_buf))
+        .map_err(|e| (InvalidAttestation, e))?;
+    let follower_nonce =
+        follower_user_data.nonce(PURPOSE_KEY_SYNC_FOLLOWER).map_err(|e| (InvalidAttestation, e))?;
+    follower_user_data
+        .check_channel_binding(channel_binding)
+        .map_err(|e| (InvalidAttestation, e))?;
+    // Reject follower public keys that are not valid secp256k1 points before doing anything else.
+    let pubk = follower_att.public_key().unwrap_or(&default_buf);
+    let pubk = k256::PublicKey::from_sec1_bytes(pubk).map_err(|_| {
+        (InvalidAttestation, anyhow!("follower public key is not a valid secp256k1 point"))
+    })?;
     // Ensure that the follower's PCRs are authorized.
-    authorize_measurements::<SM>(&attestor, governance, &follower_att).await?;
+    timeline.begin("authorization");
+    authorize_measurements::<SM>(attestor, governance, &follower_att)
+        .await
+        .map_err(|e| (UnauthorizedMeasurement, e))?;
+    timeline.begin("encryption");
     let ss = key_material;
-    let pubk = follower_att.public_key().unwrap_or(&default_buf);
-    if pubk.len() < 32 {
-        bail!("follower public key must be at least 32 bytes")
-    }
-    let enc_ss = ecies::encrypt(&pubk, ss).map_err(|x| anyhow!("encrypt {}", x))?;
+    let enc_ss = crate::ecies_envelope::encrypt(&pubk.to_sec1_bytes(), ss)
+        .map_err(|e| (InternalError, e))?;
     use sha2::Digest;
     let mut hasher = sha2::Sha256::new();
     hasher.update(&enc_ss);
     let enc_sha = hasher.finalize();
     // Now we generate an attestation document using the follower_nonce and enc_sha.
-    let leader_att: Vec<u8> = SM::new_attestation(
-        &attestor,
-        Some(follower_nonce.clone()),
-        None,
-        Some(enc_sha.to_vec().into()),
-    )?;
-    let message3 = RemoteConfigMessage3 { attestation_doc: leader_att, encrypted_message: enc_ss };
-    let message3_bytes = serde_json::to_vec(&message3)?;
-    tracing::trace!("leader: write message 3 / {} bytes", message3_bytes.len());
-    write_message(stream, &message3_bytes).await?;
-    Ok(())
+    timeline.begin("attestation");
+    let user_data = AttestationUserData::new(PURPOSE_KEY_SYNC_LEADER)
+        .with_sha256(&enc_sha)
+        .with_channel_binding(channel_binding)
+        .encode()
+        .map_err(|e| (InternalError, e))?;
+    let leader_att: Vec<u8> = timeline
+        .generate_attestation(|| {
+            SM::new_attestation(
+                attestor,
+                Some(ByteBuf::from(follower_nonce)),
+                None,
+                Some(user_data),
+            )
+        })
+        .map_err(|e| (InternalError, e))?;
+    Ok(RemoteConfigMessage3 { attestation_doc: leader_att, encrypted_message: enc_ss })
+}
+
+/// The server name followers send with `key-sync-tls`; the leader's certificate is not checked
+/// against it (see `AnyServerCertificate`).
+const KEY_SYNC_SERVER_NAME: &str = "key-sync";
+
+/// Accepts any server certificate, but still checks that the server holds its key.
+///
+/// A follower cannot authenticate the leader's self-signed certificate before key-sync;
+/// the leader is authenticated by its attestation within the protocol instead. Both
+/// attestations bind the session's `channel_binding`, so a host that terminates TLS itself
+/// (with any certificate) and relays to the leader fails key-sync.
+#[derive(Debug)]
+struct AnyServerCertificate(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for AnyServerCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &pki_types::CertificateDer<'_>,
+        _intermediates: &[pki_types::CertificateDer<'_>],
+        _server_name: &pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Wrap the follower's key-sync connection in TLS (see `key-sync-tls`).
+pub async fn connect_tls<T>(stream: T) -> Result<tokio_rustls::client::TlsStream<T>>
+where
+    T: AsyncRead,
+    T: AsyncWrite,
+    T: Unpin,
+{
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        // Exported keying material is only unique per session with TLS 1.3 (RFC 9266).
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyServerCertificate(provider)))
+        .with_no_client_auth();
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+    let server_name = pki_types::ServerName::try_from(KEY_SYNC_SERVER_NAME)?;
+    connector.connect(server_name, stream).await.context("key-sync TLS handshake failed")
+}
+
+/// The label for `channel_binding` keying material.
+const KEY_SYNC_EXPORTER_LABEL: &[u8] = b"EXPORTER-sovereign-key-sync";
+
+/// Keying material exported from a key-sync TLS session, which both sides bind in their
+/// attestation's user data so that the attestations cannot be relayed to another session.
+pub fn channel_binding<D>(connection: &rustls::ConnectionCommon<D>) -> Result<[u8; 32]> {
+    Ok(connection.export_keying_material([0u8; 32], KEY_SYNC_EXPORTER_LABEL, None)?)
 }
 
 fn random_nonce() -> Result<[u8; 32]> {
@@ -251,8 +586,10 @@ mod tests {
                 let result = serve_leader_key_sync::<MockSecmod, _>(
                     &attestor,
                     &governance,
+                    None,
                     &secret,
                     &mut server_stream,
+                    &mut KeySyncTimeline::default(),
                 )
                 .await;
                 tracing::trace!("finisehd serve_leader_key_sync");
@@ -268,7 +605,9 @@ mod tests {
                 let result = serve_follower_key_sync::<MockSecmod, _>(
                     &attestor,
                     &governance,
+                    None,
                     &mut client_stream,
+                    &mut KeySyncTimeline::default(),
                 )
                 .await;
                 tracing::trace!("finished serve_follower_key_sync");
@@ -295,4 +634,193 @@ mod tests {
         assert!(follower_secret == secret);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_key_sync_rejects_invalid_follower_public_key() -> Result<()> {
+        let (mut server_stream, mut client_stream) = tokio::io::duplex(1024);
+        let attestor = MockSecmod::init_debug_attestor();
+
+        let leader_handle = tokio::spawn(async move {
+            let mut timeline = KeySyncTimeline::default();
+            let result = serve_leader_key_sync::<MockSecmod, _>(
+                &attestor,
+                &Governance::TestingOnly,
+                None,
+                &[0xaau8, 0xbbu8, 0xccu8],
+                &mut server_stream,
+                &mut timeline,
+            )
+            .await;
+            (result, timeline)
+        });
+
+        // Act as a follower which sends a bogus public key.
+        let message1: RemoteConfigMessage1 =
+            serde_json::from_slice(&read_message(&mut client_stream).await?)?;
+        // The x-coordinate exceeds the field modulus, so this is not a point on the curve.
+        let mut bogus_public_key = vec![0x02u8];
+        bogus_public_key.extend_from_slice(&[0xffu8; 32]);
+        let attestation_doc = MockSecmod::new_attestation(
+            &attestor,
+            Some(ByteBuf::from(message1.leader_nonce)),
+            Some(ByteBuf::from(bogus_public_key)),
+            Some(ByteBuf::from(random_nonce()?)),
+        )?;
+        let message2 = RemoteConfigMessage2 { attestation_doc };
+        write_message(&mut client_stream, &serde_json::to_vec(&message2)?).await?;
+
+        // The leader explains why it aborts before closing the connection.
+        let abort: KeySyncAbort = serde_json::from_slice(&read_message(&mut client_stream).await?)?;
+        assert_eq!(abort.abort, AbortReason::InvalidAttestation);
+        assert!(abort.detail.contains("not a valid secp256k1 point"));
+
+        let (result, timeline) = leader_handle.await?;
+        let error = result.expect_err("leader must reject an invalid public key");
+        assert!(format!("{:#}", error).contains("not a valid secp256k1 point"));
+        assert_eq!(timeline.failed_phase(), Some("verify"));
+        let completed: Vec<&str> = timeline.phases().iter().map(|(phase, _)| *phase).collect();
+        assert_eq!(completed, ["nonce", "exchange"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_key_sync_follower_surfaces_abort() -> Result<()> {
+        let (mut server_stream, mut client_stream) = tokio::io::duplex(1024);
+        let attestor = MockSecmod::init_debug_attestor();
+
+        let follower_handle = tokio::spawn(async move {
+            serve_follower_key_sync::<MockSecmod, _>(
+                &attestor,
+                &Governance::TestingOnly,
+                None,
+                &mut client_stream,
+                &mut KeySyncTimeline::default(),
+            )
+            .await
+        });
+
+        // Act as a leader which refuses the follower.
+        let message1 = RemoteConfigMessage1 { leader_nonce: random_nonce()? };
+        write_message(&mut server_stream, &serde_json::to_vec(&message1)?).await?;
+        read_message(&mut server_stream).await?;
+        let abort = KeySyncAbort {
+            abort: AbortReason::UnauthorizedMeasurement,
+            detail: "message not found".to_string(),
+        };
+        write_message(&mut server_stream, &serde_json::to_vec(&abort)?).await?;
+
+        let error = follower_handle.await?.expect_err("follower must fail on abort");
+        assert_eq!(
+            error.to_string(),
+            "leader aborted key-sync (unauthorized measurement): message not found"
+        );
+        Ok(())
+    }
+
+    /// A TLS acceptor serving the certificate of a new key server, like on the HTTPS ports.
+    fn key_sync_tls_acceptor(config: &SovereignConfig) -> Result<tokio_rustls::TlsAcceptor> {
+        use crate::key_server::{KeyServer, SecretKeyMaterial};
+
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let secret = SecretKeyMaterial::generate_random(2, &mut rand_core::OsRng)?;
+        let key = KeyServer::<MockSecmod>::new(
+            MockSecmod::init_debug_attestor(),
+            config.clone(),
+            secret,
+        )?;
+        let server_config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(key.cert.clone());
+        Ok(tokio_rustls::TlsAcceptor::from(Arc::new(server_config)))
+    }
+
+    #[tokio::test]
+    async fn test_key_sync_tls() -> Result<()> {
+        let attestor = MockSecmod::init_debug_attestor();
+        let config =
+            SovereignConfig { governance: Governance::TestingOnly, ..SovereignConfig::default() };
+        let acceptor = key_sync_tls_acceptor(&config)?;
+        let (server_stream, client_stream) = tokio::io::duplex(1024);
+
+        let leader = async {
+            let mut stream = acceptor.accept(server_stream).await?;
+            let binding = channel_binding(stream.get_ref().1)?;
+            serve_leader_key_sync::<MockSecmod, _>(
+                &attestor,
+                &config.governance,
+                Some(&binding),
+                &[0xaa, 0xbb],
+                &mut stream,
+                &mut KeySyncTimeline::default(),
+            )
+            .await
+        };
+        let follower = async {
+            let mut stream = connect_tls(client_stream).await?;
+            let binding = channel_binding(stream.get_ref().1)?;
+            serve_follower_key_sync::<MockSecmod, _>(
+                &attestor,
+                &config.governance,
+                Some(&binding),
+                &mut stream,
+                &mut KeySyncTimeline::default(),
+            )
+            .await
+        };
+        let (leader, follower) = tokio::join!(leader, follower);
+        leader?;
+        assert_eq!(follower?, vec![0xaa, 0xbb]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_key_sync_tls_rejects_relay() -> Result<()> {
+        let attestor = MockSecmod::init_debug_attestor();
+        let config =
+            SovereignConfig { governance: Governance::TestingOnly, ..SovereignConfig::default() };
+        let acceptor = key_sync_tls_acceptor(&config)?;
+        // The host terminates the follower's TLS with a key of its own and relays to the leader.
+        let relay_acceptor = key_sync_tls_acceptor(&config)?;
+        let (server_stream, relay_client_stream) = tokio::io::duplex(1024);
+        let (relay_server_stream, client_stream) = tokio::io::duplex(1024);
+
+        let leader = async {
+            let mut stream = acceptor.accept(server_stream).await?;
+            let binding = channel_binding(stream.get_ref().1)?;
+            serve_leader_key_sync::<MockSecmod, _>(
+                &attestor,
+                &config.governance,
+                Some(&binding),
+                &[0xaa, 0xbb],
+                &mut stream,
+                &mut KeySyncTimeline::default(),
+            )
+            .await
+        };
+        let relay = tokio::spawn(async move {
+            let mut follower_side = relay_acceptor.accept(relay_server_stream).await?;
+            let mut leader_side = connect_tls(relay_client_stream).await?;
+            let _ = tokio::io::copy_bidirectional(&mut follower_side, &mut leader_side).await;
+            anyhow::Ok(())
+        });
+        let follower = async {
+            let mut stream = connect_tls(client_stream).await?;
+            let binding = channel_binding(stream.get_ref().1)?;
+            serve_follower_key_sync::<MockSecmod, _>(
+                &attestor,
+                &config.governance,
+                Some(&binding),
+                &mut stream,
+                &mut KeySyncTimeline::default(),
+            )
+            .await
+        };
+        let (leader, follower) = tokio::join!(leader, follower);
+        relay.abort();
+        let leader = leader.err().context("leader accepted a relayed session")?;
+        assert!(format!("{:#}", leader).contains("bound to another TLS session"), "{:#}", leader);
+        let follower = follower.err().context("follower accepted a relayed session")?;
+        assert!(follower.to_string().contains("invalid attestation"), "{:#}", follower);
+        Ok(())
+    }
 }
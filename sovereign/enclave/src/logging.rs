@@ -0,0 +1,41 @@
+//! Tracing setup with a log level that can be changed at runtime.
+
+use anyhow::{bail, Result};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, Registry};
+
+/// Handle to change the log level of the global subscriber installed by `init`.
+pub type LogLevelHandle = reload::Handle<LevelFilter, Registry>;
+
+/// Map a trace level (Trace = 0, Debug = 1, Info = 2, Warn = 3, Error = 4) to a level filter.
+pub fn level_filter(trace_level: usize) -> Result<LevelFilter> {
+    Ok(match trace_level {
+        0 => LevelFilter::TRACE,
+        1 => LevelFilter::DEBUG,
+        2 => LevelFilter::INFO,
+        3 => LevelFilter::WARN,
+        4 => LevelFilter::ERROR,
+        _ => bail!("invalid trace level {}; expected 0 (trace) to 4 (error)", trace_level),
+    })
+}
+
+/// Install the global subscriber, starting at `trace_level` (INFO for unknown values).
+pub fn init(trace_level: usize) -> LogLevelHandle {
+    let (filter, handle) =
+        reload::Layer::new(level_filter(trace_level).unwrap_or(LevelFilter::INFO));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_target(false).with_file(true).with_line_number(true))
+        .init();
+    handle
+}
+
+/// Change the log level of the global subscriber.
+#[cfg(feature = "signing")]
+pub fn set_level(handle: &LogLevelHandle, trace_level: usize) -> Result<()> {
+    let filter = level_filter(trace_level)?;
+    handle.modify(|current| *current = filter)?;
+    tracing::warn!("log level set to {}", filter);
+    Ok(())
+}
@@ -5,6 +5,10 @@
 //! - subsequent sovereigns run the key sync protocol with an existing sovereign to fetch the secret
 //! - sovereign' code, and the instance they run on, is authorized by a governance committee inside a Safe smart contract
 //! - currently, only AWS Nitro Enclaves are supported, but the code is prepared for supporting additional TEE variants, such as TDX
+//!   (see `secmod::DocumentFormat`)
+//!
+//! Without the `signing` feature, the enclave is built attestation-only: it serves attestation
+//! documents and metrics, but has no signing keys, gRPC service, or key-sync.
 
 use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
@@ -15,14 +19,27 @@ use secmod::{AttestationDocument, Secmod};
 use serde_bytes::ByteBuf;
 use std::{future::Future, pin::Pin, sync::Arc, time::Instant};
 
+#[cfg(feature = "signing")]
+mod break_glass;
 mod config;
+mod ecies_envelope;
+#[cfg(feature = "signing")]
 mod grpc;
 mod http;
+mod idle_timeout;
 mod key_server;
+#[cfg(feature = "signing")]
 mod key_sync;
+mod logging;
 mod monitoring;
+mod nonce_cache;
 mod safe;
 mod secmod;
+#[cfg(feature = "signing")]
+mod typed_data;
+mod user_data;
+mod version;
+mod websocket;
 
 #[cfg(feature = "nsm")]
 mod nsm;
@@ -30,15 +47,68 @@ mod nsm;
 #[cfg(feature = "test-utils")]
 mod mock_secmod;
 
-use config::{SecretKeyRetrieval, SovereignConfig};
+#[cfg(feature = "signing")]
+use config::SecretKeyRetrieval;
+use config::SovereignConfig;
 
+use idle_timeout::IdleTimeoutStream;
 use key_server::{KeyServer, SecretKeyMaterial};
 
+/// Default interval between heartbeat log messages.
+const DEFAULT_HEARTBEAT_INTERVAL_SECONDS: u64 = 60;
+
+/// Default time after which idle host connections are closed.
+const DEFAULT_IDLE_TIMEOUT_SECONDS: u64 = 300;
+
+/// Default number of requests served on one HTTP(S) connection before closing it.
+const DEFAULT_HTTP_MAX_REQUESTS_PER_CONNECTION: u64 = 100;
+
+/// Default time to wait for the next request on an HTTP(S) keep-alive connection.
+const DEFAULT_HTTP_KEEP_ALIVE_TIMEOUT_SECONDS: u64 = 30;
+
+/// Delay before retrying after the first of consecutive accept errors; doubled on each further error.
+const INITIAL_ACCEPT_BACKOFF: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Cap on the delay between retries of a follower's key-sync (see `key-sync-connect-attempts`).
+#[cfg(feature = "signing")]
+const MAX_KEY_SYNC_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Default cap on the delay between retries after accept errors.
+const DEFAULT_MAX_ACCEPT_BACKOFF_MILLISECONDS: u64 = 5000;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[arg(long, help = "Configuration for sovereign as a JSON string")]
     config: Option<String>,
+    #[arg(long, help = "Validate the configuration (including Safe connectivity) and exit")]
+    validate: bool,
+    #[arg(
+        long,
+        help = "Print the measurement messages governance must sign for --pcr0, --pcr1, --pcr2 \
+                (and --instance-id) and exit"
+    )]
+    measurement_manifest: bool,
+    #[arg(long, help = "PCR-0 of the enclave image in hex (e.g., from `nitro-cli describe-eif`)")]
+    pcr0: Option<String>,
+    #[arg(long, help = "PCR-1 of the enclave image in hex")]
+    pcr1: Option<String>,
+    #[arg(long, help = "PCR-2 of the enclave image in hex")]
+    pcr2: Option<String>,
+    #[arg(long, help = "Instance ID of the host (e.g., i-1234567890abcdef0)")]
+    instance_id: Option<String>,
+    #[arg(
+        long,
+        help = "Print the Safe message hash (and intermediate hashes) for --message \
+                with --safe-address on --chain-id and exit"
+    )]
+    safe_hash: bool,
+    #[arg(long, help = "Chain ID of the Safe")]
+    chain_id: Option<u64>,
+    #[arg(long, help = "Address of the Safe (0x followed by 40 hex digits)")]
+    safe_address: Option<String>,
+    #[arg(long, help = "Message to authorize (e.g., an AWS-CODE:... measurement)")]
+    message: Option<String>,
 }
 
 /// See `sovereign_main` for further information.
@@ -46,6 +116,57 @@ fn main() {
     // Parse command-line arguments
     let args = Args::parse();
 
+    #[cfg(feature = "nsm")]
+    type MainSecmod = nsm::Nsm;
+
+    #[cfg(all(not(feature = "nsm"), feature = "test-utils"))]
+    type MainSecmod = mock_secmod::MockSecmod;
+
+    #[cfg(any(feature = "nsm", feature = "test-utils"))]
+    if args.measurement_manifest {
+        let (Some(pcr0), Some(pcr1), Some(pcr2)) = (&args.pcr0, &args.pcr1, &args.pcr2) else {
+            eprintln!("--measurement-manifest requires --pcr0, --pcr1, and --pcr2");
+            std::process::exit(1);
+        };
+        match measurement_manifest::<MainSecmod>([pcr0, pcr1, pcr2], args.instance_id) {
+            Ok(manifest) => {
+                for message in manifest {
+                    println!("{}", message);
+                }
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if args.safe_hash {
+        let (Some(chain_id), Some(safe_address), Some(message)) =
+            (args.chain_id, &args.safe_address, &args.message)
+        else {
+            eprintln!("--safe-hash requires --chain-id, --safe-address, and --message");
+            std::process::exit(1);
+        };
+        if let Err(e) = safe::parse_wallet_address(safe_address) {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        let details = match safe::safe_hash_details(chain_id, safe_address, message) {
+            Ok(details) => details,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(1);
+            }
+        };
+        println!("message hash (EIP-191):  {}", details.message_hash);
+        println!("domain separator:        {}", details.domain_hash);
+        println!("SafeMessage struct hash: {}", details.struct_hash);
+        println!("Safe message hash:       {}", details.safe_hash);
+        std::process::exit(0);
+    }
+
     // Handle sovereign configuration
     let config: SovereignConfig = {
         if let Some(config_str) = args.config {
@@ -55,32 +176,18 @@ fn main() {
             std::process::exit(1);
         }
     };
-    let trace_level = match config.trace_level {
-        0 => tracing::Level::TRACE,
-        1 => tracing::Level::DEBUG,
-        2 => tracing::Level::INFO,
-        3 => tracing::Level::WARN,
-        4 => tracing::Level::ERROR,
-        _ => tracing::Level::INFO, // default to INFO for unknown values
-    };
-    tracing_subscriber::fmt()
-        .with_target(false)
-        .with_file(true)
-        .with_line_number(true)
-        .with_max_level(trace_level)
-        .init();
-
-    #[cfg(feature = "nsm")]
-    type MainSecmod = nsm::Nsm;
-
-    #[cfg(all(not(feature = "nsm"), feature = "test-utils"))]
-    type MainSecmod = mock_secmod::MockSecmod;
+    let log_level = logging::init(config.trace_level);
 
     #[cfg(any(feature = "nsm", feature = "test-utils"))]
     {
+        if args.validate {
+            let ok = validate_main::<MainSecmod>(&config);
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+
         tracing::info!("starting sovereign...");
 
-        let result = sovereign_main::<MainSecmod>(config);
+        let result = sovereign_main::<MainSecmod>(config, log_level);
 
         if let Err(e) = result {
             tracing::error!("fatal error: {}", e);
@@ -95,53 +202,271 @@ fn main() {
     }
 }
 
+/// The messages that governance must sign to authorize an enclave image with the given
+/// hex-encoded PCR-0, PCR-1, and PCR-2 and, optionally, an instance (see `--measurement-manifest`).
+///
+/// These match the `code_measurement` and `instance_measurement` checked during key-sync.
+/// Extend the PCR values with the public keys corresponding to the secret key material
+/// (the certificate key and the first `MIN_SECRET_KEYS` signing keys, which `KeyServer::new`
+/// guarantees to exist), followed by the configuration. Attestation-only builds have no signing
+/// keys and measure empty values in their place, keeping the configuration in its slot.
+fn measure_startup<SM: Secmod>(state: &KeyServer<SM>) -> Result<()> {
+    // TODO: consider using a Merkle tree of public keys so that any public key can be verified.
+    let mut measurements = vec![state.cert_public_key_der.to_vec()];
+    for pair in state.signing_keys().pairs.iter().take(key_server::MIN_SECRET_KEYS) {
+        measurements.push(pair.public_key.to_sec1_bytes().to_vec());
+    }
+    measurements.resize(key_server::CONFIG_MEASUREMENT_SLOT, Vec::new());
+    measurements.push(state.config_measurement()?);
+    state.measure(measurements)
+}
+
+fn measurement_manifest<SM: Secmod>(
+    pcrs: [&str; 3],
+    instance_id: Option<String>,
+) -> Result<Vec<String>> {
+    let mut normalized = Vec::new();
+    for (i, pcr) in pcrs.iter().enumerate() {
+        let bytes = hex::decode(pcr.trim()).with_context(|| format!("PCR-{} is not hex", i))?;
+        if bytes.len() != 48 {
+            bail!("PCR-{} has {} bytes; expected 48", i, bytes.len());
+        }
+        // Attestation documents are measured using lower-case hex.
+        normalized.push(hex::encode(bytes));
+    }
+    let mut manifest = vec![SM::measure_code(normalized.join(":"))];
+    if let Some(instance_id) = instance_id {
+        manifest.push(SM::measure_instance(instance_id));
+    }
+    Ok(manifest)
+}
+
+/// Check the configuration without generating keys or opening listeners (see `--validate`).
+/// Prints one line per check and returns whether all checks passed.
+#[tokio::main]
+async fn validate_main<SM: Secmod + 'static>(config: &SovereignConfig) -> bool {
+    use crate::config::{Governance, SafeConfig};
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let mut checks: Vec<(String, Result<()>)> = vec![("config".to_string(), config.validate())];
+    checks.push(("nsm".to_string(), SM::configure(&config.nsm)));
+    let safes: Vec<&SafeConfig> = match &config.governance {
+        Governance::TestingOnly => Vec::new(),
+        Governance::Safe(safe) => vec![safe],
+        Governance::MultiSafe { safes, .. } => safes.iter().collect(),
+    };
+    for safe in safes {
+        let address = safe::parse_wallet_address(&safe.wallet_address).map(|_| ());
+        checks.push((format!("safe {} wallet address", safe.wallet_address), address));
+        let connectivity = safe::check_safe_connectivity::<SM>(safe).await;
+        checks.push((format!("safe {} connectivity", safe.wallet_address), connectivity));
+    }
+    let mut ok = true;
+    for (name, result) in checks {
+        match result {
+            Ok(()) => println!("ok: {}", name),
+            Err(e) => {
+                println!("FAILED: {}: {:#}", name, e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Retrieve the secret key material from the leader on `port`, retrying with exponential backoff
+/// while the leader cannot be reached (see `key-sync-connect-attempts`).
+#[cfg(feature = "signing")]
+async fn retrieve_key_material<SM: Secmod + 'static>(
+    attestor: &SM::Attestor,
+    config: &SovereignConfig,
+    port: u32,
+) -> Result<(zeroize::Zeroizing<Vec<u8>>, key_sync::KeySyncTimeline)> {
+    let attempts = config.key_sync_connect_attempts();
+    let mut delay = config.key_sync_retry_delay();
+    let mut attempt = 1;
+    loop {
+        tracing::info!(
+            "retrieving secret key material from VSOCK {} (attempt {}/{})...",
+            port,
+            attempt,
+            attempts
+        );
+        let mut timeline = key_sync::KeySyncTimeline::default();
+        let result = follower_key_sync::<SM>(attestor, config, port, &mut timeline).await;
+        timeline.log_summary("follower");
+        match result {
+            Ok(key_material) => return Ok((zeroize::Zeroizing::new(key_material), timeline)),
+            // Failures after the leader's first message (e.g., a rejected attestation) are final.
+            Err(e) if attempt < attempts && timeline.phases().is_empty() => {
+                tracing::warn!("key-sync failed: {:#}; retrying in {:?}", e, delay);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_KEY_SYNC_RETRY_DELAY);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Connect to the leader on `port` and run one key-sync session as the follower.
+#[cfg(feature = "signing")]
+async fn follower_key_sync<SM: Secmod + 'static>(
+    attestor: &SM::Attestor,
+    config: &SovereignConfig,
+    port: u32,
+    timeline: &mut key_sync::KeySyncTimeline,
+) -> Result<Vec<u8>> {
+    let mut stream = SM::connect(port).await?;
+    tracing::debug!("connected accepted on VSOCK {}...", port);
+    if config.key_sync_tls() {
+        let mut stream = key_sync::connect_tls(stream).await?;
+        let channel_binding = key_sync::channel_binding(stream.get_ref().1)?;
+        key_sync::serve_follower_key_sync::<SM, _>(
+            attestor,
+            &config.governance,
+            Some(&channel_binding),
+            &mut stream,
+            timeline,
+        )
+        .await
+    } else {
+        key_sync::serve_follower_key_sync::<SM, _>(
+            attestor,
+            &config.governance,
+            None,
+            &mut stream,
+            timeline,
+        )
+        .await
+    }
+}
+
+/// Check that key-sync delivered as many secret keys as configured (see `expected-num-keys`),
+/// so that a follower does not silently adopt the key material of a differently configured pool.
+#[cfg(feature = "signing")]
+fn check_num_keys(config: &SovereignConfig, key_material: &SecretKeyMaterial) -> Result<()> {
+    let Some(expected) = config.expected_num_keys() else {
+        return Ok(());
+    };
+    let received = key_material.secret_keys.len();
+    if received != expected as usize {
+        bail!(
+            "key-sync delivered {} secret keys but {} are expected; \
+             is the leader running a different configuration?",
+            received,
+            expected
+        );
+    }
+    Ok(())
+}
+
+/// Remove the socket at `path` left behind by an earlier process, refusing to clobber it if a
+/// live process still accepts connections on it (e.g., another sovereign).
+#[cfg(feature = "signing")]
+fn remove_stale_socket(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("cannot inspect {}", path.display())),
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            bail!("{} exists and is not a socket", path.display())
+        }
+        Ok(_) => {}
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("socket {} already in use by a live process", path.display());
+    }
+    std::fs::remove_file(path)
+        .with_context(|| format!("cannot remove stale socket {}", path.display()))
+}
+
+/// Serve key-sync to one follower connected on `stream`, with the `channel_binding` of the TLS
+/// session `stream` runs over, if any.
+#[cfg(feature = "signing")]
+async fn leader_key_sync<SM: Secmod + 'static, T>(
+    state: &KeyServer<SM>,
+    channel_binding: Option<&[u8]>,
+    stream: &mut T,
+) -> Result<()>
+where
+    T: tokio::io::AsyncRead,
+    T: tokio::io::AsyncWrite,
+    T: Unpin,
+{
+    let key_material = state.extract_secret_key_material().to_bytes()?;
+    state.metrics.key_sync_active.inc();
+    let mut timeline = key_sync::KeySyncTimeline::default();
+    let result = key_sync::serve_leader_key_sync::<SM, _>(
+        &state.attestor,
+        &state.config.governance,
+        channel_binding,
+        &key_material,
+        stream,
+        &mut timeline,
+    )
+    .await;
+    state.metrics.key_sync_active.dec();
+    timeline.log_summary("leader");
+    state.metrics.observe_key_sync_phases("leader", &timeline);
+    result
+}
+
 #[tokio::main]
-pub async fn sovereign_main<SM: Secmod + 'static>(config: SovereignConfig) -> Result<()> {
+pub async fn sovereign_main<SM: Secmod + 'static>(
+    config: SovereignConfig,
+    log_level: logging::LogLevelHandle,
+) -> Result<()> {
     config.validate()?;
+    ecies_envelope::self_test()?;
 
     // TODO: this is needed for something - don't remember what...
     rustls::crypto::ring::default_provider()
         .install_default()
         .map_err(|e| anyhow!("failed to install rustls crypto provider: {:?}", e))?;
 
+    SM::configure(&config.nsm)?;
+    http::configure_circuit_breakers(
+        config.host_circuit_breaker_failures.unwrap_or(http::DEFAULT_CIRCUIT_BREAKER_FAILURES),
+        config
+            .host_circuit_breaker_cooldown_seconds
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(http::DEFAULT_CIRCUIT_BREAKER_COOLDOWN),
+    )?;
     tracing::info!("initializing attestor...");
     let attestor = SM::init_attestor()?;
 
     // Generate or retrieve secret key material for this new sovereign according to the configuration.
-    let secret_key_material = match config.secret_keys_from {
+    #[cfg(feature = "signing")]
+    let (secret_key_material, key_sync_timeline) = match config.secret_keys_from {
         SecretKeyRetrieval::Generate(num_keys) => {
             tracing::info!("generating {} secret keys...", num_keys);
-            SecretKeyMaterial::generate_random(num_keys, &mut rand_core::OsRng)?
+            (SecretKeyMaterial::generate_random(num_keys, &mut rand_core::OsRng)?, None)
         }
         SecretKeyRetrieval::KeySync(port) => {
-            tracing::info!("retreiving secret key material from VSOCK {}...", port);
-            let mut stream = SM::connect(port).await?;
-            tracing::debug!("connected accepted on VSOCK {}...", port);
-            let key_material = key_sync::serve_follower_key_sync::<SM, _>(
-                &attestor,
-                &config.governance,
-                &mut stream,
-            )
-            .await?;
-            // TODO: consider not using JSON here. Just receive the raw bytes?
-            let secret_key_material: SecretKeyMaterial = serde_json::from_slice(&key_material)?;
+            let (key_material, timeline) =
+                retrieve_key_material::<SM>(&attestor, &config, port).await?;
+            let secret_key_material = SecretKeyMaterial::from_bytes(&key_material)?;
+            check_num_keys(&config, &secret_key_material)?;
             tracing::info!("secret key material received");
-            secret_key_material
+            (secret_key_material, Some(timeline))
         }
     };
+    // Attestation-only builds need just the certificate key.
+    #[cfg(not(feature = "signing"))]
+    let secret_key_material = {
+        tracing::info!("attestation-only build: not generating signing keys");
+        SecretKeyMaterial::generate_random(0, &mut rand_core::OsRng)?
+    };
 
     // Create the full state from the config and the secret key material.
     let state = KeyServer::new(attestor, config, secret_key_material)?;
+    #[cfg(feature = "signing")]
+    if let Some(timeline) = key_sync_timeline {
+        state.metrics.observe_key_sync_phases("follower", &timeline);
+    }
 
-    // Extend the PCR values with the public keys corresponding to the secret key material.
-    // TODO: consider using a Merkle tree of public keys so that any public key can be verified.
-    let measurements = vec![
-        state.cert_public_key_der.to_vec(),
-        state.pairs[0].public_key.to_sec1_bytes().to_vec(),
-        state.pairs[1].public_key.to_sec1_bytes().to_vec(),
-        serde_json::to_vec(&state.config)?,
-    ];
-    SM::measure_enclave(&state.attestor, measurements)?;
+    measure_startup(&state)?;
 
     // Wrap inside an Arc as it needs to be shared between multiple async threads.
     // Not ideal, but still looking for a better solution...
@@ -150,75 +475,163 @@ pub async fn sovereign_main<SM: Secmod + 'static>(config: SovereignConfig) -> Re
     // Local alias to state.config.
     let config = &state.config;
 
-    let server_config = {
-        let certificate_der = state.cert.der().clone();
-        let cert_chain = vec![certificate_der];
-        let builder = rustls::ServerConfig::builder();
-        builder
-            .with_no_client_auth()
-            .with_single_cert(cert_chain, state.cert_secret_key_der.clone_key())
-            .context("failed to create TLS config")?
+    // The certificate is resolved per handshake, so that `RotateCertificate` takes effect
+    // on all TLS ports without restarting.
+    let server_config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(state.cert.clone());
+    // gRPC requires HTTP/2, negotiated using ALPN.
+    #[cfg(feature = "signing")]
+    let grpc_tls_acceptor = {
+        let mut grpc_server_config = server_config.clone();
+        grpc_server_config.alpn_protocols = vec![b"h2".to_vec()];
+        tokio_rustls::TlsAcceptor::from(Arc::new(grpc_server_config))
     };
     let tls_acceptor =
         Arc::new(tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(server_config)));
+    #[cfg(feature = "signing")]
+    let key_sync_tls_acceptor = config.key_sync_tls().then(|| tls_acceptor.clone());
     tracing::debug!("https configured");
 
-    let _grpc_handle = {
+    let sealing_key = state.sealing_key()?;
+    tracing::info!(
+        "config sealing public key: {}",
+        hex::encode(sealing_key.public_key().to_sec1_bytes())
+    );
+
+    // The gRPC servers are supervised below: if one exits, the sovereign shuts down.
+    let mut grpc_servers: tokio::task::JoinSet<(String, Result<()>)> = tokio::task::JoinSet::new();
+    #[cfg(not(feature = "signing"))]
+    let _ = log_level;
+    #[cfg(feature = "signing")]
+    {
         use grpc::pb::key_pool_service_server::KeyPoolServiceServer;
         use grpc::SignerServiceImpl;
         use tokio::net::UnixListener;
         use tokio_stream::wrappers::UnixListenerStream;
         use tonic_reflection::server::Builder;
 
+        let auth_token = config
+            .grpc_auth_token
+            .as_ref()
+            .map(|value| value.unseal(&sealing_key))
+            .transpose()
+            .context("grpc-auth-token")?;
+        if auth_token.is_some() {
+            tracing::info!("gRPC signing requests require a bearer token");
+        }
         // Create the service
-        let signer = SignerServiceImpl { key: state.clone() };
+        let signer = SignerServiceImpl {
+            key: state.clone(),
+            log_level,
+            log_level_expires_at: Default::default(),
+            auth_token,
+        };
         // Wrap the service
-        let svc = KeyPoolServiceServer::new(signer);
+        let svc = KeyPoolServiceServer::new(signer)
+            .max_decoding_message_size(config.max_grpc_decoding_message_size());
 
-        let file_descriptor_set: &[u8] = include_bytes!("descriptor.bin");
+        let file_descriptor_set: &[u8] =
+            include_bytes!(concat!(env!("OUT_DIR"), "/descriptor.bin"));
 
-        let reflection_service = Builder::configure()
-            .register_encoded_file_descriptor_set(file_descriptor_set)
-            .build_v1()?;
+        let reflection_service = if config.grpc_reflection_enabled() {
+            Some(
+                Builder::configure()
+                    .register_encoded_file_descriptor_set(file_descriptor_set)
+                    .build_v1()?,
+            )
+        } else {
+            tracing::info!("gRPC reflection disabled");
+            None
+        };
 
-        let uds_path = "/tmp/enclave.sock";
-        // Remove existing socket file if it exists
-        let _ = std::fs::remove_file(uds_path);
+        let uds_path = config.grpc_uds_path().to_string();
+        remove_stale_socket(std::path::Path::new(&uds_path))?;
         // Create a UnixListener
-        let unix_listener = UnixListener::bind(uds_path)?;
+        let unix_listener = UnixListener::bind(&uds_path)?;
         // Create a stream from the listener
         let incoming = UnixListenerStream::new(unix_listener);
 
         tracing::info!("Starting gRPC server on UDS: {}", uds_path);
 
+        // Optionally, also serve the same service over TLS on a VSOCK port.
+        if let Some(port) = config.grpc_vsock_port {
+            let listener = SM::listen(port).await?;
+            let incoming = grpc::tls_incoming::<SM>(
+                listener,
+                grpc_tls_acceptor,
+                max_accept_backoff(config),
+                state.metrics.accept_errors_total.with_label_values(&["grpc"]),
+            );
+            tracing::info!("Starting gRPC server (TLS) on VSOCK port {}", port);
+            let metrics = state.metrics.clone();
+            let reflection_service = reflection_service.clone();
+            let svc = svc.clone();
+            grpc_servers.spawn(async move {
+                let result = tonic::transport::Server::builder()
+                    .layer(monitoring::MetricsLayer { metrics })
+                    .add_optional_service(reflection_service)
+                    .add_service(svc)
+                    .serve_with_incoming(incoming)
+                    .await;
+                (format!("gRPC server on VSOCK port {}", port), result.map_err(anyhow::Error::from))
+            });
+        }
+
         let state = state.clone();
-        tokio::spawn(async move {
-            tonic::transport::Server::builder()
+        grpc_servers.spawn(async move {
+            let result = tonic::transport::Server::builder()
                 .layer(monitoring::MetricsLayer { metrics: state.metrics.clone() })
-                .add_service(reflection_service)
+                .add_optional_service(reflection_service)
                 .add_service(svc)
                 .serve_with_incoming(incoming)
-                .await
-        })
-    };
+                .await;
+            (format!("gRPC server on {}", uds_path), result.map_err(anyhow::Error::from))
+        });
+    }
 
     // Serve key-sync requests using custom protocol.
-    let key_sync_fn: ConnectionHandler<SM::Stream, Arc<KeyServer<SM>>> =
-        Arc::new(|mut stream, state: Arc<KeyServer<SM>>| {
+    #[cfg(feature = "signing")]
+    let key_sync_fn: ConnectionHandler<IdleTimeoutStream<SM::Stream>, Arc<KeyServer<SM>>> =
+        Arc::new(move |mut stream, state: Arc<KeyServer<SM>>| {
+            let tls_acceptor = key_sync_tls_acceptor.clone();
             Box::pin(async move {
                 let time_start = Instant::now();
-                let result = key_sync::serve_leader_key_sync::<SM, _>(
-                    &state.attestor,
-                    &state.config.governance,
-                    // TODO: consider not using JSON here. Just send the raw bytes?
-                    &serde_json::to_vec(&state.extract_secret_key_material())?,
-                    &mut stream,
-                )
-                .await;
+                // Held until the session completes.
+                let _permit = match &state.key_sync_permits {
+                    Some(permits) => match permits.clone().try_acquire_owned() {
+                        Ok(permit) => Some(permit),
+                        Err(_) => {
+                            tracing::warn!("key-sync (leader) rejected: too many concurrent syncs");
+                            state
+                                .metrics
+                                .stream_request_duration_seconds
+                                .with_label_values(&["key-sync", "leader_key_sync", "Rejected"])
+                                .observe(time_start.elapsed().as_secs_f64());
+                            return Ok(());
+                        }
+                    },
+                    None => None,
+                };
+                let result = match tls_acceptor {
+                    Some(tls_acceptor) => match tls_acceptor.accept(stream).await {
+                        Ok(mut tls_stream) => {
+                            match key_sync::channel_binding(tls_stream.get_ref().1) {
+                                Ok(binding) => {
+                                    leader_key_sync(&state, Some(&binding), &mut tls_stream).await
+                                }
+                                Err(e) => Err(e),
+                            }
+                        }
+                        Err(e) => Err(anyhow!("TLS accept error: {}", e)),
+                    },
+                    None => leader_key_sync(&state, None, &mut stream).await,
+                };
                 let status = match result {
                     Ok(()) => "Ok",
                     Err(e) => {
-                        tracing::error!("key-sync (leader) error: {}", e);
+                        tracing::error!("key-sync (leader) error: {:#}", e);
+                        state.metrics.count_verification_failure(&e);
                         "Failed"
                     }
                 };
@@ -231,22 +644,37 @@ pub async fn sovereign_main<SM: Secmod + 'static>(config: SovereignConfig) -> Re
                 Ok(())
             })
         });
+    #[cfg(feature = "signing")]
     let key_sync: Option<HostAcceptor<SM, Arc<KeyServer<SM>>>> = config.key_sync_port.map(|port| {
         HostAcceptor { protocol: "key-sync", method: "leader_key_sync", port, handler: key_sync_fn }
     });
+    #[cfg(not(feature = "signing"))]
+    let key_sync: Option<HostAcceptor<SM, Arc<KeyServer<SM>>>> = None;
+
+    let keep_alive_limits = http::KeepAliveLimits {
+        max_requests: config
+            .http_max_requests_per_connection
+            .unwrap_or(DEFAULT_HTTP_MAX_REQUESTS_PER_CONNECTION),
+        timeout: std::time::Duration::from_secs(
+            config
+                .http_keep_alive_timeout_seconds
+                .unwrap_or(DEFAULT_HTTP_KEEP_ALIVE_TIMEOUT_SECONDS),
+        ),
+    };
 
     // Serve prometheus monitoring using http.
     let monitoring: Option<HostAcceptor<SM, Arc<KeyServer<SM>>>> = config
         .monitoring_port
-        .map(|port| HostAcceptor::http("monitoring", port, serve_metrics::<SM>));
+        .map(|port| HostAcceptor::http("monitoring", port, keep_alive_limits, serve_metrics::<SM>));
 
     // Serve attestation using http.
-    let http_attestation: Option<HostAcceptor<SM, Arc<KeyServer<SM>>>> = config
-        .http_attestation_port
-        .map(|port| HostAcceptor::http("attestation", port, serve_attestation::<SM>));
+    let http_attestation: Option<HostAcceptor<SM, Arc<KeyServer<SM>>>> =
+        config.http_attestation_port.map(|port| {
+            HostAcceptor::http("attestation", port, keep_alive_limits, serve_attestation::<SM>)
+        });
 
     // Serve attestation using https.
-    let https_attestation_fn: ConnectionHandler<SM::Stream, Arc<KeyServer<SM>>> =
+    let https_attestation_fn: ConnectionHandler<IdleTimeoutStream<SM::Stream>, Arc<KeyServer<SM>>> =
         Arc::new(move |stream, state: Arc<KeyServer<SM>>| {
             // Move the tls_acceptor into the https accept thread.
             let tls_acceptor = tls_acceptor.clone();
@@ -254,13 +682,17 @@ pub async fn sovereign_main<SM: Secmod + 'static>(config: SovereignConfig) -> Re
                 match tls_acceptor.accept(stream).await {
                     Ok(tls_stream) => {
                         let io = hyper_util::rt::TokioIo::new(tls_stream);
-                        http::serve_http_connection::<SM, _, _, _>(io, move |x| {
-                            HostAcceptor::wrap_monitoring(
-                                "https",
-                                "attestation",
-                                serve_attestation::<SM>,
-                            )(state.clone(), x)
-                        })
+                        http::serve_http_connection::<SM, _, _, _>(
+                            io,
+                            keep_alive_limits,
+                            move |x| {
+                                HostAcceptor::wrap_monitoring(
+                                    "https",
+                                    "attestation",
+                                    serve_attestation::<SM>,
+                                )(state.clone(), x)
+                            },
+                        )
                         .await?;
                     }
                     Err(e) => {
@@ -279,6 +711,11 @@ pub async fn sovereign_main<SM: Secmod + 'static>(config: SovereignConfig) -> Re
         });
 
     let host_acceptors = HostAcceptors::<SM, Arc<KeyServer<SM>>> {
+        idle_timeout: std::time::Duration::from_secs(
+            config.idle_timeout_seconds.unwrap_or(DEFAULT_IDLE_TIMEOUT_SECONDS),
+        ),
+        max_accept_backoff: max_accept_backoff(config),
+        accept_errors_total: state.metrics.accept_errors_total.clone(),
         // Collect all values that are not-none (i.e., some).
         connections: vec![key_sync, monitoring, http_attestation, https_attestation]
             .into_iter()
@@ -286,9 +723,31 @@ pub async fn sovereign_main<SM: Secmod + 'static>(config: SovereignConfig) -> Re
             .collect(),
     };
 
+    if let Some(metrics_push) = config.metrics_push.clone() {
+        let authorization = metrics_push
+            .authorization
+            .as_ref()
+            .map(|value| value.unseal(&sealing_key))
+            .transpose()
+            .context("metrics-push authorization")?;
+        tracing::info!(
+            "pushing metrics to {} every {} seconds",
+            metrics_push.http_endpoint,
+            metrics_push.interval_seconds
+        );
+        tokio::spawn(monitoring::push_metrics::<SM>(
+            state.metrics.clone(),
+            metrics_push,
+            authorization,
+        ));
+    }
+
+    let heartbeat_interval_seconds = config.heartbeat_interval_seconds;
     host_acceptors.do_listen(state).await?;
 
-    let mut heartbeat = tokio::time::interval(std::time::Duration::from_secs(60));
+    let mut heartbeat = tokio::time::interval(std::time::Duration::from_secs(
+        heartbeat_interval_seconds.unwrap_or(DEFAULT_HEARTBEAT_INTERVAL_SECONDS),
+    ));
 
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
@@ -300,14 +759,40 @@ pub async fn sovereign_main<SM: Secmod + 'static>(config: SovereignConfig) -> Re
                 tracing::debug!("heartbeat: server is alive");
             }
         } => {}
+        Some(exited) = grpc_servers.join_next() => {
+            match exited {
+                Ok((server, Ok(()))) => bail!("{} exited unexpectedly", server),
+                Ok((server, Err(e))) => bail!("{} failed: {}", server, e),
+                Err(e) => bail!("gRPC server task failed: {}", e),
+            }
+        }
     }
 
     Ok(())
 }
 
+/// Whether an `Accept-Encoding` header value admits gzip (with a non-zero quality).
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let rejected = params.any(|param| {
+            param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()).is_some_and(|q| q <= 0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip")) && !rejected
+    })
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
 async fn serve_metrics<SM: Secmod>(
     state: Arc<KeyServer<SM>>,
-    _request: hyper::Request<hyper::body::Incoming>,
+    request: hyper::Request<hyper::body::Incoming>,
 ) -> Result<hyper::Response<http_body_util::Full<hyper::body::Bytes>>> {
     use prometheus::Encoder;
 
@@ -323,19 +808,116 @@ async fn serve_metrics<SM: Secmod>(
 
     tracing::debug!("retrieving metrics: {}", buffer);
 
+    let compress = request
+        .headers()
+        .get(hyper::header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(accepts_gzip);
     let response = hyper::Response::builder()
         .status(200)
         .header("Content-Type", encoder.format_type())
-        .body(full(buffer))
-        .unwrap();
-    Ok(response)
+        .header(hyper::header::VARY, "Accept-Encoding");
+    let response = if compress {
+        response
+            .header(hyper::header::CONTENT_ENCODING, "gzip")
+            .body(full(gzip(buffer.as_bytes())?))
+    } else {
+        response.body(full(buffer))
+    };
+    Ok(response.unwrap())
+}
+
+/// Header carrying the audience of an attestation request, see `user_data::audience_binding`.
+const AUDIENCE_HEADER: &str = "x-attestation-audience";
+
+/// Maximum number of attestations in one `POST /batch` request.
+const MAX_BATCH_ATTESTATIONS: usize = 16;
+
+/// Maximum body size of a `POST /batch` request: hex encoded fields of the maximum size, plus
+/// room for the JSON syntax.
+const MAX_BATCH_REQUEST_BYTES: usize =
+    MAX_BATCH_ATTESTATIONS * (3 * 2 * secmod::MAX_ATTESTATION_FIELD_BYTES + 256);
+
+/// One attestation of a `POST /batch` request, with the hex encoded fields of `GET /`.
+#[derive(serde::Deserialize, Debug, Default)]
+struct BatchAttestation {
+    #[serde(rename = "nonce", default)]
+    nonce: Option<String>,
+    #[serde(rename = "public-key", default)]
+    public_key: Option<String>,
+    #[serde(rename = "user-data", default)]
+    user_data: Option<String>,
+}
+
+/// A decoded `BatchAttestation`: its nonce, public key, and user data.
+type AttestationFields = (Option<ByteBuf>, Option<ByteBuf>, Option<ByteBuf>);
+
+/// Decode the JSON array of a `POST /batch` request, checking all entries before any
+/// attestation is generated.
+fn decode_attestation_batch(body: &[u8]) -> Result<Vec<AttestationFields>> {
+    let batch: Vec<BatchAttestation> =
+        serde_json::from_slice(body).context("invalid attestation batch")?;
+    if batch.is_empty() || batch.len() > MAX_BATCH_ATTESTATIONS {
+        bail!("attestation batch must have 1 to {} entries", MAX_BATCH_ATTESTATIONS);
+    }
+    let decode = |index: usize, name: &str, value: Option<String>| -> Result<Option<ByteBuf>> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let value = hex::decode(value).with_context(|| format!("entry {}: {}", index, name))?;
+        if value.len() > secmod::MAX_ATTESTATION_FIELD_BYTES {
+            bail!(
+                "entry {}: {} is {} bytes; at most {} bytes allowed",
+                index,
+                name,
+                value.len(),
+                secmod::MAX_ATTESTATION_FIELD_BYTES
+            );
+        }
+        Ok(Some(ByteBuf::from(value)))
+    };
+    batch
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            Ok((
+                decode(i, "nonce", entry.nonce)?,
+                decode(i, "public-key", entry.public_key)?,
+                decode(i, "user-data", entry.user_data)?,
+            ))
+        })
+        .collect()
 }
 
-async fn serve_attestation<SM: Secmod>(
+async fn serve_attestation<SM: Secmod + 'static>(
     state: Arc<KeyServer<SM>>,
-    request: hyper::Request<hyper::body::Incoming>,
+    mut request: hyper::Request<hyper::body::Incoming>,
 ) -> Result<hyper::Response<http_body_util::Full<hyper::body::Bytes>>> {
-    let (parts, _body) = request.into_parts();
+    if state.config.websocket_attestation
+        && request.uri().path() == "/"
+        && websocket::is_upgrade_request(&request)
+    {
+        let Some(tasks) = request.extensions().get::<http::ConnectionTasks>().cloned() else {
+            bail!("WebSocket upgrades are not supported on this connection");
+        };
+        let response = websocket::upgrade_response(&request)?;
+        let upgrade = hyper::upgrade::on(&mut request);
+        let counter = tasks.counter();
+        tasks.spawn(async move {
+            let result = match upgrade.await {
+                Ok(upgraded) => {
+                    let io = hyper_util::rt::TokioIo::new(upgraded);
+                    websocket::serve_attestation(state, &counter, io).await
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                tracing::error!("WebSocket attestation error: {}", e);
+            }
+        });
+        return Ok(response);
+    }
+    let (parts, body) = request.into_parts();
     let uri = parts.uri;
     let method = parts.method;
     tracing::info!("Received request: {} {}", method, uri);
@@ -348,12 +930,107 @@ async fn serve_attestation<SM: Secmod>(
                     None => Ok(None),
                 }
             };
-            let nonce = get_query_param("nonce")?;
+            let mut nonce = get_query_param("nonce")?;
+            // A challenge from `GET /challenge` is used as the nonce (see `nonce_cache`).
+            if let Some(challenge) = get_query_param("challenge")? {
+                if nonce.is_some() {
+                    bail!("nonce and challenge are mutually exclusive");
+                }
+                let mut challenges = state.challenges.lock().unwrap_or_else(|e| e.into_inner());
+                let valid = challenges.consume(&challenge);
+                state
+                    .metrics
+                    .attestation_challenges_outstanding
+                    .set(challenges.outstanding() as i64);
+                if !valid {
+                    bail!("unknown, expired, or already used challenge");
+                }
+                nonce = Some(challenge);
+            }
             let public_key = get_query_param("public-key")?;
-            let user_data = get_query_param("user-data")?;
-            let att = SM::new_attestation(&state.attestor, nonce, public_key, user_data)?;
+            // An audience from the header (or, if URL-safe, the `audience` query parameter)
+            // and the configured instance ID are bound into the user data (see
+            // `user_data::attestation_user_data`).
+            let audience = match parts.headers.get(AUDIENCE_HEADER) {
+                Some(value) => Some(value.to_str().context("invalid audience header")?),
+                None => http::get_query_param(query, "audience"),
+            };
+            let user_data = user_data::attestation_user_data(
+                audience,
+                state.config.instance_id.as_deref(),
+                get_query_param("user-data")?,
+            )?;
+            let att = state.metrics.time_attestation("http", || {
+                SM::new_attestation(&state.attestor, nonce, public_key, user_data)
+            })?;
             http::encode_with_encoding(att, &uri)
         }
+        // Several attestations at once, e.g., one per key of the pool: the body is a JSON array
+        // of objects with the (optional, hex encoded) `nonce`, `public-key`, and `user-data`,
+        // the response a JSON array of the base64 encoded documents in the same order.
+        // Challenges, audiences, and the instance ID are only supported by `GET /`.
+        (&hyper::Method::POST, "/batch") => {
+            use base64::engine::general_purpose::STANDARD;
+            let body = http::get_body(body, MAX_BATCH_REQUEST_BYTES).await?;
+            let batch = decode_attestation_batch(&body)?;
+            // Generating the attestations blocks on the NSM; keep it off the async workers.
+            let state = state.clone();
+            let documents = tokio::task::spawn_blocking(move || {
+                batch
+                    .into_iter()
+                    .map(|(nonce, public_key, user_data)| {
+                        let att = state.metrics.time_attestation("http", || {
+                            SM::new_attestation(&state.attestor, nonce, public_key, user_data)
+                        })?;
+                        Ok(base64::Engine::encode(&STANDARD, att))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .await
+            .context("attestation batch panicked")??;
+            Ok(hyper::Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(full(serde_json::to_vec(&documents)?))?)
+        }
+        // A single-use nonce for `GET /?challenge=...`, valid for `CHALLENGE_TTL`.
+        (&hyper::Method::GET, "/challenge") => {
+            let mut challenges = state.challenges.lock().unwrap_or_else(|e| e.into_inner());
+            let challenge = challenges.issue()?;
+            state.metrics.attestation_challenges_outstanding.set(challenges.outstanding() as i64);
+            Ok(hyper::Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "text/plain")
+                .body(full(hex::encode(challenge)))?)
+        }
+        // The certificate chain of a fresh attestation, for inspection by verifiers.
+        // Verifiers must pin the AWS Nitro root themselves rather than trust this response.
+        (&hyper::Method::GET, "/cabundle") => {
+            let att = state.metrics.time_attestation("http", || {
+                SM::new_attestation(&state.attestor, None, None, None)
+            })?;
+            let doc = SM::parse(&att)?;
+            let chain = http::certificate_chain_pem(doc.certificate(), doc.cabundle());
+            Ok(hyper::Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/x-pem-file")
+                .body(full(chain))?)
+        }
+        // The hash of the measured configuration, so that verifiers can recompute the expected
+        // measurement, and the configuration itself with plain text secrets redacted.
+        (&hyper::Method::GET, "/config-hash") => {
+            use sha2::Digest;
+            let measurement = state.config_measurement()?;
+            let body = serde_json::json!({
+                "measurement-slot": key_server::CONFIG_MEASUREMENT_SLOT,
+                "sha384": hex::encode(sha2::Sha384::digest(&measurement)),
+                "extended-pcr-value": hex::encode(nsm_attestation::extended_pcr_value(&measurement)),
+                "config": state.config.redacted(),
+            });
+            Ok(hyper::Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(full(serde_json::to_vec(&body)?))?)
+        }
+        (&hyper::Method::GET, "/version") => Ok(hyper::Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(full(serde_json::to_vec(&version::build_info())?))?),
         _ => bail!("invalid request"),
     }
 }
@@ -369,10 +1046,11 @@ struct HostAcceptor<SM: Secmod, State> {
     protocol: &'static str,
     method: &'static str,
     port: u32,
-    handler: ConnectionHandler<SM::Stream, State>,
+    handler: ConnectionHandler<IdleTimeoutStream<SM::Stream>, State>,
 }
 
 impl<SM: Secmod + 'static> HostAcceptor<SM, Arc<KeyServer<SM>>> {
+    #[allow(clippy::type_complexity)]
     fn wrap_monitoring<F, S>(
         protocol: &'static str,
         method: &'static str,
@@ -418,7 +1096,12 @@ impl<SM: Secmod + 'static> HostAcceptor<SM, Arc<KeyServer<SM>>> {
         }
     }
 
-    fn http<F, S>(method: &'static str, port: u32, service: F) -> Self
+    fn http<F, S>(
+        method: &'static str,
+        port: u32,
+        limits: http::KeepAliveLimits,
+        service: F,
+    ) -> Self
     where
         F: 'static
             + Clone
@@ -437,14 +1120,24 @@ impl<SM: Secmod + 'static> HostAcceptor<SM, Arc<KeyServer<SM>>> {
                 let time_start = Instant::now();
                 let service_state = state.clone();
                 let io = hyper_util::rt::TokioIo::new(stream);
-                let builder = hyper::server::conn::http1::Builder::new();
-                let service_fn = hyper::service::service_fn(move |x| {
+                let builder = limits.builder();
+                let counter = limits.counter();
+                let tasks = http::ConnectionTasks::new(counter.clone());
+                let connection_tasks = tasks.clone();
+                let service_fn = hyper::service::service_fn(move |mut x: Request<_>| {
                     let service = service.clone();
                     let service_state = service_state.clone();
+                    let counter = counter.clone();
+                    x.extensions_mut().insert(connection_tasks.clone());
                     async move {
-                        let resp = service(service_state.clone(), x).await.unwrap_or_else(|e| {
-                            http::error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
-                        });
+                        let mut resp =
+                            service(service_state.clone(), x).await.unwrap_or_else(|e| {
+                                http::error_response(
+                                    StatusCode::INTERNAL_SERVER_ERROR,
+                                    e.to_string(),
+                                )
+                            });
+                        counter.track(&mut resp);
                         let status = resp.status();
                         let status_str = format!("{:?}", status);
                         let elapsed = time_start.elapsed().as_secs_f64();
@@ -456,7 +1149,9 @@ impl<SM: Secmod + 'static> HostAcceptor<SM, Arc<KeyServer<SM>>> {
                         Ok::<_, hyper::Error>(resp)
                     }
                 });
-                builder.serve_connection(io, service_fn).await.map_err(anyhow::Error::from)
+                builder.serve_connection(io, service_fn).with_upgrades().await?;
+                tasks.join().await;
+                Ok(())
             }) as Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send>>
         });
         HostAcceptor { protocol, method, port, handler }
@@ -464,9 +1159,50 @@ impl<SM: Secmod + 'static> HostAcceptor<SM, Arc<KeyServer<SM>>> {
 }
 
 struct HostAcceptors<SM: Secmod, State> {
+    /// Connections without any reads or writes for this long are closed.
+    idle_timeout: std::time::Duration,
+    /// Cap on the delay between retries after consecutive accept errors.
+    max_accept_backoff: std::time::Duration,
+    /// Counts accept errors by protocol.
+    accept_errors_total: prometheus::IntCounterVec,
     connections: Vec<HostAcceptor<SM, State>>,
 }
 
+/// The configured `max-accept-backoff-milliseconds` or its default.
+fn max_accept_backoff(config: &SovereignConfig) -> std::time::Duration {
+    std::time::Duration::from_millis(
+        config.max_accept_backoff_milliseconds.unwrap_or(DEFAULT_MAX_ACCEPT_BACKOFF_MILLISECONDS),
+    )
+}
+
+/// Exponential backoff after consecutive accept errors (e.g., when running out of file
+/// descriptors), so that persistent errors do not result in a tight loop flooding the logs.
+struct AcceptBackoff {
+    max: std::time::Duration,
+    current: Option<std::time::Duration>,
+}
+
+impl AcceptBackoff {
+    fn new(max: std::time::Duration) -> Self {
+        Self { max, current: None }
+    }
+
+    /// The delay before retrying after another accept error.
+    fn next_delay(&mut self) -> std::time::Duration {
+        let delay = match self.current {
+            Some(current) => (current * 2).min(self.max),
+            None => INITIAL_ACCEPT_BACKOFF.min(self.max),
+        };
+        self.current = Some(delay);
+        delay
+    }
+
+    /// Start over after a successful accept.
+    fn reset(&mut self) {
+        self.current = None;
+    }
+}
+
 impl<SM: Secmod + 'static, State: Clone + Send + 'static> HostAcceptors<SM, State> {
     pub fn log_if_error<F>(
         service: F,
@@ -486,10 +1222,13 @@ impl<SM: Secmod + 'static, State: Clone + Send + 'static> HostAcceptors<SM, Stat
     /// using the specified connection handler and then start a loop on the
     /// current thread that accepts connections and serves them.
     pub async fn do_listen(self, state: State) -> Result<()> {
+        let idle_timeout = self.idle_timeout;
         for HostAcceptor { protocol, method, port, handler } in self.connections.into_iter() {
             let listener = SM::listen(port).await?;
             tracing::info!("serving {} (protocol {}) on VSOCK port {}", method, protocol, port);
             let state = state.clone();
+            let accept_errors_total = self.accept_errors_total.with_label_values(&[protocol]);
+            let mut backoff = AcceptBackoff::new(self.max_accept_backoff);
             // handle each listener in a separate task
             tokio::spawn(async move {
                 let state = state.clone();
@@ -499,11 +1238,28 @@ impl<SM: Secmod + 'static, State: Clone + Send + 'static> HostAcceptors<SM, Stat
                     let handler = handler.clone();
                     match SM::accept(&listener).await {
                         Ok(stream) => {
+                            backoff.reset();
                             // Handle stream in separate task.
                             tracing::debug!("starting stream handling connection on {}", port);
-                            tokio::spawn(Self::log_if_error(handler(stream, state)));
+                            let stream = IdleTimeoutStream::new(stream);
+                            let activity = stream.activity();
+                            tokio::spawn(Self::log_if_error(idle_timeout::with_idle_timeout(
+                                handler(stream, state),
+                                activity,
+                                idle_timeout,
+                            )));
+                        }
+                        Err(e) => {
+                            accept_errors_total.inc();
+                            let delay = backoff.next_delay();
+                            tracing::error!(
+                                "accept on port {}: {}; retrying in {:?}",
+                                port,
+                                e,
+                                delay
+                            );
+                            tokio::time::sleep(delay).await;
                         }
-                        Err(e) => tracing::error!("accept: {}", e.to_string()),
                     }
                 }
             });
@@ -521,13 +1277,266 @@ mod tests {
 
     #[test]
     fn test_secret_key_material_roundtrip() -> Result<()> {
-        let secret = SecretKeyMaterial::generate_random(&mut rand_core::OsRng)?;
+        let secret = SecretKeyMaterial::generate_random(2, &mut rand_core::OsRng)?;
         let attestor = MockSecmod::init_attestor()?;
         let config = SovereignConfig::default();
         let state = KeyServer::<MockSecmod>::new(attestor, config.clone(), secret.clone())?;
         let secret2 = state.extract_secret_key_material();
         assert!(secret == secret2);
+        // What the leader sends is what the follower receives.
+        let sent = state.extract_secret_key_material().to_bytes()?;
+        assert!(SecretKeyMaterial::from_bytes(&sent)? == secret);
         assert!(state.config == config);
         Ok(())
     }
+
+    #[cfg(feature = "signing")]
+    #[tokio::test]
+    async fn test_retrieve_key_material_retries() -> Result<()> {
+        // Find a free port; the leader starts listening on it only after the first attempt.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port() as u32;
+        let attestor = MockSecmod::init_debug_attestor();
+        let config = SovereignConfig {
+            governance: config::Governance::TestingOnly,
+            key_sync_connect_attempts: Some(20),
+            key_sync_retry_delay_milliseconds: Some(10),
+            ..Default::default()
+        };
+        let leader = async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let listener = MockSecmod::listen(port).await?;
+            let mut stream = MockSecmod::accept(&listener).await?;
+            key_sync::serve_leader_key_sync::<MockSecmod, _>(
+                &attestor,
+                &config.governance,
+                None,
+                &[0xaa, 0xbb],
+                &mut stream,
+                &mut key_sync::KeySyncTimeline::default(),
+            )
+            .await
+        };
+        let (leader, follower) =
+            tokio::join!(leader, retrieve_key_material::<MockSecmod>(&attestor, &config, port));
+        leader?;
+        assert_eq!(*follower?.0, vec![0xaa, 0xbb]);
+
+        // Without retries, the follower gives up at once.
+        let config = SovereignConfig { key_sync_connect_attempts: Some(1), ..config };
+        assert!(retrieve_key_material::<MockSecmod>(&attestor, &config, port).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_num_keys() -> Result<()> {
+        let key_material = SecretKeyMaterial::generate_random(3, &mut rand_core::OsRng)?;
+        let mut config = SovereignConfig {
+            secret_keys_from: SecretKeyRetrieval::KeySync(5000),
+            ..Default::default()
+        };
+        assert!(check_num_keys(&config, &key_material).is_ok());
+        config.expected_num_keys = Some(3);
+        assert!(check_num_keys(&config, &key_material).is_ok());
+        config.expected_num_keys = Some(2);
+        let err = check_num_keys(&config, &key_material).unwrap_err();
+        assert!(err.to_string().starts_with("key-sync delivered 3 secret keys but 2 are expected"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_attestation_batch() -> Result<()> {
+        let batch = decode_attestation_batch(
+            br#"[{"nonce": "0102"}, {"public-key": "03", "user-data": "04"}, {}]"#,
+        )?;
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch[0], (Some(ByteBuf::from([1, 2])), None, None));
+        assert_eq!(batch[1], (None, Some(ByteBuf::from([3])), Some(ByteBuf::from([4]))));
+        assert_eq!(batch[2], (None, None, None));
+
+        assert!(decode_attestation_batch(b"[]").is_err());
+        let too_many = format!("[{}{{}}]", "{},".repeat(MAX_BATCH_ATTESTATIONS));
+        assert!(decode_attestation_batch(too_many.as_bytes()).is_err());
+        let too_large = hex::encode(vec![0; secmod::MAX_ATTESTATION_FIELD_BYTES + 1]);
+        let err = decode_attestation_batch(
+            format!(r#"[{{}}, {{"user-data": "{}"}}]"#, too_large).as_bytes(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("entry 1: user-data is 1025 bytes"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_stale_socket() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("sovereign-test-{}.sock", std::process::id()));
+        // Nothing to remove.
+        remove_stale_socket(&path)?;
+        let listener = std::os::unix::net::UnixListener::bind(&path)?;
+        let err = remove_stale_socket(&path).unwrap_err();
+        assert!(err.to_string().contains("already in use by a live process"));
+        assert!(path.exists());
+        // Once the listener is gone, the socket is stale.
+        drop(listener);
+        remove_stale_socket(&path)?;
+        assert!(!path.exists());
+        // Other files are left alone.
+        std::fs::write(&path, b"not a socket")?;
+        assert!(remove_stale_socket(&path).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_metrics() -> Result<()> {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip("deflate, GZIP;q=0.5"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("identity, br"));
+        assert!(!accepts_gzip(""));
+
+        let metrics = "# TYPE a counter\na 1\n".repeat(100);
+        let compressed = gzip(metrics.as_bytes())?;
+        assert!(compressed.len() < metrics.len());
+        let mut decompressed = String::new();
+        std::io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(compressed.as_slice()),
+            &mut decompressed,
+        )?;
+        assert_eq!(decompressed, metrics);
+        Ok(())
+    }
+
+    #[test]
+    fn test_accept_backoff() {
+        use std::time::Duration;
+        let mut backoff = AcceptBackoff::new(Duration::from_millis(50));
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay()).collect();
+        assert_eq!(delays, [10, 20, 40, 50, 50].map(Duration::from_millis));
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_too_few_secret_keys() -> Result<()> {
+        let secret = SecretKeyMaterial::generate_random(1, &mut rand_core::OsRng)?;
+        let attestor = MockSecmod::init_attestor()?;
+        let result = KeyServer::<MockSecmod>::new(attestor, SovereignConfig::default(), secret);
+        let err = result.err().expect("a single key must be rejected");
+        assert_eq!(err.to_string(), "secret key material contains 1 keys; at least 2 are required");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rotate_certificate() -> Result<()> {
+        let secret = SecretKeyMaterial::generate_random(2, &mut rand_core::OsRng)?;
+        let config =
+            SovereignConfig { alt_names: vec!["old.example".to_string()], ..Default::default() };
+        let attestor = MockSecmod::init_debug_attestor();
+        let state = KeyServer::<MockSecmod>::new(attestor, config, secret.clone())?;
+        let contains = |der: &[u8], part: &[u8]| der.windows(part.len()).any(|x| x == part);
+        let old = state.cert.certificate_der();
+        assert!(contains(&old, b"old.example"));
+
+        let new = state.rotate_certificate(vec!["new.example".to_string()]).await?;
+        assert_eq!(state.cert.certificate_der(), new);
+        assert!(contains(&new, b"new.example") && !contains(&new, b"old.example"));
+        // The certificate key, and thus its measurement, is unchanged.
+        assert!(contains(&new, &state.cert_public_key_der));
+        assert!(state.rotate_certificate(vec!["a.example,b.example".to_string()]).await.is_err());
+
+        // Outside of debug mode, testing-only governance authorizes nothing.
+        let attestor = MockSecmod::init_attestor()?;
+        let state = KeyServer::<MockSecmod>::new(attestor, SovereignConfig::default(), secret)?;
+        let err = state.rotate_certificate(vec!["new.example".to_string()]).await.unwrap_err();
+        assert!(err.to_string().starts_with("certificate rotation not authorized"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_measure_startup() -> Result<()> {
+        let secret = SecretKeyMaterial::generate_random(3, &mut rand_core::OsRng)?;
+        let attestor = MockSecmod::init_attestor()?;
+        let state = KeyServer::<MockSecmod>::new(attestor, SovereignConfig::default(), secret)?;
+        measure_startup(&state)?;
+        let keys = state.signing_keys();
+        let pairs = &keys.pairs;
+        let expected = vec![
+            state.cert_public_key_der.clone(),
+            pairs[0].public_key.to_sec1_bytes().to_vec(),
+            pairs[1].public_key.to_sec1_bytes().to_vec(),
+            state.config.canonical_json()?,
+        ];
+        assert_eq!(attestor.measurements(), expected);
+        // Measurements cannot be replaced.
+        assert!(MockSecmod::measure_enclave(&attestor, 0, vec![vec![1]], true).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_break_glass_key() -> Result<()> {
+        let secret = SecretKeyMaterial::generate_random(2, &mut rand_core::OsRng)?;
+        let break_glass = config::BreakGlassConfig {
+            salt: "test-pool-break-glass".to_string(),
+            memory_kib: Some(64),
+            iterations: Some(1),
+            parallelism: Some(1),
+        };
+        let config =
+            SovereignConfig { break_glass: Some(break_glass.clone()), ..Default::default() };
+        let attestor = MockSecmod::init_debug_attestor();
+        let state = KeyServer::<MockSecmod>::new(attestor, config, secret.clone())?;
+        let passphrase = b"a long and randomly generated passphrase";
+        let sealing_public_key = state.sealing_key()?.public_key().to_sec1_bytes();
+        let sealed = ecies_envelope::encrypt(&sealing_public_key, passphrase)?;
+
+        assert!(state.break_glass_key().is_none());
+        assert!(state.load_break_glass_key(&sealed[1..]).await.is_err());
+        let public_key = state.load_break_glass_key(&sealed).await?;
+        let expected = break_glass::derive_key(passphrase, &break_glass)?.public_key();
+        assert_eq!(public_key, expected);
+        assert_eq!(state.break_glass_key().map(|pair| pair.public_key), Some(expected));
+        // The break-glass key is not part of the key material shared by key-sync.
+        assert!(state.extract_secret_key_material() == secret);
+
+        // Without a `break-glass` configuration, no passphrase is accepted.
+        let state = KeyServer::<MockSecmod>::new(attestor, SovereignConfig::default(), secret)?;
+        let err = state.load_break_glass_key(&sealed).await.unwrap_err();
+        assert_eq!(err.to_string(), "break-glass is not configured");
+        Ok(())
+    }
+
+    #[cfg(feature = "nsm")]
+    #[test]
+    fn test_measurement_manifest() -> Result<()> {
+        let pcrs = ["AB".repeat(48), "cd".repeat(48), "ef".repeat(48)];
+        let manifest = measurement_manifest::<nsm::Nsm>(
+            [&pcrs[0], &pcrs[1], &pcrs[2]],
+            Some("i-1234567890abcdef0".to_string()),
+        )?;
+        use sha2::Digest;
+        let pcr4 = sha2::Sha384::new()
+            .chain_update([0; 48])
+            .chain_update(b"i-1234567890abcdef0")
+            .finalize()
+            .to_vec();
+        let doc = nsm_attestation::NitroAttestationDocument {
+            module_id: String::new(),
+            digest: String::new(),
+            timestamp: 0,
+            pcrs: std::collections::HashMap::from([
+                (0, ByteBuf::from(vec![0xab; 48])),
+                (1, ByteBuf::from(vec![0xcd; 48])),
+                (2, ByteBuf::from(vec![0xef; 48])),
+                (4, ByteBuf::from(pcr4)),
+            ]),
+            certificate: ByteBuf::new(),
+            cabundle: Vec::new(),
+            public_key: None,
+            user_data: None,
+            nonce: None,
+        };
+        // The manifest matches what key-sync checks, byte for byte.
+        assert_eq!(manifest, [doc.code_measurement(), doc.instance_measurement()]);
+        assert!(measurement_manifest::<nsm::Nsm>(["00", &pcrs[1], &pcrs[2]], None).is_err());
+        Ok(())
+    }
 }
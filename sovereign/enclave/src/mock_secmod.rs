@@ -1,24 +1,54 @@
 //! Mock security module. Not secure, but good for testing.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use serde_bytes::ByteBuf;
 use tokio::net::{TcpListener, TcpStream};
 
-use crate::secmod::{AttestationDocument, Secmod};
+use crate::secmod::{AttestationDocument, DocumentFormat, Secmod};
 
 pub struct MockSecmod;
 
+/// Environment variable overriding the IP address the mock listens on and connects to.
+const MOCK_HOST_ENV: &str = "SOVEREIGN_MOCK_HOST";
+
+/// The socket address for `port`.
+///
+/// The IP address is taken from `SOVEREIGN_MOCK_HOST` (IPv4 or IPv6, e.g., `::1`);
+/// it defaults to `127.0.0.1` rather than `localhost` to avoid dual-stack ambiguity.
+fn mock_socket_addr(port: u32) -> Result<SocketAddr> {
+    let ip = match std::env::var(MOCK_HOST_ENV) {
+        Ok(host) => host
+            .parse::<IpAddr>()
+            .with_context(|| format!("invalid IP address in {}: {}", MOCK_HOST_ENV, host))?,
+        Err(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    };
+    let port = u16::try_from(port).with_context(|| format!("invalid TCP port {}", port))?;
+    Ok(SocketAddr::new(ip, port))
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct MockAttestationDocument {
     pub pcrs: std::collections::HashMap<u8, ByteBuf>,
     pub public_key: Option<ByteBuf>,
     pub user_data: Option<ByteBuf>,
     pub nonce: Option<ByteBuf>,
+    #[serde(default)]
+    pub certificate: ByteBuf,
+    #[serde(default)]
+    pub cabundle: Vec<ByteBuf>,
 }
 
 impl AttestationDocument for MockAttestationDocument {
+    #[cfg(feature = "signing")]
+    fn format(&self) -> DocumentFormat {
+        DocumentFormat::Mock
+    }
+
+    #[cfg(feature = "signing")]
     fn code_measurement(&self) -> String {
         let pcrs = &self.pcrs;
         let pcr0 = pcrs.get(&0).map(hex::encode).unwrap_or_default();
@@ -36,29 +66,68 @@ impl AttestationDocument for MockAttestationDocument {
         format!("MOCK-INSTANCE:{}", pcr4)
     }
 
+    #[cfg(feature = "signing")]
     fn nonce(&self) -> Option<&ByteBuf> {
         self.nonce.as_ref()
     }
+    #[cfg(feature = "signing")]
     fn public_key(&self) -> Option<&ByteBuf> {
         self.public_key.as_ref()
     }
+    #[cfg(feature = "signing")]
     fn user_data(&self) -> Option<&ByteBuf> {
         self.user_data.as_ref()
     }
+    fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+    fn cabundle(&self) -> &[ByteBuf] {
+        &self.cabundle
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
-pub enum MockAttestor {
+pub enum MockMode {
     #[cfg(test)]
     Debug,
     ProdLike,
 }
 
+/// The mock attestor records the measurements extended by `measure_enclave`, and the
+/// concatenated data of `extend_measurement`, so that tests can inspect them. Attestors must be
+/// `Copy`, so each one refers to its own leaked record.
+#[derive(Debug, Clone, Copy)]
+pub struct MockAttestor {
+    mode: MockMode,
+    measurements: &'static Mutex<Vec<Vec<u8>>>,
+}
+
+impl MockAttestor {
+    fn new(mode: MockMode) -> Self {
+        Self { mode, measurements: Box::leak(Box::new(Mutex::new(Vec::new()))) }
+    }
+
+    /// The measurements extended so far, in measurement slot order.
+    #[cfg(test)]
+    pub fn measurements(&self) -> Vec<Vec<u8>> {
+        self.measurements.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// The value of the mock code measurement PCR-0 to PCR-2 (zero for debug, as in AWS).
+fn mock_code_pcr(attestor: &MockAttestor) -> Vec<u8> {
+    match attestor.mode {
+        #[cfg(test)]
+        MockMode::Debug => vec![0u8],
+        MockMode::ProdLike => vec![0xffu8],
+    }
+}
+
 #[cfg(test)]
 impl MockSecmod {
     /// Produce an attestor that produces "debug" attestations.
     pub fn init_debug_attestor() -> <MockSecmod as Secmod>::Attestor {
-        MockAttestor::Debug
+        MockAttestor::new(MockMode::Debug)
     }
 }
 
@@ -72,7 +141,7 @@ impl Secmod for MockSecmod {
         port: u32,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Listener>> + Send>> {
         Box::pin(async move {
-            let addr = format!("localhost:{}", port);
+            let addr = mock_socket_addr(port)?;
             tracing::debug!("mock TCP listen {}", addr);
             let listener = TcpListener::bind(addr).await?;
             Ok(listener)
@@ -83,9 +152,9 @@ impl Secmod for MockSecmod {
         port: u32,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Stream>> + Send>> {
         Box::pin(async move {
-            let addr = format!("localhost:{}", port);
+            let addr = mock_socket_addr(port)?;
             tracing::debug!("mock TCP connect {}", addr);
-            let stream = TcpStream::connect(format!("localhost:{}", port)).await?;
+            let stream = TcpStream::connect(addr).await?;
             Ok(stream)
         })
     }
@@ -115,7 +184,12 @@ impl Secmod for MockSecmod {
     }
 
     fn init_attestor() -> Result<Self::Attestor> {
-        Ok(MockAttestor::ProdLike)
+        Ok(MockAttestor::new(MockMode::ProdLike))
+    }
+
+    fn describe_pcrs(attestor: &Self::Attestor) -> Result<BTreeMap<u16, Vec<u8>>> {
+        let pcr = mock_code_pcr(attestor);
+        Ok(BTreeMap::from([(0, pcr.clone()), (1, pcr.clone()), (2, pcr), (4, vec![0xab])]))
     }
 
     fn new_attestation(
@@ -124,11 +198,7 @@ impl Secmod for MockSecmod {
         public_key: Option<ByteBuf>,
         user_data: Option<ByteBuf>,
     ) -> Result<Vec<u8>> {
-        let pcr = match attestor {
-            #[cfg(test)]
-            MockAttestor::Debug => ByteBuf::from([0u8; 1]),
-            MockAttestor::ProdLike => ByteBuf::from([0xffu8; 1]),
-        };
+        let pcr = ByteBuf::from(mock_code_pcr(attestor));
         let v = serde_json::to_vec(&nsm_attestation::NitroAttestationDocument {
             nonce,
             public_key,
@@ -149,12 +219,49 @@ impl Secmod for MockSecmod {
     }
 
     fn parse(doc: &[u8]) -> Result<Self::Att> {
-        let att = serde_json::from_slice(doc)?;
-        Ok(att)
+        match DocumentFormat::detect(doc)? {
+            DocumentFormat::Mock => Ok(serde_json::from_slice(doc)?),
+            format => bail!("cannot verify {} attestation documents", format),
+        }
+    }
+
+    fn measure_enclave(
+        attestor: &Self::Attestor,
+        first_index: usize,
+        data: Vec<Vec<u8>>,
+        idempotent: bool,
+    ) -> Result<()> {
+        tracing::info!(
+            "measure_enclave({:?}, {}, {} items, {})",
+            attestor.mode,
+            first_index,
+            data.len(),
+            idempotent
+        );
+        let mut measurements = attestor.measurements.lock().unwrap_or_else(|e| e.into_inner());
+        // As for NSM, check all slots before extending any of them.
+        if first_index > measurements.len() {
+            bail!("measurement slot {} skips slot {}", first_index, measurements.len());
+        }
+        for (i, item) in data.iter().enumerate() {
+            match measurements.get(first_index + i) {
+                Some(existing) if idempotent && existing == item => {}
+                Some(_) => bail!("measurement slot {} already extended", first_index + i),
+                None => {}
+            }
+        }
+        let already_extended = measurements.len() - first_index;
+        measurements.extend(data.into_iter().skip(already_extended));
+        Ok(())
     }
 
-    fn measure_enclave(attestor: &Self::Attestor, data: Vec<Vec<u8>>) -> Result<()> {
-        tracing::info!("measure_enclave({:?}, {} items)", attestor, data.len());
+    #[cfg(feature = "signing")]
+    fn extend_measurement(attestor: &Self::Attestor, index: usize, data: Vec<u8>) -> Result<()> {
+        let mut measurements = attestor.measurements.lock().unwrap_or_else(|e| e.into_inner());
+        if measurements.len() <= index {
+            measurements.resize(index + 1, Vec::new());
+        }
+        measurements[index].extend(data);
         Ok(())
     }
 }
@@ -1,23 +1,49 @@
+use anyhow::{bail, Context as _, Result};
+#[cfg(feature = "signing")]
 use futures::Future;
-use prometheus::{HistogramOpts, HistogramVec, Registry};
+#[cfg(feature = "signing")]
+use prometheus::IntGaugeVec;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry};
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "signing")]
 use std::pin::Pin;
 use std::sync::Arc;
+#[cfg(feature = "signing")]
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+#[cfg(feature = "signing")]
 use tonic::codegen::http::{request, response};
+#[cfg(feature = "signing")]
 use tonic::Code;
+#[cfg(feature = "signing")]
 use tower::{Layer, Service};
 
+use crate::config::MetricsPushConfig;
+use crate::secmod::Secmod;
+
 pub struct Metrics {
     pub registry: Registry,
+    #[cfg(feature = "signing")]
     pub grpc_request_duration_seconds: HistogramVec,
     pub stream_request_duration_seconds: HistogramVec,
+    pub signing_operations_total: IntCounterVec,
+    #[cfg(feature = "signing")]
+    pub key_sync_active: IntGauge,
+    #[cfg(feature = "signing")]
+    pub key_sync_phase_duration_seconds: HistogramVec,
+    pub attestation_challenges_outstanding: IntGauge,
+    pub accept_errors_total: IntCounterVec,
+    #[cfg(feature = "signing")]
+    pub signature_budget_remaining: IntGaugeVec,
+    pub attestation_generation_duration_seconds: HistogramVec,
+    pub attestation_verification_failures_total: IntCounterVec,
 }
 
 impl Metrics {
     pub fn new() -> Self {
         let registry = Registry::new();
         let buckets = vec![0.001, 0.01, 0.1, 1.0];
+        #[cfg(feature = "signing")]
         let grpc_request_duration_seconds = HistogramVec::new(
             HistogramOpts::new("grpc_request_duration_seconds", "gRPC request duration in seconds")
                 .buckets(buckets.clone()),
@@ -30,16 +56,378 @@ impl Metrics {
             &["protocol", "method", "code"],
         )
         .expect("metric can be created");
+        let signing_operations_total = IntCounterVec::new(
+            Opts::new("signing_operations_total", "number of signing operations"),
+            &["method"],
+        )
+        .expect("metric can be created");
+        #[cfg(feature = "signing")]
+        let key_sync_active =
+            IntGauge::new("key_sync_active", "number of key-sync sessions being served")
+                .expect("metric can be created");
+        #[cfg(feature = "signing")]
+        let key_sync_phase_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "key_sync_phase_duration_seconds",
+                "duration of key-sync protocol phases in seconds",
+            )
+            .buckets(vec![0.001, 0.01, 0.1, 1.0, 10.0]),
+            &["role", "phase"],
+        )
+        .expect("metric can be created");
+        #[cfg(feature = "signing")]
         registry
             .register(Box::new(grpc_request_duration_seconds.clone()))
             .expect("collector can be registered");
         registry
             .register(Box::new(stream_request_duration_seconds.clone()))
             .expect("collector can be registered");
-        Self { registry, grpc_request_duration_seconds, stream_request_duration_seconds }
+        registry
+            .register(Box::new(signing_operations_total.clone()))
+            .expect("collector can be registered");
+        #[cfg(feature = "signing")]
+        registry.register(Box::new(key_sync_active.clone())).expect("collector can be registered");
+        #[cfg(feature = "signing")]
+        registry
+            .register(Box::new(key_sync_phase_duration_seconds.clone()))
+            .expect("collector can be registered");
+        let attestation_challenges_outstanding = IntGauge::new(
+            "attestation_challenges_outstanding",
+            "number of issued attestation challenges not yet used or expired",
+        )
+        .expect("metric can be created");
+        registry
+            .register(Box::new(attestation_challenges_outstanding.clone()))
+            .expect("collector can be registered");
+        let accept_errors_total = IntCounterVec::new(
+            Opts::new("accept_errors_total", "number of errors accepting host connections"),
+            &["protocol"],
+        )
+        .expect("metric can be created");
+        registry
+            .register(Box::new(accept_errors_total.clone()))
+            .expect("collector can be registered");
+        #[cfg(feature = "signing")]
+        let signature_budget_remaining = IntGaugeVec::new(
+            Opts::new(
+                "signature_budget_remaining",
+                "number of signatures left in the budget of each key with a signature budget",
+            ),
+            &["key_index"],
+        )
+        .expect("metric can be created");
+        #[cfg(feature = "signing")]
+        registry
+            .register(Box::new(signature_budget_remaining.clone()))
+            .expect("collector can be registered");
+        let attestation_generation_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "attestation_generation_duration_seconds",
+                "duration of generating attestation documents in seconds",
+            )
+            .buckets(vec![0.001, 0.01, 0.1, 1.0, 10.0]),
+            &["context"],
+        )
+        .expect("metric can be created");
+        registry
+            .register(Box::new(attestation_generation_duration_seconds.clone()))
+            .expect("collector can be registered");
+        let attestation_verification_failures_total = IntCounterVec::new(
+            Opts::new(
+                "attestation_verification_failures_total",
+                "number of peer attestation documents that failed to verify",
+            ),
+            &["cause"],
+        )
+        .expect("metric can be created");
+        registry
+            .register(Box::new(attestation_verification_failures_total.clone()))
+            .expect("collector can be registered");
+        registry
+            .register(Box::new(crate::http::HOST_CIRCUIT_OPEN.clone()))
+            .expect("collector can be registered");
+        Self {
+            registry,
+            #[cfg(feature = "signing")]
+            grpc_request_duration_seconds,
+            stream_request_duration_seconds,
+            signing_operations_total,
+            #[cfg(feature = "signing")]
+            key_sync_active,
+            #[cfg(feature = "signing")]
+            key_sync_phase_duration_seconds,
+            attestation_challenges_outstanding,
+            accept_errors_total,
+            #[cfg(feature = "signing")]
+            signature_budget_remaining,
+            attestation_generation_duration_seconds,
+            attestation_verification_failures_total,
+        }
+    }
+
+    /// The counters that `push_metrics` carries over restarts.
+    fn counter_vecs(&self) -> [&IntCounterVec; 3] {
+        [
+            &self.signing_operations_total,
+            &self.accept_errors_total,
+            &self.attestation_verification_failures_total,
+        ]
+    }
+
+    /// Add the counter values last pushed by a previous run (see `push_metrics`), so that the
+    /// counters continue from there instead of restarting at zero.
+    fn restore_counters(&self, pushed: &BTreeMap<Series, f64>) {
+        use prometheus::core::Collector;
+        for ((name, labels), value) in pushed {
+            let Some(counter_vec) =
+                self.counter_vecs().into_iter().find(|x| x.desc()[0].fq_name == *name)
+            else {
+                continue;
+            };
+            let labels: HashMap<&str, &str> =
+                labels.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            match counter_vec.get_metric_with(&labels) {
+                Ok(counter) => counter.inc_by(*value as u64),
+                Err(e) => tracing::warn!("cannot restore counter {}: {}", name, e),
+            }
+        }
+    }
+
+    /// Count `error` by its cause if it is a failure to verify an attestation document
+    /// (see `nsm_attestation::failure_cause`).
+    #[cfg(feature = "signing")]
+    pub fn count_verification_failure(&self, error: &anyhow::Error) {
+        if let Some(cause) = nsm_attestation::failure_cause(error) {
+            self.attestation_verification_failures_total.with_label_values(&[cause.as_str()]).inc();
+        }
+    }
+
+    /// Generate an attestation document (see `Secmod::new_attestation`), recording the duration
+    /// under `context` (e.g., "http").
+    pub fn time_attestation<T>(
+        &self,
+        context: &str,
+        generate: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let started_at = Instant::now();
+        let result = generate();
+        self.attestation_generation_duration_seconds
+            .with_label_values(&[context])
+            .observe(started_at.elapsed().as_secs_f64());
+        result
+    }
+
+    /// Record the phase durations of a key-sync session as `role` (leader or follower).
+    #[cfg(feature = "signing")]
+    pub fn observe_key_sync_phases(&self, role: &str, timeline: &crate::key_sync::KeySyncTimeline) {
+        for (phase, duration) in timeline.phases() {
+            self.key_sync_phase_duration_seconds
+                .with_label_values(&[role, phase])
+                .observe(duration.as_secs_f64());
+        }
+        if let Some(duration) = timeline.attestation_generation() {
+            self.attestation_generation_duration_seconds
+                .with_label_values(&[&format!("key-sync-{}", role)])
+                .observe(duration.as_secs_f64());
+        }
     }
 }
 
+/// Maximum size of the Pushgateway's `/metrics` response read by `push_metrics`.
+const MAX_PUSHGATEWAY_RESPONSE_BYTES: usize = 4 << 20;
+
+/// A counter series: the metric name and its labels, without the `job` and `instance` labels
+/// that the Pushgateway adds.
+type Series = (String, BTreeMap<String, String>);
+
+/// Push all metrics to the configured Pushgateway every `interval-seconds`, sending
+/// `authorization` (the unsealed `authorization` of `config`).
+/// Failures are logged and retried at the next interval.
+///
+/// Before the first push, the counters continue from the values last pushed for the job, e.g.,
+/// by the previous run before a restart. Pushes that would make a counter go backwards are
+/// rejected: a PUT replaces the job's group in the Pushgateway, so pushing lower values would
+/// reset the counters that scrapers see.
+pub async fn push_metrics<SM: Secmod + 'static>(
+    metrics: Arc<Metrics>,
+    config: MetricsPushConfig,
+    authorization: Option<String>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_seconds));
+    let mut restored = false;
+    loop {
+        interval.tick().await;
+        let pushed =
+            push_metrics_once::<SM>(&metrics, &config, authorization.as_deref(), &mut restored);
+        if let Err(e) = pushed.await {
+            tracing::warn!("failed to push metrics: {:#}", e);
+        }
+    }
+}
+
+async fn push_metrics_once<SM: Secmod + 'static>(
+    metrics: &Metrics,
+    config: &MetricsPushConfig,
+    authorization: Option<&str>,
+    restored: &mut bool,
+) -> Result<()> {
+    use prometheus::Encoder;
+    let pushed = fetch_pushed_counters::<SM>(config, authorization)
+        .await
+        .context("cannot fetch the pushed counters")?;
+    if !*restored {
+        metrics.restore_counters(&pushed);
+        *restored = true;
+        tracing::info!("restored {} pushed counters", pushed.len());
+    }
+    let families = metrics.registry.gather();
+    check_monotonic(&pushed, &counters(&families))?;
+    let encoder = prometheus::TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&families, &mut buffer)?;
+    let url = format!("{}/metrics/job/{}", config.http_endpoint.trim_end_matches('/'), config.job);
+    // PUT replaces all metrics of the job's group in the Pushgateway.
+    let request = pushgateway_request(hyper::Method::PUT, url, authorization)
+        .header(hyper::header::CONTENT_TYPE, encoder.format_type())
+        .body(crate::http::full(buffer))?;
+    let timeout = crate::http::DEFAULT_REQUEST_TIMEOUT;
+    let response =
+        crate::http::make_request::<SM>(config.http_endpoint_port, request, timeout).await?;
+    if !response.status().is_success() {
+        bail!("unexpected response status from pushgateway: {}", response.status());
+    }
+    tracing::debug!("pushed metrics to {}", config.http_endpoint);
+    Ok(())
+}
+
+fn pushgateway_request(
+    method: hyper::Method,
+    url: String,
+    authorization: Option<&str>,
+) -> hyper::http::request::Builder {
+    let request = hyper::Request::builder().method(method).uri(url);
+    match authorization {
+        Some(authorization) => request.header(hyper::header::AUTHORIZATION, authorization),
+        None => request,
+    }
+}
+
+/// The counters of the job currently held by the Pushgateway.
+async fn fetch_pushed_counters<SM: Secmod + 'static>(
+    config: &MetricsPushConfig,
+    authorization: Option<&str>,
+) -> Result<BTreeMap<Series, f64>> {
+    let url = format!("{}/metrics", config.http_endpoint.trim_end_matches('/'));
+    let request = pushgateway_request(hyper::Method::GET, url, authorization)
+        .body(crate::http::full(Vec::new()))?;
+    let timeout = crate::http::DEFAULT_REQUEST_TIMEOUT;
+    let response =
+        crate::http::make_request::<SM>(config.http_endpoint_port, request, timeout).await?;
+    if !response.status().is_success() {
+        bail!("unexpected response status from pushgateway: {}", response.status());
+    }
+    let body = crate::http::get_body(response.into_body(), MAX_PUSHGATEWAY_RESPONSE_BYTES).await?;
+    parse_pushed_counters(std::str::from_utf8(&body)?, &config.job)
+}
+
+/// The counters in `families`, e.g., as gathered from the registry.
+fn counters(families: &[prometheus::proto::MetricFamily]) -> BTreeMap<Series, f64> {
+    let mut result = BTreeMap::new();
+    for family in families {
+        if family.get_field_type() != prometheus::proto::MetricType::COUNTER {
+            continue;
+        }
+        for metric in family.get_metric() {
+            let labels = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                .collect();
+            result
+                .insert((family.get_name().to_string(), labels), metric.get_counter().get_value());
+        }
+    }
+    result
+}
+
+/// Parse the counters of `job` from a Prometheus text exposition (e.g., the Pushgateway's
+/// `/metrics`).
+fn parse_pushed_counters(text: &str, job: &str) -> Result<BTreeMap<Series, f64>> {
+    let mut counter_names = Vec::new();
+    let mut result = BTreeMap::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.split_whitespace();
+            if let (Some("TYPE"), Some(name), Some("counter")) =
+                (words.next(), words.next(), words.next())
+            {
+                counter_names.push(name.to_string());
+            }
+            continue;
+        }
+        let (name, mut labels, value) =
+            parse_sample(line).with_context(|| format!("invalid sample '{}'", line))?;
+        if !counter_names.contains(&name) || labels.remove("job").as_deref() != Some(job) {
+            continue;
+        }
+        labels.remove("instance");
+        result.insert((name, labels), value);
+    }
+    Ok(result)
+}
+
+/// Parse a sample line `name{label="value",...} value [timestamp]`.
+fn parse_sample(line: &str) -> Option<(String, BTreeMap<String, String>, f64)> {
+    let name_end = line.find(['{', ' ']).unwrap_or(line.len());
+    let (name, mut rest) = line.split_at(name_end);
+    let mut labels = BTreeMap::new();
+    if let Some(mut remaining) = rest.strip_prefix('{') {
+        loop {
+            remaining = remaining.trim_start_matches([',', ' ']);
+            if let Some(after) = remaining.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, quoted) = remaining.split_once("=\"")?;
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (i, '"') => break i + 1,
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        c => value.push(c),
+                    },
+                    (_, c) => value.push(c),
+                }
+            };
+            labels.insert(label.trim().to_string(), value);
+            remaining = &quoted[end..];
+        }
+    }
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some((name.to_string(), labels, value))
+}
+
+/// Fail if a counter of `current` is below its value in `pushed`.
+fn check_monotonic(pushed: &BTreeMap<Series, f64>, current: &BTreeMap<Series, f64>) -> Result<()> {
+    for ((name, labels), value) in current {
+        if let Some(previous) = pushed.get(&(name.clone(), labels.clone())) {
+            if value < previous {
+                bail!(
+                    "counter {}{:?} would go backwards from {} to {}",
+                    name,
+                    labels,
+                    previous,
+                    value
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "signing")]
 fn parse_grpc_path(path: &str) -> (String, String) {
     match path.chars().next() {
         Some('/') => {
@@ -56,18 +444,21 @@ fn parse_grpc_path(path: &str) -> (String, String) {
     }
 }
 
+#[cfg(feature = "signing")]
 #[derive(Clone)]
 pub struct MetricsInterceptor<S> {
     metrics: Arc<Metrics>,
     service: S,
 }
 
+#[cfg(feature = "signing")]
 impl<S> MetricsInterceptor<S> {
     pub fn new(metrics: Arc<Metrics>, service: S) -> Self {
         Self { metrics, service }
     }
 }
 
+#[cfg(feature = "signing")]
 impl<S, B, C> Service<request::Request<B>> for MetricsInterceptor<S>
 where
     S: Service<request::Request<B>, Response = response::Response<C>>,
@@ -88,6 +479,7 @@ where
     }
 }
 
+#[cfg(feature = "signing")]
 #[pin_project::pin_project]
 pub struct MetricsFuture<F> {
     metrics: Arc<Metrics>,
@@ -97,12 +489,14 @@ pub struct MetricsFuture<F> {
     inner: F,
 }
 
+#[cfg(feature = "signing")]
 impl<F> MetricsFuture<F> {
     pub fn new(metrics: Arc<Metrics>, path: String, inner: F) -> Self {
         Self { metrics, path, started_at: None, inner }
     }
 }
 
+#[cfg(feature = "signing")]
 impl<F, B, E> Future for MetricsFuture<F>
 where
     F: Future<Output = Result<response::Response<B>, E>>,
@@ -144,11 +538,13 @@ where
     }
 }
 
+#[cfg(feature = "signing")]
 #[derive(Clone)]
 pub struct MetricsLayer {
     pub metrics: std::sync::Arc<Metrics>,
 }
 
+#[cfg(feature = "signing")]
 impl<S> Layer<S> for MetricsLayer {
     type Service = MetricsInterceptor<S>;
 
@@ -156,3 +552,52 @@ impl<S> Layer<S> for MetricsLayer {
         MetricsInterceptor::new(self.metrics.clone(), service)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUSHED: &str = r#"# HELP signing_operations_total number of signing operations
+# TYPE signing_operations_total counter
+signing_operations_total{instance="",job="sovereign",method="sign_digest"} 7
+signing_operations_total{instance="",job="other",method="sign_digest"} 100
+signing_operations_total{instance="",job="sovereign",method="say \"hi\"\\n"} 1 1700000000000
+# TYPE key_sync_active gauge
+key_sync_active{instance="",job="sovereign"} 3
+# TYPE push_time_seconds gauge
+push_time_seconds{instance="",job="sovereign"} 1.7e+09
+"#;
+
+    fn series(name: &str, labels: &[(&str, &str)]) -> Series {
+        let labels = labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        (name.to_string(), labels)
+    }
+
+    #[test]
+    fn test_parse_pushed_counters() {
+        let pushed = parse_pushed_counters(PUSHED, "sovereign").unwrap();
+        let expected = BTreeMap::from([
+            (series("signing_operations_total", &[("method", "sign_digest")]), 7.0),
+            (series("signing_operations_total", &[("method", "say \"hi\"\\n")]), 1.0),
+        ]);
+        assert_eq!(pushed, expected);
+        assert!(parse_pushed_counters(PUSHED, "none").unwrap().is_empty());
+        assert!(parse_pushed_counters("# TYPE a counter\na{job=\"x} 1", "x").is_err());
+        assert_eq!(parse_sample("a 2"), Some(("a".to_string(), BTreeMap::new(), 2.0)));
+    }
+
+    #[test]
+    fn test_restore_counters() {
+        let metrics = Metrics::new();
+        metrics.signing_operations_total.with_label_values(&["sign_digest"]).inc();
+        let pushed = parse_pushed_counters(PUSHED, "sovereign").unwrap();
+        // Counters reset by a restart cannot be pushed over the previous values.
+        let err = check_monotonic(&pushed, &counters(&metrics.registry.gather())).unwrap_err();
+        assert!(err.to_string().contains("would go backwards from 7 to 1"));
+
+        metrics.restore_counters(&pushed);
+        assert_eq!(metrics.signing_operations_total.with_label_values(&["sign_digest"]).get(), 8);
+        assert_eq!(metrics.signing_operations_total.with_label_values(&["say \"hi\"\\n"]).get(), 1);
+        assert!(check_monotonic(&pushed, &counters(&metrics.registry.gather())).is_ok());
+    }
+}
@@ -0,0 +1,122 @@
+//! Server-issued attestation challenges.
+//!
+//! A verifier that cannot produce a fresh nonce itself fetches a challenge from `GET /challenge`
+//! and passes it back in `GET /?challenge=...`; the attestation document then carries the
+//! challenge as its nonce. Each challenge can be used once, within `CHALLENGE_TTL` of being issued.
+//!
+//! Issued challenges are kept in a `NonceCache`, which bounds memory: entries expire after the
+//! TTL, and once the cache is full, issuing a challenge evicts the oldest one. Under a flood of
+//! challenge requests, a challenge may thus become invalid before its TTL has passed.
+
+use anyhow::Result;
+use elliptic_curve::rand_core::{self, RngCore};
+use nsm_attestation::clock::Clock;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Window within which an issued challenge must be used.
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+
+/// Maximum number of outstanding challenges.
+pub const CHALLENGE_CACHE_CAPACITY: usize = 10_000;
+
+/// A TTL cache of single-use nonces with a hard capacity, see the module documentation.
+pub struct NonceCache {
+    ttl: Duration,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+    /// Outstanding nonces with their expiry.
+    expiries: HashMap<[u8; 32], SystemTime>,
+    /// All nonces in the order they were issued, including ones already used.
+    order: VecDeque<[u8; 32]>,
+}
+
+impl NonceCache {
+    pub fn new(ttl: Duration, capacity: usize, clock: Arc<dyn Clock>) -> Self {
+        Self { ttl, capacity, clock, expiries: HashMap::new(), order: VecDeque::new() }
+    }
+
+    /// Issue a fresh random nonce.
+    pub fn issue(&mut self) -> Result<[u8; 32]> {
+        let mut nonce = [0u8; 32];
+        rand_core::OsRng.try_fill_bytes(&mut nonce)?;
+        self.insert(nonce);
+        Ok(nonce)
+    }
+
+    fn insert(&mut self, nonce: [u8; 32]) {
+        self.expire();
+        while self.order.len() >= self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => self.expiries.remove(&oldest),
+                None => break,
+            };
+        }
+        self.expiries.insert(nonce, self.clock.now() + self.ttl);
+        self.order.push_back(nonce);
+    }
+
+    /// Use `nonce`: true if it was issued, has not expired, and has not been used before.
+    pub fn consume(&mut self, nonce: &[u8]) -> bool {
+        self.expire();
+        let Ok(nonce) = <[u8; 32]>::try_from(nonce) else {
+            return false;
+        };
+        self.expiries.remove(&nonce).is_some()
+    }
+
+    /// Number of outstanding (issued, unexpired, and unused) nonces.
+    pub fn outstanding(&self) -> usize {
+        self.expiries.len()
+    }
+
+    fn expire(&mut self) {
+        let now = self.clock.now();
+        while let Some(oldest) = self.order.front() {
+            match self.expiries.get(oldest) {
+                Some(expiry) if *expiry > now => break,
+                _ => {
+                    self.expiries.remove(oldest);
+                    self.order.pop_front();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nsm_attestation::clock::MockClock;
+
+    #[test]
+    fn test_nonce_cache_capacity_and_expiry() {
+        let clock = MockClock::at_unix_seconds(1_700_000_000);
+        let mut cache = NonceCache::new(Duration::from_secs(60), 3, Arc::new(clock.clone()));
+        for i in 1..=4 {
+            cache.insert([i; 32]);
+        }
+        // The oldest nonce was evicted to make room.
+        assert_eq!(cache.outstanding(), 3);
+        assert!(!cache.consume(&[1; 32]));
+
+        clock.advance(Duration::from_secs(30));
+        cache.insert([5; 32]);
+        assert!(!cache.consume(&[2; 32]));
+
+        // Nonces issued more than a TTL ago have expired; recent ones remain valid once.
+        clock.advance(Duration::from_secs(31));
+        assert!(!cache.consume(&[3; 32]));
+        assert!(!cache.consume(&[4; 32]));
+        assert_eq!(cache.outstanding(), 1);
+        assert!(cache.consume(&[5; 32]));
+        assert!(!cache.consume(&[5; 32]));
+        assert_eq!(cache.outstanding(), 0);
+
+        // Random nonces round-trip; malformed ones are rejected.
+        let nonce = cache.issue().unwrap();
+        assert!(!cache.consume(&nonce[..31]));
+        assert!(cache.consume(&nonce));
+    }
+}
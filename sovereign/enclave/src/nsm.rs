@@ -1,15 +1,52 @@
 //! Implementation of the security module trait for AWS NSM.
 
 use anyhow::{anyhow, bail, Result};
+use nsm_attestation::{extended_pcr_value, FIRST_SOVEREIGN_PCR};
 use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
 use tokio_vsock::{VsockAddr, VsockListener, VsockStream};
 
-use crate::secmod::{AttestationDocument, Secmod};
+use crate::config::NsmConfig;
+use crate::secmod::{AttestationDocument, DocumentFormat, Secmod};
 
 pub struct Nsm;
 
+/// The configuration applied by `Nsm::configure`. `listen` and `connect` are associated
+/// functions without an attestor, so the VSOCK addressing is process-wide.
+static NSM_CONFIG: OnceLock<NsmConfig> = OnceLock::new();
+
+fn nsm_config() -> NsmConfig {
+    NSM_CONFIG.get().cloned().unwrap_or_default()
+}
+
+/// Maximum size of each of `public_key`, `user_data`, and `nonce` accepted by NSM, see
+/// [the attestation document specification](https://github.com/aws/aws-nitro-enclaves-nsm-api/blob/main/docs/attestation_process.md).
+const NSM_MAX_ATTESTATION_FIELD_SIZE: usize = crate::secmod::MAX_ATTESTATION_FIELD_BYTES;
+
+/// Fail with a precise error if an attestation request field exceeds the NSM limit.
+fn check_attestation_field_size(name: &str, value: Option<&ByteBuf>) -> Result<()> {
+    if let Some(value) = value {
+        if value.len() > NSM_MAX_ATTESTATION_FIELD_SIZE {
+            bail!(
+                "cannot create attestation: {} is {} bytes; NSM allows at most {} bytes",
+                name,
+                value.len(),
+                NSM_MAX_ATTESTATION_FIELD_SIZE
+            );
+        }
+    }
+    Ok(())
+}
+
 /// See [AWS Attestation](https://docs.aws.amazon.com/enclaves/latest/user/set-up-attestation.html).
 impl AttestationDocument for nsm_attestation::NitroAttestationDocument {
+    #[cfg(feature = "signing")]
+    fn format(&self) -> DocumentFormat {
+        DocumentFormat::AwsNitroCose
+    }
+
+    #[cfg(feature = "signing")]
     fn code_measurement(&self) -> String {
         let pcrs = &self.pcrs;
         // Get PCR values 0,1,2 which contain code measurements
@@ -28,56 +65,119 @@ impl AttestationDocument for nsm_attestation::NitroAttestationDocument {
         format!("AWS-INSTANCE:{}", pcr4)
     }
 
+    #[cfg(feature = "signing")]
     fn nonce(&self) -> Option<&ByteBuf> {
         self.nonce.as_ref()
     }
+    #[cfg(feature = "signing")]
     fn public_key(&self) -> Option<&ByteBuf> {
         self.public_key.as_ref()
     }
+    #[cfg(feature = "signing")]
     fn user_data(&self) -> Option<&ByteBuf> {
         self.user_data.as_ref()
     }
+    fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+    fn cabundle(&self) -> &[ByteBuf] {
+        &self.cabundle
+    }
 }
 
-fn extend_pcr(nsm_fd: i32, index: u16, data: Vec<u8>) -> Result<()> {
+fn lock_pcr(nsm_fd: i32, index: u16) -> Result<()> {
+    let lock_request = nsm_io::Request::LockPCR { index };
+    match nsm_driver::nsm_process_request(nsm_fd, lock_request) {
+        nsm_io::Response::LockPCR => {
+            tracing::info!(index, "PCR locked");
+            Ok(())
+        }
+        _ => bail!("cannot lock PCR#{}", index),
+    }
+}
+
+/// Read the current value of PCR `index`.
+fn describe_pcr(nsm_fd: i32, index: u16) -> Result<Vec<u8>> {
+    let describe_request = nsm_io::Request::DescribePCR { index };
+    match nsm_driver::nsm_process_request(nsm_fd, describe_request) {
+        nsm_io::Response::DescribePCR { data, .. } => Ok(data),
+        nsm_io::Response::Error(code) => bail!("cannot describe PCR#{}: {:?}", index, code),
+        _ => bail!("cannot describe PCR#{}", index),
+    }
+}
+
+/// What needs to be done to a PCR to measure data into it, see `check_pcr`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PcrAction {
+    /// The PCR is unlocked and zero; extend and lock it.
+    Extend,
+    /// The PCR already holds the expected value; only lock it if needed.
+    Keep { locked: bool },
+}
+
+/// Check, without modifying it, that PCR `index` can be extended to hold `expected`.
+///
+/// In strict mode, the PCR must be unlocked and zero.
+/// If `idempotent` is set, a PCR which already holds exactly `expected` is accepted as is.
+fn check_pcr(nsm_fd: i32, index: u16, expected: &[u8], idempotent: bool) -> Result<PcrAction> {
     let describe_request = nsm_io::Request::DescribePCR { index };
     match nsm_driver::nsm_process_request(nsm_fd, describe_request) {
         nsm_io::Response::DescribePCR { lock, data: old_data } => {
-            if lock {
-                bail!("PCR#{} is locked", index)
-            }
+            tracing::info!(
+                index,
+                locked = lock,
+                before = %hex::encode(&old_data),
+                "PCR described"
+            );
             if old_data.len() != 48 {
                 bail!("PCR#{} wrong length {} (expected 48)", index, old_data.len())
             }
+            if idempotent && old_data == expected {
+                tracing::info!("PCR#{} already extended with expected value", index);
+                return Ok(PcrAction::Keep { locked: lock });
+            }
+            if lock {
+                bail!("PCR#{} is locked", index)
+            }
             if old_data != [0; 48] {
                 bail!("PCR#{} already in use (non-zero)", index)
             }
+            Ok(PcrAction::Extend)
         }
         _ => bail!("cannot describe PCR#{}", index),
     }
+}
+
+/// Extend PCR `index` with `data` (if needed, as determined by `check_pcr`) and lock it.
+fn extend_pcr(nsm_fd: i32, index: u16, data: Vec<u8>, action: PcrAction) -> Result<()> {
+    match action {
+        PcrAction::Keep { locked: true } => {
+            tracing::info!(index, "PCR already locked");
+            return Ok(());
+        }
+        PcrAction::Keep { locked: false } => return lock_pcr(nsm_fd, index),
+        PcrAction::Extend => {}
+    }
+    let expected = extended_pcr_value(&data);
     // Extending a PCR replaces its `old_hash` with `new_hash`
     // where `new_hash=SHA384(old_hash | new_data)` and `|` is concatenation.
     // Unused PCRs start of with 48 zero bytes.
-    let extend_request = nsm_io::Request::ExtendPCR { index, data: data.clone() };
+    let extend_request = nsm_io::Request::ExtendPCR { index, data };
     match nsm_driver::nsm_process_request(nsm_fd, extend_request) {
         nsm_io::Response::ExtendPCR { data: new_hash } => {
-            use sha2::Digest;
-            let mut hasher = sha2::Sha384::new();
-            hasher.update([0; 48]);
-            hasher.update(data);
-            let hash = hasher.finalize().to_vec();
-            if hash != new_hash {
+            tracing::info!(
+                index,
+                after = %hex::encode(&new_hash),
+                expected = %hex::encode(&expected),
+                "PCR extended"
+            );
+            if expected != new_hash {
                 bail!("extension incorrect for PCR#{}", index)
             }
         }
         _ => bail!("cannot extend PCR#{}", index),
     }
-    let lock_request = nsm_io::Request::LockPCR { index };
-    match nsm_driver::nsm_process_request(nsm_fd, lock_request) {
-        nsm_io::Response::LockPCR => {}
-        _ => bail!("cannot lock PCR#{}", index),
-    }
-    Ok(())
+    lock_pcr(nsm_fd, index)
 }
 
 impl Secmod for Nsm {
@@ -90,7 +190,7 @@ impl Secmod for Nsm {
         port: u32,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Listener>> + Send>> {
         Box::pin(async move {
-            let addr = VsockAddr::new(tokio_vsock::VMADDR_CID_ANY, port);
+            let addr = VsockAddr::new(nsm_config().listen_cid(), port);
             let listener = tokio_vsock::VsockListener::bind(addr)?;
             Ok(listener)
         })
@@ -100,8 +200,7 @@ impl Secmod for Nsm {
         port: u32,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Stream>> + Send>> {
         Box::pin(async move {
-            // TODO: remove magic port number
-            let addr = VsockAddr::new(3, port);
+            let addr = VsockAddr::new(nsm_config().peer_cid(), port);
             let stream = VsockStream::connect(addr)
                 .await
                 .map_err(|x| anyhow!("failed to connect to VSOCK {}: {}", addr, x.to_string()))?;
@@ -148,6 +247,14 @@ impl Secmod for Nsm {
         format!("AWS-INSTANCE:{}", hex_pcr4)
     }
 
+    fn configure(config: &NsmConfig) -> Result<()> {
+        if NSM_CONFIG.get().is_some_and(|current| current != config) {
+            bail!("NSM is already configured differently");
+        }
+        let _ = NSM_CONFIG.set(config.clone());
+        Ok(())
+    }
+
     /// For AWS Nitro, this calls `nsm_driver::nsm_init` to get a file descriptor.
     fn init_attestor() -> Result<Self::Attestor> {
         tracing::info!("initializing NSM...");
@@ -164,25 +271,122 @@ impl Secmod for Nsm {
         public_key: Option<ByteBuf>,
         user_data: Option<ByteBuf>,
     ) -> Result<Vec<u8>> {
+        check_attestation_field_size("public_key", public_key.as_ref())?;
+        check_attestation_field_size("user_data", user_data.as_ref())?;
+        check_attestation_field_size("nonce", nonce.as_ref())?;
         let request = nsm_io::Request::Attestation { public_key, user_data, nonce };
         match nsm_driver::nsm_process_request(*attestor, request) {
             nsm_io::Response::Attestation { document } => Ok(document),
+            nsm_io::Response::Error(code) => bail!("cannot create attestation: {:?}", code),
             _ => bail!("cannot create attestation"),
         }
     }
 
     fn parse(doc: &[u8]) -> Result<Self::Att> {
-        nsm_attestation::NitroAttestationDocument::from_cose(doc)
+        match DocumentFormat::detect(doc)? {
+            DocumentFormat::AwsNitroCose => {
+                nsm_attestation::NitroAttestationDocument::from_cose(doc)
+            }
+            #[allow(unreachable_patterns)]
+            format => bail!("cannot verify {} attestation documents", format),
+        }
     }
 
-    fn measure_enclave(attestor: &Self::Attestor, measurements: Vec<Vec<u8>>) -> Result<()> {
-        if measurements.len() > 16 {
-            bail!("at most 16 measurements supported, was {}", measurements.len());
+    fn measure_enclave(
+        attestor: &Self::Attestor,
+        first_index: usize,
+        measurements: Vec<Vec<u8>>,
+        idempotent: bool,
+    ) -> Result<()> {
+        if first_index + measurements.len() > 16 {
+            bail!(
+                "at most 16 measurements supported, was {} starting at {}",
+                measurements.len(),
+                first_index
+            );
         }
-        tracing::info!("extending PCRs with config and public keys");
-        for (index, data) in measurements.into_iter().enumerate() {
-            extend_pcr(*attestor, (index + 16) as u16, data)?;
+        let indices: Vec<u16> = (0..measurements.len())
+            .map(|i| (first_index + i) as u16 + FIRST_SOVEREIGN_PCR as u16)
+            .collect();
+        // Pre-flight: check all PCRs before extending any of them, so that a failure
+        // does not leave some PCRs extended and others not.
+        let actions = indices
+            .iter()
+            .zip(&measurements)
+            .map(|(&index, data)| {
+                tracing::info_span!("pcr", index)
+                    .in_scope(|| check_pcr(*attestor, index, &extended_pcr_value(data), idempotent))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        tracing::info!("extending PCRs {:?} with config and public keys", indices);
+        for ((index, data), action) in indices.into_iter().zip(measurements).zip(actions) {
+            let _span = tracing::info_span!("pcr", index, ?action).entered();
+            extend_pcr(*attestor, index, data, action)?;
         }
         Ok(())
     }
+
+    #[cfg(feature = "signing")]
+    fn extend_measurement(attestor: &Self::Attestor, index: usize, data: Vec<u8>) -> Result<()> {
+        use sha2::Digest;
+        if index >= 16 {
+            bail!("at most 16 measurements supported, was slot {}", index);
+        }
+        let index = index as u16 + FIRST_SOVEREIGN_PCR as u16;
+        let _span = tracing::info_span!("pcr", index).entered();
+        let mut hasher = sha2::Sha384::new();
+        hasher.update(describe_pcr(*attestor, index)?);
+        hasher.update(&data);
+        let expected = hasher.finalize().to_vec();
+        let extend_request = nsm_io::Request::ExtendPCR { index, data };
+        match nsm_driver::nsm_process_request(*attestor, extend_request) {
+            nsm_io::Response::ExtendPCR { data: new_hash } if new_hash == expected => {
+                tracing::info!(index, after = %hex::encode(&new_hash), "PCR extended");
+                Ok(())
+            }
+            nsm_io::Response::ExtendPCR { .. } => bail!("extension incorrect for PCR#{}", index),
+            nsm_io::Response::Error(code) => bail!("cannot extend PCR#{}: {:?}", index, code),
+            _ => bail!("cannot extend PCR#{}", index),
+        }
+    }
+
+    fn describe_pcrs(attestor: &Self::Attestor) -> Result<BTreeMap<u16, Vec<u8>>> {
+        let mut pcrs = BTreeMap::new();
+        for index in 0..=4 {
+            pcrs.insert(index, describe_pcr(*attestor, index)?);
+        }
+        for index in 16..32 {
+            let value = describe_pcr(*attestor, index)?;
+            if value.iter().any(|&b| b != 0) {
+                pcrs.insert(index, value);
+            }
+        }
+        Ok(pcrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_attestation_field_size() {
+        let max = ByteBuf::from(vec![0; NSM_MAX_ATTESTATION_FIELD_SIZE]);
+        let too_large = ByteBuf::from(vec![0; NSM_MAX_ATTESTATION_FIELD_SIZE + 1]);
+        assert!(check_attestation_field_size("user_data", None).is_ok());
+        assert!(check_attestation_field_size("user_data", Some(&max)).is_ok());
+        let err = check_attestation_field_size("user_data", Some(&too_large)).unwrap_err();
+        assert!(err.to_string().contains("user_data is 1025 bytes"));
+    }
+
+    #[test]
+    fn test_document_format() {
+        let pcrs = std::collections::HashMap::new();
+        let doc =
+            nsm_attestation::NitroAttestationDocument::cose_create(pcrs, None, None, None).unwrap();
+        assert_eq!(DocumentFormat::detect(&doc).unwrap(), DocumentFormat::AwsNitroCose);
+        let err = Nsm::parse(&[0x04, 0x00, 0x02, 0x00]).unwrap_err();
+        assert!(err.to_string().contains("unknown attestation document format (first byte 0x04)"));
+        assert!(Nsm::parse(&[]).is_err());
+    }
 }
@@ -7,34 +7,145 @@ use serde_json::{json, Value};
 use std::collections::HashMap;
 use tiny_keccak::{Hasher, Keccak};
 
+#[cfg(feature = "signing")]
+use crate::config::Quorum;
 use crate::config::SafeConfig;
 
+/// Authorize `message` using several Safes, requiring the quorum `require`.
+///
+/// Each Safe is evaluated independently as in `safe_authorize_message`;
+/// in particular, a Safe that has revoked the message does not count towards the quorum.
+#[cfg(feature = "signing")]
+pub async fn multi_safe_authorize_message<SM: crate::secmod::Secmod + 'static>(
+    safes: &[SafeConfig],
+    require: &Quorum,
+    message: &str,
+) -> Result<()> {
+    let mut results = Vec::with_capacity(safes.len());
+    for config in safes {
+        let result = safe_authorize_message::<SM>(config, message).await;
+        if let Err(e) = &result {
+            tracing::warn!("safe {} did not authorize message: {}", config.wallet_address, e);
+        }
+        results.push(result);
+    }
+    check_quorum(require, &results)
+}
+
+#[cfg(feature = "signing")]
+fn check_quorum(require: &Quorum, results: &[Result<()>]) -> Result<()> {
+    let approvals = results.iter().filter(|result| result.is_ok()).count();
+    let required = match require {
+        Quorum::All => results.len(),
+        Quorum::AnyOf(n) => *n,
+    };
+    if approvals < required {
+        bail!(
+            "message authorized by {} of {} safes; {} required",
+            approvals,
+            results.len(),
+            required
+        );
+    }
+    Ok(())
+}
+
+/// Parse a Safe wallet address (`0x` followed by 40 hex digits). Mixed-case addresses must
+/// carry a valid EIP-55 checksum; all-lowercase and all-uppercase ones have none to check.
+pub fn parse_wallet_address(wallet_address: &str) -> Result<[u8; 20]> {
+    let digits = wallet_address.strip_prefix("0x").context("wallet address must start with 0x")?;
+    let bytes = hex::decode(digits).context("wallet address is not hex")?;
+    let address: [u8; 20] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+        anyhow::anyhow!("wallet address has {} bytes; expected 20", bytes.len())
+    })?;
+    let mixed_case = digits.bytes().any(|c| c.is_ascii_lowercase())
+        && digits.bytes().any(|c| c.is_ascii_uppercase());
+    if mixed_case && checksum_address(&address) != wallet_address {
+        bail!(
+            "wallet address {} has an invalid EIP-55 checksum; expected {}",
+            wallet_address,
+            checksum_address(&address)
+        );
+    }
+    Ok(address)
+}
+
+/// The EIP-55 checksummed form of `address`: each hex letter is upper case if the
+/// corresponding nibble of the keccak256 hash of the lowercase hex digits is at least 8.
+pub fn checksum_address(address: &[u8; 20]) -> String {
+    let digits = hex::encode(address);
+    let hash = hex::decode(&my_keccak(digits.as_bytes())[2..]).expect("keccak256 is hex");
+    let checksummed: String = digits
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0xf };
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+/// Check that the Safe's HTTP endpoint is reachable and answers message queries.
+///
+/// Queries a message that is not expected to exist; finding it is fine too.
+pub async fn check_safe_connectivity<SM: crate::secmod::Secmod + 'static>(
+    config: &SafeConfig,
+) -> Result<()> {
+    config.check_endpoint_host()?;
+    let wallet_address = config.normalized_wallet_address()?;
+    let message_hash = safe_hash(config.chain_id, &wallet_address, "CONNECTIVITY-CHECK")?;
+    fetch_safe_message::<SM>(config, &message_hash).await?;
+    Ok(())
+}
+
+#[cfg(feature = "signing")]
 pub async fn safe_authorize_message<SM: crate::secmod::Secmod + 'static>(
     config: &SafeConfig,
     message: &str,
 ) -> Result<()> {
-    let SafeConfig { wallet_address, threshold, http_endpoint_port, http_endpoint, chain_id } =
-        config;
+    config.check_endpoint_host()?;
+    let SafeConfig { threshold, chain_id, .. } = config;
+    let wallet_address = config.normalized_wallet_address()?;
 
     // Check for revocation first
     let revoke_message = format!("REVOKE: {}", message);
-    let revoke_hash = safe_hash(*chain_id, &wallet_address, &revoke_message);
-    match fetch_safe_message::<SM>(*http_endpoint_port, http_endpoint, &revoke_hash).await? {
+    let revoke_hash = safe_hash(*chain_id, &wallet_address, &revoke_message)?;
+    match fetch_safe_message::<SM>(config, &revoke_hash).await? {
         FetchResult::Found(_) => bail!("message has been revoked"),
         FetchResult::NotFound => (), // This is what we want - no revocation exists
     }
 
     // Now check the actual message
-    let message_hash = safe_hash(*chain_id, &wallet_address, message);
-    let safe_message =
-        match fetch_safe_message::<SM>(*http_endpoint_port, http_endpoint, &message_hash).await? {
-            FetchResult::Found(msg) => msg,
-            FetchResult::NotFound => bail!("message not found"),
-        };
+    let message_hash = safe_hash(*chain_id, &wallet_address, message)?;
+    let safe_message = match fetch_safe_message::<SM>(config, &message_hash).await? {
+        FetchResult::Found(msg) => *msg,
+        FetchResult::NotFound => bail!("message not found"),
+    };
 
-    if safe_message.safe != *wallet_address {
+    if !safe_message.safe.eq_ignore_ascii_case(&wallet_address) {
         bail!("safe address mismatch");
     }
+    // Confirmations are not verified yet; log what verification would find.
+    let hash: [u8; 32] = hex::decode(message_hash.trim_start_matches("0x"))?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("invalid message hash"))?;
+    for confirmation in &safe_message.confirmations {
+        match parse_safe_signature(&confirmation.signature).and_then(|s| s.recover_signer(&hash)) {
+            Ok(signer) => tracing::debug!(
+                "confirmation by {} recovers to 0x{}",
+                confirmation.owner,
+                hex::encode(signer)
+            ),
+            Err(e) => {
+                tracing::debug!("confirmation by {} not verifiable: {}", confirmation.owner, e)
+            }
+        }
+    }
     if safe_message.confirmations.len() < *threshold {
         bail!("not enough confirmations");
     }
@@ -42,6 +153,80 @@ pub async fn safe_authorize_message<SM: crate::secmod::Secmod + 'static>(
     Ok(())
 }
 
+/// An owner's ECDSA signature from a Safe confirmation, normalized for public key recovery.
+#[derive(Debug, PartialEq)]
+pub struct SafeEcdsaSignature {
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    /// Zero or one (whether the y coordinate of the ephemeral point is odd).
+    pub recovery_id: u8,
+    /// Whether the signature is over the `eth_sign`-prefixed hash
+    /// (`"\x19Ethereum Signed Message:\n32" || hash`) rather than the hash itself.
+    pub eth_sign: bool,
+}
+
+#[cfg(feature = "signing")]
+impl SafeEcdsaSignature {
+    /// Recover the Ethereum address of the signer of the Safe message hash `hash`.
+    pub fn recover_signer(&self, hash: &[u8; 32]) -> Result<[u8; 20]> {
+        use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+        let digest = if self.eth_sign {
+            let mut hasher = Keccak::v256();
+            let mut output = [0u8; 32];
+            hasher.update(b"\x19Ethereum Signed Message:\n32");
+            hasher.update(hash);
+            hasher.finalize(&mut output);
+            output
+        } else {
+            *hash
+        };
+        let signature = Signature::from_scalars(self.r, self.s)?;
+        let recovery_id = RecoveryId::from_byte(self.recovery_id).context("invalid recovery ID")?;
+        let key = VerifyingKey::recover_from_prehash(&digest, &signature, recovery_id)?;
+        let mut hasher = Keccak::v256();
+        let mut output = [0u8; 32];
+        hasher.update(&key.to_encoded_point(false).as_bytes()[1..]);
+        hasher.finalize(&mut output);
+        Ok(output[12..].try_into()?)
+    }
+}
+
+/// Parse a confirmation signature (hex, optionally `0x`-prefixed).
+///
+/// Supported are the 65 byte `r || s || v` form, where `v` is 27 or 28 for ECDSA and
+/// 31 or 32 for `eth_sign` signatures, and the EIP-2098 64 byte compact form
+/// `r || (y_parity << 255 | s)`. Contract signatures (`v = 0`, EIP-1271, possibly followed by
+/// a dynamic part) and approved hashes (`v = 1`) cannot be verified off-chain and are rejected.
+#[cfg(feature = "signing")]
+pub fn parse_safe_signature(signature: &str) -> Result<SafeEcdsaSignature> {
+    let bytes = hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+        .context("signature is not hex")?;
+    let r: [u8; 32] = bytes.get(..32).context("signature too short")?.try_into()?;
+    if bytes.len() == 64 {
+        let mut s: [u8; 32] = bytes[32..64].try_into()?;
+        let recovery_id = s[0] >> 7;
+        s[0] &= 0x7f;
+        return Ok(SafeEcdsaSignature { r, s, recovery_id, eth_sign: false });
+    }
+    if bytes.len() < 65 {
+        bail!("unsupported signature length {}", bytes.len());
+    }
+    let s: [u8; 32] = bytes[32..64].try_into()?;
+    let v = bytes[64];
+    match v {
+        0 => bail!(
+            "contract signature (EIP-1271) by 0x{} requires an on-chain call",
+            hex::encode(&r[12..])
+        ),
+        1 => bail!("approved hash by 0x{} requires an on-chain call", hex::encode(&r[12..])),
+        _ if bytes.len() != 65 => bail!("unsupported signature length {}", bytes.len()),
+        27 | 28 => Ok(SafeEcdsaSignature { r, s, recovery_id: v - 27, eth_sign: false }),
+        31 | 32 => Ok(SafeEcdsaSignature { r, s, recovery_id: v - 31, eth_sign: true }),
+        _ => bail!("unsupported signature v {}", v),
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct SafeMessageConfirmation {
     pub owner: String,
@@ -74,13 +259,45 @@ struct SafeMessage {
 
 #[derive(Debug)]
 enum FetchResult {
-    Found(SafeMessage),
+    Found(Box<SafeMessage>),
     NotFound,
 }
 
+/// Look up a message at the endpoints of `config`, see `SafeConfig::fallback_endpoints`.
 async fn fetch_safe_message<SM: crate::secmod::Secmod + 'static>(
-    out_port: u32,
+    config: &SafeConfig,
+    message_hash: &str,
+) -> Result<FetchResult> {
+    let mut not_found = false;
+    let mut errors = Vec::new();
+    for (http_endpoint, http_endpoint_port) in config.endpoints() {
+        let result = fetch_safe_message_from::<SM>(
+            http_endpoint,
+            http_endpoint_port,
+            config.request_timeout(),
+            message_hash,
+        )
+        .await;
+        match result {
+            Ok(FetchResult::Found(message)) => return Ok(FetchResult::Found(message)),
+            // Another endpoint may not be lagging behind.
+            Ok(FetchResult::NotFound) => not_found = true,
+            Err(e) => {
+                tracing::warn!("Safe endpoint {} failed: {:#}", http_endpoint, e);
+                errors.push(format!("{}: {:#}", http_endpoint, e));
+            }
+        }
+    }
+    if !not_found {
+        bail!("all Safe endpoints failed: {}", errors.join("; "));
+    }
+    Ok(FetchResult::NotFound)
+}
+
+async fn fetch_safe_message_from<SM: crate::secmod::Secmod + 'static>(
     http_endpoint: &str,
+    http_endpoint_port: u32,
+    timeout: std::time::Duration,
     message_hash: &str,
 ) -> Result<FetchResult> {
     let url = format!("{}/{}/", http_endpoint, message_hash);
@@ -104,7 +321,7 @@ async fn fetch_safe_message<SM: crate::secmod::Secmod + 'static>(
         .body(crate::http::full(Vec::new()))?;
 
     tracing::trace!("using 'safe' request message {:#?}", request);
-    let response = crate::http::make_request::<SM>(out_port, request).await?;
+    let response = crate::http::make_request::<SM>(http_endpoint_port, request, timeout).await?;
 
     match response.status() {
         StatusCode::OK => {
@@ -118,11 +335,72 @@ async fn fetch_safe_message<SM: crate::secmod::Secmod + 'static>(
     }
 }
 
-fn safe_hash(chain_id: u64, safe_address: &str, message: &str) -> String {
+/// The hashes involved in authorizing a message with a Safe (all `0x`-prefixed hex).
+#[derive(Debug, PartialEq)]
+pub struct SafeHash {
+    /// EIP-191 hash of the message, which is the `SafeMessage` that the owners sign.
+    pub message_hash: String,
+    /// EIP-712 domain separator of the Safe (`chainId`, `verifyingContract`).
+    pub domain_hash: String,
+    /// EIP-712 struct hash of the `SafeMessage`.
+    pub struct_hash: String,
+    /// The EIP-712 hash under which the transaction service stores the message.
+    pub safe_hash: String,
+}
+
+/// Compute the hashes for `message` (see `--safe-hash`), exactly as checked by the sovereign.
+pub fn safe_hash_details(chain_id: u64, safe_address: &str, message: &str) -> Result<SafeHash> {
     let message_hash = inner_hash(message);
     let typed_data = get_typed_data(chain_id, safe_address, &message_hash);
-    let encoding = encode_typed_data(typed_data);
-    my_keccak(&encoding)
+    let (domain_hash, struct_hash) = typed_data_hashes(&typed_data, "SafeMessage")?;
+    let encoding = encode_typed_data(typed_data, "SafeMessage")?;
+    let safe_hash = my_keccak(&encoding);
+    tracing::trace!(%message_hash, %safe_hash, "Safe message hash");
+    Ok(SafeHash {
+        message_hash,
+        domain_hash: format!("0x{}", domain_hash),
+        struct_hash: format!("0x{}", struct_hash),
+        safe_hash,
+    })
+}
+
+/// The parameters of a Safe `execTransaction`, which the owners sign as the EIP-712 `SafeTx`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafeTransaction {
+    pub to: [u8; 20],
+    /// Big-endian `uint256` value in wei.
+    pub value: [u8; 32],
+    pub data: Vec<u8>,
+    /// 0 for `CALL`, 1 for `DELEGATECALL`.
+    pub operation: u8,
+    pub safe_tx_gas: u64,
+    pub base_gas: u64,
+    pub gas_price: u64,
+    pub gas_token: [u8; 20],
+    pub refund_receiver: [u8; 20],
+    pub nonce: u64,
+}
+
+/// The EIP-712 hash of `transaction` for the Safe at `safe_address`, i.e., the hash that the
+/// owners sign to approve it (`getTransactionHash` of the Safe contract).
+#[cfg(feature = "signing")]
+pub fn safe_transaction_hash(
+    chain_id: u64,
+    safe_address: &[u8; 20],
+    transaction: &SafeTransaction,
+) -> Result<[u8; 32]> {
+    let typed_data = get_safe_transaction_typed_data(chain_id, safe_address, transaction);
+    let encoding = encode_typed_data(typed_data, "SafeTx")?;
+    let mut output = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(&encoding);
+    hasher.finalize(&mut output);
+    tracing::trace!(safe_tx_hash = %hex::encode(output), "Safe transaction hash");
+    Ok(output)
+}
+
+fn safe_hash(chain_id: u64, safe_address: &str, message: &str) -> Result<String> {
+    Ok(safe_hash_details(chain_id, safe_address, message)?.safe_hash)
 }
 
 fn my_keccak(data: &[u8]) -> String {
@@ -178,132 +456,460 @@ fn get_typed_data(chain_id: u64, safe_address: &str, message: &str) -> HashMap<S
     typed_data.into_iter().collect()
 }
 
-fn encode_typed_data(typed_data: HashMap<String, Value>) -> Vec<u8> {
-    let domain = typed_data.get("domain").unwrap().as_object().unwrap();
-    let types = typed_data.get("types").unwrap().as_object().unwrap();
-    let message = typed_data.get("message").unwrap().as_object().unwrap();
+#[cfg(feature = "signing")]
+fn get_safe_transaction_typed_data(
+    chain_id: u64,
+    safe_address: &[u8; 20],
+    transaction: &SafeTransaction,
+) -> HashMap<String, Value> {
+    let address = |address: &[u8; 20]| format!("0x{}", hex::encode(address));
+    let mut typed_data = Vec::new();
 
-    let domain_hash = hash_struct("EIP712Domain", domain, types);
-    let message_hash = hash_struct("SafeMessage", message, types);
+    typed_data.push((
+        "types".to_string(),
+        json!({
+            "EIP712Domain": [
+                {"type": "uint256", "name": "chainId"},
+                {"type": "address", "name": "verifyingContract"}
+            ],
+            "SafeTx": [
+                {"type": "address", "name": "to"},
+                {"type": "uint256", "name": "value"},
+                {"type": "bytes", "name": "data"},
+                {"type": "uint8", "name": "operation"},
+                {"type": "uint256", "name": "safeTxGas"},
+                {"type": "uint256", "name": "baseGas"},
+                {"type": "uint256", "name": "gasPrice"},
+                {"type": "address", "name": "gasToken"},
+                {"type": "address", "name": "refundReceiver"},
+                {"type": "uint256", "name": "nonce"}
+            ]
+        }),
+    ));
 
-    let mut parts = Vec::new();
-    parts.push(hex::decode("1901").unwrap());
-    parts.push(hex::decode(&domain_hash).unwrap());
-    parts.push(hex::decode(&message_hash).unwrap());
-    parts.concat()
+    typed_data.push((
+        "domain".to_string(),
+        json!({
+            "verifyingContract": address(safe_address),
+            "chainId": chain_id
+        }),
+    ));
+
+    typed_data.push((
+        "message".to_string(),
+        json!({
+            "to": address(&transaction.to),
+            "value": format!("0x{}", hex::encode(transaction.value)),
+            "data": format!("0x{}", hex::encode(&transaction.data)),
+            "operation": transaction.operation,
+            "safeTxGas": transaction.safe_tx_gas,
+            "baseGas": transaction.base_gas,
+            "gasPrice": transaction.gas_price,
+            "gasToken": address(&transaction.gas_token),
+            "refundReceiver": address(&transaction.refund_receiver),
+            "nonce": transaction.nonce
+        }),
+    ));
+
+    typed_data.into_iter().collect()
+}
+
+/// The domain separator and the struct hash of the `primary_type` message (hex without `0x`).
+fn typed_data_hashes(
+    typed_data: &HashMap<String, Value>,
+    primary_type: &str,
+) -> Result<(String, String)> {
+    let object = |key: &str| {
+        typed_data
+            .get(key)
+            .and_then(Value::as_object)
+            .with_context(|| format!("typed data has no {} object", key))
+    };
+    let domain = object("domain")?;
+    let types = object("types")?;
+    let message = object("message")?;
+
+    let domain_hash = hash_struct("EIP712Domain", domain, types)?;
+    let message_hash = hash_struct(primary_type, message, types)?;
+    Ok((domain_hash, message_hash))
+}
+
+fn encode_typed_data(typed_data: HashMap<String, Value>, primary_type: &str) -> Result<Vec<u8>> {
+    let (domain_hash, message_hash) = typed_data_hashes(&typed_data, primary_type)?;
+
+    let mut parts = vec![vec![0x19, 0x01]];
+    parts.push(hex::decode(&domain_hash)?);
+    parts.push(hex::decode(&message_hash)?);
+    Ok(parts.concat())
 }
 
 fn hash_struct(
     primary_type: &str,
     data: &serde_json::Map<String, Value>,
     types: &serde_json::Map<String, Value>,
-) -> String {
-    let encoded = encode_data(data, primary_type, types);
+) -> Result<String> {
+    let encoded = encode_data(data, primary_type, types)?;
     let result = my_keccak(&encoded)[2..].to_string();
-    result
+    tracing::trace!(primary_type, hash = %result, "EIP-712 struct hash");
+    Ok(result)
+}
+
+/// The `(type, name)` pairs of the fields of `type_name`.
+fn type_fields<'a>(
+    type_name: &str,
+    types: &'a serde_json::Map<String, Value>,
+) -> Result<Vec<(&'a str, &'a str)>> {
+    let fields = types
+        .get(type_name)
+        .and_then(Value::as_array)
+        .with_context(|| format!("missing type {}", type_name))?;
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let property = |key: &str| {
+                field.get(key).and_then(Value::as_str).with_context(|| {
+                    format!("missing {} of field {} in {} type", key, i, type_name)
+                })
+            };
+            Ok((property("type")?, property("name")?))
+        })
+        .collect()
 }
 
 fn encode_data(
     data: &serde_json::Map<String, Value>,
     primary_type: &str,
     types: &serde_json::Map<String, Value>,
-) -> Vec<u8> {
-    let type_hash = hash_type(primary_type, types);
+) -> Result<Vec<u8>> {
+    let type_hash = hash_type(primary_type, types)?;
     let mut encoded_values: Vec<Value> = Vec::new();
     encoded_values.push(Value::String(hex::encode(&type_hash)));
 
-    let type_fields = types.get(primary_type).unwrap().as_array().unwrap();
-    for field in type_fields {
-        let field_obj = field.as_object().unwrap();
-        let field_type = field_obj.get("type").unwrap().as_str().unwrap();
-        let field_name = field_obj.get("name").unwrap().as_str().unwrap();
-        let value = data.get(field_name).unwrap();
+    for (field_type, field_name) in type_fields(primary_type, types)? {
+        let value = data
+            .get(field_name)
+            .with_context(|| format!("missing field {} in {} type", field_name, primary_type))?;
 
-        let encoded_field = encode_field(field_type, value);
+        let encoded_field = encode_field(field_type, value)
+            .with_context(|| format!("invalid field {} in {} type", field_name, primary_type))?;
         encoded_values.push(encoded_field);
     }
 
-    let result = encode_abi_parameters(&encoded_values);
-    result
+    encode_abi_parameters(&encoded_values)
+        .with_context(|| format!("failed to encode {}", primary_type))
 }
 
-fn encode_field(type_str: &str, value: &Value) -> Value {
+fn encode_field(type_str: &str, value: &Value) -> Result<Value> {
     if type_str == "bytes" {
-        let value_str = value.as_str().unwrap();
-        if value_str.starts_with("0x") {
-            let hex_str = &value_str[2..]; // Removes 0x
-            let bytes = hex::decode(hex_str).unwrap();
-            Value::String(my_keccak(&bytes))
+        let value_str = value.as_str().context("bytes value must be a string")?;
+        if let Some(hex_str) = value_str.strip_prefix("0x") {
+            let bytes = hex::decode(hex_str).context("invalid hex in bytes value")?;
+            Ok(Value::String(my_keccak(&bytes)))
         } else {
-            value.clone()
+            Ok(value.clone())
         }
     } else {
-        value.clone()
+        Ok(value.clone())
     }
 }
 
-fn hash_type(primary_type: &str, types: &serde_json::Map<String, Value>) -> Vec<u8> {
-    let encoded_type = encode_type(primary_type, types);
-    hex::decode(&my_keccak(encoded_type.as_bytes())[2..]).unwrap()
+fn hash_type(primary_type: &str, types: &serde_json::Map<String, Value>) -> Result<Vec<u8>> {
+    let encoded_type = encode_type(primary_type, types)?;
+    let type_hash = my_keccak(encoded_type.as_bytes());
+    tracing::trace!(%encoded_type, hash = %type_hash, "EIP-712 type hash");
+    Ok(hex::decode(&type_hash[2..])?)
 }
 
-fn encode_type(primary_type: &str, types: &serde_json::Map<String, Value>) -> String {
-    let fields = types.get(primary_type).unwrap().as_array().unwrap();
-    let field_strs: Vec<String> = fields
-        .iter()
-        .map(|f| {
-            let f_obj = f.as_object().unwrap();
-            format!(
-                "{} {}",
-                f_obj.get("type").unwrap().as_str().unwrap(),
-                f_obj.get("name").unwrap().as_str().unwrap()
-            )
-        })
+fn encode_type(primary_type: &str, types: &serde_json::Map<String, Value>) -> Result<String> {
+    let field_strs: Vec<String> = type_fields(primary_type, types)?
+        .into_iter()
+        .map(|(field_type, field_name)| format!("{} {}", field_type, field_name))
         .collect();
 
-    format!("{}({})", primary_type, field_strs.join(","))
+    Ok(format!("{}({})", primary_type, field_strs.join(",")))
 }
 
-fn encode_abi_parameters(values: &[Value]) -> Vec<u8> {
+fn encode_abi_parameters(values: &[Value]) -> Result<Vec<u8>> {
     let mut result = Vec::new();
     for v in values {
-        result.extend(encode_abi_parameter(v));
+        result.extend(encode_abi_parameter(v)?);
     }
-    result
+    Ok(result)
 }
 
-fn encode_abi_parameter(v: &Value) -> Vec<u8> {
+fn encode_abi_parameter(v: &Value) -> Result<Vec<u8>> {
     let enc = match v {
         Value::Number(n) => {
             // Convert integers to 32-byte representation
-            let n = n.as_u64().unwrap();
+            let n = n.as_u64().with_context(|| format!("{} is not a uint64", n))?;
             let mut bytes = [0u8; 32];
             bytes[32 - 8..].copy_from_slice(&n.to_be_bytes());
             bytes.to_vec()
         }
         Value::String(s) => {
-            if s.starts_with("0x") {
-                // Convert hex strings to bytes
-                let s = &s[2..]; // Remove '0x' prefix
-                                 // Pad to 32 bytes (64 hex chars)
-                let padded = format!("{:0>64}", s);
-                hex::decode(padded).unwrap()
-            } else {
-                // Regular string - treat as hex string
-                hex::decode(s).unwrap()
+            // Hex strings with `0x` are left-padded to 32 bytes; others must be exactly 32 bytes.
+            let bytes = match s.strip_prefix("0x") {
+                Some(s) if s.len() <= 64 => hex::decode(format!("{:0>64}", s)),
+                Some(_) => bail!("hex value longer than 32 bytes"),
+                None => hex::decode(s),
             }
+            .context("invalid hex value")?;
+            if bytes.len() != 32 {
+                bail!("hex value must be 32 bytes, not {}", bytes.len());
+            }
+            bytes
         }
         Value::Array(arr) => {
             // Handle byte arrays
             let mut padded = vec![0u8; 32];
             for (i, b) in arr.iter().enumerate() {
                 if i < 32 {
-                    padded[i] = b.as_u64().unwrap() as u8;
+                    padded[i] = b
+                        .as_u64()
+                        .and_then(|b| u8::try_from(b).ok())
+                        .context("byte array element out of range")?;
                 }
             }
             padded
         }
         _ => Vec::new(),
     };
-    enc
+    Ok(enc)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[cfg(feature = "signing")]
+    use anyhow::anyhow;
+
+    // A message approved by the first Safe, but not by the second.
+    #[cfg(feature = "signing")]
+    fn split_approval() -> Vec<Result<()>> {
+        vec![Ok(()), Err(anyhow!("message not found"))]
+    }
+
+    #[test]
+    fn test_safe_hash_details() {
+        let details =
+            safe_hash_details(1, "0x0000000000000000000000000000000000000001", "AWS-CODE:00:00:00")
+                .unwrap();
+        let expected = SafeHash {
+            message_hash: "0xd131b5d920dec5ad1e62adc0d8050d6525982d443a769dc23b6c560479048243"
+                .to_string(),
+            domain_hash: "0xd9578c14d681a2ed4541d001ebd7db00c3958ac20f5416fe5eadcefe1330095b"
+                .to_string(),
+            struct_hash: "0xa02c238cbe7fd995813b4826061883453a1dc1bd30a2429df9e42702f21f9a33"
+                .to_string(),
+            safe_hash: "0xaf1c5a685c77f572c84521babba3e93e76ad12bc6e90c7e9ae57a43fa83ae9d9"
+                .to_string(),
+        };
+        assert_eq!(details, expected);
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_safe_transaction_hash() {
+        let types = get_safe_transaction_typed_data(1, &[0; 20], &safe_transaction());
+        let types = types.get("types").unwrap().as_object().unwrap();
+        // SAFE_TX_TYPEHASH of the Safe contracts.
+        assert_eq!(
+            hex::encode(hash_type("SafeTx", types).unwrap()),
+            "bb8310d486368db6bd6f849402fdd73ad53d316b5a4b2644ad6efe0f941286d8"
+        );
+
+        let safe_address =
+            parse_wallet_address("0xd8da6bf26964af9d7eed9e03e53415d37aa96045").unwrap();
+        let hash = safe_transaction_hash(1, &safe_address, &safe_transaction()).unwrap();
+        // Computed independently following the Safe contracts' `getTransactionHash`:
+        // keccak256(0x1901 || keccak256(abi.encode(DOMAIN_SEPARATOR_TYPEHASH, chainId, safe))
+        // || keccak256(abi.encode(SAFE_TX_TYPEHASH, to, value, keccak256(data), operation,
+        // safeTxGas, baseGas, gasPrice, gasToken, refundReceiver, nonce))).
+        assert_eq!(
+            hex::encode(hash),
+            "9acdf32fcfa311096f4970f1f38847390e560af7641fc9775d0dbd7f4e953e8e"
+        );
+
+        // The hash commits to every field, the chain and the Safe.
+        let changes: [fn(&mut SafeTransaction); 10] = [
+            |tx| tx.to[0] = 1,
+            |tx| tx.value[31] = 1,
+            |tx| tx.data.push(0),
+            |tx| tx.operation = 1,
+            |tx| tx.safe_tx_gas = 1,
+            |tx| tx.base_gas = 1,
+            |tx| tx.gas_price = 1,
+            |tx| tx.gas_token[0] = 1,
+            |tx| tx.refund_receiver[0] = 1,
+            |tx| tx.nonce = 8,
+        ];
+        for change in changes {
+            let mut transaction = safe_transaction();
+            change(&mut transaction);
+            assert_ne!(safe_transaction_hash(1, &safe_address, &transaction).unwrap(), hash);
+        }
+        assert_ne!(safe_transaction_hash(5, &safe_address, &safe_transaction()).unwrap(), hash);
+        assert_ne!(safe_transaction_hash(1, &[0; 20], &safe_transaction()).unwrap(), hash);
+    }
+
+    // An ERC-20 transfer of 1000 units to 0x1111..11, sending along 1 ether.
+    #[cfg(feature = "signing")]
+    fn safe_transaction() -> SafeTransaction {
+        let mut to = [0u8; 20];
+        to[19] = 2;
+        let mut value = [0u8; 32];
+        value[24..].copy_from_slice(&1_000_000_000_000_000_000u64.to_be_bytes());
+        let mut data = hex::decode("a9059cbb").unwrap();
+        data.extend([0u8; 12]);
+        data.extend([0x11u8; 20]);
+        data.extend([0u8; 30]);
+        data.extend(1000u16.to_be_bytes());
+        SafeTransaction {
+            to,
+            value,
+            data,
+            operation: 0,
+            safe_tx_gas: 0,
+            base_gas: 0,
+            gas_price: 0,
+            gas_token: [0; 20],
+            refund_receiver: [0; 20],
+            nonce: 7,
+        }
+    }
+
+    #[test]
+    fn test_malformed_typed_data() {
+        let typed_data = |types: Value, message: Value| -> HashMap<String, Value> {
+            HashMap::from([
+                ("types".to_string(), types),
+                ("domain".to_string(), json!({"chainId": 1})),
+                ("message".to_string(), message),
+            ])
+        };
+        let types = |fields: Value| {
+            let domain = json!([{"type": "uint256", "name": "chainId"}]);
+            json!({"EIP712Domain": domain, "SafeMessage": fields})
+        };
+        let error =
+            |typed_data| format!("{:#}", encode_typed_data(typed_data, "SafeMessage").unwrap_err());
+
+        let valid = typed_data(
+            types(json!([{"type": "bytes", "name": "message"}])),
+            json!({"message": "0x00"}),
+        );
+        assert!(encode_typed_data(valid, "SafeMessage").is_ok());
+
+        let missing_value =
+            typed_data(types(json!([{"type": "bytes", "name": "message"}])), json!({}));
+        assert_eq!(error(missing_value), "missing field message in SafeMessage type");
+        let missing_name =
+            typed_data(types(json!([{"type": "bytes"}])), json!({"message": "0x00"}));
+        assert_eq!(error(missing_name), "missing name of field 0 in SafeMessage type");
+        let missing_type = typed_data(json!({}), json!({}));
+        assert_eq!(error(missing_type), "missing type EIP712Domain");
+        let bad_hex = typed_data(
+            types(json!([{"type": "bytes", "name": "message"}])),
+            json!({"message": "0xzz"}),
+        );
+        assert!(error(bad_hex).starts_with("invalid field message in SafeMessage type"));
+        let mut no_domain = typed_data(json!({}), json!({}));
+        no_domain.remove("domain");
+        assert_eq!(error(no_domain), "typed data has no domain object");
+    }
+
+    #[test]
+    fn test_parse_wallet_address() {
+        // Examples of EIP-55.
+        for address in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let parsed = parse_wallet_address(address).unwrap();
+            assert_eq!(checksum_address(&parsed), address);
+            // Without the checksum, i.e., in a single case.
+            assert_eq!(parse_wallet_address(&address.to_lowercase()).unwrap(), parsed);
+            let upper = format!("0x{}", address[2..].to_uppercase());
+            assert_eq!(parse_wallet_address(&upper).unwrap(), parsed);
+        }
+        // A wrong checksum (one letter's case flipped).
+        let err = parse_wallet_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").unwrap_err();
+        assert!(err.to_string().contains("invalid EIP-55 checksum"));
+        // Malformed addresses.
+        assert!(parse_wallet_address("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_err());
+        assert!(parse_wallet_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAe").is_err());
+        assert!(parse_wallet_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA").is_err());
+        assert!(parse_wallet_address("0xzaAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").is_err());
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_quorum_all() {
+        assert!(check_quorum(&Quorum::All, &split_approval()).is_err());
+        assert!(check_quorum(&Quorum::All, &[Ok(()), Ok(())]).is_ok());
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_parse_safe_signature() {
+        let r = "11".repeat(32);
+        let s = "22".repeat(32);
+        let ecdsa = parse_safe_signature(&format!("0x{}{}1c", r, s)).unwrap();
+        assert_eq!(
+            ecdsa,
+            SafeEcdsaSignature { r: [0x11; 32], s: [0x22; 32], recovery_id: 1, eth_sign: false }
+        );
+        let eth_sign = parse_safe_signature(&format!("{}{}1f", r, s)).unwrap();
+        assert_eq!(eth_sign.recovery_id, 0);
+        assert!(eth_sign.eth_sign);
+
+        // EIP-2098 compact form, with and without the y parity bit set.
+        let compact = parse_safe_signature(&format!("0x{}a2{}", r, "22".repeat(31))).unwrap();
+        assert_eq!(compact, ecdsa);
+        let compact = parse_safe_signature(&format!("0x{}{}", r, s)).unwrap();
+        assert_eq!(compact.recovery_id, 0);
+        assert_eq!(compact.s, [0x22; 32]);
+
+        // Contract signature with a dynamic part, approved hash, and malformed signatures.
+        let owner = format!("{}{}", "00".repeat(12), "ab".repeat(20));
+        let offset = format!("{:064x}", 65);
+        let contract = format!("0x{}{}00{:064x}cafe", owner, offset, 2);
+        let err = parse_safe_signature(&contract).unwrap_err();
+        assert!(err.to_string().contains("EIP-1271"));
+        assert!(parse_safe_signature(&format!("0x{}{}01", owner, s)).is_err());
+        assert!(parse_safe_signature(&format!("0x{}{}1b00", r, s)).is_err());
+        assert!(parse_safe_signature(&format!("0x{}{}05", r, s)).is_err());
+        assert!(parse_safe_signature(&format!("0x{}", r)).is_err());
+        assert!(parse_safe_signature("0xzz").is_err());
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_recover_safe_signer() {
+        let secret_key = k256::SecretKey::from_slice(&[5; 32]).unwrap();
+        let pair = crate::key_server::SecretPubKeyPair::from_secret_key(secret_key);
+        let hash = [9u8; 32];
+        let sig = pair.ecdsa_sign_prehash(&hash).unwrap();
+        let v = 27 + sig.is_y_odd as u8;
+        let signature = format!("0x{}{}{:02x}", hex::encode(sig.r), hex::encode(sig.s), v);
+        let parsed = parse_safe_signature(&signature).unwrap();
+        assert_eq!(parsed.recover_signer(&hash).unwrap(), pair.ethereum_address());
+        assert_ne!(parsed.recover_signer(&[8; 32]).unwrap(), pair.ethereum_address());
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_quorum_any_of() {
+        assert!(check_quorum(&Quorum::AnyOf(1), &split_approval()).is_ok());
+        assert!(check_quorum(&Quorum::AnyOf(2), &split_approval()).is_err());
+        assert!(check_quorum(
+            &Quorum::AnyOf(1),
+            &[Err(anyhow!("revoked")), Err(anyhow!("revoked"))]
+        )
+        .is_err());
+    }
 }
@@ -3,23 +3,78 @@
 
 use anyhow::{bail, Result};
 use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+
+use crate::config::NsmConfig;
+
+/// The formats of attestation documents, one per kind of security module.
+///
+/// Each `Secmod::parse` dispatches on the detected format to the matching constructor
+/// (e.g., `NitroAttestationDocument::from_cose`), so that further TEE types, such as Intel
+/// TDX quotes, can be added as new variants with their own parsers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocumentFormat {
+    /// AWS Nitro: a CBOR document in a COSE_Sign1 envelope, signed by the NSM.
+    AwsNitroCose,
+    /// The JSON documents of `MockSecmod`.
+    #[cfg(feature = "test-utils")]
+    Mock,
+}
+
+impl DocumentFormat {
+    /// Recognize the format of `doc` from its first byte.
+    pub fn detect(doc: &[u8]) -> Result<Self> {
+        match doc.first() {
+            // COSE_Sign1 is a CBOR array of four items, optionally with CBOR tag 18.
+            Some(0x84) | Some(0xd2) => Ok(DocumentFormat::AwsNitroCose),
+            #[cfg(feature = "test-utils")]
+            Some(b'{') => Ok(DocumentFormat::Mock),
+            Some(byte) => bail!("unknown attestation document format (first byte {:#04x})", byte),
+            None => bail!("empty attestation document"),
+        }
+    }
+}
+
+impl std::fmt::Display for DocumentFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DocumentFormat::AwsNitroCose => write!(f, "AWS Nitro (COSE)"),
+            #[cfg(feature = "test-utils")]
+            DocumentFormat::Mock => write!(f, "mock"),
+        }
+    }
+}
 
 /// Abstract trait representing an attestation document from a security module
 /// implementation. This trait is intended to be implemented by some datatype
 /// that represents a parsed and validated attestation document.
 pub trait AttestationDocument: Send + Sync {
+    /// The format this document was parsed from.
+    #[cfg(feature = "signing")]
+    fn format(&self) -> DocumentFormat;
     /// Typically supplied by the entity that has requested this attestation documet.
+    #[cfg(feature = "signing")]
     fn nonce(&self) -> Option<&ByteBuf>;
     /// If used, typically a public key supplied the the entity that has requested this attestation document.
+    #[cfg(feature = "signing")]
     fn public_key(&self) -> Option<&ByteBuf>;
     /// May be used for any extra data the the entity that has requested this attestation document wishes to include.
+    #[cfg(feature = "signing")]
     fn user_data(&self) -> Option<&ByteBuf>;
+    /// The DER-encoded certificate that signed this attestation document (empty if none).
+    fn certificate(&self) -> &[u8];
+    /// The DER-encoded CA certificates leading to `certificate`, starting with the root.
+    ///
+    /// These are served for inspection only: verifiers must trust the security module
+    /// vendor's root (e.g., the AWS Nitro root), never a root supplied by the enclave.
+    fn cabundle(&self) -> &[ByteBuf];
 
     /// Return a string that represents the measurement of the code running inside the enclave.
     ///
     /// For AWS, this is the string `AWS-CODE:{PCR-0}:{PCR-1}:{PCR-2}`,
     /// where the PCR measurements are hex-encoded, as in the output of
     /// `nitro-cli build-enclave`.
+    #[cfg(feature = "signing")]
     fn code_measurement(&self) -> String;
 
     /// Return a string that represents the measurement of the instance on which this enclave runs.
@@ -31,6 +86,10 @@ pub trait AttestationDocument: Send + Sync {
     fn instance_measurement(&self) -> String;
 }
 
+/// Maximum size of each of `nonce`, `public_key`, and `user_data` of an attestation, as
+/// imposed by NSM; requests are checked against it up front regardless of the security module.
+pub const MAX_ATTESTATION_FIELD_BYTES: usize = 1024;
+
 /// This trait represents a security module that aa sovereign interacts with
 /// for generating cryptographically signed attestations and related operations.
 pub trait Secmod {
@@ -74,6 +133,12 @@ pub trait Secmod {
     /// ```
     fn measure_instance(instance: String) -> String;
 
+    /// Apply the `nsm` section of the configuration; called once at startup, before any
+    /// `listen` or `connect`. Security modules other than NSM ignore it.
+    fn configure(_config: &NsmConfig) -> Result<()> {
+        Ok(())
+    }
+
     /// Create a new attestor for this security module.
     fn init_attestor() -> Result<Self::Attestor>;
 
@@ -88,14 +153,37 @@ pub trait Secmod {
     ) -> Result<Vec<u8>>;
 
     /// Parse and validate the signature on a signed attestation document,
-    /// as returned from `new_attestation`.
+    /// as returned from `new_attestation`. Documents of formats that this security module
+    /// cannot verify are rejected (see `DocumentFormat`).
     ///
     /// Note: for secure enclaves, this method must also verify the signature of the attestation document.
     fn parse(doc: &[u8]) -> Result<Self::Att>;
 
-    fn measure_enclave(attestor: &Self::Attestor, data: Vec<Vec<u8>>) -> Result<()>;
+    /// Extend the enclave's measurements with `data`, one measurement per item,
+    /// starting at measurement slot `first_index` (for AWS, slot `i` is PCR `16 + i`).
+    ///
+    /// If `idempotent` is set, measurements that have already been extended with
+    /// the same data (e.g., after a restart within the same enclave) are accepted.
+    fn measure_enclave(
+        attestor: &Self::Attestor,
+        first_index: usize,
+        data: Vec<Vec<u8>>,
+        idempotent: bool,
+    ) -> Result<()>;
+
+    /// Extend measurement slot `index` with `data` without locking it, so that it can be extended
+    /// again later (see `KeyServer::rotate_keys`).
+    #[cfg(feature = "signing")]
+    fn extend_measurement(attestor: &Self::Attestor, index: usize, data: Vec<u8>) -> Result<()>;
+
+    /// Read back the enclave's current measurements, mapping measurement register index to value.
+    ///
+    /// For AWS, these are the code and instance measurements PCR-0 to PCR-4
+    /// and the PCRs 16 to 31 that have been extended by `measure_enclave` (non-zero).
+    fn describe_pcrs(attestor: &Self::Attestor) -> Result<BTreeMap<u16, Vec<u8>>>;
 }
 
+#[cfg(feature = "signing")]
 pub trait AttestationDocumentExt: AttestationDocument {
     fn verify(
         &self,
@@ -104,8 +192,9 @@ pub trait AttestationDocumentExt: AttestationDocument {
         expected_user_data: Option<&ByteBuf>,
     ) -> Result<()> {
         if let Some(expected) = expected_nonce {
+            // Nonce and user data are compared in constant time; see `nsm_attestation::ct_eq`.
             match self.nonce() {
-                Some(actual) if actual == expected => {
+                Some(actual) if nsm_attestation::ct_eq(actual, expected) => {
                     tracing::debug!("nonce ok");
                 }
                 _ => bail!("nonce mismatch"),
@@ -121,7 +210,7 @@ pub trait AttestationDocumentExt: AttestationDocument {
         }
         if let Some(expected) = expected_user_data {
             match self.user_data() {
-                Some(actual) if actual == expected => {
+                Some(actual) if nsm_attestation::ct_eq(actual, expected) => {
                     tracing::debug!("user_data ok");
                 }
                 _ => bail!("user data mismatch"),
@@ -131,4 +220,5 @@ pub trait AttestationDocumentExt: AttestationDocument {
     }
 }
 
+#[cfg(feature = "signing")]
 impl<T: AttestationDocument> AttestationDocumentExt for T {}
@@ -0,0 +1,445 @@
+//! EIP-712 typed structured data in the JSON shape of `eth_signTypedData_v4`, i.e., an object
+//! with `types`, `primaryType`, `domain`, and `message`.
+//!
+//! Unlike the fixed Safe encoding in `safe`, the types are taken from the payload: structs may
+//! reference other structs (which are then appended to the type encoding in alphabetical order)
+//! and fields may be arrays (`T[]` or `T[n]`, encoded as the hash of the concatenated encodings
+//! of their elements).
+//!
+//! See https://eips.ethereum.org/EIPS/eip-712 for details.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use tiny_keccak::{Hasher, Keccak};
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+/// The domain fields in the order of the specification, for payloads that do not declare an
+/// `EIP712Domain` type (as accepted by `eth_signTypedData_v4`).
+const DOMAIN_FIELDS: [(&str, &str); 5] = [
+    ("name", "string"),
+    ("version", "string"),
+    ("chainId", "uint256"),
+    ("verifyingContract", "address"),
+    ("salt", "bytes32"),
+];
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TypedField {
+    pub name: String,
+    pub r#type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TypedData {
+    pub types: BTreeMap<String, Vec<TypedField>>,
+    #[serde(rename = "primaryType")]
+    pub primary_type: String,
+    pub domain: Map<String, Value>,
+    #[serde(default)]
+    pub message: Map<String, Value>,
+}
+
+impl TypedData {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("invalid eth_signTypedData_v4 payload")
+    }
+
+    /// The digest that is signed, `keccak256(0x19 || 0x01 || domainSeparator || hashStruct(message))`
+    /// (without the struct hash if the primary type is `EIP712Domain`).
+    pub fn digest(&self) -> Result<[u8; 32]> {
+        let mut encoded = vec![0x19, 0x01];
+        encoded.extend(self.domain_separator()?);
+        if self.primary_type != DOMAIN_TYPE {
+            encoded.extend(self.struct_hash()?);
+        }
+        Ok(keccak256(&encoded))
+    }
+
+    pub fn domain_separator(&self) -> Result<[u8; 32]> {
+        self.hash_struct(DOMAIN_TYPE, &self.domain).context("domain")
+    }
+
+    /// `hashStruct` of the message as its primary type.
+    pub fn struct_hash(&self) -> Result<[u8; 32]> {
+        self.hash_struct(&self.primary_type, &self.message).context("message")
+    }
+
+    fn fields(&self, type_name: &str) -> Option<Vec<TypedField>> {
+        if let Some(fields) = self.types.get(type_name) {
+            return Some(fields.clone());
+        }
+        if type_name != DOMAIN_TYPE {
+            return None;
+        }
+        let fields = DOMAIN_FIELDS
+            .iter()
+            .filter(|(name, _)| self.domain.contains_key(*name))
+            .map(|(name, r#type)| TypedField { name: name.to_string(), r#type: r#type.to_string() })
+            .collect();
+        Some(fields)
+    }
+
+    /// The struct types referenced (directly or not) by `type_name`, including itself.
+    fn dependencies(&self, type_name: &str, found: &mut BTreeSet<String>) -> Result<()> {
+        let type_name = base_type(type_name);
+        if found.contains(type_name) {
+            return Ok(());
+        }
+        let Some(fields) = self.fields(type_name) else {
+            return Ok(());
+        };
+        found.insert(type_name.to_string());
+        for field in fields {
+            self.dependencies(&field.r#type, found)?;
+        }
+        Ok(())
+    }
+
+    /// `encodeType`, e.g., `Mail(Person from,Person to,string contents)Person(string name,...)`.
+    pub fn encode_type(&self, type_name: &str) -> Result<String> {
+        let mut dependencies = BTreeSet::new();
+        self.dependencies(type_name, &mut dependencies)?;
+        if !dependencies.remove(type_name) {
+            bail!("unknown struct type: {}", type_name);
+        }
+        let mut encoded = String::new();
+        for name in std::iter::once(type_name).chain(dependencies.iter().map(String::as_str)) {
+            let fields = self.fields(name).context("unknown struct type")?;
+            let fields: Vec<String> =
+                fields.iter().map(|field| format!("{} {}", field.r#type, field.name)).collect();
+            encoded.push_str(&format!("{}({})", name, fields.join(",")));
+        }
+        Ok(encoded)
+    }
+
+    fn hash_struct(&self, type_name: &str, data: &Map<String, Value>) -> Result<[u8; 32]> {
+        let fields =
+            self.fields(type_name).with_context(|| format!("unknown type {}", type_name))?;
+        let mut encoded = keccak256(self.encode_type(type_name)?.as_bytes()).to_vec();
+        for field in fields {
+            let value = data
+                .get(&field.name)
+                .with_context(|| format!("missing field {} of {}", field.name, type_name))?;
+            let value = self
+                .encode_value(&field.r#type, value)
+                .with_context(|| format!("field {} of {}", field.name, type_name))?;
+            encoded.extend(value);
+        }
+        Ok(keccak256(&encoded))
+    }
+
+    /// The 32 byte encoding of `value` as a member of a struct.
+    fn encode_value(&self, r#type: &str, value: &Value) -> Result<[u8; 32]> {
+        if let Some((element_type, length)) = array_type(r#type)? {
+            let elements = value.as_array().context("expected an array")?;
+            if length.is_some_and(|length| length != elements.len()) {
+                bail!("expected {} elements, got {}", length.unwrap_or_default(), elements.len());
+            }
+            let mut encoded = Vec::new();
+            for element in elements {
+                encoded.extend(self.encode_value(element_type, element)?);
+            }
+            return Ok(keccak256(&encoded));
+        }
+        if self.types.contains_key(r#type) {
+            let data = value.as_object().context("expected an object")?;
+            return self.hash_struct(r#type, data);
+        }
+        encode_atomic(r#type, value)
+    }
+}
+
+/// The element type and length (if fixed) of an array type.
+fn array_type(r#type: &str) -> Result<Option<(&str, Option<usize>)>> {
+    let Some(prefix) = r#type.strip_suffix(']') else {
+        return Ok(None);
+    };
+    let (element_type, length) = prefix.rsplit_once('[').context("malformed array type")?;
+    if length.is_empty() {
+        return Ok(Some((element_type, None)));
+    }
+    let length = length.parse().with_context(|| format!("invalid array length: {}", length))?;
+    Ok(Some((element_type, Some(length))))
+}
+
+/// A type without its array dimensions, e.g., `Person` for `Person[][2]`.
+fn base_type(r#type: &str) -> &str {
+    r#type.split('[').next().unwrap_or(r#type)
+}
+
+fn encode_atomic(r#type: &str, value: &Value) -> Result<[u8; 32]> {
+    let mut encoded = [0u8; 32];
+    match r#type {
+        "bytes" => return Ok(keccak256(&hex_bytes(value)?)),
+        "string" => return Ok(keccak256(value.as_str().context("expected a string")?.as_bytes())),
+        "bool" => encoded[31] = value.as_bool().context("expected a boolean")? as u8,
+        "address" => {
+            let address = hex_bytes(value)?;
+            if address.len() != 20 {
+                bail!("address must be 20 bytes - was {}", address.len());
+            }
+            encoded[12..].copy_from_slice(&address);
+        }
+        _ => {
+            if let Some(size) = r#type.strip_prefix("bytes") {
+                let size: usize = size.parse().context("invalid bytes type")?;
+                let bytes = hex_bytes(value)?;
+                if !(1..=32).contains(&size) || bytes.len() > size {
+                    bail!("{} cannot hold {} bytes", r#type, bytes.len());
+                }
+                encoded[..bytes.len()].copy_from_slice(&bytes);
+            } else if let Some(bits) = r#type.strip_prefix("uint") {
+                encoded = encode_integer(value, integer_bits(bits)?, false)?;
+            } else if let Some(bits) = r#type.strip_prefix("int") {
+                encoded = encode_integer(value, integer_bits(bits)?, true)?;
+            } else {
+                bail!("unknown type: {}", r#type);
+            }
+        }
+    }
+    Ok(encoded)
+}
+
+fn integer_bits(bits: &str) -> Result<usize> {
+    match bits.parse() {
+        Ok(bits) if bits % 8 == 0 && (8..=256).contains(&bits) => Ok(bits),
+        _ => bail!("invalid integer size: {}", bits),
+    }
+}
+
+/// A (`signed`) `bits` wide integer given as a JSON number, a decimal string, or a `0x`-prefixed
+/// hex string, as a 256 bit big-endian two's complement.
+fn encode_integer(value: &Value, bits: usize, signed: bool) -> Result<[u8; 32]> {
+    let (negative, magnitude) = match value {
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => (false, u256_from_digits(&n.to_string(), 10)?),
+            (None, Some(n)) => (true, u256_from_digits(&n.unsigned_abs().to_string(), 10)?),
+            _ => bail!("{} is not an integer (use a string for large values)", n),
+        },
+        Value::String(s) => {
+            let (negative, s) = match s.strip_prefix('-') {
+                Some(s) => (true, s),
+                None => (false, s.as_str()),
+            };
+            let magnitude = match s.strip_prefix("0x") {
+                Some(hex) => u256_from_digits(hex, 16)?,
+                None => u256_from_digits(s, 10)?,
+            };
+            (negative, magnitude)
+        }
+        _ => bail!("expected an integer"),
+    };
+    let length = bit_length(&magnitude);
+    let is_zero = length == 0;
+    if negative && !is_zero {
+        // The most negative value, -2^(bits - 1), has a magnitude one bit longer than the others.
+        let most_negative =
+            length == bits && magnitude.iter().map(|b| b.count_ones()).sum::<u32>() == 1;
+        if !signed || (length >= bits && !most_negative) {
+            bail!("integer out of range");
+        }
+        let mut encoded = magnitude.map(|b| !b);
+        for byte in encoded.iter_mut().rev() {
+            let (sum, carry) = byte.overflowing_add(1);
+            *byte = sum;
+            if !carry {
+                break;
+            }
+        }
+        return Ok(encoded);
+    }
+    if length > bits - signed as usize {
+        bail!("integer out of range");
+    }
+    Ok(magnitude)
+}
+
+/// Parse digits in `radix` (10 or 16) as a 256 bit big-endian integer.
+fn u256_from_digits(digits: &str, radix: u32) -> Result<[u8; 32]> {
+    if digits.is_empty() {
+        bail!("empty integer");
+    }
+    let mut result = [0u8; 32];
+    for c in digits.chars() {
+        let digit = c.to_digit(radix).with_context(|| format!("invalid digit: {}", c))?;
+        let mut carry = digit;
+        for byte in result.iter_mut().rev() {
+            let value = *byte as u32 * radix + carry;
+            *byte = value as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            bail!("integer exceeds 256 bits");
+        }
+    }
+    Ok(result)
+}
+
+fn bit_length(value: &[u8; 32]) -> usize {
+    match value.iter().position(|&b| b != 0) {
+        Some(i) => (32 - i) * 8 - value[i].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+fn hex_bytes(value: &Value) -> Result<Vec<u8>> {
+    let value = value.as_str().context("expected a hex string")?;
+    let digits = value.strip_prefix("0x").context("hex string must start with 0x")?;
+    hex::decode(digits).context("invalid hex string")
+}
+
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut output = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    hasher.finalize(&mut output);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // The example of the EIP-712 specification.
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallet", "type": "address"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person"},
+                {"name": "contents", "type": "string"}
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    #[test]
+    fn test_mail() -> Result<()> {
+        let typed_data = TypedData::from_json(MAIL)?;
+        assert_eq!(
+            typed_data.encode_type("Mail")?,
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            hex::encode(typed_data.domain_separator()?),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            hex::encode(typed_data.struct_hash()?),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+        assert_eq!(
+            hex::encode(typed_data.digest()?),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+
+        // The domain type may be left out and is then inferred from the domain.
+        let mut inferred = typed_data.clone();
+        inferred.types.remove(DOMAIN_TYPE);
+        assert_eq!(inferred.digest()?, typed_data.digest()?);
+        Ok(())
+    }
+
+    // Nested structs in arrays, as in the `eth_signTypedData_v4` example of MetaMask.
+    #[test]
+    fn test_arrays() -> Result<()> {
+        let typed_data = TypedData::from_json(
+            r#"{
+                "types": {
+                    "Group": [
+                        {"name": "name", "type": "string"},
+                        {"name": "members", "type": "Person[]"}
+                    ],
+                    "Mail": [
+                        {"name": "from", "type": "Person"},
+                        {"name": "to", "type": "Person[]"},
+                        {"name": "contents", "type": "string"}
+                    ],
+                    "Person": [
+                        {"name": "name", "type": "string"},
+                        {"name": "wallets", "type": "address[]"}
+                    ]
+                },
+                "primaryType": "Mail",
+                "domain": {
+                    "chainId": 1,
+                    "name": "Ether Mail",
+                    "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC",
+                    "version": "1"
+                },
+                "message": {
+                    "contents": "Hello, Bob!",
+                    "from": {
+                        "name": "Cow",
+                        "wallets": [
+                            "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826",
+                            "0xDeaDbeefdEAdbeefdEadbEEFdeadbeEFdEaDbeeF"
+                        ]
+                    },
+                    "to": [
+                        {
+                            "name": "Bob",
+                            "wallets": [
+                                "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB",
+                                "0xB0BdaBea57B0BDABeA57b0bdABEA57b0BDabEa57",
+                                "0xB0B0b0b0b0b0B000000000000000000000000000"
+                            ]
+                        }
+                    ]
+                }
+            }"#,
+        )?;
+        assert_eq!(
+            typed_data.encode_type("Group")?,
+            "Group(string name,Person[] members)Person(string name,address[] wallets)"
+        );
+        assert_eq!(
+            hex::encode(typed_data.digest()?),
+            "a85c2e2b118698e88db68a8105b794a8cc7cec074e89ef991cb4f5f533819cc2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_integer() -> Result<()> {
+        let encode = |value: Value, bits, signed| encode_integer(&value, bits, signed);
+        assert_eq!(
+            encode(Value::from(1000), 256, false)?,
+            encode(Value::from("0x3e8"), 256, false)?
+        );
+        assert_eq!(encode(Value::from(-1), 8, true)?, [0xff; 32]);
+        assert_eq!(hex::encode(&encode(Value::from("-128"), 8, true)?[31..]), "80");
+        assert!(encode(Value::from("-129"), 8, true).is_err());
+        assert!(encode(Value::from(128), 8, true).is_err());
+        assert!(encode(Value::from(256), 8, false).is_err());
+        assert!(encode(Value::from(-1), 8, false).is_err());
+        let max = format!("0x{}", "ff".repeat(32));
+        assert_eq!(encode(Value::from(max), 256, false)?, [0xff; 32]);
+        assert!(encode(Value::from(format!("0x1{}", "00".repeat(32))), 256, false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+//! Structured `user_data` of attestation documents.
+//!
+//! Instead of an opaque 32 byte value, the sovereign puts a CBOR encoded `AttestationUserData`
+//! into the `user_data` of its attestation documents, prefixed with the self-described CBOR
+//! tag (`0xd9d9f7`, RFC 8949 section 3.4.6) so that it can be told apart from legacy values.
+//! Its fields are named and versioned, so that further data can be bound without ambiguity.
+//!
+//! For the transition, `parse` also accepts legacy opaque values (which sovereigns produced
+//! before, e.g., the raw follower nonce in key-sync).
+//!
+//! Attestations served over HTTP can be bound to an audience, i.e., the relying party they are
+//! intended for, so that they cannot be replayed to another service that trusts the same
+//! enclave (see `audience_binding`). The audience only changes `user_data`: the `nonce` and
+//! `public_key` of the request are bound as before, and the `user_data` supplied by the client
+//! is bound through its SHA-256 hash in the `sha256` field, so the two cannot collide. Without
+//! an audience, client-supplied `user_data` is used verbatim, as before.
+//!
+//! If `instance-id` is configured, attestations served over HTTP also carry the instance ID,
+//! structured as with an audience. The enclave cannot observe its instance ID, so this value is
+//! self-reported: verifiers must only trust it after checking that it hashes to the measured
+//! PCR4 (see `UserData::verified_instance_id`).
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+#[cfg(feature = "signing")]
+use crate::secmod::Secmod;
+
+/// Current version of `AttestationUserData`.
+pub const USER_DATA_VERSION: u32 = 1;
+
+/// The self-described CBOR tag, which prefixes structured user data.
+const SELF_DESCRIBED_CBOR: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+/// Purposes of attestation documents produced by the sovereign.
+#[cfg(feature = "signing")]
+pub const PURPOSE_KEY_SYNC_FOLLOWER: &str = "key-sync-follower";
+#[cfg(feature = "signing")]
+pub const PURPOSE_KEY_SYNC_LEADER: &str = "key-sync-leader";
+#[cfg(feature = "signing")]
+pub const PURPOSE_SIGNATURE: &str = "signature";
+pub const PURPOSE_ATTESTATION: &str = "attestation";
+
+/// Maximum length of an attestation audience.
+pub const MAX_AUDIENCE_BYTES: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AttestationUserData {
+    #[serde(rename = "version")]
+    pub version: u32,
+    /// What the attestation document is for, e.g., `PURPOSE_KEY_SYNC_LEADER`.
+    #[serde(rename = "purpose")]
+    pub purpose: String,
+    /// A nonce chosen by the attester for its peer to echo (e.g., the follower nonce).
+    #[serde(rename = "nonce", default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<ByteBuf>,
+    /// SHA-256 hash of the data bound by the attestation (e.g., the encrypted secret state).
+    #[serde(rename = "sha256", default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<ByteBuf>,
+    /// The relying party the attestation is intended for (e.g., `https://verifier.example`).
+    #[serde(rename = "audience", default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+    /// The configured (self-reported) instance ID of the host, see the module documentation.
+    #[serde(rename = "instance-id", default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// Keying material exported from the TLS session the attestation is sent over, binding
+    /// the attestation to that session (see `key_sync::channel_binding`).
+    #[serde(rename = "channel-binding", default, skip_serializing_if = "Option::is_none")]
+    pub channel_binding: Option<ByteBuf>,
+}
+
+/// The `user_data` of an attestation document, see the module documentation.
+#[cfg(feature = "signing")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserData {
+    Structured(AttestationUserData),
+    Legacy(Vec<u8>),
+}
+
+impl AttestationUserData {
+    pub fn new(purpose: &str) -> Self {
+        Self {
+            version: USER_DATA_VERSION,
+            purpose: purpose.to_string(),
+            nonce: None,
+            sha256: None,
+            audience: None,
+            instance_id: None,
+            channel_binding: None,
+        }
+    }
+
+    #[cfg(feature = "signing")]
+    pub fn with_nonce(self, nonce: &[u8]) -> Self {
+        Self { nonce: Some(ByteBuf::from(nonce)), ..self }
+    }
+
+    pub fn with_sha256(self, sha256: &[u8]) -> Self {
+        Self { sha256: Some(ByteBuf::from(sha256)), ..self }
+    }
+
+    pub fn with_audience(self, audience: &str) -> Self {
+        Self { audience: Some(audience.to_string()), ..self }
+    }
+
+    pub fn with_instance_id(self, instance_id: &str) -> Self {
+        Self { instance_id: Some(instance_id.to_string()), ..self }
+    }
+
+    #[cfg(feature = "signing")]
+    pub fn with_channel_binding(self, channel_binding: Option<&[u8]>) -> Self {
+        Self { channel_binding: channel_binding.map(ByteBuf::from), ..self }
+    }
+
+    pub fn encode(&self) -> Result<ByteBuf> {
+        let mut encoded = SELF_DESCRIBED_CBOR.to_vec();
+        encoded.extend(serde_cbor::to_vec(self)?);
+        Ok(ByteBuf::from(encoded))
+    }
+}
+
+/// The user data of an attestation for `audience`, binding the client-supplied `user_data`
+/// (if any) through its SHA-256 hash, see the module documentation.
+pub fn audience_binding(audience: &str, user_data: Option<&[u8]>) -> Result<AttestationUserData> {
+    use sha2::Digest;
+    if audience.is_empty() || audience.len() > MAX_AUDIENCE_BYTES {
+        bail!("audience must be 1 to {} bytes", MAX_AUDIENCE_BYTES);
+    }
+    let binding = AttestationUserData::new(PURPOSE_ATTESTATION).with_audience(audience);
+    Ok(match user_data {
+        Some(user_data) => binding.with_sha256(&sha2::Sha256::digest(user_data)),
+        None => binding,
+    })
+}
+
+/// The user data of an attestation served over HTTP for the client-supplied `audience` and
+/// `user_data`, with the configured `instance_id`, see the module documentation.
+pub fn attestation_user_data(
+    audience: Option<&str>,
+    instance_id: Option<&str>,
+    user_data: Option<ByteBuf>,
+) -> Result<Option<ByteBuf>> {
+    use sha2::Digest;
+    let binding = match (audience, instance_id) {
+        (None, None) => return Ok(user_data),
+        (Some(audience), _) => {
+            audience_binding(audience, user_data.as_ref().map(|x| x.as_slice()))?
+        }
+        (None, Some(_)) => {
+            let binding = AttestationUserData::new(PURPOSE_ATTESTATION);
+            match &user_data {
+                Some(user_data) => binding.with_sha256(&sha2::Sha256::digest(user_data)),
+                None => binding,
+            }
+        }
+    };
+    let binding = match instance_id {
+        Some(instance_id) => binding.with_instance_id(instance_id),
+        None => binding,
+    };
+    Ok(Some(binding.encode()?))
+}
+
+#[cfg(feature = "signing")]
+impl UserData {
+    pub fn parse(user_data: &[u8]) -> Result<Self> {
+        let Some(cbor) = user_data.strip_prefix(&SELF_DESCRIBED_CBOR) else {
+            return Ok(UserData::Legacy(user_data.to_vec()));
+        };
+        match serde_cbor::from_slice::<AttestationUserData>(cbor) {
+            Ok(structured) if structured.version == USER_DATA_VERSION => {
+                Ok(UserData::Structured(structured))
+            }
+            Ok(structured) => bail!("unsupported user data version {}", structured.version),
+            // A legacy value that happens to start with the tag.
+            Err(_) => Ok(UserData::Legacy(user_data.to_vec())),
+        }
+    }
+
+    /// The nonce for `purpose`; legacy user data is the nonce itself.
+    pub fn nonce(&self, purpose: &str) -> Result<Vec<u8>> {
+        match self {
+            UserData::Legacy(nonce) => Ok(nonce.clone()),
+            UserData::Structured(structured) => match (&structured.nonce, &structured.purpose) {
+                (Some(nonce), p) if p == purpose => Ok(nonce.to_vec()),
+                (None, _) => bail!("user data contains no nonce"),
+                (_, p) => bail!("user data is for {}, not {}", p, purpose),
+            },
+        }
+    }
+
+    /// Check that the attestation was intended for `audience`, as verifiers should.
+    #[cfg(test)]
+    pub fn check_audience(&self, audience: &str) -> Result<()> {
+        match self {
+            UserData::Structured(AttestationUserData { audience: Some(bound), .. })
+                if bound == audience =>
+            {
+                Ok(())
+            }
+            UserData::Structured(AttestationUserData { audience: Some(bound), .. }) => {
+                bail!("attestation is for audience {}, not {}", bound, audience)
+            }
+            _ => bail!("attestation is not bound to an audience"),
+        }
+    }
+
+    /// The instance ID reported in the user data, once checked against `instance_measurement`
+    /// (i.e., `doc.instance_measurement()` of the verified document): it is self-reported by
+    /// the enclave, only PCR4 is measured.
+    #[allow(dead_code)]
+    pub fn verified_instance_id<SM: Secmod>(&self, instance_measurement: &str) -> Result<String> {
+        let UserData::Structured(AttestationUserData { instance_id: Some(instance_id), .. }) = self
+        else {
+            bail!("user data contains no instance ID");
+        };
+        if SM::measure_instance(instance_id.clone()) != instance_measurement {
+            bail!("instance ID {} does not match the instance measurement", instance_id);
+        }
+        Ok(instance_id.clone())
+    }
+
+    /// Check that the attestation was sent over the TLS session with `channel_binding`, if any.
+    pub fn check_channel_binding(&self, channel_binding: Option<&[u8]>) -> Result<()> {
+        let Some(expected) = channel_binding else {
+            return Ok(());
+        };
+        match self {
+            UserData::Structured(AttestationUserData { channel_binding: Some(bound), .. })
+                if nsm_attestation::ct_eq(bound, expected) =>
+            {
+                Ok(())
+            }
+            UserData::Structured(AttestationUserData { channel_binding: Some(_), .. }) => {
+                bail!("attestation is bound to another TLS session")
+            }
+            _ => bail!("attestation is not bound to the TLS session"),
+        }
+    }
+
+    /// Check that the user data binds `sha256` for `purpose`; legacy user data is the hash itself.
+    pub fn check_sha256(&self, purpose: &str, sha256: &[u8]) -> Result<()> {
+        let bound = match self {
+            UserData::Legacy(bound) => bound.as_slice(),
+            UserData::Structured(structured) => {
+                if structured.purpose != purpose {
+                    bail!("user data is for {}, not {}", structured.purpose, purpose);
+                }
+                structured.sha256.as_deref().map(|x| x.as_slice()).unwrap_or_default()
+            }
+        };
+        if !nsm_attestation::ct_eq(bound, sha256) {
+            bail!("user data mismatch");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "signing"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_data_roundtrip_and_legacy() {
+        let user_data = AttestationUserData::new(PURPOSE_KEY_SYNC_LEADER).with_sha256(&[7; 32]);
+        let encoded = user_data.encode().unwrap();
+        assert_eq!(encoded[..3], SELF_DESCRIBED_CBOR);
+        let parsed = UserData::parse(&encoded).unwrap();
+        assert_eq!(parsed, UserData::Structured(user_data));
+        assert!(parsed.check_sha256(PURPOSE_KEY_SYNC_LEADER, &[7; 32]).is_ok());
+        assert!(parsed.check_sha256(PURPOSE_KEY_SYNC_LEADER, &[8; 32]).is_err());
+        assert!(parsed.check_sha256(PURPOSE_SIGNATURE, &[7; 32]).is_err());
+        assert!(parsed.nonce(PURPOSE_KEY_SYNC_LEADER).is_err());
+
+        let follower = AttestationUserData::new(PURPOSE_KEY_SYNC_FOLLOWER).with_nonce(&[1; 32]);
+        let parsed = UserData::parse(&follower.encode().unwrap()).unwrap();
+        assert_eq!(parsed.nonce(PURPOSE_KEY_SYNC_FOLLOWER).unwrap(), [1; 32]);
+
+        // Opaque values from earlier versions are still understood.
+        let parsed = UserData::parse(&[9; 32]).unwrap();
+        assert_eq!(parsed, UserData::Legacy(vec![9; 32]));
+        assert_eq!(parsed.nonce(PURPOSE_KEY_SYNC_FOLLOWER).unwrap(), [9; 32]);
+        assert!(parsed.check_sha256(PURPOSE_KEY_SYNC_LEADER, &[9; 32]).is_ok());
+
+        // Audience bindings hash the client's user data and are checked by the verifier.
+        let binding = audience_binding("https://verifier.example", Some(b"client data")).unwrap();
+        let parsed = UserData::parse(&binding.encode().unwrap()).unwrap();
+        assert!(parsed.check_audience("https://verifier.example").is_ok());
+        assert!(parsed.check_audience("https://other.example").is_err());
+        use sha2::Digest;
+        let client_hash = sha2::Sha256::digest(b"client data");
+        assert!(parsed.check_sha256(PURPOSE_ATTESTATION, &client_hash).is_ok());
+        let parsed = UserData::parse(&follower.encode().unwrap()).unwrap();
+        assert!(parsed.check_audience("https://verifier.example").is_err());
+        assert!(audience_binding("", None).is_err());
+        assert!(audience_binding(&"a".repeat(MAX_AUDIENCE_BYTES + 1), None).is_err());
+
+        // Future versions are rejected rather than misinterpreted.
+        let future = AttestationUserData { version: 2, ..AttestationUserData::new("x") };
+        assert!(UserData::parse(&future.encode().unwrap()).is_err());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_instance_id() {
+        use crate::mock_secmod::MockSecmod;
+        const INSTANCE_ID: &str = "i-1234567890abcdef0";
+
+        // Without an audience or instance ID, client data is used verbatim.
+        let client_data = Some(ByteBuf::from(b"client data".to_vec()));
+        assert_eq!(attestation_user_data(None, None, client_data.clone()).unwrap(), client_data);
+
+        let encoded = attestation_user_data(None, Some(INSTANCE_ID), client_data).unwrap();
+        let parsed = UserData::parse(&encoded.unwrap()).unwrap();
+        let measurement = MockSecmod::measure_instance(INSTANCE_ID.to_string());
+        assert_eq!(parsed.verified_instance_id::<MockSecmod>(&measurement).unwrap(), INSTANCE_ID);
+        let other = MockSecmod::measure_instance("i-0000000000000000".to_string());
+        assert!(parsed.verified_instance_id::<MockSecmod>(&other).is_err());
+        use sha2::Digest;
+        let client_hash = sha2::Sha256::digest(b"client data");
+        assert!(parsed.check_sha256(PURPOSE_ATTESTATION, &client_hash).is_ok());
+        assert!(parsed.check_audience("https://verifier.example").is_err());
+
+        // With an audience, both are bound.
+        let encoded =
+            attestation_user_data(Some("https://verifier.example"), Some(INSTANCE_ID), None)
+                .unwrap();
+        let parsed = UserData::parse(&encoded.unwrap()).unwrap();
+        assert!(parsed.check_audience("https://verifier.example").is_ok());
+        assert!(parsed.verified_instance_id::<MockSecmod>(&measurement).is_ok());
+        let parsed = UserData::parse(&[9; 32]).unwrap();
+        assert!(parsed.verified_instance_id::<MockSecmod>(&measurement).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! Build information reported by `GET /version` and the `GetVersion` RPC, for confirming which
+//! build a sovereign runs. The measurements (PCRs) remain the authoritative identity.
+
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Hash of the commit the binary was built from, or "unknown" (see `build.rs`).
+pub const GIT_COMMIT: &str = env!("SOVEREIGN_GIT_COMMIT");
+
+/// Unix time of the build as given by `SOURCE_DATE_EPOCH` (the commit time in reproducible
+/// builds), or "unknown".
+pub const BUILD_TIMESTAMP: &str = env!("SOVEREIGN_BUILD_TIMESTAMP");
+
+#[derive(Serialize, Debug)]
+pub struct BuildInfo {
+    #[serde(rename = "version")]
+    pub version: &'static str,
+    #[serde(rename = "git-commit")]
+    pub git_commit: &'static str,
+    #[serde(rename = "build-timestamp")]
+    pub build_timestamp: &'static str,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo { version: VERSION, git_commit: GIT_COMMIT, build_timestamp: BUILD_TIMESTAMP }
+}
@@ -0,0 +1,367 @@
+//! Minimal WebSocket (RFC 6455) server support for the attestation endpoint.
+//!
+//! A client upgrades `GET /` and then sends one frame per attestation request:
+//! a binary frame containing the raw nonce or a text frame containing the hex-encoded nonce.
+//! Each request is answered by a binary frame containing the COSE attestation document.
+//! Fragmented messages and extensions are not supported.
+
+use anyhow::{bail, Result};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::{header, Request, Response, StatusCode};
+use serde_bytes::ByteBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::http::full;
+use crate::http::RequestCounter;
+use crate::key_server::KeyServer;
+use crate::secmod::{Secmod, MAX_ATTESTATION_FIELD_BYTES};
+
+/// Appended to the client's key to compute `Sec-WebSocket-Accept`.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Maximum payload of a frame sent by the client: a nonce of the largest size the NSM accepts,
+/// hex-encoded in a text frame.
+const MAX_CLIENT_PAYLOAD: usize = 2 * MAX_ATTESTATION_FIELD_BYTES;
+
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xa;
+
+/// Close code for a message that is too big to process (RFC 6455, section 7.4.1).
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Returned by `read_frame` for a frame exceeding its payload limit.
+#[derive(Debug)]
+pub struct FrameTooLarge(pub u64);
+
+impl std::fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "frame too large: {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
+/// A single (unfragmented) WebSocket frame.
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// The `Sec-WebSocket-Accept` value for the client's `Sec-WebSocket-Key`.
+pub fn accept_key(client_key: &str) -> String {
+    let mut context = ring::digest::Context::new(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY);
+    context.update(client_key.as_bytes());
+    context.update(WEBSOCKET_GUID.as_bytes());
+    base64::Engine::encode(&base64::engine::general_purpose::STANDARD, context.finish())
+}
+
+fn header_contains_token<B>(request: &Request<B>, name: header::HeaderName, token: &str) -> bool {
+    request.headers().get_all(name).iter().any(|value| {
+        value
+            .to_str()
+            .map(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+            .unwrap_or(false)
+    })
+}
+
+/// Whether `request` asks for a WebSocket upgrade.
+pub fn is_upgrade_request<B>(request: &Request<B>) -> bool {
+    request.method() == hyper::Method::GET
+        && header_contains_token(request, header::CONNECTION, "upgrade")
+        && header_contains_token(request, header::UPGRADE, "websocket")
+}
+
+/// The `101 Switching Protocols` response accepting the upgrade `request`.
+pub fn upgrade_response<B>(request: &Request<B>) -> Result<Response<Full<Bytes>>> {
+    let version = request.headers().get(header::SEC_WEBSOCKET_VERSION);
+    if version.map(|v| v.as_bytes()) != Some(b"13") {
+        bail!("unsupported WebSocket version {:?}", version);
+    }
+    let Some(key) = request.headers().get(header::SEC_WEBSOCKET_KEY) else {
+        bail!("missing Sec-WebSocket-Key");
+    };
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept_key(key.to_str()?))
+        .body(full(Vec::new()))?)
+}
+
+/// Read a masked client frame with a payload of at most `max_payload` bytes.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, max_payload: usize) -> Result<Frame> {
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header).await?;
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0f;
+    if !fin || header[0] & 0x70 != 0 {
+        bail!("fragmented frames and extensions are not supported");
+    }
+    if header[1] & 0x80 == 0 {
+        bail!("client frames must be masked");
+    }
+    let len = match header[1] & 0x7f {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > max_payload as u64 {
+        return Err(FrameTooLarge(len).into());
+    }
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).await?;
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Frame { opcode, payload })
+}
+
+/// Write an unmasked (server) frame.
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    opcode: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let mut header = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => header.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    writer.write_all(&header).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Send a close frame with `code` and `reason`, then fail with `reason`.
+async fn close_with_error<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    code: u16,
+    reason: String,
+) -> Result<()> {
+    let payload = [&code.to_be_bytes()[..], reason.as_bytes()].concat();
+    write_frame(stream, OPCODE_CLOSE, &payload).await?;
+    bail!(reason);
+}
+
+/// Serve attestation requests on an upgraded WebSocket connection until the client closes it
+/// or the connection's request limit in `counter` is reached.
+pub async fn serve_attestation<SM: Secmod, S: AsyncRead + AsyncWrite + Unpin>(
+    state: Arc<KeyServer<SM>>,
+    counter: &RequestCounter,
+    mut stream: S,
+) -> Result<()> {
+    loop {
+        let frame = match read_frame(&mut stream, MAX_CLIENT_PAYLOAD).await {
+            Ok(frame) => frame,
+            Err(e) if e.is::<FrameTooLarge>() => {
+                return close_with_error(&mut stream, CLOSE_MESSAGE_TOO_BIG, e.to_string()).await;
+            }
+            Err(e) => return Err(e),
+        };
+        let nonce = match frame.opcode {
+            OPCODE_BINARY => frame.payload,
+            OPCODE_TEXT => match hex::decode(frame.payload.trim_ascii()) {
+                Ok(nonce) => nonce,
+                Err(e) => {
+                    // 1007: invalid frame payload data.
+                    let reason = format!("invalid hex nonce: {}", e);
+                    return close_with_error(&mut stream, 1007, reason).await;
+                }
+            },
+            OPCODE_PING => {
+                write_frame(&mut stream, OPCODE_PONG, &frame.payload).await?;
+                continue;
+            }
+            OPCODE_PONG => continue,
+            OPCODE_CLOSE => {
+                write_frame(&mut stream, OPCODE_CLOSE, &frame.payload).await?;
+                return Ok(());
+            }
+            opcode => bail!("unexpected WebSocket opcode {}", opcode),
+        };
+        if nonce.len() > MAX_ATTESTATION_FIELD_BYTES {
+            let reason = format!(
+                "nonce has {} bytes; at most {} allowed",
+                nonce.len(),
+                MAX_ATTESTATION_FIELD_BYTES
+            );
+            return close_with_error(&mut stream, CLOSE_MESSAGE_TOO_BIG, reason).await;
+        }
+        tracing::debug!("WebSocket attestation request with {} byte nonce", nonce.len());
+        let document = state.metrics.time_attestation("http", || {
+            SM::new_attestation(&state.attestor, Some(ByteBuf::from(nonce)), None, None)
+        })?;
+        write_frame(&mut stream, OPCODE_BINARY, &document).await?;
+        if counter.count() {
+            // 1000: normal closure.
+            let payload = [&1000u16.to_be_bytes()[..], b"request limit reached"].concat();
+            write_frame(&mut stream, OPCODE_CLOSE, &payload).await?;
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455, section 1.3.
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_is_upgrade_request() {
+        let request = Request::get("/")
+            .header(header::CONNECTION, "keep-alive, Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+            .body(())
+            .unwrap();
+        assert!(is_upgrade_request(&request));
+        let response = upgrade_response(&request).unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            response.headers().get(header::SEC_WEBSOCKET_ACCEPT).unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert!(!is_upgrade_request(&Request::get("/").body(()).unwrap()));
+    }
+
+    /// Encode a masked client frame.
+    fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![0x80 | opcode];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_read_frame() {
+        // "Hello" from RFC 6455, section 5.7.
+        let masked = hex::decode("818537fa213d7f9f4d5158").unwrap();
+        let frame = read_frame(&mut masked.as_slice(), 125).await.unwrap();
+        assert_eq!(frame, Frame { opcode: OPCODE_TEXT, payload: b"Hello".to_vec() });
+
+        let payload = vec![0xab; 300];
+        let masked = client_frame(OPCODE_BINARY, &payload);
+        let frame = read_frame(&mut masked.as_slice(), 300).await.unwrap();
+        assert_eq!(frame, Frame { opcode: OPCODE_BINARY, payload });
+        let err = read_frame(&mut masked.as_slice(), 299).await.unwrap_err();
+        assert!(err.is::<FrameTooLarge>());
+
+        // Unmasked frames are rejected.
+        let unmasked = hex::decode("810548656c6c6f").unwrap();
+        assert!(read_frame(&mut unmasked.as_slice(), 125).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_frame() {
+        let mut out = Vec::new();
+        write_frame(&mut out, OPCODE_TEXT, b"Hello").await.unwrap();
+        // Unmasked "Hello" from RFC 6455, section 5.7.
+        assert_eq!(out, hex::decode("810548656c6c6f").unwrap());
+
+        let mut out = Vec::new();
+        write_frame(&mut out, OPCODE_BINARY, &[0; 256]).await.unwrap();
+        assert_eq!(out[..4], [0x82, 126, 0x01, 0x00]);
+        assert_eq!(out.len(), 4 + 256);
+    }
+
+    /// Read a server frame; it is unmasked, so masking it with zeros lets `read_frame` parse it.
+    #[cfg(feature = "test-utils")]
+    async fn read_server_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Frame {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header).await.unwrap();
+        let len = match header[1] {
+            126 => reader.read_u16().await.unwrap() as usize,
+            127 => reader.read_u64().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut frame = vec![header[0], 0x80 | 127];
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload).await.unwrap();
+        frame.extend(payload);
+        read_frame(&mut frame.as_slice(), len).await.unwrap()
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn test_serve_attestation_limits() {
+        use crate::http::KeepAliveLimits;
+        use crate::key_server::SecretKeyMaterial;
+        use crate::mock_secmod::MockSecmod;
+        use std::time::Duration;
+
+        let secret =
+            SecretKeyMaterial::generate_random(2, &mut elliptic_curve::rand_core::OsRng).unwrap();
+        let attestor = MockSecmod::init_debug_attestor();
+        let state =
+            Arc::new(KeyServer::<MockSecmod>::new(attestor, Default::default(), secret).unwrap());
+        let limits = KeepAliveLimits { max_requests: 2, timeout: Duration::from_secs(1) };
+
+        // A nonce beyond the NSM limit is refused with 1009.
+        let (mut client, server) = tokio::io::duplex(1 << 16);
+        let counter = limits.counter();
+        let serve = serve_attestation(state.clone(), &counter, server);
+        let nonce = [0xab; MAX_ATTESTATION_FIELD_BYTES + 1];
+        client.write_all(&client_frame(OPCODE_BINARY, &nonce)).await.unwrap();
+        assert!(serve.await.is_err());
+        let close = read_server_frame(&mut client).await;
+        assert_eq!(close.opcode, OPCODE_CLOSE);
+        assert_eq!(close.payload[..2], CLOSE_MESSAGE_TOO_BIG.to_be_bytes());
+
+        // So is a frame longer than any valid request, before its payload is read.
+        let (mut client, server) = tokio::io::duplex(1 << 16);
+        let serve = serve_attestation(state.clone(), &counter, server);
+        let oversized = client_frame(OPCODE_BINARY, &[0; MAX_CLIENT_PAYLOAD + 1]);
+        client.write_all(&oversized[..8]).await.unwrap();
+        assert!(serve.await.is_err());
+        let close = read_server_frame(&mut client).await;
+        assert_eq!(close.payload[..2], CLOSE_MESSAGE_TOO_BIG.to_be_bytes());
+
+        // The session ends once the connection's request limit is reached.
+        let (mut client, server) = tokio::io::duplex(1 << 16);
+        let counter = limits.counter();
+        let serve = tokio::spawn(async move {
+            serve_attestation(state, &counter, server).await.unwrap();
+        });
+        for _ in 0..2 {
+            client.write_all(&client_frame(OPCODE_BINARY, b"nonce")).await.unwrap();
+        }
+        serve.await.unwrap();
+        for _ in 0..2 {
+            assert_eq!(read_server_frame(&mut client).await.opcode, OPCODE_BINARY);
+        }
+        let close = read_server_frame(&mut client).await;
+        assert_eq!(close.opcode, OPCODE_CLOSE);
+        assert_eq!(close.payload, [&1000u16.to_be_bytes()[..], b"request limit reached"].concat());
+    }
+}
@@ -0,0 +1,81 @@
+//! Sources of the current time.
+//!
+//! Time-dependent checks (certificate validity, attestation freshness, nonce expiry) take a
+//! `Clock` instead of calling `SystemTime::now()` directly, so that tests can use a `MockClock`
+//! that only moves when told to.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// Seconds since the Unix epoch (0 for times before it).
+    fn unix_seconds(&self) -> u64 {
+        self.now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+    }
+
+    /// Milliseconds since the Unix epoch (0 for times before it).
+    fn unix_millis(&self) -> u64 {
+        self.now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+    }
+}
+
+/// The wall clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that stands still until advanced; clones share the same time.
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Clone, Debug)]
+pub struct MockClock(std::sync::Arc<std::sync::Mutex<SystemTime>>);
+
+#[cfg(any(test, feature = "test-utils"))]
+impl MockClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self(std::sync::Arc::new(std::sync::Mutex::new(now)))
+    }
+
+    /// A clock at `seconds` after the Unix epoch.
+    pub fn at_unix_seconds(seconds: u64) -> Self {
+        Self::new(UNIX_EPOCH + std::time::Duration::from_secs(seconds))
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = now;
+    }
+
+    pub fn advance(&self, duration: std::time::Duration) {
+        let mut now = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        *now += duration;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::at_unix_seconds(1_700_000_000);
+        let shared = clock.clone();
+        assert_eq!(clock.unix_seconds(), 1_700_000_000);
+        shared.advance(Duration::from_millis(1500));
+        assert_eq!(clock.unix_millis(), 1_700_000_001_500);
+        clock.set(UNIX_EPOCH - Duration::from_secs(1));
+        assert_eq!(shared.unix_seconds(), 0);
+    }
+}
@@ -6,6 +6,9 @@
 // the COSE envelope before being able to verify the signature on the COSE envelope.
 use anyhow::{anyhow, bail, Result};
 use aws_nitro_enclaves_cose::CoseSign1;
+
+pub mod clock;
+pub use clock::{Clock, SystemClock};
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 
@@ -15,10 +18,19 @@ pub struct NitroAttestationDocument {
     pub digest: String,
     pub timestamp: u64,
     pub pcrs: std::collections::HashMap<u8, ByteBuf>,
+    /// The NSM leaf certificate (DER). Its key signs the COSE envelope; it belongs to the
+    /// Nitro hypervisor, not to the enclave application.
     pub certificate: ByteBuf,
     pub cabundle: Vec<ByteBuf>,
+    // The optional fields are CBOR null in NSM documents, but other encoders omit them.
+    /// The application public key that the enclave asked the NSM to include (e.g., an ephemeral
+    /// key for encrypting to the enclave). The NSM signs it but does not vouch for it: only the
+    /// PCRs say which code chose it. Bind it with `VerifyOptions::public_key`.
+    #[serde(default)]
     pub public_key: Option<ByteBuf>,
+    #[serde(default)]
     pub user_data: Option<ByteBuf>,
+    #[serde(default)]
     pub nonce: Option<ByteBuf>,
 }
 
@@ -64,13 +76,43 @@ lazy_static::lazy_static! {
         cert_builder.build()
     };
 
-    pub static ref TEST_ROOT_CA_PEM : Vec<u8> = {
-        let pem = TEST_ROOT_CA_CERT.to_pem().unwrap();
-        pem
+    pub static ref TEST_ROOT_CA_PEM : Vec<u8> = TEST_ROOT_CA_CERT.to_pem().unwrap();
+
+    pub static ref TEST_INTERMEDIATE_CA_KEY: PKey<Private> = {
+        let ec_group = openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+        let ec_key = openssl::ec::EcKey::generate(&ec_group).unwrap();
+        PKey::from_ec_key(ec_key).unwrap()
+    };
+
+    /// An intermediate CA issued by the test root CA, see `cose_create_via_intermediate`.
+    pub static ref TEST_INTERMEDIATE_CA_CERT: X509 = {
+        let mut x509_name = X509NameBuilder::new().unwrap();
+        x509_name.append_entry_by_text("C", "US").unwrap();
+        x509_name.append_entry_by_text("O", "Test Organization").unwrap();
+        x509_name.append_entry_by_text("CN", "Test Intermediate CA").unwrap();
+        let x509_name = x509_name.build();
+
+        let mut cert_builder = X509::builder().unwrap();
+        cert_builder.set_version(2).unwrap();
+        cert_builder.set_subject_name(&x509_name).unwrap();
+        cert_builder.set_issuer_name(TEST_ROOT_CA_CERT.subject_name()).unwrap();
+        cert_builder.set_pubkey(&TEST_INTERMEDIATE_CA_KEY).unwrap();
+        cert_builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert_builder.set_not_after(&Asn1Time::days_from_now(365 * 5).unwrap()).unwrap();
+
+        let basic_constraints = openssl::x509::extension::BasicConstraints::new().critical().ca().build().unwrap();
+        cert_builder.append_extension(basic_constraints).unwrap();
+
+        cert_builder.sign(&TEST_ROOT_CA_KEY, MessageDigest::sha256()).unwrap();
+
+        cert_builder.build()
     };
 
 }
 
+/// The AWS Nitro Enclaves root certificate, available from
+/// https://aws-nitro-enclaves.amazonaws.com/AWS_NitroEnclaves_Root-G1.zip.
+/// Used unless another root is configured, see `set_root_ca_pem`.
 #[cfg(not(feature = "test-utils"))]
 static AWS_ROOT_CA_PEM: &[u8] = b"-----BEGIN CERTIFICATE-----
 MIICETCCAZagAwIBAgIRAPkxdWgbkK/hHUbMtOTn+FYwCgYIKoZIzj0EAwMwSTEL
@@ -87,15 +129,199 @@ rfMCMQCi85sWBbJwKKXdS6BptQFuZbT73o/gBh1qUxl/nNr12UO8Yfwr6wPLb+6N
 IwLz3/Y=
 -----END CERTIFICATE-----";
 
+/// The root certificate (PEM) that documents are verified against by default.
+static ROOT_CA_PEM: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+
+/// The embedded root certificate (PEM).
+fn embedded_root_ca_pem() -> &'static [u8] {
+    #[cfg(not(feature = "test-utils"))]
+    let pem = AWS_ROOT_CA_PEM;
+    // TODO: remove this once not needed!
+    #[cfg(feature = "test-utils")]
+    let pem: &'static [u8] = &TEST_ROOT_CA_PEM;
+    pem
+}
+
+/// Verify documents against the root certificate `pem` instead of the embedded one (unless
+/// overridden per call with `VerifyOptions::root_ca_pem`). Fails if `pem` is not a certificate
+/// or if the root has already been set or used.
+pub fn set_root_ca_pem(pem: &[u8]) -> Result<()> {
+    X509::from_pem(pem).map_err(|e| anyhow!("invalid root certificate: {}", e))?;
+    ROOT_CA_PEM.set(pem.to_vec()).map_err(|_| anyhow!("root certificate already set"))
+}
+
+/// Like `set_root_ca_pem`, with the certificate read from the PEM file at `path`.
+pub fn load_root_ca_pem(path: &std::path::Path) -> Result<()> {
+    let pem = std::fs::read(path)
+        .map_err(|e| anyhow!("cannot read root certificate {}: {}", path.display(), e))?;
+    set_root_ca_pem(&pem)
+}
+
+/// The root certificate (PEM) that documents are verified against by default: the one set with
+/// `set_root_ca_pem`, else the embedded one.
+fn default_root_ca_pem() -> &'static [u8] {
+    ROOT_CA_PEM.get_or_init(|| embedded_root_ca_pem().to_vec())
+}
+
+/// Compare two byte strings in constant time (for a given length; lengths are not secret).
+///
+/// Used for the nonce and user data, which may be derived from secrets (e.g., the key-sync
+/// transcript), so that timing does not reveal how many leading bytes matched.
+/// PCRs and public keys are public and compared with `==`.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    use subtle::ConstantTimeEq;
+    a.ct_eq(b).into()
+}
+
+/// SHA-256 fingerprint of a DER-encoded certificate, as pinned in `VerifyOptions`.
+pub fn cert_fingerprint(cert_der: &[u8]) -> [u8; 32] {
+    use sha2::Digest;
+    sha2::Sha256::digest(cert_der).into()
+}
+
+/// Options for verifying an attestation document, see `from_cose_with_options`.
+#[derive(Clone, Copy)]
+pub struct VerifyOptions<'a> {
+    /// Clock at whose time certificate validity is checked.
+    pub clock: &'a dyn Clock,
+    /// SHA-256 fingerprints (see `cert_fingerprint`) of intermediate CA certificates. If not
+    /// empty, at least one certificate of the verified chain from the leaf to the root must
+    /// match one of them; unused `cabundle` certificates do not count. This detects unexpected
+    /// changes of the AWS chain.
+    pub pinned_intermediates: &'a [[u8; 32]],
+    /// Root certificate (PEM) to verify the chain against instead of the default one (see
+    /// `set_root_ca_pem`).
+    pub root_ca_pem: Option<&'a [u8]>,
+    /// The application public key the relying party expects in the document's `public_key`
+    /// field (not the key of the NSM leaf certificate, which is always checked). If set,
+    /// documents without this exact `public_key` are rejected along with those whose
+    /// signature does not verify, so that the binding cannot be forgotten.
+    pub public_key: Option<&'a [u8]>,
+}
+
+impl Default for VerifyOptions<'_> {
+    fn default() -> Self {
+        Self { clock: &SystemClock, pinned_intermediates: &[], root_ca_pem: None, public_key: None }
+    }
+}
+
+/// Why verifying an attestation document failed, e.g., for counting failures by cause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailureCause {
+    /// The COSE_Sign1 envelope is malformed.
+    CoseParse,
+    /// The CBOR payload is not an attestation document.
+    CborParse,
+    /// The leaf or a `cabundle` certificate cannot be parsed.
+    CertificateParse,
+    /// The certificate chain does not verify up to the root (or is too long).
+    Chain,
+    /// No certificate of the verified chain is pinned (see `VerifyOptions::pinned_intermediates`).
+    PinnedIntermediate,
+    /// The COSE signature does not verify with the leaf certificate.
+    Signature,
+    /// A PCR does not hold the expected value.
+    PcrMismatch,
+    /// The application `public_key` field is missing or not the expected one.
+    PublicKeyMismatch,
+    UserDataMismatch,
+    NonceMismatch,
+}
+
+impl FailureCause {
+    /// A name for the cause, suitable as a metric label (e.g., `pcr_mismatch`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureCause::CoseParse => "cose_parse",
+            FailureCause::CborParse => "cbor_parse",
+            FailureCause::CertificateParse => "certificate_parse",
+            FailureCause::Chain => "chain",
+            FailureCause::PinnedIntermediate => "pinned_intermediate",
+            FailureCause::Signature => "signature",
+            FailureCause::PcrMismatch => "pcr_mismatch",
+            FailureCause::PublicKeyMismatch => "public_key_mismatch",
+            FailureCause::UserDataMismatch => "user_data_mismatch",
+            FailureCause::NonceMismatch => "nonce_mismatch",
+        }
+    }
+
+    /// Attach this cause to `error`.
+    fn error(self, error: impl Into<anyhow::Error>) -> anyhow::Error {
+        VerificationError { cause: self, error: error.into() }.into()
+    }
+}
+
+impl std::fmt::Display for FailureCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when verification fails; its message is that of the underlying error.
+/// Use `failure_cause` to find it behind any context that callers added.
+#[derive(Debug)]
+pub struct VerificationError {
+    pub cause: FailureCause,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for VerificationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// The cause of a failure to verify an attestation document, or `None` if `error` did not
+/// come from verification.
+pub fn failure_cause(error: &anyhow::Error) -> Option<FailureCause> {
+    error.downcast_ref::<VerificationError>().map(|e| e.cause)
+}
+
+/// Maximum number of certificates in the `cabundle` of a document. AWS chains consist of the
+/// root, a regional, a zonal, and an instance CA certificate (four, before the leaf); the limit
+/// leaves room for an additional level but keeps crafted bundles from inflating chain building.
+pub const MAX_CABUNDLE_CERTIFICATES: usize = 8;
+
+/// The first PCR that the sovereign extends with its own measurements.
+pub const FIRST_SOVEREIGN_PCR: u8 = 16;
+
+/// Number of signing public keys the sovereign measures at startup (its `MIN_SECRET_KEYS`).
+pub const MEASURED_SIGNING_KEYS: usize = 2;
+
+/// The value of a zero PCR after extending it with `data`, i.e., `SHA384([0; 48] | data)`.
+pub fn extended_pcr_value(data: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha384::new();
+    hasher.update([0; 48]);
+    hasher.update(data);
+    hasher.finalize().to_vec()
+}
+
 impl NitroAttestationDocument {
-    // TODO: consider time validation.
-    fn verify_cert_chain(leaf_cert: &X509, ca_certs: &[X509], root_cert: &X509) -> Result<()> {
+    /// Verify the chain, checking certificate validity at the time of `clock`.
+    /// Returns the verified chain, from the leaf up to the root.
+    fn verify_cert_chain(
+        leaf_cert: &X509,
+        ca_certs: &[X509],
+        root_cert: &X509,
+        clock: &dyn Clock,
+    ) -> Result<Vec<X509>> {
         use openssl::stack::Stack;
         use openssl::x509::store::X509StoreBuilder;
+        use openssl::x509::verify::X509VerifyParam;
         use openssl::x509::X509StoreContext;
         // Create a new store and add the root cert
         let mut store = X509StoreBuilder::new()?;
         store.add_cert(root_cert.clone())?;
+        let mut param = X509VerifyParam::new()?;
+        param.set_time(clock.unix_seconds().try_into()?);
+        store.set_param(&param)?;
         let store = store.build();
         // Create a stack for the intermediate certs
         let mut stack = Stack::new()?;
@@ -112,56 +338,133 @@ impl NitroAttestationDocument {
                     cref.error(),
                     cref.error_depth()
                 );
+                return Ok(None);
             }
-            Ok(verify_result)
+            // Certificates of the cabundle that are not on the verified path do not count.
+            let chain =
+                cref.chain().map(|chain| chain.iter().map(|cert| cert.to_owned()).collect());
+            Ok(chain)
         };
-        let ok = ctx.init(&store, leaf_cert, &stack, verifier)?;
-        if !ok {
-            bail!("certificate chain verification failed")
+        match ctx.init(&store, leaf_cert, &stack, verifier)? {
+            Some(chain) => Ok(chain),
+            None => bail!("certificate chain verification failed"),
+        }
+    }
+
+    /// Check that at least one certificate of the verified `chain` is pinned, if any are.
+    fn verify_pinned_intermediates(
+        chain: &[X509],
+        pinned_intermediates: &[[u8; 32]],
+    ) -> Result<()> {
+        if pinned_intermediates.is_empty() {
+            return Ok(());
+        }
+        let fingerprints = chain
+            .iter()
+            .map(|cert| Ok(cert_fingerprint(&cert.to_der()?)))
+            .collect::<Result<Vec<_>>>()?;
+        if !fingerprints.iter().any(|fingerprint| pinned_intermediates.contains(fingerprint)) {
+            let fingerprints: Vec<String> = fingerprints.iter().map(hex::encode).collect();
+            bail!(
+                "no certificate of the verified chain matches a pinned intermediate: {:?}",
+                fingerprints
+            );
         }
         Ok(())
     }
 
     // TODO: What about the digest field?
-    fn verify_nitro_attestation(cose: &CoseSign1) -> Result<Self> {
+    fn verify_nitro_attestation(cose: &CoseSign1, options: &VerifyOptions) -> Result<Self> {
         use aws_nitro_enclaves_cose::crypto::Openssl;
         // Get payload without verification to access the cert chain
         let payload = cose
             .get_payload::<Openssl>(None)
-            .map_err(|e| anyhow!("CoseSign1::get_payload: {}", e))?;
-        let attestation: NitroAttestationDocument = serde_cbor::from_slice(&payload)?;
-        #[cfg(not(feature = "test-utils"))]
-        let root_cert_pem = AWS_ROOT_CA_PEM;
-        // TODO: remove this once not needed!
-        #[cfg(feature = "test-utils")]
-        let root_cert_pem = &*TEST_ROOT_CA_PEM;
+            .map_err(|e| FailureCause::CoseParse.error(anyhow!("CoseSign1::get_payload: {}", e)))?;
+        let attestation: NitroAttestationDocument =
+            serde_cbor::from_slice(&payload).map_err(|e| FailureCause::CborParse.error(e))?;
+        let root_cert_pem = match options.root_ca_pem {
+            Some(pem) => pem,
+            None => default_root_ca_pem(),
+        };
         // Parse root cert
-        let root_cert = X509::from_pem(root_cert_pem)?;
+        let root_cert = X509::from_pem(root_cert_pem).map_err(|e| FailureCause::Chain.error(e))?;
+        if attestation.cabundle.len() > MAX_CABUNDLE_CERTIFICATES {
+            return Err(FailureCause::Chain.error(anyhow!(
+                "cabundle has {} certificates; at most {} allowed",
+                attestation.cabundle.len(),
+                MAX_CABUNDLE_CERTIFICATES
+            )));
+        }
         // Parse leaf cert and bundle
-        let leaf_cert = X509::from_der(&attestation.certificate)?;
+        let leaf_cert = X509::from_der(&attestation.certificate)
+            .map_err(|e| FailureCause::CertificateParse.error(e))?;
         let ca_certs: Vec<X509> = attestation
             .cabundle
             .iter()
             .map(|cert_der| X509::from_der(cert_der))
-            .collect::<Result<_, _>>()?;
+            .collect::<Result<_, _>>()
+            .map_err(|e| FailureCause::CertificateParse.error(e))?;
         // Verify cert chain
-        Self::verify_cert_chain(&leaf_cert, &ca_certs, &root_cert)?;
+        let chain = Self::verify_cert_chain(&leaf_cert, &ca_certs, &root_cert, options.clock)
+            .map_err(|e| FailureCause::Chain.error(e))?;
+        Self::verify_pinned_intermediates(&chain, options.pinned_intermediates)
+            .map_err(|e| FailureCause::PinnedIntermediate.error(e))?;
         // Get signing key from leaf cert
-        let signing_key = leaf_cert.public_key()?;
+        let signing_key =
+            leaf_cert.public_key().map_err(|e| FailureCause::CertificateParse.error(e))?;
         // Now verify the COSE signature
-        let ok = cose
-            .verify_signature::<Openssl>(&signing_key)
-            .map_err(|e| anyhow!("CoseSign1::verify_signature: {}", e))?;
+        let ok = cose.verify_signature::<Openssl>(&signing_key).map_err(|e| {
+            FailureCause::Signature.error(anyhow!("CoseSign1::verify_signature: {}", e))
+        })?;
         if !ok {
-            bail!("signature does not verify");
+            return Err(FailureCause::Signature.error(anyhow!("signature does not verify")));
+        }
+        if let Some(expected) = options.public_key {
+            attestation.verify_public_key(expected)?;
         }
         Ok(attestation)
     }
 
     pub fn from_cose(cose_document: &[u8]) -> Result<Self> {
+        Self::from_cose_with_options(cose_document, &VerifyOptions::default())
+    }
+
+    /// Like `from_cose`, but checking certificate validity at the time of `clock`.
+    pub fn from_cose_with_clock(cose_document: &[u8], clock: &dyn Clock) -> Result<Self> {
+        Self::from_cose_with_options(cose_document, &VerifyOptions { clock, ..Default::default() })
+    }
+
+    /// Like `from_cose`, but verifying the chain against the root certificate `root_ca_pem`.
+    pub fn from_cose_with_root_pem(cose_document: &[u8], root_ca_pem: &[u8]) -> Result<Self> {
+        let options = VerifyOptions { root_ca_pem: Some(root_ca_pem), ..Default::default() };
+        Self::from_cose_with_options(cose_document, &options)
+    }
+
+    /// Like `from_cose`, but also requiring the application `public_key` field to be
+    /// `public_key`, see `VerifyOptions::public_key`.
+    pub fn from_cose_with_public_key(cose_document: &[u8], public_key: &[u8]) -> Result<Self> {
+        let options = VerifyOptions { public_key: Some(public_key), ..Default::default() };
+        Self::from_cose_with_options(cose_document, &options)
+    }
+
+    /// Like `from_cose`, but verifying according to `options`.
+    pub fn from_cose_with_options(cose_document: &[u8], options: &VerifyOptions) -> Result<Self> {
         let cose = CoseSign1::from_bytes(cose_document)
-            .map_err(|e| anyhow!("CoseSign1::from_bytes: {}", e))?;
-        Self::verify_nitro_attestation(&cose)
+            .map_err(|e| FailureCause::CoseParse.error(anyhow!("CoseSign1::from_bytes: {}", e)))?;
+        Self::verify_nitro_attestation(&cose, options).inspect_err(|e| {
+            let cause = failure_cause(e).map(|cause| cause.as_str());
+            match describe_cose(cose_document) {
+                Ok(description) => {
+                    tracing::error!(
+                        cause,
+                        "attestation verification failed ({}): {}",
+                        e,
+                        description
+                    )
+                }
+                Err(d) => tracing::error!(cause, "attestation verification failed ({}): {}", e, d),
+            }
+        })
     }
 
     pub fn verify(
@@ -177,38 +480,176 @@ impl NitroAttestationDocument {
                     Some(actual_value) if actual_value == expected_value => {
                         tracing::debug!("PCR{} ok", pcr_idx);
                     }
-                    _ => bail!("PCR{} mismatch or not found", pcr_idx),
+                    _ => {
+                        return Err(FailureCause::PcrMismatch
+                            .error(anyhow!("PCR{} mismatch or not found", pcr_idx)))
+                    }
                 }
             }
         }
         if let Some(expected) = expected_public_key {
-            match self.public_key.as_ref() {
-                Some(actual) if actual == expected => {
-                    tracing::debug!("public_key ok");
-                }
-                _ => bail!("public key mismatch"),
-            }
+            self.verify_public_key(expected)?;
         }
         if let Some(expected) = expected_user_data {
             match self.user_data.as_ref() {
-                Some(actual) if actual == expected => {
+                Some(actual) if ct_eq(actual, expected) => {
                     tracing::debug!("user_data ok");
                 }
-                _ => bail!("user data mismatch"),
+                _ => {
+                    return Err(FailureCause::UserDataMismatch.error(anyhow!("user data mismatch")))
+                }
             }
         }
         if let Some(expected) = expected_nonce {
             match self.nonce.as_ref() {
-                Some(actual) if actual == expected => {
+                Some(actual) if ct_eq(actual, expected) => {
                     tracing::debug!("nonce ok");
                 }
-                _ => bail!("nonce mismatch"),
+                _ => return Err(FailureCause::NonceMismatch.error(anyhow!("nonce mismatch"))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the application `public_key` field is `expected`.
+    fn verify_public_key(&self, expected: &[u8]) -> Result<()> {
+        match self.public_key.as_ref() {
+            Some(actual) if actual.as_slice() == expected => {
+                tracing::debug!("public_key ok");
+                Ok(())
+            }
+            Some(_) => Err(FailureCause::PublicKeyMismatch.error(anyhow!("public key mismatch"))),
+            None => Err(FailureCause::PublicKeyMismatch.error(anyhow!("public key missing"))),
+        }
+    }
+
+    /// Check that the PCRs extended by the sovereign at startup (PCR 16 onwards) hold, in order,
+    /// the DER-encoded public key of its TLS certificate, its first `MEASURED_SIGNING_KEYS`
+    /// SEC1-encoded signing public keys, and the canonical JSON of its configuration (object keys
+    /// sorted, no whitespace, `instance-id` left out). This binds the attestation to this key
+    /// material.
+    ///
+    /// Fewer signing keys are padded with empty values, as measured by attestation-only builds;
+    /// more are rejected, since they are not measured.
+    pub fn verify_sovereign_measurements(
+        &self,
+        cert_public_key_der: &[u8],
+        signing_public_keys: &[&[u8]],
+        config_json: &[u8],
+    ) -> Result<()> {
+        if signing_public_keys.len() > MEASURED_SIGNING_KEYS {
+            bail!(
+                "{} signing public keys given; only the first {} are measured",
+                signing_public_keys.len(),
+                MEASURED_SIGNING_KEYS
+            );
+        }
+        let padding = MEASURED_SIGNING_KEYS - signing_public_keys.len();
+        let measurements = std::iter::once(cert_public_key_der)
+            .chain(signing_public_keys.iter().copied())
+            .chain(std::iter::repeat(&[][..]).take(padding))
+            .chain(std::iter::once(config_json));
+        for (i, data) in measurements.enumerate() {
+            let index = FIRST_SOVEREIGN_PCR as usize + i;
+            let expected = extended_pcr_value(data);
+            match self.pcrs.get(&(index as u8)) {
+                Some(actual) if actual.as_slice() == expected => {
+                    tracing::debug!("PCR{} ok", index);
+                }
+                Some(actual) => {
+                    return Err(FailureCause::PcrMismatch.error(anyhow!(
+                        "PCR{} mismatch: expected {} was {}",
+                        index,
+                        hex::encode(&expected),
+                        hex::encode(actual)
+                    )))
+                }
+                None => {
+                    return Err(FailureCause::PcrMismatch.error(anyhow!("PCR{} not found", index)))
+                }
             }
         }
         Ok(())
     }
 }
 
+/// Unverified details of a COSE-signed attestation document, for diagnosing verification failures.
+#[derive(Debug, Default)]
+pub struct CoseDescription {
+    /// The COSE algorithm from the protected headers (e.g., -35 for ES384).
+    pub algorithm: Option<i128>,
+    /// Subject and issuer of the certificate that signed the document.
+    pub cert_subject: Option<String>,
+    pub cert_issuer: Option<String>,
+    /// Subjects of the CA bundle, starting with the root.
+    pub cabundle_subjects: Vec<String>,
+    pub signature: Vec<u8>,
+}
+
+impl std::fmt::Display for CoseDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "algorithm {:?}, certificate subject {:?} issuer {:?}, cabundle {:?}, signature {}",
+            self.algorithm,
+            self.cert_subject,
+            self.cert_issuer,
+            self.cabundle_subjects,
+            hex::encode(&self.signature)
+        )
+    }
+}
+
+fn name_string(name: &openssl::x509::X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry.data().as_utf8().map(|x| x.to_string()).unwrap_or_default();
+            format!("{}={}", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Describe a COSE_Sign1 attestation document `[protected, unprotected, payload, signature]`
+/// without verifying it; see `CoseDescription`.
+pub fn describe_cose(cose_document: &[u8]) -> Result<CoseDescription> {
+    use serde_cbor::Value;
+    // The COSE_Sign1 array may be tagged (tag 18).
+    let items = match serde_cbor::from_slice(cose_document)? {
+        Value::Array(items) => items,
+        Value::Tag(18, value) => match *value {
+            Value::Array(items) => items,
+            _ => bail!("not a COSE_Sign1 array"),
+        },
+        _ => bail!("not a COSE_Sign1 array"),
+    };
+    let [Value::Bytes(protected), _, Value::Bytes(payload), Value::Bytes(signature)] =
+        items.as_slice()
+    else {
+        bail!("malformed COSE_Sign1 array");
+    };
+    let mut description = CoseDescription { signature: signature.clone(), ..Default::default() };
+    // The protected headers are a serialized map; label 1 is the algorithm.
+    if let Ok(Value::Map(headers)) = serde_cbor::from_slice::<Value>(protected) {
+        if let Some(Value::Integer(algorithm)) = headers.get(&Value::Integer(1)) {
+            description.algorithm = Some(*algorithm);
+        }
+    }
+    let attestation: NitroAttestationDocument = serde_cbor::from_slice(payload)?;
+    if let Ok(cert) = X509::from_der(&attestation.certificate) {
+        description.cert_subject = Some(name_string(cert.subject_name()));
+        description.cert_issuer = Some(name_string(cert.issuer_name()));
+    }
+    for cert_der in &attestation.cabundle {
+        description.cabundle_subjects.push(match X509::from_der(cert_der) {
+            Ok(cert) => name_string(cert.subject_name()),
+            Err(e) => format!("<invalid: {}>", e),
+        });
+    }
+    Ok(description)
+}
+
 impl NitroAttestationDocument {
     /// Generate a Cose1 envelope with an AWS-like attestation document
     /// with the specified components. The document is signed by the test key.
@@ -218,6 +659,38 @@ impl NitroAttestationDocument {
         public_key: Option<ByteBuf>,
         user_data: Option<ByteBuf>,
         nonce: Option<ByteBuf>,
+    ) -> Result<Vec<u8>> {
+        let cabundle = vec![ByteBuf::from(TEST_ROOT_CA_CERT.to_der()?)];
+        let issuer: (&X509, &PKey<Private>) = (&TEST_ROOT_CA_CERT, &TEST_ROOT_CA_KEY);
+        Self::cose_create_with_chain(pcrs, public_key, user_data, nonce, issuer, cabundle)
+    }
+
+    /// Like `cose_create`, but with the leaf certificate issued by the test intermediate CA,
+    /// which the `cabundle` contains after the root, as in AWS chains.
+    #[cfg(feature = "test-utils")]
+    pub fn cose_create_via_intermediate(
+        pcrs: std::collections::HashMap<u8, ByteBuf>,
+        public_key: Option<ByteBuf>,
+        user_data: Option<ByteBuf>,
+        nonce: Option<ByteBuf>,
+    ) -> Result<Vec<u8>> {
+        let cabundle = vec![
+            ByteBuf::from(TEST_ROOT_CA_CERT.to_der()?),
+            ByteBuf::from(TEST_INTERMEDIATE_CA_CERT.to_der()?),
+        ];
+        let issuer: (&X509, &PKey<Private>) =
+            (&TEST_INTERMEDIATE_CA_CERT, &TEST_INTERMEDIATE_CA_KEY);
+        Self::cose_create_with_chain(pcrs, public_key, user_data, nonce, issuer, cabundle)
+    }
+
+    #[cfg(feature = "test-utils")]
+    fn cose_create_with_chain(
+        pcrs: std::collections::HashMap<u8, ByteBuf>,
+        public_key: Option<ByteBuf>,
+        user_data: Option<ByteBuf>,
+        nonce: Option<ByteBuf>,
+        (issuer_cert, issuer_key): (&X509, &PKey<Private>),
+        cabundle: Vec<ByteBuf>,
     ) -> Result<Vec<u8>> {
         // Generate leaf certificate signed by the test root CA
         let ec_group = openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1)?;
@@ -234,13 +707,13 @@ impl NitroAttestationDocument {
         let mut cert_builder = X509::builder()?;
         cert_builder.set_version(2)?;
         cert_builder.set_subject_name(&x509_name)?;
-        cert_builder.set_issuer_name(TEST_ROOT_CA_CERT.subject_name())?;
+        cert_builder.set_issuer_name(issuer_cert.subject_name())?;
         cert_builder.set_pubkey(&leaf_key)?;
 
         use anyhow::Context;
         cert_builder.set_not_before(Asn1Time::days_from_now(0).context("asn1")?.as_ref())?;
         cert_builder.set_not_after(Asn1Time::days_from_now(365).context("asn1")?.as_ref())?;
-        cert_builder.sign(&TEST_ROOT_CA_KEY, MessageDigest::sha256())?;
+        cert_builder.sign(issuer_key, MessageDigest::sha256())?;
         let cert = cert_builder.build();
 
         let doc = Self {
@@ -249,7 +722,7 @@ impl NitroAttestationDocument {
             timestamp: 1234567890,
             pcrs,
             certificate: ByteBuf::from(cert.to_der()?),
-            cabundle: vec![ByteBuf::from(TEST_ROOT_CA_CERT.to_der()?)],
+            cabundle,
             public_key,
             user_data,
             nonce,
@@ -338,5 +811,267 @@ mod tests {
                 .is_err(),
             "Verification should fail with wrong PCRs"
         );
+        let wrong_nonce = ByteBuf::from(b"test-nonCe");
+        let err = attestation.verify(None, None, None, Some(&wrong_nonce)).unwrap_err();
+        assert_eq!(failure_cause(&err), Some(FailureCause::NonceMismatch));
+        let wrong_user_data = ByteBuf::from(b"test-user-data-");
+        let err = attestation.verify(None, None, Some(&wrong_user_data), None).unwrap_err();
+        assert_eq!(failure_cause(&err), Some(FailureCause::UserDataMismatch));
+    }
+
+    #[test]
+    fn test_certificate_validity_uses_clock() {
+        use clock::MockClock;
+        use std::time::Duration;
+        let cose_doc =
+            NitroAttestationDocument::cose_create(HashMap::new(), None, None, None).unwrap();
+        let clock = MockClock::new(std::time::SystemTime::now());
+        assert!(NitroAttestationDocument::from_cose_with_clock(&cose_doc, &clock).is_ok());
+        // The test leaf certificate is valid for a year from now.
+        clock.advance(Duration::from_secs(2 * 365 * 24 * 60 * 60));
+        assert!(NitroAttestationDocument::from_cose_with_clock(&cose_doc, &clock).is_err());
+        clock.set(std::time::UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        assert!(NitroAttestationDocument::from_cose_with_clock(&cose_doc, &clock).is_err());
+    }
+
+    #[test]
+    fn test_pinned_intermediates() {
+        let intermediate = cert_fingerprint(&TEST_INTERMEDIATE_CA_CERT.to_der().unwrap());
+        let pinned = [[0x11; 32], intermediate];
+        let options = VerifyOptions { pinned_intermediates: &pinned, ..Default::default() };
+        let cose_doc = NitroAttestationDocument::cose_create_via_intermediate(
+            HashMap::new(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(NitroAttestationDocument::from_cose(&cose_doc).is_ok());
+        assert!(NitroAttestationDocument::from_cose_with_options(&cose_doc, &options).is_ok());
+
+        // A chain that verifies up to the root but lacks the pinned intermediate is rejected.
+        let cose_doc =
+            NitroAttestationDocument::cose_create(HashMap::new(), None, None, None).unwrap();
+        assert!(NitroAttestationDocument::from_cose(&cose_doc).is_ok());
+        let err =
+            NitroAttestationDocument::from_cose_with_options(&cose_doc, &options).unwrap_err();
+        assert!(err.to_string().starts_with("no certificate of the verified chain matches"));
+        assert_eq!(failure_cause(&err), Some(FailureCause::PinnedIntermediate));
+
+        // Appending the pinned intermediate does not help if the leaf chains to the root without it.
+        let cabundle = vec![
+            ByteBuf::from(TEST_ROOT_CA_CERT.to_der().unwrap()),
+            ByteBuf::from(TEST_INTERMEDIATE_CA_CERT.to_der().unwrap()),
+        ];
+        let issuer: (&X509, &PKey<Private>) = (&TEST_ROOT_CA_CERT, &TEST_ROOT_CA_KEY);
+        let cose_doc = NitroAttestationDocument::cose_create_with_chain(
+            HashMap::new(),
+            None,
+            None,
+            None,
+            issuer,
+            cabundle,
+        )
+        .unwrap();
+        assert!(NitroAttestationDocument::from_cose(&cose_doc).is_ok());
+        let err =
+            NitroAttestationDocument::from_cose_with_options(&cose_doc, &options).unwrap_err();
+        assert_eq!(failure_cause(&err), Some(FailureCause::PinnedIntermediate));
+    }
+
+    #[test]
+    fn test_root_ca_pem() {
+        let cose_doc =
+            NitroAttestationDocument::cose_create(HashMap::new(), None, None, None).unwrap();
+        let root = TEST_ROOT_CA_CERT.to_pem().unwrap();
+        assert!(NitroAttestationDocument::from_cose_with_root_pem(&cose_doc, &root).is_ok());
+        // A chain that does not lead to the given root is rejected.
+        let other_root = TEST_INTERMEDIATE_CA_CERT.to_pem().unwrap();
+        let err =
+            NitroAttestationDocument::from_cose_with_root_pem(&cose_doc, &other_root).unwrap_err();
+        assert_eq!(failure_cause(&err), Some(FailureCause::Chain));
+        let err =
+            NitroAttestationDocument::from_cose_with_root_pem(&cose_doc, b"garbage").unwrap_err();
+        assert_eq!(failure_cause(&err), Some(FailureCause::Chain));
+        // Roots that are not certificates are rejected upfront.
+        assert!(set_root_ca_pem(b"garbage").is_err());
+        assert!(load_root_ca_pem(std::path::Path::new("/nonexistent/root.pem")).is_err());
+    }
+
+    #[test]
+    fn test_public_key_binding() {
+        let public_key = ByteBuf::from(b"enclave-public-key");
+        let cose_doc = NitroAttestationDocument::cose_create(
+            HashMap::new(),
+            Some(public_key.clone()),
+            None,
+            None,
+        )
+        .unwrap();
+        let attestation =
+            NitroAttestationDocument::from_cose_with_public_key(&cose_doc, &public_key).unwrap();
+        assert_eq!(attestation.public_key, Some(public_key));
+        let err = NitroAttestationDocument::from_cose_with_public_key(&cose_doc, b"other-key")
+            .unwrap_err();
+        assert_eq!(failure_cause(&err), Some(FailureCause::PublicKeyMismatch));
+        assert!(err.to_string().contains("mismatch"));
+
+        // Documents without a public key do not match any.
+        let cose_doc =
+            NitroAttestationDocument::cose_create(HashMap::new(), None, None, None).unwrap();
+        let err = NitroAttestationDocument::from_cose_with_public_key(&cose_doc, b"other-key")
+            .unwrap_err();
+        assert_eq!(failure_cause(&err), Some(FailureCause::PublicKeyMismatch));
+        assert!(err.to_string().contains("missing"));
+    }
+
+    #[test]
+    fn test_cabundle_limit() {
+        let root = ByteBuf::from(TEST_ROOT_CA_CERT.to_der().unwrap());
+        let issuer: (&X509, &PKey<Private>) = (&TEST_ROOT_CA_CERT, &TEST_ROOT_CA_KEY);
+        let create = |count| {
+            let cabundle = vec![root.clone(); count];
+            NitroAttestationDocument::cose_create_with_chain(
+                HashMap::new(),
+                None,
+                None,
+                None,
+                issuer,
+                cabundle,
+            )
+            .unwrap()
+        };
+        assert!(NitroAttestationDocument::from_cose(&create(MAX_CABUNDLE_CERTIFICATES)).is_ok());
+        let err = NitroAttestationDocument::from_cose(&create(MAX_CABUNDLE_CERTIFICATES + 1))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("cabundle has 9 certificates"));
+        assert_eq!(failure_cause(&err), Some(FailureCause::Chain));
+    }
+
+    #[test]
+    fn test_failure_cause() {
+        let cose_doc =
+            NitroAttestationDocument::cose_create(HashMap::new(), None, None, None).unwrap();
+        let cause = |document: &[u8]| {
+            let err = NitroAttestationDocument::from_cose(document).unwrap_err();
+            // The cause survives context added by callers.
+            failure_cause(&err.context("verifying peer"))
+        };
+        assert_eq!(cause(b"not cose"), Some(FailureCause::CoseParse));
+        // Flipping a bit of the signature (at the end of the document).
+        let mut tampered = cose_doc.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(cause(&tampered), Some(FailureCause::Signature));
+        assert_eq!(FailureCause::PcrMismatch.to_string(), "pcr_mismatch");
+        assert_eq!(failure_cause(&anyhow!("unrelated")), None);
+    }
+
+    // A document payload whose optional fields are absent keys.
+    const ABSENT_OPTIONAL_FIELDS: &str = "\
+        a6696d6f64756c655f696468692d302d656e633066646967657374665348413338346974696d6573\
+        74616d701b0000018bcfe568006470637273a10044000000006b6365727469666963617465420102\
+        68636162756e646c65814103";
+
+    // The same payload with the optional fields as explicit nulls (as NSM encodes them).
+    const NULL_OPTIONAL_FIELDS: &str = "\
+        a9696d6f64756c655f696468692d302d656e633066646967657374665348413338346974696d6573\
+        74616d701b0000018bcfe568006470637273a10044000000006b6365727469666963617465420102\
+        68636162756e646c658141036a7075626c69635f6b6579f669757365725f64617461f6656e6f6e63\
+        65f6";
+
+    // The same payload with a null public key, no user data, and the nonce `aa`.
+    const MIXED_OPTIONAL_FIELDS: &str = "\
+        a8696d6f64756c655f696468692d302d656e633066646967657374665348413338346974696d6573\
+        74616d701b0000018bcfe568006470637273a10044000000006b6365727469666963617465420102\
+        68636162756e646c658141036a7075626c69635f6b6579f6656e6f6e636541aa";
+
+    #[test]
+    fn test_optional_fields() {
+        let parse = |fixture: &str| -> NitroAttestationDocument {
+            let bytes: Vec<u8> = (0..fixture.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&fixture[i..i + 2], 16).unwrap())
+                .collect();
+            serde_cbor::from_slice(&bytes).unwrap()
+        };
+        for fixture in [ABSENT_OPTIONAL_FIELDS, NULL_OPTIONAL_FIELDS] {
+            let doc = parse(fixture);
+            assert_eq!(doc.module_id, "i-0-enc0");
+            assert_eq!(doc.timestamp, 1700000000000);
+            assert_eq!(doc.cabundle, [ByteBuf::from([3])]);
+            assert_eq!((doc.public_key, doc.user_data, doc.nonce), (None, None, None));
+        }
+        let doc = parse(MIXED_OPTIONAL_FIELDS);
+        assert_eq!((doc.public_key, doc.user_data), (None, None));
+        assert_eq!(doc.nonce, Some(ByteBuf::from([0xaa])));
+    }
+
+    #[test]
+    fn test_describe_cose() {
+        let cose_doc =
+            NitroAttestationDocument::cose_create(HashMap::new(), None, None, None).unwrap();
+        let description = describe_cose(&cose_doc).unwrap();
+        // The test leaf key is a P-256 key, i.e., ES256.
+        assert_eq!(description.algorithm, Some(-7));
+        assert_eq!(
+            description.cert_subject.as_deref(),
+            Some("C=US, O=Test Organization, CN=Test Leaf Certificate")
+        );
+        assert_eq!(
+            description.cert_issuer.as_deref(),
+            Some("C=US, O=Test Organization, CN=Test Root CA")
+        );
+        assert_eq!(description.cabundle_subjects, ["C=US, O=Test Organization, CN=Test Root CA"]);
+        assert_eq!(description.signature.len(), 64);
+        assert!(describe_cose(b"not cbor").is_err());
+    }
+
+    #[test]
+    fn test_verify_sovereign_measurements() {
+        let cert_public_key = b"cert-public-key".as_slice();
+        let signing_public_keys = [b"signing-key-1".as_slice(), b"signing-key-2".as_slice()];
+        let config = br#"{"alt-names":[]}"#.as_slice();
+        let pcrs: HashMap<u8, ByteBuf> = [cert_public_key, signing_public_keys[0]]
+            .into_iter()
+            .chain([signing_public_keys[1], config])
+            .enumerate()
+            .map(|(i, data)| (16 + i as u8, ByteBuf::from(extended_pcr_value(data))))
+            .collect();
+        let doc = NitroAttestationDocument {
+            module_id: String::new(),
+            digest: String::new(),
+            timestamp: 0,
+            pcrs,
+            certificate: ByteBuf::new(),
+            cabundle: Vec::new(),
+            public_key: None,
+            user_data: None,
+            nonce: None,
+        };
+        assert!(doc
+            .verify_sovereign_measurements(cert_public_key, &signing_public_keys, config)
+            .is_ok());
+        let swapped = [signing_public_keys[1], signing_public_keys[0]];
+        let err = doc.verify_sovereign_measurements(cert_public_key, &swapped, config).unwrap_err();
+        assert!(err.to_string().starts_with("PCR17 mismatch"));
+        let err = doc
+            .verify_sovereign_measurements(cert_public_key, &signing_public_keys, b"{}")
+            .unwrap_err();
+        assert!(err.to_string().starts_with("PCR19 mismatch"));
+        let three_keys = [signing_public_keys[0], signing_public_keys[1], config];
+        let err =
+            doc.verify_sovereign_measurements(cert_public_key, &three_keys, config).unwrap_err();
+        assert_eq!(err.to_string(), "3 signing public keys given; only the first 2 are measured");
+
+        // Attestation-only builds measure empty values in place of the signing keys.
+        let mut pcrs = doc.pcrs.clone();
+        pcrs.insert(17, ByteBuf::from(extended_pcr_value(b"")));
+        pcrs.insert(18, ByteBuf::from(extended_pcr_value(b"")));
+        let doc = NitroAttestationDocument { pcrs, ..doc };
+        assert!(doc.verify_sovereign_measurements(cert_public_key, &[], config).is_ok());
+        let err = doc
+            .verify_sovereign_measurements(cert_public_key, &signing_public_keys[..1], config)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("PCR17 mismatch"));
     }
 }
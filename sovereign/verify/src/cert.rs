@@ -1,23 +1,30 @@
+use openssl::asn1::Asn1Time;
+use openssl::x509::{X509NameRef, X509};
 use rustls::crypto::ring::default_provider;
 use rustls::pki_types::{CertificateDer, UnixTime};
 use rustls::server::ParsedCertificate;
 use rustls::{client::verify_server_cert_signed_by_trust_anchor, RootCertStore};
+use rustls::{CertificateError, Error as RustlsError};
 use rustls_pki_types::SignatureVerificationAlgorithm; // Add this import
 use serde_bytes::ByteBuf;
+use std::cmp::Ordering;
 use std::error::Error as StdError;
 
 pub fn verify_certificate(
     root_ca: &[u8],
     cert_bytes: &[u8],
-    ca_bundle: &Vec<ByteBuf>,
+    ca_bundle: &[ByteBuf],
 ) -> Result<(), Box<dyn StdError>> {
     // Create root store
     let mut root_store = RootCertStore::empty();
-    root_store.add(CertificateDer::from(root_ca.to_vec()))?;
+    root_store
+        .add(CertificateDer::from(root_ca.to_vec()))
+        .map_err(|e| format!("cannot use the pinned root certificate: {}", e))?;
 
     // Convert cert to ParsedCertificate
     let cert_der = CertificateDer::from(cert_bytes.to_vec());
-    let cert = ParsedCertificate::try_from(&cert_der)?;
+    let cert = ParsedCertificate::try_from(&cert_der)
+        .map_err(|e| format!("cannot parse the leaf certificate: {}", e))?;
 
     // Convert intermediates to CertificateDer
     let intermediates: Vec<CertificateDer> =
@@ -39,6 +46,74 @@ pub fn verify_certificate(
         supported_algs,
     ) {
         Ok(_) => Ok(()),
-        Err(e) => Err(Box::new(e)),
+        Err(e) => Err(describe_failure(&e, cert_bytes, ca_bundle, now).into()),
     }
 }
+
+/// Explain a chain verification failure: its cause (time, signature, or issuer), the leaf's
+/// subject and issuer, the number of CA certificates supplied, and the verification time.
+fn describe_failure(
+    error: &RustlsError,
+    cert_bytes: &[u8],
+    ca_bundle: &[ByteBuf],
+    now: UnixTime,
+) -> String {
+    let leaf = X509::from_der(cert_bytes).ok();
+    let bundle: Vec<X509> = ca_bundle.iter().filter_map(|der| X509::from_der(der).ok()).collect();
+    let expired = leaf.iter().chain(bundle.iter()).find_map(|cert| outside_validity(cert, now));
+    let cause = match (error, expired) {
+        (_, Some(expired)) => format!("time-related: {}", expired),
+        (RustlsError::InvalidCertificate(CertificateError::BadSignature), None) => {
+            "signature-related: a certificate signature does not verify".to_string()
+        }
+        (RustlsError::InvalidCertificate(CertificateError::UnknownIssuer), None) => {
+            "unknown issuer: the chain does not lead to the pinned root (is it current?)"
+                .to_string()
+        }
+        (RustlsError::InvalidCertificate(CertificateError::Expired), None)
+        | (RustlsError::InvalidCertificate(CertificateError::NotValidYet), None) => {
+            "time-related".to_string()
+        }
+        (_, None) => "other".to_string(),
+    };
+    let (subject, issuer) = match &leaf {
+        Some(leaf) => (describe_name(leaf.subject_name()), describe_name(leaf.issuer_name())),
+        None => ("?".to_string(), "?".to_string()),
+    };
+    format!(
+        "certificate chain verification failed ({}): {}; leaf subject: {}, issuer: {}; \
+         {} CA bundle certificates supplied; verified at unix time {}",
+        cause,
+        error,
+        subject,
+        issuer,
+        ca_bundle.len(),
+        now.as_secs()
+    )
+}
+
+/// Why `cert` is not valid at `now`, if it is not.
+fn outside_validity(cert: &X509, now: UnixTime) -> Option<String> {
+    let now = Asn1Time::from_unix(now.as_secs() as i64).ok()?;
+    let subject = describe_name(cert.subject_name());
+    if cert.not_after().compare(&now).ok()? == Ordering::Less {
+        return Some(format!("{} expired at {}", subject, cert.not_after()));
+    }
+    if cert.not_before().compare(&now).ok()? == Ordering::Greater {
+        return Some(format!("{} is not valid before {}", subject, cert.not_before()));
+    }
+    None
+}
+
+/// A name as comma-separated `key=value` pairs, e.g., `CN=aws.nitro-enclaves`.
+fn describe_name(name: &X509NameRef) -> String {
+    let entries: Vec<String> = name
+        .entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry.data().as_utf8().map(|v| v.to_string()).unwrap_or_default();
+            format!("{}={}", key, value)
+        })
+        .collect();
+    entries.join(", ")
+}
@@ -0,0 +1,160 @@
+//! Legacy (EIP-155) Ethereum transactions, for end-to-end checks of transaction signing.
+
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use rlp::{Rlp, RlpStream};
+use tiny_keccak::{Hasher, Keccak};
+
+/// Number of RLP items of a legacy transaction without signature, or with an EIP-155 signature
+/// (the chain ID followed by two zeros while unsigned; `v`, `r`, and `s` once signed).
+const EIP155_ITEMS: usize = 9;
+
+/// Number of RLP items of a legacy transaction that are covered by the signature besides the
+/// chain ID: nonce, gas price, gas limit, to, value, and data.
+const LEGACY_FIELDS: usize = 6;
+
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut output = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    hasher.finalize(&mut output);
+    output
+}
+
+/// The Ethereum address of `key`: the last 20 bytes of the Keccak-256 hash of the
+/// uncompressed public key (without the 0x04 prefix).
+pub fn ethereum_address(key: &VerifyingKey) -> [u8; 20] {
+    let point = key.to_encoded_point(false);
+    let hash = keccak256(&point.as_bytes()[1..]);
+    hash[12..].try_into().expect("20 bytes")
+}
+
+/// An unsigned legacy test transaction for `SignEthereumTransaction`, in the EIP-155 signing
+/// form `rlp([nonce, gas_price, gas_limit, to, value, data, chain_id, 0, 0])`. The fields are
+/// those of the example in EIP-155.
+pub fn unsigned_legacy_test_transaction(chain_id: u64) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(EIP155_ITEMS);
+    stream.append(&9u64); // nonce
+    stream.append(&20_000_000_000u64); // gas_price
+    stream.append(&21000u64); // gas_limit
+    stream.append(&[0x35u8; 20].to_vec()); // to
+    stream.append(&1_000_000_000_000_000_000u64); // value
+    stream.append(&Vec::<u8>::new()); // data
+    stream.append(&chain_id);
+    stream.append(&0u8);
+    stream.append(&0u8);
+    stream.out().to_vec()
+}
+
+/// Left-pad a big-endian RLP integer to 32 bytes.
+fn to_32_bytes(value: &[u8]) -> Result<[u8; 32], Box<dyn std::error::Error>> {
+    if value.len() > 32 {
+        return Err(format!("signature component too long: {} bytes", value.len()).into());
+    }
+    let mut result = [0u8; 32];
+    result[32 - value.len()..].copy_from_slice(value);
+    Ok(result)
+}
+
+/// Decode the signed legacy transaction `signed`, check that it signs the fields of the
+/// unsigned EIP-155 transaction `unsigned` for its chain ID, and return the address recovered
+/// from its signature.
+///
+/// The signing hash is `keccak256(unsigned)`; `v` must be `chain_id * 2 + 35 + y_parity`.
+pub fn recover_legacy_signer(
+    unsigned: &[u8],
+    signed: &[u8],
+) -> Result<[u8; 20], Box<dyn std::error::Error>> {
+    let unsigned_rlp = Rlp::new(unsigned);
+    if unsigned_rlp.item_count()? != EIP155_ITEMS {
+        return Err("unsigned transaction is not in EIP-155 form".into());
+    }
+    let chain_id: u64 = unsigned_rlp.val_at(LEGACY_FIELDS)?;
+    let rlp = Rlp::new(signed);
+    let item_count = rlp.item_count()?;
+    if item_count != EIP155_ITEMS {
+        return Err(format!("signed transaction has {} RLP items; expected 9", item_count).into());
+    }
+    for i in 0..LEGACY_FIELDS {
+        if rlp.at(i)?.as_raw() != unsigned_rlp.at(i)?.as_raw() {
+            return Err("signed transaction fields differ from the unsigned transaction".into());
+        }
+    }
+    let signing_hash = keccak256(unsigned);
+
+    let v: u64 = rlp.val_at(LEGACY_FIELDS)?;
+    let y_parity = v
+        .checked_sub(chain_id * 2 + 35)
+        .filter(|&y_parity| y_parity <= 1)
+        .ok_or_else(|| format!("v {} is not an EIP-155 v for chain ID {}", v, chain_id))?;
+    let r = to_32_bytes(&rlp.val_at::<Vec<u8>>(LEGACY_FIELDS + 1)?)?;
+    let s = to_32_bytes(&rlp.val_at::<Vec<u8>>(LEGACY_FIELDS + 2)?)?;
+    let recovery_id = RecoveryId::from_byte(y_parity as u8).expect("y parity is 0 or 1");
+    let signature = Signature::from_scalars(r, s)?;
+    let key = VerifyingKey::recover_from_prehash(&signing_hash, &signature, recovery_id)?;
+    Ok(ethereum_address(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    /// Sign `unsigned` (EIP-155 form) as `SignEthereumTransaction` does.
+    fn sign(key: &SigningKey, unsigned: &[u8]) -> Vec<u8> {
+        let chain_id: u64 = Rlp::new(unsigned).val_at(LEGACY_FIELDS).unwrap();
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&keccak256(unsigned)).unwrap();
+        let (r, s) = signature.split_bytes();
+        let rlp = Rlp::new(unsigned);
+        let mut stream = RlpStream::new_list(EIP155_ITEMS);
+        for i in 0..LEGACY_FIELDS {
+            stream.append_raw(rlp.at(i).unwrap().as_raw(), 1);
+        }
+        stream.append(&(chain_id * 2 + 35 + u64::from(recovery_id.is_y_odd())));
+        stream.append(&trim_leading_zeros(&r));
+        stream.append(&trim_leading_zeros(&s));
+        stream.out().to_vec()
+    }
+
+    fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+        let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+        &bytes[start..]
+    }
+
+    #[test]
+    fn test_eip155_example() {
+        // The example of EIP-155: private key 0x4646...46, chain ID 1.
+        let unsigned = unsigned_legacy_test_transaction(1);
+        assert_eq!(
+            hex::encode(&unsigned),
+            "ec098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080\
+             018080"
+        );
+        let signed = hex::decode(
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f\
+             761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+        )
+        .unwrap();
+        let signer = recover_legacy_signer(&unsigned, &signed).unwrap();
+        assert_eq!(hex::encode(signer), "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f");
+    }
+
+    #[test]
+    fn test_sign_recover_roundtrip() {
+        let key = SigningKey::from_slice(&[0x42; 32]).unwrap();
+        let address = ethereum_address(key.verifying_key());
+        for chain_id in [1, 5, 11155111] {
+            let unsigned = unsigned_legacy_test_transaction(chain_id);
+            let signed = sign(&key, &unsigned);
+            assert_eq!(recover_legacy_signer(&unsigned, &signed).unwrap(), address);
+            // Signed for another chain, or with different fields.
+            let other_chain = unsigned_legacy_test_transaction(chain_id + 1);
+            let recovered = recover_legacy_signer(&other_chain, &signed);
+            assert!(recovered.map_or(true, |signer| signer != address));
+        }
+        let mut tampered = sign(&key, &unsigned_legacy_test_transaction(1));
+        // The last byte of the value (after the 2 byte list header and 40 bytes of fields).
+        tampered[41] ^= 1;
+        assert!(recover_legacy_signer(&unsigned_legacy_test_transaction(1), &tampered).is_err());
+    }
+}
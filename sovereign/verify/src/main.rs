@@ -3,11 +3,20 @@ use k256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
 use serde::Deserialize;
 use serde_bytes::ByteBuf;
 use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use clap::Parser;
 use reqwest::{self};
 
 mod cert;
+mod eth;
+mod report;
+
+pub mod pb {
+    tonic::include_proto!("key_pool");
+}
+
+use report::{Check, Report};
 
 // TODO: update this with changes to enclave!!!
 
@@ -16,6 +25,66 @@ mod cert;
 struct Args {
     #[arg(short, long, help = "Base URL of the enclave proxy")]
     url: String,
+    #[arg(long, help = "Print a JSON report of all attestation checks to stdout")]
+    json: bool,
+    #[arg(long, help = "Maximum accepted age of the attestation document in seconds")]
+    max_age_secs: Option<u64>,
+    #[arg(
+        long = "expected-pcr",
+        value_name = "N=HEX",
+        value_parser = parse_expected_pcr,
+        help = "Expected value of PCR N (hex); may be repeated, e.g., for PCR 0, 1, 2, and 4"
+    )]
+    expected_pcrs: Vec<(u8, Vec<u8>)>,
+    #[arg(long, value_name = "HEX", help = "Expected public key in the attestation document")]
+    expected_public_key: Option<String>,
+    #[arg(long, value_name = "HEX", help = "Expected user data in the attestation document")]
+    expected_user_data: Option<String>,
+    #[arg(
+        long,
+        value_name = "CHAIN_ID",
+        requires = "grpc_url",
+        help = "Also sign a legacy EIP-155 transaction for this chain with key 1 (SignEthereumTransaction) and check the signer"
+    )]
+    tx_chain_id: Option<u64>,
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "URL of the enclave's gRPC service, e.g., http://127.0.0.1:50051 through a forwarding proxy"
+    )]
+    grpc_url: Option<String>,
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        help = "Bearer token for the gRPC service (grpc-auth-token)"
+    )]
+    grpc_auth_token: Option<String>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "PEM file with the root certificate to verify against instead of the AWS Nitro Enclaves root, e.g., after AWS rotated it"
+    )]
+    root_ca: Option<std::path::PathBuf>,
+}
+
+/// Parse a pinned PCR value of the form `N=HEX`.
+fn parse_expected_pcr(arg: &str) -> Result<(u8, Vec<u8>), String> {
+    let (index, value) =
+        arg.split_once('=').ok_or_else(|| format!("expected N=HEX, was '{}'", arg))?;
+    let index: u8 = index.parse().map_err(|e| format!("invalid PCR index '{}': {}", index, e))?;
+    if index >= 32 {
+        return Err(format!("PCR index must be less than 32, was {}", index));
+    }
+    let value = hex::decode(value.trim_start_matches("0x"))
+        .map_err(|e| format!("invalid hex value for PCR{}: {}", index, e))?;
+    Ok((index, value))
+}
+
+fn decode_hex_arg(name: &str, value: Option<&String>) -> Result<Option<Vec<u8>>, String> {
+    value
+        .map(|x| hex::decode(x.trim_start_matches("0x")))
+        .transpose()
+        .map_err(|e| format!("invalid hex value for {}: {}", name, e))
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,11 +95,19 @@ pub struct NitroAttestationDocument {
     pcrs: std::collections::HashMap<u8, ByteBuf>,
     certificate: ByteBuf,
     cabundle: Vec<ByteBuf>,
+    // The optional fields are CBOR null in NSM documents, but other encoders omit them.
+    #[serde(default)]
     public_key: Option<ByteBuf>,
+    #[serde(default)]
     user_data: Option<ByteBuf>,
+    #[serde(default)]
     nonce: Option<ByteBuf>,
 }
 
+/// Verify an attestation document and collect the outcome of every check in a report.
+///
+/// An error is only returned if the document cannot be decoded at all;
+/// failed checks are recorded in the report instead.
 fn verify_attestation(
     root_cert: &[u8],
     cose_document: &[u8],
@@ -38,7 +115,8 @@ fn verify_attestation(
     expected_public_key: Option<&[u8]>,
     expected_user_data: Option<&[u8]>,
     expected_nonce: Option<&[u8]>,
-) -> Result<NitroAttestationDocument, Box<dyn std::error::Error>> {
+    max_age: Option<Duration>,
+) -> Result<(NitroAttestationDocument, Report), Box<dyn std::error::Error>> {
     tracing::debug!("Cose from bytes...");
     let cose_sign1 = CoseSign1::from_bytes(cose_document)?;
     tracing::debug!("Cose get payload...");
@@ -46,60 +124,74 @@ fn verify_attestation(
     tracing::debug!("Serde from slice...");
     let doc: NitroAttestationDocument = serde_cbor::from_slice(&payload)?;
     tracing::debug!("Attestation document: {:#?}", doc);
+    let mut report = Report::default();
     if let Some(expected) = expected_pcrs {
         for (&pcr_idx, expected_value) in expected {
-            match doc.pcrs.get(&pcr_idx) {
-                Some(actual_value) if actual_value == expected_value => {
-                    tracing::debug!("PCR{} ok", pcr_idx);
-                }
-                _ => return Err(format!("PCR{} mismatch or not found", pcr_idx).into()),
-            }
+            let actual = doc.pcrs.get(&pcr_idx).map(|x| x.as_slice());
+            report.push(Check::compare(format!("pcr{}", pcr_idx), expected_value, actual));
         }
     }
     if let Some(expected) = expected_public_key {
-        match doc.public_key.as_ref() {
-            Some(actual) => {
-                if actual.as_slice() == expected {
-                    tracing::debug!("public_key ok");
-                } else {
-                    return Err(format!(
-                        "public key mismatch: expected {:#?}, actual {:#?}",
-                        expected, actual
-                    )
-                    .into());
-                }
-            }
-            _ => return Err(format!("missing public key in attestation document").into()),
-        }
+        let actual = doc.public_key.as_ref().map(|x| x.as_slice());
+        report.push(Check::compare("public_key", expected, actual));
     }
     if let Some(expected) = expected_user_data {
-        match doc.user_data.as_ref() {
-            Some(actual) if actual.as_slice() == expected => {
-                tracing::debug!("user_data ok");
-            }
-            _ => return Err("User data mismatch".into()),
-        }
+        let actual = doc.user_data.as_ref().map(|x| x.as_slice());
+        report.push(Check::compare("user_data", expected, actual));
     }
     if let Some(expected) = expected_nonce {
-        match doc.nonce.as_ref() {
-            Some(actual) if actual.as_slice() == expected => {
-                tracing::debug!("nonce ok");
-            }
-            _ => return Err("User data mismatch".into()),
-        }
+        let actual = doc.nonce.as_ref().map(|x| x.as_slice());
+        report.push(Check::compare("nonce", expected, actual));
+    }
+    report.push(Check::from_result(
+        "cert_chain",
+        cert::verify_certificate(root_cert, &doc.certificate, &doc.cabundle),
+    ));
+    report.push(Check::from_result("signature", verify_signature(&cose_sign1, &doc.certificate)));
+    if let Some(max_age) = max_age {
+        report.push(check_freshness(doc.timestamp, max_age));
+    }
+    Ok((doc, report))
+}
+
+/// Verify the COSE signature using the public key of the leaf certificate.
+fn verify_signature(
+    cose_sign1: &CoseSign1,
+    certificate: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let leaf_cert = openssl::x509::X509::from_der(certificate)?;
+    let signing_key = leaf_cert.public_key()?;
+    if !cose_sign1.verify_signature::<Openssl>(&signing_key)? {
+        return Err("signature does not verify".into());
     }
-    cert::verify_certificate(root_cert, &doc.certificate, &doc.cabundle)?;
-    Ok(doc)
+    Ok(())
 }
 
-async fn verify_main(args_url: &str, root_cert: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-    let base_url = format!("http://{}", args_url);
+/// The attestation timestamp is in milliseconds since the UNIX epoch.
+fn check_freshness(timestamp_ms: u64, max_age: Duration) -> Check {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let age_ms = now_ms.saturating_sub(timestamp_ms);
+    let max_age_ms = max_age.as_millis() as u64;
+    Check {
+        name: "timestamp".to_string(),
+        passed: age_ms <= max_age_ms,
+        expected: Some(format!("age <= {}ms", max_age_ms)),
+        observed: Some(format!("age {}ms", age_ms)),
+        error: None,
+    }
+}
+
+async fn verify_main(args: &Args, root_cert: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let base_url = format!("http://{}", args.url);
 
     let client = reqwest::Client::new();
 
     // 1. Get Attestation Document
     let attestation_doc = client
-        .get(&format!("{}/attestation?encoding=binary", base_url))
+        .get(format!("{}/attestation?encoding=binary", base_url))
         .send()
         .await?
         .bytes()
@@ -109,7 +201,7 @@ async fn verify_main(args_url: &str, root_cert: &[u8]) -> Result<(), Box<dyn std
 
     // 2. Get Public Keys
     let pubkey1 = client
-        .get(&format!("{}/public_key", base_url))
+        .get(format!("{}/public_key", base_url))
         .header("x-public-key", "1")
         .send()
         .await?
@@ -117,7 +209,7 @@ async fn verify_main(args_url: &str, root_cert: &[u8]) -> Result<(), Box<dyn std
         .await?;
 
     let pubkey2 = client
-        .get(&format!("{}/public_key", base_url))
+        .get(format!("{}/public_key", base_url))
         .header("x-public-key", "2")
         .send()
         .await?
@@ -132,7 +224,27 @@ async fn verify_main(args_url: &str, root_cert: &[u8]) -> Result<(), Box<dyn std
     hasher.update(&pubkey2);
     let _expected_public_key = hasher.finalize(); // this is a 32-byte array
 
-    verify_attestation(root_cert, attestation_doc.as_ref(), None, None, None, None)?;
+    let max_age = args.max_age_secs.map(Duration::from_secs);
+    let expected_pcrs: BTreeMap<u8, Vec<u8>> = args.expected_pcrs.iter().cloned().collect();
+    let expected_public_key =
+        decode_hex_arg("--expected-public-key", args.expected_public_key.as_ref())?;
+    let expected_user_data =
+        decode_hex_arg("--expected-user-data", args.expected_user_data.as_ref())?;
+    let (_doc, report) = verify_attestation(
+        root_cert,
+        attestation_doc.as_ref(),
+        Some(&expected_pcrs).filter(|x| !x.is_empty()),
+        expected_public_key.as_deref(),
+        expected_user_data.as_deref(),
+        None,
+        max_age,
+    )?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    if let Some(failure) = report.failures().next() {
+        return Err(format!("attestation verification failed: {}", failure).into());
+    }
 
     // 3. Signing Test
     // Prepare test vector [0, 1, ..., 31]
@@ -140,7 +252,7 @@ async fn verify_main(args_url: &str, root_cert: &[u8]) -> Result<(), Box<dyn std
 
     // Sign with key 1
     let signature1 = client
-        .post(&format!("{}/sign", base_url))
+        .post(format!("{}/sign", base_url))
         .header("x-ecdsa-signing-key", "1")
         .body(test_vector.clone())
         .send()
@@ -150,7 +262,7 @@ async fn verify_main(args_url: &str, root_cert: &[u8]) -> Result<(), Box<dyn std
 
     // Sign with key 2
     let signature2 = client
-        .post(&format!("{}/sign", base_url))
+        .post(format!("{}/sign", base_url))
         .header("x-ecdsa-signing-key", "2")
         .body(test_vector.clone())
         .send()
@@ -169,14 +281,55 @@ async fn verify_main(args_url: &str, root_cert: &[u8]) -> Result<(), Box<dyn std
     verifying_key1.verify(&test_vector, &signature1_obj)?;
     verifying_key2.verify(&test_vector, &signature2_obj)?;
 
-    println!("Signatures verified successfully!");
+    tracing::info!("Signatures verified successfully!");
+
+    // 5. Transaction Signing Test
+    if let (Some(chain_id), Some(grpc_url)) = (args.tx_chain_id, &args.grpc_url) {
+        let unsigned_tx = eth::unsigned_legacy_test_transaction(chain_id);
+        let signed_tx =
+            sign_ethereum_transaction(grpc_url, args.grpc_auth_token.as_deref(), &unsigned_tx)
+                .await?;
+        let signer = eth::recover_legacy_signer(&unsigned_tx, &signed_tx)?;
+        let expected_signer = eth::ethereum_address(&verifying_key1);
+        if signer != expected_signer {
+            return Err(format!(
+                "transaction signed by 0x{}; expected 0x{}",
+                hex::encode(signer),
+                hex::encode(expected_signer)
+            )
+            .into());
+        }
+        tracing::info!("Transaction signature verified successfully!");
+    }
 
     Ok(())
 }
 
+/// Sign `unsigned_tx` with key 1 using the `SignEthereumTransaction` RPC.
+async fn sign_ethereum_transaction(
+    grpc_url: &str,
+    auth_token: Option<&str>,
+    unsigned_tx: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use pb::key_pool_service_client::KeyPoolServiceClient;
+    use pb::{SignEthereumTransactionRequest, SigningKey};
+
+    let mut client = KeyPoolServiceClient::connect(grpc_url.to_string()).await?;
+    let mut request = tonic::Request::new(SignEthereumTransactionRequest {
+        signing_key: Some(SigningKey { key_index: 1, ..Default::default() }),
+        tx_data: unsigned_tx.to_vec(),
+    });
+    if let Some(auth_token) = auth_token {
+        request.metadata_mut().insert("authorization", format!("Bearer {}", auth_token).parse()?);
+    }
+    Ok(client.sign_ethereum_transaction(request).await?.into_inner().tx_data)
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt()
+        // Keep stdout clean for the JSON report.
+        .with_writer(std::io::stderr)
         .with_thread_ids(true)
         .with_target(false)
         .with_file(true)
@@ -187,7 +340,10 @@ async fn main() {
 
     let args = Args::parse();
 
-    // TODO: Available as a file from xxx
+    // The AWS Nitro Enclaves root certificate, available from
+    // https://aws-nitro-enclaves.amazonaws.com/AWS_NitroEnclaves_Root-G1.zip.
+    // It must be pinned here: the chain served by the enclave's `GET /cabundle`
+    // endpoint is for inspection only and is not a trust anchor.
     const AWS_ROOT_CA_PEM: &[u8] = b"-----BEGIN CERTIFICATE-----
 MIICETCCAZagAwIBAgIRAPkxdWgbkK/hHUbMtOTn+FYwCgYIKoZIzj0EAwMwSTEL
 MAkGA1UEBhMCVVMxDzANBgNVBAoMBkFtYXpvbjEMMAoGA1UECwwDQVdTMRswGQYD
@@ -203,11 +359,26 @@ rfMCMQCi85sWBbJwKKXdS6BptQFuZbT73o/gBh1qUxl/nNr12UO8Yfwr6wPLb+6N
 IwLz3/Y=
 -----END CERTIFICATE-----";
 
-    let pems = pem::parse_many(AWS_ROOT_CA_PEM).unwrap();
-    assert_eq!(pems.len(), 1);
+    let root_ca_pem = match &args.root_ca {
+        Some(path) => match std::fs::read(path) {
+            Ok(root_ca_pem) => root_ca_pem,
+            Err(e) => {
+                tracing::error!("cannot read root certificate {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => AWS_ROOT_CA_PEM.to_vec(),
+    };
+    let pems = match pem::parse_many(&root_ca_pem) {
+        Ok(pems) if pems.len() == 1 => pems,
+        _ => {
+            tracing::error!("root certificate must be exactly one PEM certificate");
+            std::process::exit(1);
+        }
+    };
     let pem = &pems[0];
 
-    if let Err(e) = verify_main(&args.url, pem.contents()).await {
+    if let Err(e) = verify_main(&args, pem.contents()).await {
         tracing::error!("Error: {}", e);
         std::process::exit(1);
     }
@@ -0,0 +1,85 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Outcome of a single check performed while verifying an attestation document.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    /// Compare an expected value with the (possibly missing) value observed in the document.
+    pub fn compare(name: impl Into<String>, expected: &[u8], observed: Option<&[u8]>) -> Self {
+        Check {
+            name: name.into(),
+            passed: observed == Some(expected),
+            expected: Some(hex::encode(expected)),
+            observed: observed.map(hex::encode),
+            error: None,
+        }
+    }
+
+    /// A check that either succeeded or failed with an error.
+    pub fn from_result<E: fmt::Display>(name: impl Into<String>, result: Result<(), E>) -> Self {
+        Check {
+            name: name.into(),
+            passed: result.is_ok(),
+            expected: None,
+            observed: None,
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, if self.passed { "ok" } else { "failed" })?;
+        if let Some(expected) = &self.expected {
+            write!(f, ", expected {}", expected)?;
+        }
+        if let Some(observed) = &self.observed {
+            write!(f, ", observed {}", observed)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ", error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// All checks performed on an attestation document.
+/// Verification continues past failed checks so that the report is complete.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub passed: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report { passed: true, checks: Vec::new() }
+    }
+}
+
+impl Report {
+    pub fn push(&mut self, check: Check) {
+        if check.passed {
+            tracing::debug!("{}", check);
+        } else {
+            tracing::error!("{}", check);
+        }
+        self.passed &= check.passed;
+        self.checks.push(check);
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+}